@@ -1,13 +1,27 @@
 use crate::{
     application_error::{Error, Result},
-    database::Database,
+    credential::CredentialCipher,
+    database::{tables::v2::AccountTable, Database},
+    dlsite::{client::DLsiteClient, endpoints::DLsiteEndpoints},
+    library::watcher::LibraryWatcher,
+    services::{
+        context::ServiceContext,
+        download_queue_service::{DownloadQueueService, DownloadTask},
+        library_service::LibraryService,
+    },
     window::{BuildableWindow, MainWindow},
 };
+use log::error;
 use parking_lot::{MappedMutexGuard, Mutex, MutexGuard};
 use rusqlite::Connection;
-use std::{fs::create_dir_all, mem::MaybeUninit, sync::Arc};
+use std::{collections::HashMap, fs::create_dir_all, mem::MaybeUninit, sync::Arc};
 use tauri::{App, AppHandle, Manager};
 
+/// Name of the locally generated key file used to encrypt the credentials.
+pub const CREDENTIAL_KEY_FILE_NAME: &str = "credential.key";
+/// Name of the file present when the credentials are encrypted with a master password instead.
+pub const MASTER_PASSWORD_FILE_NAME: &str = "credential.master.json";
+
 static mut APPLICATION: MaybeUninit<Arc<Application>> = MaybeUninit::uninit();
 
 pub fn use_application() -> &'static Application {
@@ -26,8 +40,12 @@ pub fn create_application(app: &App) -> Result<Arc<Application>> {
 
 pub struct Application {
     app_handle: AppHandle,
-    database: Mutex<Option<Database>>,
+    context: ServiceContext,
     is_updating_product: Mutex<bool>,
+    /// running download tasks keyed by their download job id
+    download_tasks: Mutex<HashMap<i64, DownloadTask>>,
+    /// `None` while the download root does not exist
+    library_watcher: Mutex<Option<LibraryWatcher>>,
 }
 
 impl Application {
@@ -35,13 +53,30 @@ impl Application {
         let app_dir = app.path().app_config_dir()?;
         create_dir_all(&app_dir).map_err(|err| Error::AppDirCreationError { io_error: err })?;
 
-        let mut database = Database::load(app_dir.join("database.db"))?;
-        rusqlite::vtab::array::load_module(database.connection_mut())?;
+        let database = Database::load(app_dir.join("database.db"))?;
+
+        let credential_cipher = if app_dir.join(MASTER_PASSWORD_FILE_NAME).exists() {
+            None
+        } else {
+            Some(
+                CredentialCipher::load_or_create_key_file(app_dir.join(CREDENTIAL_KEY_FILE_NAME))
+                    .map_err(|err| Error::CredentialKeyError { error: err })?,
+            )
+        };
+
+        let dlsite_endpoints =
+            DLsiteEndpoints::load().map_err(|err| Error::DLsiteEndpointsError { error: err })?;
+        let dlsite_client = DLsiteClient::anonymous(dlsite_endpoints)
+            .map_err(|err| Error::DLsiteClientError { error: err })?;
+
+        let thumbnail_dir = app.path().app_data_dir()?.join("thumbnails");
 
         Ok(Self {
             app_handle: app.handle().clone(),
-            database: Mutex::new(Some(database)),
+            context: ServiceContext::new(database, credential_cipher, dlsite_client, thumbnail_dir),
             is_updating_product: Mutex::new(false),
+            download_tasks: Mutex::new(HashMap::new()),
+            library_watcher: Mutex::new(None),
         })
     }
 
@@ -49,30 +84,71 @@ impl Application {
         &self.app_handle
     }
 
+    pub fn context(&self) -> &ServiceContext {
+        &self.context
+    }
+
     pub fn connection(&self) -> MappedMutexGuard<Connection> {
-        MutexGuard::map(self.database.lock(), |storage| {
-            storage.as_mut().unwrap().connection_mut()
-        })
+        self.context.connection()
     }
 
     pub fn is_updating_product(&self) -> MutexGuard<bool> {
         self.is_updating_product.lock()
     }
 
+    pub fn download_tasks(&self) -> MutexGuard<HashMap<i64, DownloadTask>> {
+        self.download_tasks.lock()
+    }
+
+    pub fn credential_cipher(&self) -> MutexGuard<Option<CredentialCipher>> {
+        self.context.credential_cipher()
+    }
+
+    pub fn dlsite_endpoints(&self) -> &DLsiteEndpoints {
+        self.context.dlsite_client().endpoints()
+    }
+
+    pub fn dlsite_client(&self) -> &DLsiteClient {
+        self.context.dlsite_client()
+    }
+
+    pub fn library_watcher(&self) -> MutexGuard<Option<LibraryWatcher>> {
+        self.library_watcher.lock()
+    }
+
     pub fn init(&self) -> Result<()> {
-        self.database.lock().as_ref().unwrap().prepare()?;
+        self.context.prepare()?;
+
+        if self.credential_cipher().is_some() {
+            if let Err(err) = AccountTable::encrypt_plaintext(
+                &mut self.connection(),
+                self.credential_cipher().as_ref(),
+            ) {
+                error!("[init] failed to encrypt plaintext credentials: {:?}", err);
+            }
+        }
+
         Ok(())
     }
 
     pub fn run(&self) -> Result<()> {
         MainWindow.build(&self.app_handle)?;
+
+        // a locked application restores the queue once the credentials are unlocked
+        if self.credential_cipher().is_some() {
+            if let Err(err) = DownloadQueueService::new().restore() {
+                error!("[run] failed to restore the download queue: {:?}", err);
+            }
+        }
+
+        if let Err(err) = LibraryService::new().watch_downloads() {
+            error!("[run] failed to watch the download root: {:?}", err);
+        }
+
         Ok(())
     }
 
     pub fn drop_storage(&self) -> Result<()> {
-        if let Some(storage) = self.database.lock().take() {
-            storage.drop()?;
-        }
-        Ok(())
+        self.context.drop_storage()
     }
 }
@@ -5,6 +5,14 @@ pub type Result<T> = std::result::Result<T, Error>;
 pub enum ApplicationError {
     #[error("cannot create app directory due to: {io_error}")]
     AppDirCreationError { io_error: std::io::Error },
+    #[error("cannot load credential key due to: {error}")]
+    CredentialKeyError { error: anyhow::Error },
+    #[error("cannot load DLsite endpoints due to: {error}")]
+    DLsiteEndpointsError { error: anyhow::Error },
+    #[error("cannot create DLsite client due to: {error}")]
+    DLsiteClientError { error: anyhow::Error },
+    #[error("cannot back up database due to: {io_error}")]
+    DatabaseBackupError { io_error: std::io::Error },
     #[error("database error: {rusqlite_error}")]
     DatabaseError {
         #[from]
@@ -1,10 +1,12 @@
 use super::error::CommandResult;
 use crate::{
+    application::use_application,
     database::{
         models::v2::{Account, CreatingAccount, SimpleAccount, UpdatingAccount},
         tables::v2::AccountTable,
     },
-    dlsite::api::{get_product_count, login, LoginError},
+    dlsite::{api::LoginError, client::DLsiteClient},
+    services::dlsite_service::DLsiteService,
     window::{AccountEditWindow, AccountManagementWindow, WindowInfoProvider},
 };
 use log::warn;
@@ -12,12 +14,19 @@ use tauri::{Manager, Runtime, Window};
 
 #[tauri::command]
 pub fn account_management_list_accounts() -> CommandResult<Vec<Account>> {
-    Ok(AccountTable::get_all()?)
+    Ok(AccountTable::get_all(
+        &use_application().connection(),
+        use_application().credential_cipher().as_ref(),
+    )?)
 }
 
 #[tauri::command]
 pub fn account_management_get_account(account_id: i64) -> CommandResult<Option<SimpleAccount>> {
-    Ok(AccountTable::get_one_simple(account_id)?)
+    Ok(AccountTable::get_one_simple(
+        &use_application().connection(),
+        use_application().credential_cipher().as_ref(),
+        account_id,
+    )?)
 }
 
 #[tauri::command]
@@ -26,7 +35,11 @@ pub fn account_management_add_account<R: Runtime>(
     window: Window<R>,
     account: CreatingAccount,
 ) -> CommandResult<()> {
-    let account_id = AccountTable::insert_one(account.clone())?;
+    let account_id = AccountTable::insert_one(
+        &use_application().connection(),
+        use_application().credential_cipher().as_ref(),
+        account.clone(),
+    )?;
 
     if let Some(window) = app_handle.get_webview_window(&AccountManagementWindow.label()) {
         window.emit(
@@ -34,7 +47,7 @@ pub fn account_management_add_account<R: Runtime>(
             SimpleAccount {
                 id: account_id,
                 username: account.username.to_owned(),
-                password: account.password.to_owned(),
+                password: account.password.clone(),
                 memo: account.memo.map(|memo| memo.to_owned()),
             },
         )?;
@@ -50,7 +63,12 @@ pub fn account_management_update_account<R: Runtime>(
     window: Window<R>,
     account: UpdatingAccount,
 ) -> CommandResult<()> {
-    AccountTable::update_one(account.clone())?;
+    AccountTable::update_one(
+        &use_application().connection(),
+        use_application().credential_cipher().as_ref(),
+        account.clone(),
+    )?;
+    DLsiteService::new().invalidate_client(account.id);
 
     if let Some(window) = app_handle.get_webview_window(&AccountManagementWindow.label()) {
         window.emit(
@@ -58,7 +76,7 @@ pub fn account_management_update_account<R: Runtime>(
             SimpleAccount {
                 id: account.id,
                 username: account.username.to_owned(),
-                password: account.password.to_owned(),
+                password: account.password.clone(),
                 memo: account.memo.map(|memo| memo.to_owned()),
             },
         )?;
@@ -73,7 +91,8 @@ pub fn account_management_remove_account<R: Runtime>(
     app_handle: tauri::AppHandle<R>,
     account_id: i64,
 ) -> CommandResult<()> {
-    AccountTable::remove_one(account_id)?;
+    AccountTable::remove_one(&use_application().connection(), account_id)?;
+    DLsiteService::new().invalidate_client(account_id);
 
     if let Some(window) = app_handle.get_webview_window(&AccountManagementWindow.label()) {
         window.emit("remove-account", account_id)?;
@@ -91,8 +110,14 @@ pub async fn account_management_test_account(
     username: String,
     password: String,
 ) -> CommandResult<isize> {
-    let cookie = match login(username, password).await {
-        Ok(cookie) => cookie,
+    let client = match DLsiteClient::login(
+        use_application().dlsite_endpoints().clone(),
+        username,
+        password,
+    )
+    .await
+    {
+        Ok(client) => client,
         Err(err) => {
             warn!(
                 "[account_management_test_account] failed to test account: {:?}",
@@ -106,7 +131,8 @@ pub async fn account_management_test_account(
         }
     };
 
-    Ok(get_product_count(cookie)
+    Ok(client
+        .get_product_count()
         .await
         .map(|count| count as isize)?)
 }
@@ -0,0 +1,27 @@
+use super::error::CommandResult;
+use crate::services::credential_service::{CredentialService, CredentialServiceError};
+
+#[tauri::command]
+pub fn credential_is_locked() -> CommandResult<bool> {
+    Ok(CredentialService::new().is_locked())
+}
+
+#[tauri::command]
+pub fn credential_has_master_password() -> CommandResult<bool> {
+    Ok(CredentialService::new().has_master_password()?)
+}
+
+/// Returns `false` if the master password is wrong, so that it can be asked again.
+#[tauri::command]
+pub fn credential_unlock(master_password: String) -> CommandResult<bool> {
+    match CredentialService::new().unlock(&master_password) {
+        Ok(()) => Ok(true),
+        Err(CredentialServiceError::WrongMasterPassword) => Ok(false),
+        Err(err) => Err(err.into()),
+    }
+}
+
+#[tauri::command]
+pub fn credential_set_master_password(master_password: Option<String>) -> CommandResult<()> {
+    Ok(CredentialService::new().set_master_password(master_password.as_deref())?)
+}
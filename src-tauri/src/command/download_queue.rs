@@ -0,0 +1,51 @@
+use super::error::CommandResult;
+use crate::{
+    database::models::v2::DownloadJob, dlsite::product_id::ProductId,
+    services::download_queue_service::DownloadQueueService,
+};
+
+#[tauri::command]
+pub async fn download_queue_list() -> CommandResult<Vec<DownloadJob>> {
+    Ok(DownloadQueueService::new().list()?)
+}
+
+#[tauri::command]
+pub async fn download_queue_enqueue(
+    account_id: i64,
+    product_id: ProductId,
+    decompress: Option<bool>,
+) -> CommandResult<i64> {
+    Ok(DownloadQueueService::new().enqueue(account_id, &product_id, decompress.unwrap_or(true))?)
+}
+
+#[tauri::command]
+pub async fn download_queue_pause(job_id: i64) -> CommandResult<()> {
+    Ok(DownloadQueueService::new().pause(job_id)?)
+}
+
+#[tauri::command]
+pub async fn download_queue_resume(job_id: i64) -> CommandResult<()> {
+    Ok(DownloadQueueService::new().resume(job_id)?)
+}
+
+#[tauri::command]
+pub async fn download_queue_cancel(job_id: i64) -> CommandResult<()> {
+    Ok(DownloadQueueService::new().cancel(job_id)?)
+}
+
+#[tauri::command]
+pub async fn download_queue_reorder(job_ids: Vec<i64>) -> CommandResult<()> {
+    Ok(DownloadQueueService::new().reorder(&job_ids)?)
+}
+
+#[tauri::command]
+pub async fn download_queue_get_max_concurrent_downloads() -> CommandResult<u32> {
+    Ok(DownloadQueueService::new().get_max_concurrent_downloads()?)
+}
+
+#[tauri::command]
+pub async fn download_queue_set_max_concurrent_downloads(
+    max_concurrent_downloads: u32,
+) -> CommandResult<()> {
+    Ok(DownloadQueueService::new().set_max_concurrent_downloads(max_concurrent_downloads)?)
+}
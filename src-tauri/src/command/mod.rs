@@ -1,11 +1,19 @@
 mod account_management;
+mod credential;
+mod download_queue;
 mod error;
 mod product;
 mod setting;
 mod window;
 
-use crate::database::tables::v2::SettingTable;
-use anyhow::Error as AnyError;
+use crate::{
+    application::use_application,
+    database::tables::v2::SettingTable,
+    library::{folder_template::FolderTemplate, scanner::DEFAULT_SCAN_DEPTH},
+};
+use anyhow::{Context, Error as AnyError};
+use log::warn;
+use rusqlite::Connection;
 use std::path::PathBuf;
 use tauri::{generate_handler, Builder, Manager, Runtime};
 
@@ -28,13 +36,29 @@ where
             account_management::account_management_update_account,
             account_management::account_management_remove_account,
             account_management::account_management_test_account,
+            credential::credential_is_locked,
+            credential::credential_has_master_password,
+            credential::credential_unlock,
+            credential::credential_set_master_password,
+            download_queue::download_queue_list,
+            download_queue::download_queue_enqueue,
+            download_queue::download_queue_pause,
+            download_queue::download_queue_resume,
+            download_queue::download_queue_cancel,
+            download_queue::download_queue_reorder,
+            download_queue::download_queue_get_max_concurrent_downloads,
+            download_queue::download_queue_set_max_concurrent_downloads,
             product::product_list_products,
+            product::product_parse_query,
             product::product_list_product_downloads,
+            product::product_get_metadata,
             product::product_download_product,
             product::product_open_downloaded_folder,
             product::product_remove_downloaded_product,
+            product::product_fix_file_names,
             setting::setting_get,
             setting::setting_browse_default_root_directory,
+            setting::setting_check_folder_template,
             setting::setting_close,
             setting::setting_save_and_close,
             window::show_window,
@@ -47,7 +71,7 @@ where
 pub fn get_product_download_path<R: Runtime>(
     app_handle: &tauri::AppHandle<R>,
 ) -> Result<PathBuf, AnyError> {
-    let setting = SettingTable::get()?;
+    let setting = SettingTable::get(&use_application().connection())?;
     let setting = setting.unwrap_or_default();
 
     let path = setting.download_root_dir.unwrap_or_else(|| {
@@ -60,3 +84,36 @@ pub fn get_product_download_path<R: Runtime>(
 
     Ok(path)
 }
+
+/// Gets the configured folder template. An invalid template falls back to the default one,
+/// since it is validated on save and only an outdated one could be invalid.
+pub fn get_product_folder_template(connection: &Connection) -> Result<FolderTemplate, AnyError> {
+    let setting = SettingTable::get(connection)
+        .with_context(|| format!("[get_product_folder_template]"))?
+        .unwrap_or_default();
+
+    let template = match setting.folder_template {
+        Some(template) if !template.is_empty() => template,
+        _ => return Ok(FolderTemplate::default()),
+    };
+
+    Ok(match FolderTemplate::parse(&template) {
+        Ok(template) => template,
+        Err(err) => {
+            warn!(
+                "[get_product_folder_template] the folder template `{}` is not valid; using the default one: {}",
+                template, err
+            );
+            FolderTemplate::default()
+        }
+    })
+}
+
+/// Gets the configured number of folder levels searched for downloaded products.
+pub fn get_product_scan_depth() -> Result<u32, AnyError> {
+    let setting = SettingTable::get(&use_application().connection())
+        .with_context(|| format!("[get_product_scan_depth]"))?
+        .unwrap_or_default();
+
+    Ok(setting.scan_depth.unwrap_or(DEFAULT_SCAN_DEPTH))
+}
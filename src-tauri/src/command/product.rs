@@ -1,144 +1,118 @@
 use super::{error::CommandResult, get_product_download_path};
 use crate::{
+    application::use_application,
     database::{
-        models::v2::{Product, ProductDownload},
-        tables::v2::{ProductDownloadTable, ProductTable},
+        models::v2::{Product, ProductDownload, ProductMetadata},
+        tables::v2::{ProductDownloadTable, ProductMetadataTable, ProductTable},
+    },
+    dlsite::{
+        dto::{DLsiteProductAgeCategory, DLsiteProductType},
+        product_id::ProductId,
+    },
+    library::name_encoding::{NameEncoding, NameFix},
+    product_query::{
+        ast::{ProductFilter, ProductQueryTerm},
+        parser::{self, ProductQuerySyntaxError},
+    },
+    services::{
+        download_queue_service::DownloadQueueService, download_service::DownloadService,
+        library_service::LibraryService,
     },
-    dlsite::dto::{DLsiteProductAgeCategory, DLsiteProductType},
-    services::download_service::DownloadService,
     window::{MainWindow, WindowInfoProvider},
 };
 use anyhow::Context;
-use serde::{Deserialize, Serialize};
+use serde::Deserialize;
 use std::path::Path;
-use tauri::{Manager, Runtime};
+use tauri::{async_runtime::spawn_blocking, Manager, Runtime};
 use tauri_plugin_shell::ShellExt;
 
 #[derive(Default, Debug, Clone, Deserialize)]
 pub struct ProductQuery<'a> {
+    /// A query written in the product query language; see [`parser::parse`].
     pub query: Option<&'a str>,
     pub ty: Option<DLsiteProductType>,
     pub age: Option<DLsiteProductAgeCategory>,
     pub order_by_asc: bool,
 }
 
-#[derive(Debug, Clone, Serialize)]
-pub struct ProductDownloadProgressEvent<'a> {
-    pub product_id: &'a str,
-    pub progress: usize,
-    pub decompressing: bool,
-}
-
-#[derive(Debug, Clone, Serialize)]
-pub struct ProductDownloadEndEvent<'a> {
-    pub product_id: &'a str,
-    pub downloaded_path: Option<&'a Path>,
-}
-
 #[tauri::command]
 pub async fn product_list_products<'a>(
     query: Option<ProductQuery<'a>>,
 ) -> CommandResult<Vec<Product>> {
     let query = query.unwrap_or_default();
-    let results = ProductTable::get_many(query.query, query.ty, query.age, query.order_by_asc)
-        .with_context(|| format!("[command/product_list_products] ProductTable::get_many"))?;
+    let mut parsed_query = parser::parse(query.query.unwrap_or_default())
+        .with_context(|| format!("[command/product_list_products] parser::parse"))?;
+
+    if let Some(ty) = query.ty {
+        parsed_query.terms.push(ProductQueryTerm {
+            negated: false,
+            filter: ProductFilter::Type(vec![ty]),
+        });
+    }
+
+    if let Some(age) = query.age {
+        parsed_query.terms.push(ProductQueryTerm {
+            negated: false,
+            filter: ProductFilter::Age(vec![age]),
+        });
+    }
+
+    let results = ProductTable::get_many(
+        &use_application().connection(),
+        &parsed_query,
+        query.order_by_asc,
+    )
+    .with_context(|| format!("[command/product_list_products] ProductTable::get_many"))?;
     Ok(results)
 }
 
+/// Checks the query without running it. Returns every syntax error, or nothing if it is valid.
+#[tauri::command]
+pub async fn product_parse_query(query: String) -> CommandResult<Vec<ProductQuerySyntaxError>> {
+    Ok(match parser::parse(&query) {
+        Ok(_) => vec![],
+        Err(err) => err.errors,
+    })
+}
+
 #[tauri::command]
 pub async fn product_list_product_downloads(
-    product_ids: Vec<String>,
+    product_ids: Vec<ProductId>,
 ) -> CommandResult<Vec<ProductDownload>> {
-    let results = ProductDownloadTable::get_many(product_ids.into_iter()).with_context(|| {
-        format!("[command/product_list_product_downloads] ProductDownloadTable::get_many")
-    })?;
+    let results =
+        ProductDownloadTable::get_many(&use_application().connection(), product_ids.into_iter())
+            .with_context(|| {
+                format!("[command/product_list_product_downloads] ProductDownloadTable::get_many")
+            })?;
     Ok(results)
 }
 
 #[tauri::command]
-pub async fn product_download_product<R: Runtime>(
-    app_handle: tauri::AppHandle<R>,
+pub async fn product_get_metadata(product_id: ProductId) -> CommandResult<Option<ProductMetadata>> {
+    let result = ProductMetadataTable::get_one(&use_application().connection(), &product_id)
+        .with_context(|| format!("[command/product_get_metadata] ProductMetadataTable::get_one"))?;
+    Ok(result)
+}
+
+/// Queues the product for download. The download itself is run by the download queue,
+/// which emits `download-begin`, `download-progress` and `download-end` as it goes.
+#[tauri::command]
+pub async fn product_download_product(
     account_id: i64,
-    product_id: String,
+    product_id: ProductId,
     decompress: Option<bool>,
 ) -> CommandResult<()> {
-    if let Some(window) = app_handle.get_webview_window(&MainWindow.label()) {
-        window.emit("download-begin", &product_id)?;
-    }
-
-    let path = get_product_download_path(&app_handle)?;
-    let downloaded_path = if decompress.unwrap_or(true) {
-        DownloadService::new()
-            .download_with_decompression(
-                account_id,
-                &product_id,
-                &path,
-                |progress, total_progress, decompressing| {
-                    if let Some(window) = app_handle.get_webview_window(&MainWindow.label()) {
-                        window
-                            .emit(
-                                "download-progress",
-                                ProductDownloadProgressEvent {
-                                    product_id: &product_id,
-                                    progress: (progress as f64 / total_progress as f64 * 100f64)
-                                        .round()
-                                        as usize,
-                                    decompressing,
-                                },
-                            )
-                            .ok();
-                    }
-                },
-            )
-            .await
-    } else {
-        DownloadService::new()
-            .download(
-                account_id,
-                &product_id,
-                &path,
-                |progress, total_progress| {
-                    if let Some(window) = app_handle.get_webview_window(&MainWindow.label()) {
-                        window
-                            .emit(
-                                "download-progress",
-                                ProductDownloadProgressEvent {
-                                    product_id: &product_id,
-                                    progress: (progress as f64 / total_progress as f64 * 100f64)
-                                        .round()
-                                        as usize,
-                                    decompressing: false,
-                                },
-                            )
-                            .ok();
-                    }
-                },
-            )
-            .await
-    };
-
-    if let Some(window) = app_handle.get_webview_window(&MainWindow.label()) {
-        window.emit(
-            "download-end",
-            ProductDownloadEndEvent {
-                product_id: &product_id,
-                downloaded_path: downloaded_path.as_ref().map(|path| path.as_path()).ok(),
-            },
-        )?;
-    }
-
-    match downloaded_path {
-        Ok(_) => Ok(()),
-        Err(err) => Err(err.into()),
-    }
+    DownloadQueueService::new().enqueue(account_id, &product_id, decompress.unwrap_or(true))?;
+    Ok(())
 }
 
 #[tauri::command]
 pub async fn product_open_downloaded_folder<R: Runtime>(
     app_handle: tauri::AppHandle<R>,
-    product_id: String,
+    product_id: ProductId,
 ) -> CommandResult<()> {
-    let path = if let Some(download) = ProductDownloadTable::get_one(&product_id)? {
+    let download = ProductDownloadTable::get_one(&use_application().connection(), &product_id)?;
+    let path = if let Some(download) = download {
         download.path
     } else {
         if let Some(window) = app_handle.get_webview_window(&MainWindow.label()) {
@@ -148,8 +122,8 @@ pub async fn product_open_downloaded_folder<R: Runtime>(
         return Ok(());
     };
 
-    if !path.is_dir() {
-        ProductDownloadTable::remove_one(&product_id)?;
+    if !path.exists() {
+        ProductDownloadTable::remove_one(&use_application().connection(), &product_id)?;
 
         if let Some(window) = app_handle.get_webview_window(&MainWindow.label()) {
             window.emit("download-invalid", &product_id)?;
@@ -158,6 +132,13 @@ pub async fn product_open_downloaded_folder<R: Runtime>(
         return Ok(());
     }
 
+    // a scanned archive that is not extracted is shown in its folder
+    let path = if path.is_file() {
+        path.parent().map(Path::to_path_buf).unwrap_or(path)
+    } else {
+        path
+    };
+
     app_handle.shell().open(path.to_str().unwrap(), None)?;
 
     Ok(())
@@ -166,7 +147,7 @@ pub async fn product_open_downloaded_folder<R: Runtime>(
 #[tauri::command]
 pub async fn product_remove_downloaded_product<R: Runtime>(
     app_handle: tauri::AppHandle<R>,
-    product_id: String,
+    product_id: ProductId,
 ) -> CommandResult<()> {
     let path = get_product_download_path(&app_handle)?;
 
@@ -180,3 +161,16 @@ pub async fn product_remove_downloaded_product<R: Runtime>(
 
     Ok(())
 }
+
+/// Fixes the mojibake file names in the extracted folder of the product. The encoding given,
+/// if any, is kept for the product and used when it is extracted again.
+#[tauri::command]
+pub async fn product_fix_file_names(
+    product_id: ProductId,
+    encoding: Option<NameEncoding>,
+) -> CommandResult<Vec<NameFix>> {
+    let fixes =
+        spawn_blocking(move || LibraryService::new().fix_product_names(&product_id, encoding))
+            .await??;
+    Ok(fixes)
+}
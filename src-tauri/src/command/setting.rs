@@ -2,13 +2,17 @@ use super::error::CommandResult;
 use crate::{
     application::use_application,
     database::{models::v2::Setting, tables::v2::SettingTable},
+    library::{folder_template::FolderTemplate, scanner::MAX_SCAN_DEPTH},
+    services::library_service::LibraryService,
 };
+use anyhow::anyhow;
+use log::warn;
 use tauri::{Runtime, Window};
 use tauri_plugin_dialog::DialogExt;
 
 #[tauri::command]
 pub async fn setting_get() -> CommandResult<Setting> {
-    Ok(SettingTable::get()?.unwrap_or_default())
+    Ok(SettingTable::get(&use_application().connection())?.unwrap_or_default())
 }
 
 #[tauri::command]
@@ -22,6 +26,14 @@ pub async fn setting_browse_default_root_directory() -> CommandResult<Option<Str
         .map(|err| err.to_str().unwrap().to_owned()))
 }
 
+/// Checks the folder template without saving it. Returns the error message, or nothing if it is valid.
+#[tauri::command]
+pub async fn setting_check_folder_template(template: String) -> CommandResult<Option<String>> {
+    Ok(FolderTemplate::parse(&template)
+        .err()
+        .map(|err| err.to_string()))
+}
+
 #[tauri::command]
 pub async fn setting_close<R: Runtime>(window: Window<R>) -> CommandResult<()> {
     window.close()?;
@@ -33,7 +45,30 @@ pub async fn setting_save_and_close<R: Runtime>(
     window: Window<R>,
     setting: Setting,
 ) -> CommandResult<()> {
-    SettingTable::insert(&setting)?;
+    if let Some(template) = setting
+        .folder_template
+        .as_deref()
+        .filter(|template| !template.is_empty())
+    {
+        FolderTemplate::parse(template)?;
+    }
+
+    if let Some(scan_depth) = setting.scan_depth {
+        if !(1..=MAX_SCAN_DEPTH).contains(&scan_depth) {
+            return Err(anyhow!("the scan depth must be between 1 and {}", MAX_SCAN_DEPTH).into());
+        }
+    }
+
+    SettingTable::insert(&use_application().connection(), &setting)?;
+
+    // the download root may have changed
+    if let Err(err) = LibraryService::new().watch_downloads() {
+        warn!(
+            "[setting_save_and_close] failed to watch the download root: {:?}",
+            err
+        );
+    }
+
     window.close()?;
     Ok(())
 }
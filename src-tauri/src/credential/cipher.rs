@@ -0,0 +1,144 @@
+use aes_gcm::{
+    aead::{rand_core::RngCore, Aead, KeyInit, OsRng},
+    Aes256Gcm, Key, Nonce,
+};
+use anyhow::{anyhow, Context, Error};
+use argon2::Argon2;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use serde::{Deserialize, Serialize};
+use std::{fs, path::Path};
+
+/// Prefix of every encrypted value stored in the database.
+/// Values without this prefix are legacy plaintext values.
+const ENCRYPTED_PREFIX: &str = "enc:v1:";
+const KEY_LEN: usize = 32;
+const NONCE_LEN: usize = 12;
+const SALT_LEN: usize = 16;
+/// Known plaintext encrypted with the master password key, used to verify the master password.
+const VERIFIER_PLAINTEXT: &str = "dlsite-manager";
+
+/// Encrypts and decrypts credentials stored in the database with AES-256-GCM.
+#[derive(Clone)]
+pub struct CredentialCipher {
+    cipher: Aes256Gcm,
+}
+
+/// Content of the master password file.
+/// Its presence means the credentials are encrypted with a key derived from a master password.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MasterPasswordFile {
+    pub salt: String,
+    pub verifier: String,
+}
+
+impl CredentialCipher {
+    pub fn from_key(key: &[u8; KEY_LEN]) -> Self {
+        Self {
+            cipher: Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(key)),
+        }
+    }
+
+    /// Loads the key file at the given path, generating a new random key if it does not exist.
+    pub fn load_or_create_key_file(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let path = path.as_ref();
+
+        if path.exists() {
+            let key = fs::read(path)
+                .with_context(|| format!("[load_or_create_key_file]"))
+                .with_context(|| format!("failed to read key file `{}`", path.display()))?;
+            let key = <[u8; KEY_LEN]>::try_from(key.as_slice())
+                .map_err(|_| anyhow!("key file `{}` is corrupted", path.display()))?;
+            return Ok(Self::from_key(&key));
+        }
+
+        let mut key = [0u8; KEY_LEN];
+        OsRng.fill_bytes(&mut key);
+        fs::write(path, key)
+            .with_context(|| format!("[load_or_create_key_file]"))
+            .with_context(|| format!("failed to write key file `{}`", path.display()))?;
+
+        Ok(Self::from_key(&key))
+    }
+
+    /// Derives a key from the master password and creates the content of a new master password file.
+    pub fn create_master_password(
+        master_password: &str,
+    ) -> Result<(Self, MasterPasswordFile), Error> {
+        let mut salt = [0u8; SALT_LEN];
+        OsRng.fill_bytes(&mut salt);
+
+        let cipher = Self::derive(master_password, &salt)?;
+        let file = MasterPasswordFile {
+            salt: STANDARD.encode(salt),
+            verifier: cipher.encrypt(VERIFIER_PLAINTEXT)?,
+        };
+
+        Ok((cipher, file))
+    }
+
+    /// Derives a key from the master password, verifying it against the master password file.
+    pub fn unlock_master_password(
+        master_password: &str,
+        file: &MasterPasswordFile,
+    ) -> Result<Self, Error> {
+        let salt = STANDARD
+            .decode(&file.salt)
+            .map_err(|_| anyhow!("master password file is corrupted"))?;
+        let cipher = Self::derive(master_password, &salt)?;
+
+        match cipher.decrypt(&file.verifier) {
+            Ok(verifier) if verifier == VERIFIER_PLAINTEXT => Ok(cipher),
+            _ => Err(anyhow!("wrong master password")),
+        }
+    }
+
+    fn derive(master_password: &str, salt: &[u8]) -> Result<Self, Error> {
+        let mut key = [0u8; KEY_LEN];
+        Argon2::default()
+            .hash_password_into(master_password.as_bytes(), salt, &mut key)
+            .map_err(|err| anyhow!("failed to derive key from master password: {}", err))?;
+        Ok(Self::from_key(&key))
+    }
+
+    pub fn encrypt(&self, plaintext: &str) -> Result<String, Error> {
+        let mut nonce = [0u8; NONCE_LEN];
+        OsRng.fill_bytes(&mut nonce);
+
+        let ciphertext = self
+            .cipher
+            .encrypt(Nonce::from_slice(&nonce), plaintext.as_bytes())
+            .map_err(|_| anyhow!("failed to encrypt credential"))?;
+
+        let mut payload = nonce.to_vec();
+        payload.extend(ciphertext);
+
+        Ok(format!("{}{}", ENCRYPTED_PREFIX, STANDARD.encode(payload)))
+    }
+
+    /// Decrypts the value. Legacy plaintext values are returned as is.
+    pub fn decrypt(&self, value: &str) -> Result<String, Error> {
+        let payload = match value.strip_prefix(ENCRYPTED_PREFIX) {
+            Some(payload) => payload,
+            None => return Ok(value.to_owned()),
+        };
+        let payload = STANDARD
+            .decode(payload)
+            .map_err(|_| anyhow!("encrypted credential is corrupted"))?;
+
+        if payload.len() < NONCE_LEN {
+            return Err(anyhow!("encrypted credential is corrupted"));
+        }
+
+        let (nonce, ciphertext) = payload.split_at(NONCE_LEN);
+        let plaintext = self
+            .cipher
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .map_err(|_| anyhow!("failed to decrypt credential; the key does not match"))?;
+
+        String::from_utf8(plaintext).map_err(|_| anyhow!("decrypted credential is not valid UTF-8"))
+    }
+
+    pub fn is_encrypted(value: &str) -> bool {
+        value.starts_with(ENCRYPTED_PREFIX)
+    }
+}
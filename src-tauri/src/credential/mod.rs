@@ -0,0 +1,8 @@
+mod cipher;
+mod secret;
+
+pub use cipher::*;
+pub use secret::*;
+
+#[cfg(test)]
+mod tests;
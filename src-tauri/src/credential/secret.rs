@@ -0,0 +1,36 @@
+use serde::{Deserialize, Serialize};
+use std::fmt::{Debug, Display};
+
+/// A string that must never reach logs or error messages, such as a password or a cookie.
+/// Its `Debug` and `Display` implementations are redacted; use [`Secret::expose`] to read it.
+#[derive(Serialize, Deserialize, Default, Clone, PartialEq, Eq)]
+#[serde(transparent)]
+pub struct Secret(String);
+
+impl Secret {
+    pub fn new(value: impl Into<String>) -> Self {
+        Self(value.into())
+    }
+
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<String> for Secret {
+    fn from(value: String) -> Self {
+        Self(value)
+    }
+}
+
+impl Debug for Secret {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Secret(***)")
+    }
+}
+
+impl Display for Secret {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "***")
+    }
+}
@@ -0,0 +1,53 @@
+use super::CredentialCipher;
+use base64::{engine::general_purpose::STANDARD, Engine};
+
+/// Flips a bit of the decoded payload of an encrypted value; the nonce comes first, and the
+/// authentication tag last.
+fn tamper(value: &str, index: impl FnOnce(usize) -> usize) -> String {
+    let payload = value.strip_prefix("enc:v1:").unwrap();
+    let mut payload = STANDARD.decode(payload).unwrap();
+    let index = index(payload.len());
+    payload[index] ^= 1;
+
+    format!("enc:v1:{}", STANDARD.encode(payload))
+}
+
+#[test]
+fn encrypt_and_decrypt_credentials() {
+    let cipher = CredentialCipher::from_key(&[7; 32]);
+    let encrypted = cipher.encrypt("password").unwrap();
+
+    assert!(CredentialCipher::is_encrypted(&encrypted));
+    assert!(!encrypted.contains("password"));
+    assert_eq!(cipher.decrypt(&encrypted).unwrap(), "password");
+    // a fresh nonce every time
+    assert_ne!(cipher.encrypt("password").unwrap(), encrypted);
+    // legacy plaintext values are passed through
+    assert_eq!(cipher.decrypt("password").unwrap(), "password");
+    assert!(CredentialCipher::from_key(&[8; 32])
+        .decrypt(&encrypted)
+        .is_err());
+}
+
+#[test]
+fn unlock_with_master_password() {
+    let (cipher, file) = CredentialCipher::create_master_password("master").unwrap();
+    let encrypted = cipher.encrypt("password").unwrap();
+
+    let unlocked = CredentialCipher::unlock_master_password("master", &file).unwrap();
+    assert_eq!(unlocked.decrypt(&encrypted).unwrap(), "password");
+
+    assert!(CredentialCipher::unlock_master_password("wrong", &file).is_err());
+    assert!(CredentialCipher::unlock_master_password("", &file).is_err());
+}
+
+#[test]
+fn reject_tampered_credentials() {
+    let cipher = CredentialCipher::from_key(&[7; 32]);
+    let encrypted = cipher.encrypt("password").unwrap();
+
+    assert!(cipher.decrypt(&tamper(&encrypted, |_| 0)).is_err());
+    assert!(cipher.decrypt(&tamper(&encrypted, |len| len - 1)).is_err());
+    assert!(cipher.decrypt("enc:v1:AAAA").is_err());
+    assert!(cipher.decrypt("enc:v1:not base64").is_err());
+}
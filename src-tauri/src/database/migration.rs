@@ -0,0 +1,351 @@
+use super::tables::{
+    v2::{
+        AccountTable, DownloadJobTable, ProductDownloadTable, ProductManifestTable,
+        ProductMetadataTable, ProductNameEncodingTable, ProductTable, SettingTable, ThumbnailTable,
+    },
+    Table,
+};
+use crate::dlsite::product_id::ProductId;
+use log::{info, warn};
+use rusqlite::{Connection, OptionalExtension, Transaction};
+
+/// A single schema migration. Migrations are applied in ascending `version` order and
+/// each one is applied exactly once; never edit a migration after it has been released,
+/// add a new one instead.
+pub struct Migration {
+    pub version: i64,
+    pub name: &'static str,
+    pub up: fn(&Transaction) -> rusqlite::Result<()>,
+}
+
+/// Every migration, in order. The latest version is the current schema version.
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "create_v2_tables",
+        up: create_v2_tables,
+    },
+    Migration {
+        version: 2,
+        name: "migrate_legacy_accounts",
+        up: migrate_legacy_accounts,
+    },
+    Migration {
+        version: 3,
+        name: "add_product_metadata",
+        up: add_product_metadata,
+    },
+    Migration {
+        version: 4,
+        name: "add_thumbnails",
+        up: add_thumbnails,
+    },
+    Migration {
+        version: 5,
+        name: "add_folder_template",
+        up: add_folder_template,
+    },
+    Migration {
+        version: 6,
+        name: "add_scan_depth",
+        up: add_scan_depth,
+    },
+    Migration {
+        version: 7,
+        name: "add_missing_downloads",
+        up: add_missing_downloads,
+    },
+    Migration {
+        version: 8,
+        name: "add_product_manifests",
+        up: add_product_manifests,
+    },
+    Migration {
+        version: 9,
+        name: "add_extraction_skip_reasons",
+        up: add_extraction_skip_reasons,
+    },
+    Migration {
+        version: 10,
+        name: "add_product_name_encodings",
+        up: add_product_name_encodings,
+    },
+    Migration {
+        version: 11,
+        name: "normalize_product_ids",
+        up: normalize_product_ids,
+    },
+];
+
+pub fn get_schema_version_ddl() -> &'static str {
+    r#"
+CREATE TABLE IF NOT EXISTS schema_migrations (
+    version INTEGER PRIMARY KEY NOT NULL,
+    name TEXT NOT NULL,
+    applied_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+"#
+}
+
+/// Returns the version of the latest migration applied to the database, or `0` if none.
+pub fn get_schema_version(connection: &Connection) -> rusqlite::Result<i64> {
+    connection.query_row(
+        "SELECT IFNULL(MAX(version), 0) FROM schema_migrations",
+        [],
+        |row| row.get(0),
+    )
+}
+
+/// Returns `true` if the database contains any table, i.e. it is not a fresh database.
+pub fn has_any_table(connection: &Connection) -> rusqlite::Result<bool> {
+    Ok(connection
+        .query_row(
+            "SELECT 1 FROM sqlite_master WHERE type = 'table' AND name != 'schema_migrations' LIMIT 1",
+            [],
+            |_| Ok(()),
+        )
+        .optional()?
+        .is_some())
+}
+
+/// Applies every pending migration in a single transaction.
+pub fn apply_pending(connection: &mut Connection, current_version: i64) -> rusqlite::Result<()> {
+    let tx = connection.transaction()?;
+
+    for migration in MIGRATIONS
+        .iter()
+        .filter(|migration| current_version < migration.version)
+    {
+        info!(
+            "[apply_pending] applying migration {} `{}`",
+            migration.version, migration.name
+        );
+
+        (migration.up)(&tx)?;
+        tx.execute(
+            "INSERT INTO schema_migrations (version, name) VALUES (?1, ?2)",
+            (migration.version, migration.name),
+        )?;
+    }
+
+    tx.commit()
+}
+
+fn create_v2_tables(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(SettingTable::get_ddl())?;
+    tx.execute_batch(AccountTable::get_ddl())?;
+    tx.execute_batch(ProductTable::get_ddl())?;
+    tx.execute_batch(ProductDownloadTable::get_ddl())?;
+    tx.execute_batch(DownloadJobTable::get_ddl())?;
+    Ok(())
+}
+
+/// Copies the accounts of the pre-v2 `accounts` table, if any, into `v2_accounts`.
+/// Products are not copied, since they are fetched again from DLsite.
+/// Accounts that already exist in `v2_accounts` (by username) are skipped.
+fn migrate_legacy_accounts(tx: &Transaction) -> rusqlite::Result<()> {
+    let columns = get_table_columns(tx, "accounts")?;
+
+    if columns.is_empty() {
+        return Ok(());
+    }
+
+    if !columns.iter().any(|column| column == "username")
+        || !columns.iter().any(|column| column == "password")
+    {
+        warn!(
+            "[migrate_legacy_accounts] the legacy `accounts` table has an unknown layout; skipping"
+        );
+        return Ok(());
+    }
+
+    let memo = if columns.iter().any(|column| column == "memo") {
+        "legacy.memo"
+    } else {
+        "NULL"
+    };
+    let migrated = tx.execute(
+        &format!(
+            r#"
+INSERT INTO v2_accounts (
+    username,
+    password,
+    memo
+)
+SELECT
+    legacy.username,
+    legacy.password,
+    {}
+FROM accounts AS legacy
+WHERE legacy.username NOT IN (SELECT username FROM v2_accounts)
+"#,
+            memo
+        ),
+        [],
+    )?;
+
+    info!(
+        "[migrate_legacy_accounts] migrated {} legacy account(s)",
+        migrated
+    );
+    Ok(())
+}
+
+/// Adds the product metadata tables and rebuilds the search index with the metadata columns.
+/// The metadata itself is fetched later, so the index is repopulated from the products only.
+fn add_product_metadata(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(ProductMetadataTable::get_ddl())?;
+    tx.execute_batch(
+        r#"
+DROP TABLE IF EXISTS v2_indexed_products;
+
+CREATE VIRTUAL TABLE v2_indexed_products USING fts5 (
+    id,
+    title,
+    group_id,
+    group_name,
+    genres,
+    creators,
+    series_name,
+    description,
+    tokenize = 'trigram'
+);
+
+INSERT INTO v2_indexed_products (
+    id,
+    title,
+    group_id,
+    group_name
+) SELECT
+    id,
+    title,
+    group_id,
+    group_name
+FROM v2_products;
+"#,
+    )?;
+    Ok(())
+}
+
+fn add_thumbnails(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(ThumbnailTable::get_ddl())
+}
+
+fn add_folder_template(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch("ALTER TABLE v2_settings ADD COLUMN folder_template TEXT;")
+}
+
+fn add_scan_depth(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch("ALTER TABLE v2_settings ADD COLUMN scan_depth INTEGER;")
+}
+
+/// Adds the time a downloaded product was found missing.
+fn add_missing_downloads(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch("ALTER TABLE v2_product_downloads ADD COLUMN missing_at TEXT NULL;")
+}
+
+fn add_product_manifests(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(ProductManifestTable::get_ddl())
+}
+
+fn add_extraction_skip_reasons(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "ALTER TABLE v2_product_downloads ADD COLUMN extraction_skip_reason TEXT NULL;",
+    )
+}
+
+fn add_product_name_encodings(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(ProductNameEncodingTable::get_ddl())
+}
+
+/// The tables holding a product ID, with the column it is in. `v2_products` comes first,
+/// so that a product is renamed before the rows referencing it.
+const PRODUCT_ID_COLUMNS: &[(&str, &str)] = &[
+    ("v2_products", "id"),
+    ("v2_indexed_products", "id"),
+    ("v2_product_downloads", "product_id"),
+    ("v2_download_jobs", "product_id"),
+    ("v2_product_details", "product_id"),
+    ("v2_product_genres", "product_id"),
+    ("v2_product_creators", "product_id"),
+    ("v2_thumbnails", "product_id"),
+    ("v2_product_manifests", "product_id"),
+    ("v2_product_name_encodings", "product_id"),
+];
+
+/// Rewrites the product IDs stored before they were validated, e.g. `rj123456`, in their
+/// canonical form; such an ID fails to load and breaks every query listing it.
+/// Rows of an ID that is not a product ID at all are dropped, and so are the rows of an ID
+/// whose canonical form is already stored. Either way they are kept in the backup.
+fn normalize_product_ids(tx: &Transaction) -> rusqlite::Result<()> {
+    for (table, column) in PRODUCT_ID_COLUMNS {
+        let ids = {
+            let mut stmt = tx.prepare(&format!("SELECT DISTINCT {} FROM {}", column, table))?;
+            let ids = stmt
+                .query_map([], |row| row.get::<_, String>(0))?
+                .collect::<rusqlite::Result<Vec<_>>>()?;
+            ids
+        };
+        let mut renamed = 0;
+
+        for id in ids {
+            let product_id = match ProductId::parse(&id) {
+                Ok(product_id) if product_id.as_str() == id => continue,
+                Ok(product_id) => product_id,
+                Err(err) => {
+                    warn!(
+                        "[normalize_product_ids] dropping the rows in `{}`: {}",
+                        table, err
+                    );
+                    tx.execute(
+                        &format!("DELETE FROM {} WHERE {} = ?1", table, column),
+                        [&id],
+                    )?;
+                    continue;
+                }
+            };
+            let is_duplicate = tx
+                .query_row(
+                    &format!("SELECT 1 FROM {} WHERE {} = ?1 LIMIT 1", table, column),
+                    [product_id.as_str()],
+                    |_| Ok(()),
+                )
+                .optional()?
+                .is_some();
+
+            if is_duplicate {
+                warn!(
+                    "[normalize_product_ids] dropping the rows of the product id `{}` in `{}`, since `{}` is already stored",
+                    id, table, product_id
+                );
+                tx.execute(
+                    &format!("DELETE FROM {} WHERE {} = ?1", table, column),
+                    [&id],
+                )?;
+            } else {
+                tx.execute(
+                    &format!("UPDATE {} SET {} = ?1 WHERE {} = ?2", table, column, column),
+                    [product_id.as_str(), id.as_str()],
+                )?;
+                renamed += 1;
+            }
+        }
+
+        if renamed != 0 {
+            info!(
+                "[normalize_product_ids] normalized {} product id(s) in `{}`",
+                renamed, table
+            );
+        }
+    }
+
+    Ok(())
+}
+
+fn get_table_columns(tx: &Transaction, table: &str) -> rusqlite::Result<Vec<String>> {
+    let mut stmt = tx.prepare("SELECT name FROM pragma_table_info(?1)")?;
+    let columns = stmt
+        .query_map([table], |row| row.get(0))?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    Ok(columns)
+}
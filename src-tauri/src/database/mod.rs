@@ -1,22 +1,25 @@
+pub mod migration;
 pub mod models;
 pub mod tables;
 
-use self::tables::{
-    v2::{AccountTable, ProductDownloadTable, ProductTable, SettingTable},
-    Table,
-};
-use crate::application_error::Result;
+use crate::application_error::{Error, Result};
+use log::info;
 use rusqlite::Connection;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 pub struct Database {
+    path: PathBuf,
     connection: Connection,
 }
 
 impl Database {
     pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let connection = Connection::open(&path)?;
+        rusqlite::vtab::array::load_module(&connection)?;
+
         Ok(Self {
-            connection: Connection::open(path)?.into(),
+            path: path.as_ref().to_owned(),
+            connection,
         })
     }
 
@@ -24,25 +27,59 @@ impl Database {
         &mut self.connection
     }
 
-    pub fn prepare(&self) -> Result<()> {
+    /// Brings the schema up to date by applying every pending migration.
+    /// An existing database is backed up next to it before any migration is applied.
+    pub fn prepare(&mut self) -> Result<()> {
         self.connection.execute_batch(&format!(
             "
 PRAGMA journal_mode = WAL;
 PRAGMA foreign_keys = ON;
 
-BEGIN;
-{}
-{}
-{}
 {}
-COMMIT;
 ",
-            SettingTable::get_ddl(),
-            AccountTable::get_ddl(),
-            ProductTable::get_ddl(),
-            ProductDownloadTable::get_ddl(),
+            migration::get_schema_version_ddl(),
         ))?;
 
+        let current_version = migration::get_schema_version(&self.connection)?;
+        let latest_version = migration::MIGRATIONS
+            .last()
+            .map_or(0, |migration| migration.version);
+
+        if latest_version <= current_version {
+            return Ok(());
+        }
+
+        if migration::has_any_table(&self.connection)? {
+            self.backup(current_version)?;
+        }
+
+        info!(
+            "[prepare] migrating the database from version {} to version {}",
+            current_version, latest_version
+        );
+
+        migration::apply_pending(&mut self.connection, current_version)?;
+        Ok(())
+    }
+
+    /// Writes a consistent copy of the database to `<database>.v<version>.bak`.
+    fn backup(&self, version: i64) -> Result<()> {
+        let mut backup_path = self.path.clone().into_os_string();
+        backup_path.push(format!(".v{}.bak", version));
+        let backup_path = PathBuf::from(backup_path);
+
+        info!(
+            "[backup] backing up the database to `{}`",
+            backup_path.display()
+        );
+
+        if backup_path.exists() {
+            std::fs::remove_file(&backup_path)
+                .map_err(|err| Error::DatabaseBackupError { io_error: err })?;
+        }
+
+        self.connection
+            .execute("VACUUM INTO ?1", [backup_path.to_string_lossy()])?;
         Ok(())
     }
 
@@ -51,3 +88,6 @@ COMMIT;
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests;
@@ -1,4 +1,11 @@
-use crate::dlsite::dto::{DLsiteProductAgeCategory, DLsiteProductType};
+use crate::{
+    credential::Secret,
+    dlsite::{
+        dto::{DLsiteProductAgeCategory, DLsiteProductCreator, DLsiteProductType},
+        product_id::ProductId,
+    },
+    library::name_encoding::NameEncoding,
+};
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use std::path::{Path, PathBuf};
@@ -7,24 +14,26 @@ use std::path::{Path, PathBuf};
 pub struct Account {
     pub id: i64,
     pub username: String,
-    pub password: String,
+    pub password: Secret,
     pub memo: Option<String>,
     pub product_count: i32,
-    pub cookie_json: String,
+    /// never sent to the frontend
+    #[serde(skip_serializing)]
+    pub cookie_json: Secret,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct SimpleAccount {
     pub id: i64,
     pub username: String,
-    pub password: String,
+    pub password: Secret,
     pub memo: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct CreatingAccount<'a> {
     pub username: &'a str,
-    pub password: &'a str,
+    pub password: Secret,
     pub memo: Option<&'a str>,
 }
 
@@ -32,13 +41,13 @@ pub struct CreatingAccount<'a> {
 pub struct UpdatingAccount<'a> {
     pub id: i64,
     pub username: &'a str,
-    pub password: &'a str,
+    pub password: Secret,
     pub memo: Option<&'a str>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Product {
-    pub id: String,
+    pub id: ProductId,
     /// it can be `NULL` if the product is not owned by any account (found in local)
     pub account_id: Option<i64>,
     pub ty: DLsiteProductType,
@@ -50,9 +59,9 @@ pub struct Product {
     pub registered_at: Option<DateTime<Utc>>,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Debug, Clone)]
 pub struct CreatingProduct<'a> {
-    pub id: &'a str,
+    pub id: &'a ProductId,
     /// it can be `NULL` if the product is not owned by any account (found in local)
     pub account_id: Option<i64>,
     pub ty: DLsiteProductType,
@@ -64,27 +73,111 @@ pub struct CreatingProduct<'a> {
     pub registered_at: Option<DateTime<Utc>>,
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ProductMetadata {
+    pub product_id: ProductId,
+    pub description: Option<String>,
+    pub series_id: Option<String>,
+    pub series_name: Option<String>,
+    pub file_format: Option<String>,
+    pub page_count: Option<u32>,
+    pub track_count: Option<u32>,
+    pub genres: Vec<String>,
+    pub creators: Vec<DLsiteProductCreator>,
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct ProductDownload {
-    pub product_id: String,
+    pub product_id: ProductId,
     pub path: PathBuf,
+    /// when a scan first found the folder missing; cleared when it is found again
+    pub missing_at: Option<DateTime<Utc>>,
+    /// why the downloaded files were not extracted, if they were meant to be
+    pub extraction_skip_reason: Option<String>,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Debug, Clone)]
 pub struct CreatingProductDownload<'a> {
-    pub product_id: &'a str,
+    pub product_id: &'a ProductId,
     pub path: &'a Path,
 }
 
+/// A file of a downloaded product as it was when the download finished.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ProductManifestFile {
+    /// the path relative to the product folder, separated by `/`
+    pub path: String,
+    pub size: u64,
+    /// the SHA-256 of the content in lowercase hex
+    pub hash: String,
+}
+
+/// The encoding the file names in the zip archives of a product are decoded in,
+/// overriding the guess.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ProductNameEncoding {
+    pub product_id: ProductId,
+    pub encoding: NameEncoding,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Thumbnail {
+    pub product_id: ProductId,
+    pub url: String,
+    pub file_name: String,
+    pub content_type: String,
+    pub size: u64,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct CreatingThumbnail<'a> {
+    pub product_id: &'a ProductId,
+    pub url: &'a str,
+    pub file_name: &'a str,
+    pub content_type: &'a str,
+    pub size: u64,
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Setting {
     pub download_root_dir: Option<PathBuf>,
+    /// the layout of the product folders; see [`crate::library::folder_template::FolderTemplate`]
+    pub folder_template: Option<String>,
+    /// the number of folder levels searched for downloaded products; see [`crate::library::scanner`]
+    pub scan_depth: Option<u32>,
 }
 
 impl Default for Setting {
     fn default() -> Self {
         Self {
             download_root_dir: None,
+            folder_template: None,
+            scan_depth: None,
         }
     }
 }
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DownloadJobStatus {
+    Queued,
+    Active,
+    Paused,
+    Failed,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DownloadJob {
+    pub id: i64,
+    pub account_id: i64,
+    pub product_id: ProductId,
+    pub decompress: bool,
+    pub status: DownloadJobStatus,
+    pub error: Option<String>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct CreatingDownloadJob<'a> {
+    pub account_id: i64,
+    pub product_id: &'a ProductId,
+    pub decompress: bool,
+}
@@ -2,6 +2,8 @@ pub mod v2;
 
 /// Represents a table in the database.
 pub trait Table {
-    /// Returns the DDL for the table.
+    /// Returns the DDL for the table, as it was when the table was introduced.
+    /// It is applied by a migration in `database::migration`; later schema changes,
+    /// such as a new column, must be added as new migrations instead of editing it.
     fn get_ddl() -> &'static str;
 }
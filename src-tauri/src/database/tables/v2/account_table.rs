@@ -1,12 +1,13 @@
 use super::DBResult;
 use crate::{
-    application::use_application,
+    credential::{CredentialCipher, Secret},
     database::{
         models::v2::{Account, CreatingAccount, SimpleAccount, UpdatingAccount},
         tables::Table,
     },
 };
-use rusqlite::{named_params, OptionalExtension};
+use anyhow::anyhow;
+use rusqlite::{named_params, Connection, OptionalExtension};
 use serde_rusqlite::*;
 
 pub struct AccountTable;
@@ -29,8 +30,11 @@ CREATE TABLE IF NOT EXISTS v2_accounts (
 impl AccountTable {
     /// Inserts a single account into the database.
     /// Returns the ID of the inserted account.
-    pub fn insert_one(account: CreatingAccount) -> DBResult<i64> {
-        let connection = use_application().connection();
+    pub fn insert_one(
+        connection: &Connection,
+        cipher: Option<&CredentialCipher>,
+        account: CreatingAccount,
+    ) -> DBResult<i64> {
         let mut stmt = connection.prepare(
             r#"
 INSERT INTO v2_accounts (
@@ -45,13 +49,19 @@ INSERT INTO v2_accounts (
 "#,
         )?;
 
-        let id = stmt.insert(to_params_named(&account)?.to_slice().as_slice())?;
+        let id = stmt.insert(named_params! {
+            ":username": account.username,
+            ":password": encrypt(cipher, &account.password)?,
+            ":memo": account.memo,
+        })?;
         Ok(id)
     }
 
     /// Retrieves all accounts from the database.
-    pub fn get_all() -> DBResult<Vec<Account>> {
-        let connection = use_application().connection();
+    pub fn get_all(
+        connection: &Connection,
+        cipher: Option<&CredentialCipher>,
+    ) -> DBResult<Vec<Account>> {
         let mut stmt = connection.prepare(
             r#"
 SELECT
@@ -70,12 +80,18 @@ ORDER BY id ASC
         let accounts = stmt
             .query_and_then([], |row| from_row_with_columns::<Account>(row, &columns))?
             .collect::<std::result::Result<Vec<_>, _>>()?;
-        Ok(accounts)
+        accounts
+            .into_iter()
+            .map(|account| decrypt_account(cipher, account))
+            .collect()
     }
 
     /// Retrieves a single account from the database.
-    pub fn get_one(id: i64) -> DBResult<Option<Account>> {
-        let connection = use_application().connection();
+    pub fn get_one(
+        connection: &Connection,
+        cipher: Option<&CredentialCipher>,
+        id: i64,
+    ) -> DBResult<Option<Account>> {
         let mut stmt = connection.prepare(
             r#"
 SELECT
@@ -99,12 +115,17 @@ WHERE id = :id
             )
             .optional()?
             .transpose()?;
-        Ok(account)
+        account
+            .map(|account| decrypt_account(cipher, account))
+            .transpose()
     }
 
     /// Retrieves a single account (simple) from the database.
-    pub fn get_one_simple(id: i64) -> DBResult<Option<SimpleAccount>> {
-        let connection = use_application().connection();
+    pub fn get_one_simple(
+        connection: &Connection,
+        cipher: Option<&CredentialCipher>,
+        id: i64,
+    ) -> DBResult<Option<SimpleAccount>> {
         let mut stmt = connection.prepare(
             r#"
 SELECT
@@ -126,12 +147,22 @@ WHERE id = :id
             )
             .optional()?
             .transpose()?;
-        Ok(account)
+        account
+            .map(|account| {
+                Ok(SimpleAccount {
+                    password: decrypt(cipher, &account.password)?,
+                    ..account
+                })
+            })
+            .transpose()
     }
 
     /// Updates a single account in the database.
-    pub fn update_one(account: UpdatingAccount) -> DBResult<()> {
-        let connection = use_application().connection();
+    pub fn update_one(
+        connection: &Connection,
+        cipher: Option<&CredentialCipher>,
+        account: UpdatingAccount,
+    ) -> DBResult<()> {
         let mut stmt = connection.prepare(
             r#"
 UPDATE v2_accounts
@@ -146,15 +177,18 @@ WHERE id = :id
         stmt.execute(named_params! {
             ":id": account.id,
             ":username": account.username,
-            ":password": account.password,
+            ":password": encrypt(cipher, &account.password)?,
             ":memo": account.memo
         })?;
         Ok(())
     }
 
     /// Updates a single account's product count in the database.
-    pub fn update_one_product_count(id: i64, product_count: i32) -> DBResult<()> {
-        let connection = use_application().connection();
+    pub fn update_one_product_count(
+        connection: &Connection,
+        id: i64,
+        product_count: i32,
+    ) -> DBResult<()> {
         let mut stmt = connection.prepare(
             r#"
 UPDATE v2_accounts
@@ -172,8 +206,12 @@ WHERE id = :id
     }
 
     /// Updates a single account's cookie JSON in the database.
-    pub fn update_one_cookie_json(id: i64, cookie_json: &str) -> DBResult<()> {
-        let connection = use_application().connection();
+    pub fn update_one_cookie_json(
+        connection: &Connection,
+        cipher: Option<&CredentialCipher>,
+        id: i64,
+        cookie_json: &Secret,
+    ) -> DBResult<()> {
         let mut stmt = connection.prepare(
             r#"
 UPDATE v2_accounts
@@ -185,14 +223,78 @@ WHERE id = :id
 
         stmt.execute(named_params! {
             ":id": id,
-            ":cookie_json": cookie_json
+            ":cookie_json": encrypt(cipher, cookie_json)?
         })?;
         Ok(())
     }
 
+    /// Re-encrypts the password and the cookie JSON of the given (decrypted) accounts
+    /// with the current credential key in a single transaction.
+    pub fn update_many_secrets(
+        connection: &mut Connection,
+        cipher: Option<&CredentialCipher>,
+        accounts: &[Account],
+    ) -> DBResult<()> {
+        let tx = connection.transaction()?;
+        {
+            let mut stmt = tx.prepare(
+                r#"
+UPDATE v2_accounts
+SET
+    password = :password,
+    cookie_json = :cookie_json
+WHERE id = :id
+"#,
+            )?;
+
+            for account in accounts {
+                stmt.execute(named_params! {
+                    ":id": account.id,
+                    ":password": encrypt(cipher, &account.password)?,
+                    ":cookie_json": encrypt(cipher, &account.cookie_json)?
+                })?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Encrypts every password and cookie JSON that is still stored in plaintext.
+    pub fn encrypt_plaintext(
+        connection: &mut Connection,
+        cipher: Option<&CredentialCipher>,
+    ) -> DBResult<()> {
+        let accounts = Self::get_all(connection, cipher)?;
+        let has_plaintext = {
+            let mut stmt = connection.prepare(
+                r#"
+SELECT
+    password,
+    cookie_json
+FROM v2_accounts
+"#,
+            )?;
+            let secrets = stmt
+                .query_map([], |row| {
+                    Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+                })?
+                .collect::<std::result::Result<Vec<_>, _>>()?;
+
+            secrets.iter().any(|(password, cookie_json)| {
+                !CredentialCipher::is_encrypted(password)
+                    || !CredentialCipher::is_encrypted(cookie_json)
+            })
+        };
+
+        if has_plaintext {
+            Self::update_many_secrets(connection, cipher, &accounts)?;
+        }
+
+        Ok(())
+    }
+
     /// Removes a single account from the database.
-    pub fn remove_one(id: i64) -> DBResult<()> {
-        let connection = use_application().connection();
+    pub fn remove_one(connection: &Connection, id: i64) -> DBResult<()> {
         let mut stmt = connection.prepare(
             r#"
 DELETE FROM v2_accounts
@@ -204,3 +306,23 @@ WHERE id = :id
         Ok(())
     }
 }
+
+fn encrypt(cipher: Option<&CredentialCipher>, secret: &Secret) -> DBResult<String> {
+    let cipher = cipher
+        .ok_or_else(|| anyhow!("credentials are locked; unlock them with the master password"))?;
+    Ok(cipher.encrypt(secret.expose())?)
+}
+
+fn decrypt(cipher: Option<&CredentialCipher>, value: &Secret) -> DBResult<Secret> {
+    let cipher = cipher
+        .ok_or_else(|| anyhow!("credentials are locked; unlock them with the master password"))?;
+    Ok(Secret::new(cipher.decrypt(value.expose())?))
+}
+
+fn decrypt_account(cipher: Option<&CredentialCipher>, account: Account) -> DBResult<Account> {
+    Ok(Account {
+        password: decrypt(cipher, &account.password)?,
+        cookie_json: decrypt(cipher, &account.cookie_json)?,
+        ..account
+    })
+}
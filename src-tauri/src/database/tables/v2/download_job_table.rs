@@ -0,0 +1,328 @@
+use super::DBResult;
+use crate::{
+    database::{
+        models::v2::{CreatingDownloadJob, DownloadJob, DownloadJobStatus},
+        tables::Table,
+    },
+    dlsite::product_id::ProductId,
+};
+use rusqlite::{named_params, Connection, OptionalExtension};
+use serde::Serialize;
+use serde_rusqlite::*;
+
+pub struct DownloadJobTable;
+
+impl Table for DownloadJobTable {
+    fn get_ddl() -> &'static str {
+        r#"
+CREATE TABLE IF NOT EXISTS v2_download_jobs (
+    id INTEGER PRIMARY KEY NOT NULL,
+    order_index INTEGER NOT NULL DEFAULT 0,
+    account_id INTEGER NOT NULL,
+    product_id TEXT NOT NULL UNIQUE,
+    decompress INTEGER NOT NULL DEFAULT 1,
+    status TEXT NOT NULL DEFAULT 'Queued',
+    error TEXT,
+
+    FOREIGN KEY(account_id) REFERENCES v2_accounts(id) ON UPDATE CASCADE ON DELETE CASCADE
+);
+
+CREATE TABLE IF NOT EXISTS v2_download_queue_settings (
+    id INTEGER PRIMARY KEY CHECK (id = 1),
+    max_concurrent_downloads INTEGER NOT NULL DEFAULT 2
+);
+"#
+    }
+}
+
+#[derive(Serialize)]
+struct StatusParam<'a> {
+    pub id: i64,
+    pub status: DownloadJobStatus,
+    pub error: Option<&'a str>,
+}
+
+impl DownloadJobTable {
+    /// Inserts a single download job at the end of the queue.
+    /// If a job for the same product already exists, it is re-queued in place.
+    /// Returns the ID of the inserted (or re-queued) job.
+    pub fn insert_one(connection: &Connection, job: CreatingDownloadJob) -> DBResult<i64> {
+        let mut stmt = connection.prepare(
+            r#"
+INSERT INTO v2_download_jobs (
+    order_index,
+    account_id,
+    product_id,
+    decompress,
+    status
+) VALUES (
+    (SELECT IFNULL(MAX(order_index), 0) + 1 FROM v2_download_jobs),
+    :account_id,
+    :product_id,
+    :decompress,
+    'Queued'
+) ON CONFLICT (product_id) DO UPDATE SET
+    account_id = excluded.account_id,
+    decompress = excluded.decompress,
+    status = 'Queued',
+    error = NULL
+RETURNING id
+"#,
+        )?;
+
+        let id = stmt.query_row(to_params_named(&job)?.to_slice().as_slice(), |row| {
+            row.get::<_, i64>(0)
+        })?;
+        Ok(id)
+    }
+
+    /// Retrieves all download jobs in queue order.
+    pub fn get_all(connection: &Connection) -> DBResult<Vec<DownloadJob>> {
+        let mut stmt = connection.prepare(
+            r#"
+SELECT
+    id,
+    account_id,
+    product_id,
+    decompress,
+    status,
+    error
+FROM v2_download_jobs
+ORDER BY order_index ASC, id ASC
+"#,
+        )?;
+
+        let columns = columns_from_statement(&stmt);
+        let jobs = stmt
+            .query_and_then([], |row| {
+                from_row_with_columns::<DownloadJob>(row, &columns)
+            })?
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        Ok(jobs)
+    }
+
+    /// Retrieves a single download job.
+    pub fn get_one(connection: &Connection, id: i64) -> DBResult<Option<DownloadJob>> {
+        let mut stmt = connection.prepare(
+            r#"
+SELECT
+    id,
+    account_id,
+    product_id,
+    decompress,
+    status,
+    error
+FROM v2_download_jobs
+WHERE id = :id
+"#,
+        )?;
+
+        let job = stmt
+            .query_row(
+                named_params! {
+                    ":id": id,
+                },
+                |row| Ok(from_row::<DownloadJob>(row)),
+            )
+            .optional()?
+            .transpose()?;
+        Ok(job)
+    }
+
+    /// Retrieves a single download job by its product ID.
+    pub fn get_one_by_product_id(
+        connection: &Connection,
+        product_id: &ProductId,
+    ) -> DBResult<Option<DownloadJob>> {
+        let mut stmt = connection.prepare(
+            r#"
+SELECT
+    id,
+    account_id,
+    product_id,
+    decompress,
+    status,
+    error
+FROM v2_download_jobs
+WHERE product_id = :product_id
+"#,
+        )?;
+
+        let job = stmt
+            .query_row(
+                named_params! {
+                    ":product_id": product_id,
+                },
+                |row| Ok(from_row::<DownloadJob>(row)),
+            )
+            .optional()?
+            .transpose()?;
+        Ok(job)
+    }
+
+    /// Retrieves the first `limit` queued jobs in queue order.
+    pub fn get_many_queued(connection: &Connection, limit: u32) -> DBResult<Vec<DownloadJob>> {
+        let mut stmt = connection.prepare(
+            r#"
+SELECT
+    id,
+    account_id,
+    product_id,
+    decompress,
+    status,
+    error
+FROM v2_download_jobs
+WHERE status = 'Queued'
+ORDER BY order_index ASC, id ASC
+LIMIT :limit
+"#,
+        )?;
+
+        let columns = columns_from_statement(&stmt);
+        let jobs = stmt
+            .query_and_then(
+                named_params! {
+                    ":limit": limit,
+                },
+                |row| from_row_with_columns::<DownloadJob>(row, &columns),
+            )?
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        Ok(jobs)
+    }
+
+    /// Updates a single download job's status and error message.
+    pub fn update_one_status(
+        connection: &Connection,
+        id: i64,
+        status: DownloadJobStatus,
+        error: Option<&str>,
+    ) -> DBResult<()> {
+        let mut stmt = connection.prepare(
+            r#"
+UPDATE v2_download_jobs
+SET
+    status = :status,
+    error = :error
+WHERE id = :id
+"#,
+        )?;
+
+        stmt.execute(
+            to_params_named(StatusParam { id, status, error })?
+                .to_slice()
+                .as_slice(),
+        )?;
+        Ok(())
+    }
+
+    /// Moves every job in the `from` status to the `to` status.
+    pub fn update_many_status(
+        connection: &Connection,
+        from: DownloadJobStatus,
+        to: DownloadJobStatus,
+    ) -> DBResult<()> {
+        #[derive(Serialize)]
+        struct Params {
+            pub from: DownloadJobStatus,
+            pub to: DownloadJobStatus,
+        }
+
+        let mut stmt = connection.prepare(
+            r#"
+UPDATE v2_download_jobs
+SET
+    status = :to
+WHERE status = :from
+"#,
+        )?;
+
+        stmt.execute(to_params_named(Params { from, to })?.to_slice().as_slice())?;
+        Ok(())
+    }
+
+    /// Reorders the queue so that the given job IDs come first, in the given order.
+    /// Jobs not listed keep their relative order after the listed ones.
+    pub fn update_order(connection: &mut Connection, ids: &[i64]) -> DBResult<()> {
+        let tx = connection.transaction()?;
+        {
+            let mut shift_stmt = tx.prepare(
+                r#"
+UPDATE v2_download_jobs
+SET
+    order_index = order_index + :count
+"#,
+            )?;
+            let mut update_stmt = tx.prepare(
+                r#"
+UPDATE v2_download_jobs
+SET
+    order_index = :order_index
+WHERE id = :id
+"#,
+            )?;
+
+            shift_stmt.execute(named_params! {
+                ":count": ids.len() as i64,
+            })?;
+
+            for (index, id) in ids.iter().enumerate() {
+                update_stmt.execute(named_params! {
+                    ":id": id,
+                    ":order_index": index as i64,
+                })?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Removes a single download job.
+    pub fn remove_one(connection: &Connection, id: i64) -> DBResult<()> {
+        let mut stmt = connection.prepare(
+            r#"
+DELETE FROM v2_download_jobs
+WHERE id = :id
+"#,
+        )?;
+
+        stmt.execute(&[(":id", &id)])?;
+        Ok(())
+    }
+
+    /// Gets the maximum number of downloads allowed to run at once.
+    pub fn get_max_concurrent_downloads(connection: &Connection) -> DBResult<u32> {
+        let mut stmt = connection.prepare(
+            r#"
+SELECT
+    max_concurrent_downloads
+FROM v2_download_queue_settings
+WHERE id = 1
+"#,
+        )?;
+
+        let max_concurrent_downloads = stmt
+            .query_row([], |row| row.get::<_, u32>(0))
+            .optional()?
+            .unwrap_or(2);
+        Ok(max_concurrent_downloads)
+    }
+
+    /// Sets the maximum number of downloads allowed to run at once.
+    pub fn set_max_concurrent_downloads(
+        connection: &Connection,
+        max_concurrent_downloads: u32,
+    ) -> DBResult<()> {
+        let mut stmt = connection.prepare(
+            r#"
+INSERT INTO v2_download_queue_settings (id, max_concurrent_downloads) VALUES (1, :max_concurrent_downloads)
+ON CONFLICT(id) DO UPDATE SET
+    max_concurrent_downloads = excluded.max_concurrent_downloads;
+"#,
+        )?;
+
+        stmt.execute(named_params! {
+            ":max_concurrent_downloads": max_concurrent_downloads,
+        })?;
+        Ok(())
+    }
+}
@@ -1,12 +1,22 @@
 mod account_table;
+mod download_job_table;
 mod product_download_table;
+mod product_manifest_table;
+mod product_metadata_table;
+mod product_name_encoding_table;
 mod product_table;
 mod setting_table;
+mod thumbnail_table;
 
 pub use account_table::*;
+pub use download_job_table::*;
 pub use product_download_table::*;
+pub use product_manifest_table::*;
+pub use product_metadata_table::*;
+pub use product_name_encoding_table::*;
 pub use product_table::*;
 pub use setting_table::*;
+pub use thumbnail_table::*;
 
 use thiserror::Error;
 
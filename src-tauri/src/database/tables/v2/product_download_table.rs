@@ -1,17 +1,31 @@
 use super::DBResult;
 use crate::{
-    application::use_application,
     database::{
         models::v2::{CreatingProductDownload, ProductDownload},
         tables::Table,
     },
+    dlsite::product_id::ProductId,
 };
-use rusqlite::types::Value;
+use chrono::{DateTime, SecondsFormat, Utc};
+use rusqlite::{named_params, types::Value, Connection};
 use serde_rusqlite::*;
 use std::rc::Rc;
 
 pub struct ProductDownloadTable;
 
+const UPSERT_SQL: &str = r#"
+INSERT INTO v2_product_downloads (
+    product_id,
+    path
+) VALUES (
+    :product_id,
+    :path
+)
+ON CONFLICT(product_id) DO UPDATE SET
+    path = excluded.path,
+    missing_at = NULL
+"#;
+
 impl Table for ProductDownloadTable {
     fn get_ddl() -> &'static str {
         r#"
@@ -26,33 +40,91 @@ CREATE TABLE IF NOT EXISTS v2_product_downloads (
 }
 
 impl ProductDownloadTable {
-    /// Inserts a single product download into the database.
-    pub fn insert_one(download: CreatingProductDownload) -> DBResult<()> {
-        let connection = use_application().connection();
+    /// Inserts or updates a single product download in the database. It is no longer missing.
+    pub fn insert_one(connection: &Connection, download: CreatingProductDownload) -> DBResult<()> {
+        let mut stmt = connection.prepare(UPSERT_SQL)?;
+
+        stmt.execute(to_params_named(download)?.to_slice().as_slice())?;
+        Ok(())
+    }
+
+    /// Applies a scan of the download root in a single transaction. The found product downloads
+    /// are inserted or updated, and the missing ones are marked so, keeping their paths.
+    pub fn sync_scanned<'a>(
+        connection: &mut Connection,
+        found: impl Iterator<Item = CreatingProductDownload<'a>>,
+        missing_ids: &[ProductId],
+        missing_at: DateTime<Utc>,
+    ) -> DBResult<()> {
+        let tx = connection.transaction()?;
+        {
+            let mut upsert_stmt = tx.prepare(UPSERT_SQL)?;
+
+            for download in found {
+                upsert_stmt.execute(to_params_named(download)?.to_slice().as_slice())?;
+            }
+        }
+
+        let missing_ids = Rc::new(
+            missing_ids
+                .iter()
+                .cloned()
+                .map(Value::from)
+                .collect::<Vec<_>>(),
+        );
+        tx.execute(
+            r#"
+UPDATE v2_product_downloads
+SET
+    missing_at = :missing_at
+WHERE product_id IN rarray(:ids)
+    AND missing_at IS NULL
+"#,
+            named_params! {
+                ":ids": missing_ids,
+                // the serde format of `DateTime<Utc>`, as read back by `from_row`
+                ":missing_at": missing_at.to_rfc3339_opts(SecondsFormat::AutoSi, true),
+            },
+        )?;
+
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Retrieves every product download from the database.
+    pub fn get_all(connection: &Connection) -> DBResult<Vec<ProductDownload>> {
         let mut stmt = connection.prepare(
             r#"
-INSERT INTO v2_product_downloads (
+SELECT
     product_id,
-    path
-) VALUES (
-    :product_id,
-    :path
-)
+    path,
+    missing_at,
+    extraction_skip_reason
+FROM v2_product_downloads
 "#,
         )?;
 
-        stmt.execute(to_params_named(download)?.to_slice().as_slice())?;
-        Ok(())
+        let columns = columns_from_statement(&stmt);
+        let product_downloads = stmt
+            .query_and_then([], |row| {
+                from_row_with_columns::<ProductDownload>(row, &columns)
+            })?
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        Ok(product_downloads)
     }
 
     /// Retrieves many product downloads from the database.
-    pub fn get_many(product_ids: impl Iterator<Item = String>) -> DBResult<Vec<ProductDownload>> {
-        let connection = use_application().connection();
+    pub fn get_many(
+        connection: &Connection,
+        product_ids: impl Iterator<Item = ProductId>,
+    ) -> DBResult<Vec<ProductDownload>> {
         let mut stmt = connection.prepare(
             r#"
 SELECT
     product_id,
-    path
+    path,
+    missing_at,
+    extraction_skip_reason
 FROM v2_product_downloads WHERE product_id IN rarray(?)
 "#,
         )?;
@@ -68,13 +140,17 @@ FROM v2_product_downloads WHERE product_id IN rarray(?)
     }
 
     /// Retrieves a single product download from the database.
-    pub fn get_one(product_id: &str) -> DBResult<Option<ProductDownload>> {
-        let connection = use_application().connection();
+    pub fn get_one(
+        connection: &Connection,
+        product_id: &ProductId,
+    ) -> DBResult<Option<ProductDownload>> {
         let mut stmt = connection.prepare(
             r#"
 SELECT
     product_id,
-    path
+    path,
+    missing_at,
+    extraction_skip_reason
 FROM v2_product_downloads
 WHERE product_id = :product_id
 "#,
@@ -87,30 +163,62 @@ WHERE product_id = :product_id
         Ok(product_download)
     }
 
-    /// Removes a single product download from the database.
-    pub fn remove_one(product_id: &str) -> DBResult<()> {
-        let connection = use_application().connection();
+    /// Updates the paths of many product downloads in a single transaction.
+    pub fn update_many_paths<'a>(
+        connection: &mut Connection,
+        downloads: impl Iterator<Item = CreatingProductDownload<'a>>,
+    ) -> DBResult<()> {
+        let tx = connection.transaction()?;
+        {
+            let mut stmt = tx.prepare(
+                r#"
+UPDATE v2_product_downloads
+SET
+    path = :path
+WHERE product_id = :product_id
+"#,
+            )?;
+
+            for download in downloads {
+                stmt.execute(to_params_named(download)?.to_slice().as_slice())?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Records why the downloaded files of the product were not extracted, or clears it.
+    pub fn update_one_extraction_skip_reason(
+        connection: &Connection,
+        product_id: &ProductId,
+        extraction_skip_reason: Option<&str>,
+    ) -> DBResult<()> {
         let mut stmt = connection.prepare(
             r#"
-DELETE FROM v2_product_downloads
+UPDATE v2_product_downloads
+SET
+    extraction_skip_reason = :extraction_skip_reason
 WHERE product_id = :product_id
 "#,
         )?;
 
-        stmt.execute(&[(":product_id", &product_id)])?;
+        stmt.execute(named_params! {
+            ":product_id": product_id,
+            ":extraction_skip_reason": extraction_skip_reason,
+        })?;
         Ok(())
     }
 
-    /// Removes many product downloads from the database.
-    pub fn remove_many() -> DBResult<()> {
-        let connection = use_application().connection();
+    /// Removes a single product download from the database.
+    pub fn remove_one(connection: &Connection, product_id: &ProductId) -> DBResult<()> {
         let mut stmt = connection.prepare(
             r#"
 DELETE FROM v2_product_downloads
+WHERE product_id = :product_id
 "#,
         )?;
 
-        stmt.execute([])?;
+        stmt.execute(&[(":product_id", &product_id)])?;
         Ok(())
     }
 }
@@ -0,0 +1,118 @@
+use super::DBResult;
+use crate::{
+    database::{models::v2::ProductManifestFile, tables::Table},
+    dlsite::product_id::ProductId,
+};
+use rusqlite::{named_params, Connection};
+use serde_rusqlite::*;
+
+pub struct ProductManifestTable;
+
+impl Table for ProductManifestTable {
+    fn get_ddl() -> &'static str {
+        r#"
+CREATE TABLE IF NOT EXISTS v2_product_manifests (
+    product_id TEXT NOT NULL,
+    path TEXT NOT NULL,
+    size INTEGER NOT NULL,
+    hash TEXT NOT NULL,
+
+    PRIMARY KEY(product_id, path),
+    FOREIGN KEY(product_id) REFERENCES v2_products(id) ON UPDATE CASCADE ON DELETE CASCADE
+);
+"#
+    }
+}
+
+impl ProductManifestTable {
+    /// Replaces the manifest of a single product in a single transaction.
+    pub fn replace_one(
+        connection: &mut Connection,
+        product_id: &ProductId,
+        files: &[ProductManifestFile],
+    ) -> DBResult<()> {
+        let tx = connection.transaction()?;
+        {
+            tx.execute(
+                r#"
+DELETE FROM v2_product_manifests
+WHERE product_id = :product_id
+"#,
+                named_params! {
+                    ":product_id": product_id,
+                },
+            )?;
+
+            let mut stmt = tx.prepare(
+                r#"
+INSERT INTO v2_product_manifests (
+    product_id,
+    path,
+    size,
+    hash
+) VALUES (
+    :product_id,
+    :path,
+    :size,
+    :hash
+)
+"#,
+            )?;
+
+            for file in files {
+                stmt.execute(named_params! {
+                    ":product_id": product_id,
+                    ":path": file.path,
+                    ":size": file.size as i64,
+                    ":hash": file.hash,
+                })?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Retrieves the manifest of a single product; it is empty if none is recorded.
+    pub fn get_one(
+        connection: &Connection,
+        product_id: &ProductId,
+    ) -> DBResult<Vec<ProductManifestFile>> {
+        let mut stmt = connection.prepare(
+            r#"
+SELECT
+    path,
+    size,
+    hash
+FROM v2_product_manifests
+WHERE product_id = :product_id
+ORDER BY path ASC
+"#,
+        )?;
+
+        let columns = columns_from_statement(&stmt);
+        let files = stmt
+            .query_and_then(
+                named_params! {
+                    ":product_id": product_id,
+                },
+                |row| from_row_with_columns::<ProductManifestFile>(row, &columns),
+            )?
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        Ok(files)
+    }
+
+    /// Removes the manifest of a single product.
+    pub fn remove_one(connection: &Connection, product_id: &ProductId) -> DBResult<()> {
+        let mut stmt = connection.prepare(
+            r#"
+DELETE FROM v2_product_manifests
+WHERE product_id = :product_id
+"#,
+        )?;
+
+        stmt.execute(named_params! {
+            ":product_id": product_id,
+        })?;
+        Ok(())
+    }
+}
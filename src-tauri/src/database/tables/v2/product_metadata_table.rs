@@ -0,0 +1,332 @@
+use super::{DBResult, INDEX_PRODUCT_SQL};
+use crate::{
+    database::{models::v2::ProductMetadata, tables::Table},
+    dlsite::{
+        dto::{DLsiteProductCreator, DLsiteProductCreatorRole, DLsiteProductMetadata},
+        product_id::ProductId,
+    },
+};
+use rusqlite::{named_params, types::Value, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use serde_rusqlite::*;
+use std::rc::Rc;
+
+pub struct ProductMetadataTable;
+
+impl Table for ProductMetadataTable {
+    fn get_ddl() -> &'static str {
+        r#"
+CREATE TABLE IF NOT EXISTS v2_product_details (
+    product_id TEXT NOT NULL PRIMARY KEY,
+    description TEXT,
+    series_id TEXT,
+    series_name TEXT,
+    file_format TEXT,
+    page_count INTEGER,
+    track_count INTEGER,
+
+    FOREIGN KEY(product_id) REFERENCES v2_products(id) ON UPDATE CASCADE ON DELETE CASCADE
+);
+
+CREATE TABLE IF NOT EXISTS v2_genres (
+    id INTEGER PRIMARY KEY NOT NULL,
+    name TEXT NOT NULL UNIQUE
+);
+
+CREATE TABLE IF NOT EXISTS v2_product_genres (
+    product_id TEXT NOT NULL,
+    genre_id INTEGER NOT NULL,
+
+    PRIMARY KEY(product_id, genre_id),
+    FOREIGN KEY(product_id) REFERENCES v2_products(id) ON UPDATE CASCADE ON DELETE CASCADE,
+    FOREIGN KEY(genre_id) REFERENCES v2_genres(id) ON UPDATE CASCADE ON DELETE CASCADE
+);
+
+CREATE TABLE IF NOT EXISTS v2_creators (
+    id INTEGER PRIMARY KEY NOT NULL,
+    name TEXT NOT NULL UNIQUE
+);
+
+CREATE TABLE IF NOT EXISTS v2_product_creators (
+    product_id TEXT NOT NULL,
+    creator_id INTEGER NOT NULL,
+    role TEXT NOT NULL,
+
+    PRIMARY KEY(product_id, creator_id, role),
+    FOREIGN KEY(product_id) REFERENCES v2_products(id) ON UPDATE CASCADE ON DELETE CASCADE,
+    FOREIGN KEY(creator_id) REFERENCES v2_creators(id) ON UPDATE CASCADE ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS v2_product_details_series_id ON v2_product_details(series_id);
+CREATE INDEX IF NOT EXISTS v2_product_genres_genre_id ON v2_product_genres(genre_id);
+CREATE INDEX IF NOT EXISTS v2_product_creators_creator_id ON v2_product_creators(creator_id);
+"#
+    }
+}
+
+#[derive(Serialize)]
+struct CreatorParam<'a> {
+    pub product_id: &'a ProductId,
+    pub role: DLsiteProductCreatorRole,
+    pub name: &'a str,
+}
+
+impl ProductMetadataTable {
+    /// Inserts or replaces the metadata of many products, and refreshes their search index.
+    /// The products must already exist in the database.
+    pub fn insert_many<'a>(
+        connection: &mut Connection,
+        metadata: impl Iterator<Item = (&'a ProductId, &'a DLsiteProductMetadata)>,
+    ) -> DBResult<()> {
+        let tx = connection.transaction()?;
+        {
+            let mut details_stmt = tx.prepare(
+                r#"
+INSERT INTO v2_product_details (
+    product_id,
+    description,
+    series_id,
+    series_name,
+    file_format,
+    page_count,
+    track_count
+) VALUES (
+    :product_id,
+    :description,
+    :series_id,
+    :series_name,
+    :file_format,
+    :page_count,
+    :track_count
+) ON CONFLICT (product_id) DO UPDATE SET
+    description = excluded.description,
+    series_id = excluded.series_id,
+    series_name = excluded.series_name,
+    file_format = excluded.file_format,
+    page_count = excluded.page_count,
+    track_count = excluded.track_count
+"#,
+            )?;
+            let mut genres_remove_stmt = tx.prepare(
+                r#"
+DELETE FROM v2_product_genres
+WHERE product_id = :product_id
+"#,
+            )?;
+            let mut genre_insert_stmt = tx.prepare(
+                r#"
+INSERT INTO v2_genres (name) VALUES (:name)
+ON CONFLICT (name) DO NOTHING
+"#,
+            )?;
+            let mut product_genre_insert_stmt = tx.prepare(
+                r#"
+INSERT OR IGNORE INTO v2_product_genres (
+    product_id,
+    genre_id
+) SELECT :product_id, id FROM v2_genres WHERE name = :name
+"#,
+            )?;
+            let mut creators_remove_stmt = tx.prepare(
+                r#"
+DELETE FROM v2_product_creators
+WHERE product_id = :product_id
+"#,
+            )?;
+            let mut creator_insert_stmt = tx.prepare(
+                r#"
+INSERT INTO v2_creators (name) VALUES (:name)
+ON CONFLICT (name) DO NOTHING
+"#,
+            )?;
+            let mut product_creator_insert_stmt = tx.prepare(
+                r#"
+INSERT OR IGNORE INTO v2_product_creators (
+    product_id,
+    creator_id,
+    role
+) SELECT :product_id, id, :role FROM v2_creators WHERE name = :name
+"#,
+            )?;
+            let mut index_remove_stmt = tx.prepare(
+                r#"
+DELETE FROM v2_indexed_products
+WHERE id = :id
+"#,
+            )?;
+            let mut index_insert_stmt = tx.prepare(INDEX_PRODUCT_SQL)?;
+
+            for (product_id, metadata) in metadata {
+                details_stmt.execute(named_params! {
+                    ":product_id": product_id,
+                    ":description": metadata.description,
+                    ":series_id": metadata.series_id,
+                    ":series_name": metadata.series_name,
+                    ":file_format": metadata.file_format,
+                    ":page_count": metadata.page_count,
+                    ":track_count": metadata.track_count,
+                })?;
+
+                genres_remove_stmt.execute(named_params! {
+                    ":product_id": product_id,
+                })?;
+
+                for genre in &metadata.genres {
+                    genre_insert_stmt.execute(named_params! {
+                        ":name": genre,
+                    })?;
+                    product_genre_insert_stmt.execute(named_params! {
+                        ":product_id": product_id,
+                        ":name": genre,
+                    })?;
+                }
+
+                creators_remove_stmt.execute(named_params! {
+                    ":product_id": product_id,
+                })?;
+
+                for creator in &metadata.creators {
+                    creator_insert_stmt.execute(named_params! {
+                        ":name": creator.name,
+                    })?;
+                    product_creator_insert_stmt.execute(
+                        to_params_named(CreatorParam {
+                            product_id,
+                            role: creator.role,
+                            name: &creator.name,
+                        })?
+                        .to_slice()
+                        .as_slice(),
+                    )?;
+                }
+
+                index_remove_stmt.execute(named_params! {
+                    ":id": product_id,
+                })?;
+                index_insert_stmt.execute(named_params! {
+                    ":id": product_id,
+                })?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Retrieves the metadata of a single product.
+    pub fn get_one(
+        connection: &Connection,
+        product_id: &ProductId,
+    ) -> DBResult<Option<ProductMetadata>> {
+        let mut details_stmt = connection.prepare(
+            r#"
+SELECT
+    product_id,
+    description,
+    series_id,
+    series_name,
+    file_format,
+    page_count,
+    track_count
+FROM v2_product_details
+WHERE product_id = :product_id
+"#,
+        )?;
+        let mut genres_stmt = connection.prepare(
+            r#"
+SELECT
+    genre.name
+FROM v2_product_genres AS product_genre
+INNER JOIN v2_genres AS genre ON genre.id = product_genre.genre_id
+WHERE product_genre.product_id = :product_id
+ORDER BY genre.name ASC
+"#,
+        )?;
+        let mut creators_stmt = connection.prepare(
+            r#"
+SELECT
+    product_creator.role,
+    creator.name
+FROM v2_product_creators AS product_creator
+INNER JOIN v2_creators AS creator ON creator.id = product_creator.creator_id
+WHERE product_creator.product_id = :product_id
+ORDER BY product_creator.role ASC, creator.name ASC
+"#,
+        )?;
+
+        #[derive(Deserialize)]
+        struct Details {
+            pub product_id: ProductId,
+            pub description: Option<String>,
+            pub series_id: Option<String>,
+            pub series_name: Option<String>,
+            pub file_format: Option<String>,
+            pub page_count: Option<u32>,
+            pub track_count: Option<u32>,
+        }
+
+        let details = match details_stmt
+            .query_row(
+                named_params! {
+                    ":product_id": product_id,
+                },
+                |row| Ok(from_row::<Details>(row)),
+            )
+            .optional()?
+            .transpose()?
+        {
+            Some(details) => details,
+            None => return Ok(None),
+        };
+        let genres = genres_stmt
+            .query_map(
+                named_params! {
+                    ":product_id": product_id,
+                },
+                |row| row.get::<_, String>(0),
+            )?
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        let columns = columns_from_statement(&creators_stmt);
+        let creators = creators_stmt
+            .query_and_then(
+                named_params! {
+                    ":product_id": product_id,
+                },
+                |row| from_row_with_columns::<DLsiteProductCreator>(row, &columns),
+            )?
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+
+        Ok(Some(ProductMetadata {
+            product_id: details.product_id,
+            description: details.description,
+            series_id: details.series_id,
+            series_name: details.series_name,
+            file_format: details.file_format,
+            page_count: details.page_count,
+            track_count: details.track_count,
+            genres,
+            creators,
+        }))
+    }
+
+    /// Retrieves the IDs of the products that have no metadata yet, among the given IDs.
+    pub fn get_many_missing_product_ids(
+        connection: &Connection,
+        product_ids: impl Iterator<Item = ProductId>,
+    ) -> DBResult<Vec<ProductId>> {
+        let mut stmt = connection.prepare(
+            r#"
+SELECT
+    product.id
+FROM v2_products AS product
+WHERE product.id IN rarray(?)
+    AND product.id NOT IN (SELECT product_id FROM v2_product_details)
+"#,
+        )?;
+
+        let product_ids = Rc::new(product_ids.map(Value::from).collect::<Vec<_>>());
+        let ids = stmt
+            .query_map([product_ids], |row| row.get::<_, ProductId>(0))?
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        Ok(ids)
+    }
+}
@@ -0,0 +1,87 @@
+use super::DBResult;
+use crate::{
+    database::{models::v2::ProductNameEncoding, tables::Table},
+    dlsite::product_id::ProductId,
+};
+use rusqlite::{named_params, Connection};
+use serde_rusqlite::*;
+
+pub struct ProductNameEncodingTable;
+
+impl Table for ProductNameEncodingTable {
+    fn get_ddl() -> &'static str {
+        r#"
+CREATE TABLE IF NOT EXISTS v2_product_name_encodings (
+    product_id TEXT NOT NULL PRIMARY KEY,
+    encoding TEXT NOT NULL,
+
+    FOREIGN KEY(product_id) REFERENCES v2_products(id) ON UPDATE CASCADE ON DELETE CASCADE
+);
+"#
+    }
+}
+
+impl ProductNameEncodingTable {
+    /// Inserts or updates the name encoding of a single product.
+    pub fn insert_one(
+        connection: &Connection,
+        name_encoding: &ProductNameEncoding,
+    ) -> DBResult<()> {
+        let mut stmt = connection.prepare(
+            r#"
+INSERT INTO v2_product_name_encodings (
+    product_id,
+    encoding
+) VALUES (
+    :product_id,
+    :encoding
+)
+ON CONFLICT(product_id) DO UPDATE SET
+    encoding = excluded.encoding
+"#,
+        )?;
+
+        stmt.execute(to_params_named(name_encoding)?.to_slice().as_slice())?;
+        Ok(())
+    }
+
+    /// Retrieves the name encoding of a single product, if it is overridden.
+    pub fn get_one(
+        connection: &Connection,
+        product_id: &ProductId,
+    ) -> DBResult<Option<ProductNameEncoding>> {
+        let mut stmt = connection.prepare(
+            r#"
+SELECT
+    product_id,
+    encoding
+FROM v2_product_name_encodings
+WHERE product_id = :product_id
+"#,
+        )?;
+
+        let mut rows = stmt.query_and_then(
+            named_params! {
+                ":product_id": product_id,
+            },
+            |row| from_row::<ProductNameEncoding>(row),
+        )?;
+        let name_encoding = rows.next().transpose()?;
+        Ok(name_encoding)
+    }
+
+    /// Removes the name encoding of a single product, leaving it to be guessed.
+    pub fn remove_one(connection: &Connection, product_id: &ProductId) -> DBResult<()> {
+        let mut stmt = connection.prepare(
+            r#"
+DELETE FROM v2_product_name_encodings
+WHERE product_id = :product_id
+"#,
+        )?;
+
+        stmt.execute(named_params! {
+            ":product_id": product_id,
+        })?;
+        Ok(())
+    }
+}
@@ -4,12 +4,49 @@ use crate::{
         models::v2::{CreatingProduct, Product},
         tables::Table,
     },
-    dlsite::dto::{DLsiteProductAgeCategory, DLsiteProductType},
-    use_application,
+    dlsite::product_id::ProductId,
+    product_query::ast::{ProductFilter, ProductQuery},
 };
-use anyhow::Context;
-use serde::Serialize;
+use chrono::SecondsFormat;
+use rusqlite::{named_params, types::Value, Connection, OptionalExtension, ToSql, Transaction};
 use serde_rusqlite::*;
+use std::rc::Rc;
+
+/// Indexes a single product, along with its metadata if any, for full-text search.
+/// The product must not be indexed already.
+pub(crate) const INDEX_PRODUCT_SQL: &str = r#"
+INSERT INTO v2_indexed_products (
+    id,
+    title,
+    group_id,
+    group_name,
+    genres,
+    creators,
+    series_name,
+    description
+) SELECT
+    product.id,
+    product.title,
+    product.group_id,
+    product.group_name,
+    (
+        SELECT group_concat(genre.name, ' ')
+        FROM v2_product_genres AS product_genre
+        INNER JOIN v2_genres AS genre ON genre.id = product_genre.genre_id
+        WHERE product_genre.product_id = product.id
+    ),
+    (
+        SELECT group_concat(creator.name, ' ')
+        FROM v2_product_creators AS product_creator
+        INNER JOIN v2_creators AS creator ON creator.id = product_creator.creator_id
+        WHERE product_creator.product_id = product.id
+    ),
+    details.series_name,
+    details.description
+FROM v2_products AS product
+LEFT JOIN v2_product_details AS details ON details.product_id = product.id
+WHERE product.id = :id
+"#;
 
 pub struct ProductTable;
 
@@ -50,140 +87,189 @@ CREATE VIRTUAL TABLE IF NOT EXISTS v2_indexed_products USING fts5 (
 
 impl ProductTable {
     /// Inserts many products into the database. Note that the account will not be overwritten.
-    pub fn insert_many<'a>(products: impl Iterator<Item = CreatingProduct<'a>>) -> DBResult<()> {
-        let mut connection = use_application().connection();
+    pub fn insert_many<'a>(
+        connection: &mut Connection,
+        products: impl Iterator<Item = CreatingProduct<'a>>,
+    ) -> DBResult<()> {
         let tx = connection.transaction()?;
-        {
-            let mut insert_stmt = tx.prepare(
-                r#"
-INSERT INTO v2_products (
-    id,
-    account_id,
-    ty,
-    age,
-    title,
-    thumbnail,
-    group_id,
-    group_name,
-    registered_at
-) VALUES (
-    :id,
-    :account_id,
-    :ty,
-    :age,
-    :title,
-    :thumbnail,
-    :group_id,
-    :group_name,
-    :registered_at
-) ON CONFLICT (id) DO UPDATE SET
-    ty = excluded.ty,
-    age = excluded.age,
-    title = excluded.title,
-    thumbnail = excluded.thumbnail,
-    group_id = excluded.group_id,
-    group_name = excluded.group_name,
-    registered_at = excluded.registered_at
+        upsert_many(&tx, products, false)?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Replaces every owned product with the given products in a single transaction.
+    /// Owned products missing from the given products are removed, except the downloaded ones,
+    /// which are kept as products not owned by any account.
+    pub fn replace_many_owned<'a>(
+        connection: &mut Connection,
+        products: impl Iterator<Item = CreatingProduct<'a>>,
+    ) -> DBResult<()> {
+        let tx = connection.transaction()?;
+        let mut product_ids = Vec::new();
+
+        upsert_many(
+            &tx,
+            products.inspect(|product| product_ids.push(Value::from(product.id.to_owned()))),
+            true,
+        )?;
+
+        let product_ids = Rc::new(product_ids);
+        tx.execute(
+            r#"
+UPDATE v2_products
+SET
+    account_id = NULL
+WHERE account_id IS NOT NULL
+    AND id NOT IN rarray(:ids)
+    AND id IN (SELECT product_id FROM v2_product_downloads)
 "#,
-            )?;
-            let mut index_remove_stmt = tx.prepare(
-                r#"
+            named_params! {
+                ":ids": product_ids,
+            },
+        )?;
+        tx.execute(
+            r#"
 DELETE FROM v2_indexed_products
-WHERE id = :id
+WHERE id IN (
+    SELECT id FROM v2_products
+    WHERE account_id IS NOT NULL
+        AND id NOT IN rarray(:ids)
+)
 "#,
-            )?;
-            let mut index_insert_stmt = tx.prepare(
-                r#"
-INSERT INTO v2_indexed_products (
-    id,
-    title,
-    group_id,
-    group_name
-) VALUES (
-    :id,
-    :title,
-    :group_id,
-    :group_name
-)"#,
-            )?;
-
-            for product in products {
-                insert_stmt.execute(to_params_named(&product)?.to_slice().as_slice())?;
-                index_remove_stmt.execute(
-                    to_params_named_with_fields(&product, &["id"])?
-                        .to_slice()
-                        .as_slice(),
-                )?;
-                index_insert_stmt.execute(
-                    to_params_named_with_fields(
-                        &product,
-                        &["id", "title", "group_id", "group_name"],
-                    )?
-                    .to_slice()
-                    .as_slice(),
-                )?;
-            }
-        }
+            named_params! {
+                ":ids": product_ids,
+            },
+        )?;
+        tx.execute(
+            r#"
+DELETE FROM v2_products
+WHERE account_id IS NOT NULL
+    AND id NOT IN rarray(:ids)
+"#,
+            named_params! {
+                ":ids": product_ids,
+            },
+        )?;
+
         tx.commit()?;
         Ok(())
     }
 
-    /// Retrieves products from the database with optional filters.
-    pub fn get_many(
-        query: Option<&str>,
-        ty: Option<DLsiteProductType>,
-        age: Option<DLsiteProductAgeCategory>,
-        order_by_asc: bool,
-    ) -> DBResult<Vec<Product>> {
-        let mut where_clause = String::new();
-        let mut params = vec![];
-        where_clause.push_str("TRUE");
+    /// Applies an incremental sync of a single account in a single transaction.
+    /// The added products are assigned to the account, and the removed products of the account
+    /// are removed, except the downloaded ones, which are kept as products not owned by any account.
+    pub fn sync_owned<'a>(
+        connection: &mut Connection,
+        account_id: i64,
+        added: impl Iterator<Item = CreatingProduct<'a>>,
+        removed_ids: &[ProductId],
+    ) -> DBResult<()> {
+        let tx = connection.transaction()?;
 
-        if let Some(query) = query {
-            let query = query.trim();
+        upsert_many(&tx, added, true)?;
 
-            if !query.is_empty() {
-                #[derive(Serialize)]
-                struct QueryParam<'a> {
-                    pub query: &'a str,
-                }
+        let removed_ids = Rc::new(
+            removed_ids
+                .iter()
+                .cloned()
+                .map(Value::from)
+                .collect::<Vec<_>>(),
+        );
+        tx.execute(
+            r#"
+UPDATE v2_products
+SET
+    account_id = NULL
+WHERE account_id = :account_id
+    AND id IN rarray(:ids)
+    AND id IN (SELECT product_id FROM v2_product_downloads)
+"#,
+            named_params! {
+                ":account_id": account_id,
+                ":ids": removed_ids,
+            },
+        )?;
+        tx.execute(
+            r#"
+DELETE FROM v2_indexed_products
+WHERE id IN (
+    SELECT id FROM v2_products
+    WHERE account_id = :account_id
+        AND id IN rarray(:ids)
+)
+"#,
+            named_params! {
+                ":account_id": account_id,
+                ":ids": removed_ids,
+            },
+        )?;
+        tx.execute(
+            r#"
+DELETE FROM v2_products
+WHERE account_id = :account_id
+    AND id IN rarray(:ids)
+"#,
+            named_params! {
+                ":account_id": account_id,
+                ":ids": removed_ids,
+            },
+        )?;
 
-                where_clause.push_str(" AND v2_indexed_products MATCH :query");
-                params.push(
-                    to_params_named(QueryParam { query })
-                        .with_context(|| format!("[query build] query"))?,
-                );
-            }
-        }
+        tx.commit()?;
+        Ok(())
+    }
 
-        if let Some(ty) = ty {
-            #[derive(Serialize)]
-            struct QueryTy {
-                pub ty: DLsiteProductType,
-            }
+    /// Retrieves the IDs of every owned product along with its owning account ID.
+    pub fn get_many_owned_ids(connection: &Connection) -> DBResult<Vec<(ProductId, i64)>> {
+        let mut stmt = connection.prepare(
+            r#"
+SELECT
+    id,
+    account_id
+FROM v2_products
+WHERE account_id IS NOT NULL
+"#,
+        )?;
 
-            where_clause.push_str(" AND product.ty = :ty");
-            params.push(
-                to_params_named(QueryTy { ty }).with_context(|| format!("[query build] ty"))?,
-            );
-        }
+        let ids = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        Ok(ids)
+    }
 
-        if let Some(age) = age {
-            #[derive(Serialize)]
-            struct QueryAge {
-                pub age: DLsiteProductAgeCategory,
-            }
+    /// Retrieves which of the given product IDs are already in the database.
+    pub fn get_many_known_ids(
+        connection: &Connection,
+        ids: impl Iterator<Item = ProductId>,
+    ) -> DBResult<Vec<ProductId>> {
+        let mut stmt = connection.prepare(
+            r#"
+SELECT
+    id
+FROM v2_products
+WHERE id IN rarray(?)
+"#,
+        )?;
 
-            where_clause.push_str(" AND product.age = :age");
-            params.push(
-                to_params_named(QueryAge { age }).with_context(|| format!("[query build] age"))?,
-            );
-        }
+        let ids = Rc::new(ids.map(Value::from).collect::<Vec<_>>());
+        let ids = stmt
+            .query_map([ids], |row| row.get(0))?
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        Ok(ids)
+    }
 
-        let params = params
+    /// Retrieves products from the database matching the given query.
+    pub fn get_many(
+        connection: &Connection,
+        query: &ProductQuery,
+        order_by_asc: bool,
+    ) -> DBResult<Vec<Product>> {
+        let mut compiler = QueryCompiler::default();
+        let where_clause = compiler.compile(query);
+        let params = compiler
+            .params
             .iter()
-            .map(|param| param.to_slice())
-            .flatten()
+            .map(|(name, value)| (name.as_str(), value.as_ref()))
             .collect::<Vec<_>>();
 
         let order_by_clause = if order_by_asc {
@@ -192,7 +278,6 @@ INSERT INTO v2_indexed_products (
             "product.registered_at DESC, product.id DESC"
         };
 
-        let connection = use_application().connection();
         let mut stmt = connection.prepare(
             format!(
                 r#"
@@ -206,8 +291,7 @@ SELECT
     product.group_id,
     product.group_name,
     product.registered_at
-FROM v2_indexed_products
-INNER JOIN v2_products AS product ON product.id = v2_indexed_products.id
+FROM v2_products AS product
 WHERE {}
 ORDER BY {}
 "#,
@@ -225,33 +309,291 @@ ORDER BY {}
         Ok(products)
     }
 
-    /// Removes many products from the database.
-    /// It does not remove the product which is not owned by any account.
-    pub fn remove_many_owned() -> DBResult<()> {
-        let connection = use_application().connection();
+    /// Retrieves a single product from the database.
+    pub fn get_one(connection: &Connection, id: &ProductId) -> DBResult<Option<Product>> {
         let mut stmt = connection.prepare(
             r#"
-DELETE FROM v2_products
-WHERE account_id IS NOT NULL
+SELECT
+    id,
+    account_id,
+    ty,
+    age,
+    title,
+    thumbnail,
+    group_id,
+    group_name,
+    registered_at
+FROM v2_products
+WHERE id = :id
 "#,
         )?;
 
-        stmt.execute([])?;
-        Ok(())
+        let product = stmt
+            .query_row(
+                named_params! {
+                    ":id": id,
+                },
+                |row| Ok(from_row::<Product>(row)),
+            )
+            .optional()?
+            .transpose()?;
+        Ok(product)
     }
+}
 
-    /// Removes many products from the database.
-    /// It does not remove the product which is owned by any account.
-    pub fn remove_many_not_owned() -> DBResult<()> {
-        let connection = use_application().connection();
-        let mut stmt = connection.prepare(
-            r#"
-DELETE FROM v2_products
-WHERE account_id IS NULL
+/// Inserts or updates the given products and their search index within the given transaction.
+fn upsert_many<'a>(
+    tx: &Transaction,
+    products: impl Iterator<Item = CreatingProduct<'a>>,
+    overwrite_account: bool,
+) -> DBResult<()> {
+    let mut insert_stmt = tx.prepare(&format!(
+        r#"
+INSERT INTO v2_products (
+    id,
+    account_id,
+    ty,
+    age,
+    title,
+    thumbnail,
+    group_id,
+    group_name,
+    registered_at
+) VALUES (
+    :id,
+    :account_id,
+    :ty,
+    :age,
+    :title,
+    :thumbnail,
+    :group_id,
+    :group_name,
+    :registered_at
+) ON CONFLICT (id) DO UPDATE SET{}
+    ty = excluded.ty,
+    age = excluded.age,
+    title = excluded.title,
+    thumbnail = excluded.thumbnail,
+    group_id = excluded.group_id,
+    group_name = excluded.group_name,
+    registered_at = excluded.registered_at
+"#,
+        if overwrite_account {
+            "\n    account_id = excluded.account_id,"
+        } else {
+            ""
+        }
+    ))?;
+    let mut index_remove_stmt = tx.prepare(
+        r#"
+DELETE FROM v2_indexed_products
+WHERE id = :id
 "#,
+    )?;
+    let mut index_insert_stmt = tx.prepare(INDEX_PRODUCT_SQL)?;
+
+    for product in products {
+        insert_stmt.execute(to_params_named(&product)?.to_slice().as_slice())?;
+        index_remove_stmt.execute(
+            to_params_named_with_fields(&product, &["id"])?
+                .to_slice()
+                .as_slice(),
+        )?;
+        index_insert_stmt.execute(
+            to_params_named_with_fields(&product, &["id"])?
+                .to_slice()
+                .as_slice(),
         )?;
+    }
 
-        stmt.execute([])?;
-        Ok(())
+    Ok(())
+}
+
+/// Compiles a product query into a SQL condition over `v2_products AS product`,
+/// binding every value as a parameter.
+#[derive(Default)]
+struct QueryCompiler {
+    pub params: Vec<(String, Box<dyn ToSql>)>,
+}
+
+impl QueryCompiler {
+    pub fn compile(&mut self, query: &ProductQuery) -> String {
+        if query.terms.is_empty() {
+            return "TRUE".to_owned();
+        }
+
+        query
+            .terms
+            .iter()
+            .map(|term| {
+                let condition = self.compile_filter(&term.filter);
+
+                if term.negated {
+                    format!("NOT IFNULL({}, FALSE)", condition)
+                } else {
+                    condition
+                }
+            })
+            .collect::<Vec<_>>()
+            .join("\n    AND ")
+    }
+
+    fn compile_filter(&mut self, filter: &ProductFilter) -> String {
+        match filter {
+            ProductFilter::Text(text) | ProductFilter::Phrase(text) => {
+                // the trigram tokenizer cannot match less than 3 characters
+                if text.chars().count() < 3 {
+                    let pattern = self.bind(make_like_pattern(text));
+                    format!(
+                        r#"(product.id IN (
+        SELECT id FROM v2_indexed_products
+        WHERE title LIKE {0} ESCAPE '\'
+            OR group_id LIKE {0} ESCAPE '\'
+            OR group_name LIKE {0} ESCAPE '\'
+            OR genres LIKE {0} ESCAPE '\'
+            OR creators LIKE {0} ESCAPE '\'
+            OR series_name LIKE {0} ESCAPE '\'
+            OR description LIKE {0} ESCAPE '\'
+    ))"#,
+                        pattern
+                    )
+                } else {
+                    let phrase = self.bind(format!("\"{}\"", text.replace('"', "\"\"")));
+                    format!(
+                        "(product.id IN (SELECT id FROM v2_indexed_products WHERE v2_indexed_products MATCH {}))",
+                        phrase
+                    )
+                }
+            }
+            ProductFilter::Id(ids) => {
+                let ids = self.bind_array(ids.iter().cloned().map(Value::from));
+                format!("(product.id IN rarray({}))", ids)
+            }
+            ProductFilter::Title(title) => {
+                let pattern = self.bind(make_like_pattern(title));
+                format!("(product.title LIKE {} ESCAPE '\\')", pattern)
+            }
+            ProductFilter::Circle(circle) => {
+                let id = self.bind(circle.clone());
+                let pattern = self.bind(make_like_pattern(circle));
+                format!(
+                    "(product.group_id = {} OR product.group_name LIKE {} ESCAPE '\\')",
+                    id, pattern
+                )
+            }
+            ProductFilter::Genre(genre) => {
+                let pattern = self.bind(make_like_pattern(genre));
+                format!(
+                    r#"EXISTS (
+        SELECT 1 FROM v2_product_genres AS product_genre
+        INNER JOIN v2_genres AS genre ON genre.id = product_genre.genre_id
+        WHERE product_genre.product_id = product.id
+            AND genre.name LIKE {} ESCAPE '\'
+    )"#,
+                    pattern
+                )
+            }
+            ProductFilter::Creator { name, voice_only } => {
+                let pattern = self.bind(make_like_pattern(name));
+                format!(
+                    r#"EXISTS (
+        SELECT 1 FROM v2_product_creators AS product_creator
+        INNER JOIN v2_creators AS creator ON creator.id = product_creator.creator_id
+        WHERE product_creator.product_id = product.id
+            AND creator.name LIKE {} ESCAPE '\'{}
+    )"#,
+                    pattern,
+                    if *voice_only {
+                        "\n            AND product_creator.role = 'Voice'"
+                    } else {
+                        ""
+                    }
+                )
+            }
+            ProductFilter::Series(series) => {
+                let id = self.bind(series.clone());
+                let pattern = self.bind(make_like_pattern(series));
+                format!(
+                    r#"EXISTS (
+        SELECT 1 FROM v2_product_details AS details
+        WHERE details.product_id = product.id
+            AND (details.series_id = {} OR details.series_name LIKE {} ESCAPE '\')
+    )"#,
+                    id, pattern
+                )
+            }
+            ProductFilter::Type(types) => {
+                let types = self.bind_array(types.iter().map(|ty| Value::from(ty.to_string())));
+                format!("(product.ty IN rarray({}))", types)
+            }
+            ProductFilter::Age(ages) => {
+                let ages = self.bind_array(ages.iter().map(|age| Value::from(age.to_string())));
+                format!("(product.age IN rarray({}))", ages)
+            }
+            ProductFilter::Downloaded(downloaded) => format!(
+                "(product.id {}IN (SELECT product_id FROM v2_product_downloads WHERE missing_at IS NULL))",
+                if *downloaded { "" } else { "NOT " }
+            ),
+            ProductFilter::Registered(comparison) => {
+                // `registered_at` is stored in the serde format of `DateTime<Utc>`,
+                // which sorts lexicographically
+                let (start, end) = comparison.range();
+                let mut conditions = vec!["product.registered_at IS NOT NULL".to_owned()];
+
+                if let Some(start) = start {
+                    let start = self.bind(start.to_rfc3339_opts(SecondsFormat::AutoSi, true));
+                    conditions.push(format!("product.registered_at >= {}", start));
+                }
+
+                if let Some(end) = end {
+                    let end = self.bind(end.to_rfc3339_opts(SecondsFormat::AutoSi, true));
+                    conditions.push(format!("product.registered_at < {}", end));
+                }
+
+                format!("({})", conditions.join(" AND "))
+            }
+            ProductFilter::Account(accounts) => {
+                let mut conditions = vec![];
+                let ids = accounts.iter().flatten().copied().collect::<Vec<_>>();
+
+                if !ids.is_empty() {
+                    let ids = self.bind_array(ids.into_iter().map(Value::from));
+                    conditions.push(format!("product.account_id IN rarray({})", ids));
+                }
+
+                if accounts.iter().any(|account| account.is_none()) {
+                    conditions.push("product.account_id IS NULL".to_owned());
+                }
+
+                format!("({})", conditions.join(" OR "))
+            }
+        }
+    }
+
+    fn bind(&mut self, value: impl ToSql + 'static) -> String {
+        let name = format!(":p{}", self.params.len());
+        self.params.push((name.clone(), Box::new(value)));
+        name
     }
+
+    fn bind_array(&mut self, values: impl Iterator<Item = Value>) -> String {
+        self.bind(Rc::new(values.collect::<Vec<_>>()))
+    }
+}
+
+/// Makes a `LIKE` pattern matching the text anywhere, to be used with `ESCAPE '\'`.
+fn make_like_pattern(text: &str) -> String {
+    let mut pattern = String::with_capacity(text.len() + 2);
+    pattern.push('%');
+
+    for c in text.chars() {
+        if c == '%' || c == '_' || c == '\\' {
+            pattern.push('\\');
+        }
+
+        pattern.push(c);
+    }
+
+    pattern.push('%');
+    pattern
 }
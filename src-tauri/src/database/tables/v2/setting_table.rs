@@ -1,9 +1,6 @@
 use super::DBResult;
-use crate::{
-    application::use_application,
-    database::{models::v2::Setting, tables::Table},
-};
-use rusqlite::OptionalExtension;
+use crate::database::{models::v2::Setting, tables::Table};
+use rusqlite::{Connection, OptionalExtension};
 use serde_rusqlite::*;
 
 pub struct SettingTable;
@@ -21,13 +18,14 @@ CREATE TABLE IF NOT EXISTS v2_settings (
 
 impl SettingTable {
     /// Inserts or updates the singleton setting in the database.
-    pub fn insert(setting: &Setting) -> DBResult<()> {
-        let connection = use_application().connection();
+    pub fn insert(connection: &Connection, setting: &Setting) -> DBResult<()> {
         let mut stmt = connection.prepare(
             r#"
-INSERT INTO v2_settings (id, download_root_dir) VALUES (1, :download_root_dir)
+INSERT INTO v2_settings (id, download_root_dir, folder_template, scan_depth) VALUES (1, :download_root_dir, :folder_template, :scan_depth)
 ON CONFLICT(id) DO UPDATE SET
-    download_root_dir = excluded.download_root_dir;
+    download_root_dir = excluded.download_root_dir,
+    folder_template = excluded.folder_template,
+    scan_depth = excluded.scan_depth;
 "#,
         )?;
 
@@ -36,12 +34,11 @@ ON CONFLICT(id) DO UPDATE SET
     }
 
     /// Gets the singleton setting from the database.
-    pub fn get() -> DBResult<Option<Setting>> {
-        let connection = use_application().connection();
+    pub fn get(connection: &Connection) -> DBResult<Option<Setting>> {
         let mut stmt = connection.prepare(
             r#"
 SELECT
-    id, download_root_dir
+    id, download_root_dir, folder_template, scan_depth
 FROM v2_settings
 WHERE id = 1;
 "#,
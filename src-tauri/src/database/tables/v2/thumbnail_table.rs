@@ -0,0 +1,171 @@
+use super::DBResult;
+use crate::{
+    database::{
+        models::v2::{CreatingThumbnail, Thumbnail},
+        tables::Table,
+    },
+    dlsite::product_id::ProductId,
+};
+use rusqlite::{named_params, types::Value, Connection, OptionalExtension};
+use serde_rusqlite::*;
+use std::rc::Rc;
+
+pub struct ThumbnailTable;
+
+impl Table for ThumbnailTable {
+    fn get_ddl() -> &'static str {
+        r#"
+CREATE TABLE IF NOT EXISTS v2_thumbnails (
+    product_id TEXT NOT NULL PRIMARY KEY,
+    url TEXT NOT NULL,
+    file_name TEXT NOT NULL,
+    content_type TEXT NOT NULL,
+    size INTEGER NOT NULL,
+    cached_at INTEGER NOT NULL DEFAULT (unixepoch()),
+    accessed_at INTEGER NOT NULL DEFAULT (unixepoch()),
+
+    FOREIGN KEY(product_id) REFERENCES v2_products(id) ON UPDATE CASCADE ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS v2_thumbnails_accessed_at ON v2_thumbnails(accessed_at);
+"#
+    }
+}
+
+impl ThumbnailTable {
+    /// Inserts or replaces a single cached thumbnail.
+    pub fn insert_one(connection: &Connection, thumbnail: CreatingThumbnail) -> DBResult<()> {
+        let mut stmt = connection.prepare(
+            r#"
+INSERT INTO v2_thumbnails (
+    product_id,
+    url,
+    file_name,
+    content_type,
+    size
+) VALUES (
+    :product_id,
+    :url,
+    :file_name,
+    :content_type,
+    :size
+) ON CONFLICT (product_id) DO UPDATE SET
+    url = excluded.url,
+    file_name = excluded.file_name,
+    content_type = excluded.content_type,
+    size = excluded.size,
+    cached_at = unixepoch(),
+    accessed_at = unixepoch()
+"#,
+        )?;
+
+        stmt.execute(to_params_named(&thumbnail)?.to_slice().as_slice())?;
+        Ok(())
+    }
+
+    /// Retrieves a single cached thumbnail.
+    pub fn get_one(connection: &Connection, product_id: &ProductId) -> DBResult<Option<Thumbnail>> {
+        let mut stmt = connection.prepare(
+            r#"
+SELECT
+    product_id,
+    url,
+    file_name,
+    content_type,
+    size
+FROM v2_thumbnails
+WHERE product_id = :product_id
+"#,
+        )?;
+
+        let thumbnail = stmt
+            .query_row(
+                named_params! {
+                    ":product_id": product_id,
+                },
+                |row| Ok(from_row::<Thumbnail>(row)),
+            )
+            .optional()?
+            .transpose()?;
+        Ok(thumbnail)
+    }
+
+    /// Retrieves every cached thumbnail, least recently used first.
+    pub fn get_all_least_recently_used(connection: &Connection) -> DBResult<Vec<Thumbnail>> {
+        let mut stmt = connection.prepare(
+            r#"
+SELECT
+    product_id,
+    url,
+    file_name,
+    content_type,
+    size
+FROM v2_thumbnails
+ORDER BY accessed_at ASC, cached_at ASC
+"#,
+        )?;
+
+        let columns = columns_from_statement(&stmt);
+        let thumbnails = stmt
+            .query_and_then([], |row| from_row_with_columns::<Thumbnail>(row, &columns))?
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        Ok(thumbnails)
+    }
+
+    /// Retrieves the IDs and thumbnail URLs of the products whose thumbnail is not cached,
+    /// or was cached from a different URL.
+    pub fn get_many_missing(connection: &Connection) -> DBResult<Vec<(ProductId, String)>> {
+        let mut stmt = connection.prepare(
+            r#"
+SELECT
+    product.id,
+    product.thumbnail
+FROM v2_products AS product
+LEFT JOIN v2_thumbnails AS thumbnail ON thumbnail.product_id = product.id
+WHERE product.thumbnail != ''
+    AND (thumbnail.product_id IS NULL OR thumbnail.url != product.thumbnail)
+"#,
+        )?;
+
+        let missing = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        Ok(missing)
+    }
+
+    /// Marks the thumbnail as used now. It is only written if it was not used in the last hour,
+    /// so that showing the product list does not write to the database for every image.
+    pub fn touch_one(connection: &Connection, product_id: &ProductId) -> DBResult<()> {
+        let mut stmt = connection.prepare(
+            r#"
+UPDATE v2_thumbnails
+SET
+    accessed_at = unixepoch()
+WHERE product_id = :product_id
+    AND accessed_at < unixepoch() - 3600
+"#,
+        )?;
+
+        stmt.execute(named_params! {
+            ":product_id": product_id,
+        })?;
+        Ok(())
+    }
+
+    /// Removes many cached thumbnails.
+    pub fn remove_many(
+        connection: &Connection,
+        product_ids: impl Iterator<Item = ProductId>,
+    ) -> DBResult<()> {
+        let mut stmt = connection.prepare(
+            r#"
+DELETE FROM v2_thumbnails
+WHERE product_id IN rarray(?)
+"#,
+        )?;
+
+        let product_ids = Rc::new(product_ids.map(Value::from).collect::<Vec<_>>());
+        stmt.execute([product_ids])?;
+        Ok(())
+    }
+}
@@ -0,0 +1,209 @@
+use super::{
+    migration::{apply_pending, get_schema_version, get_schema_version_ddl, MIGRATIONS},
+    tables::{
+        v2::{AccountTable, ProductDownloadTable, ProductTable, SettingTable},
+        Table,
+    },
+};
+use crate::dlsite::product_id::ProductId;
+use rusqlite::Connection;
+
+fn migrate(connection: &mut Connection) {
+    connection.execute_batch(get_schema_version_ddl()).unwrap();
+
+    let version = get_schema_version(connection).unwrap();
+    apply_pending(connection, version).unwrap();
+
+    assert_eq!(
+        get_schema_version(connection).unwrap(),
+        MIGRATIONS.last().unwrap().version
+    );
+}
+
+fn columns(connection: &Connection, table: &str) -> Vec<String> {
+    let mut stmt = connection
+        .prepare("SELECT name FROM pragma_table_info(?1)")
+        .unwrap();
+    let columns = stmt
+        .query_map([table], |row| row.get(0))
+        .unwrap()
+        .collect::<rusqlite::Result<Vec<_>>>()
+        .unwrap();
+    columns
+}
+
+fn assert_latest_schema(connection: &Connection) {
+    let settings = columns(connection, "v2_settings");
+    assert!(settings.contains(&"folder_template".to_owned()));
+    assert!(settings.contains(&"scan_depth".to_owned()));
+
+    let downloads = columns(connection, "v2_product_downloads");
+    assert!(downloads.contains(&"missing_at".to_owned()));
+    assert!(downloads.contains(&"extraction_skip_reason".to_owned()));
+
+    let indexed_products = columns(connection, "v2_indexed_products");
+    assert!(indexed_products.contains(&"description".to_owned()));
+
+    for table in [
+        "v2_download_jobs",
+        "v2_product_details",
+        "v2_thumbnails",
+        "v2_product_manifests",
+        "v2_product_name_encodings",
+    ] {
+        assert!(!columns(connection, table).is_empty(), "{}", table);
+    }
+}
+
+#[test]
+fn migrate_empty_database() {
+    let mut connection = Connection::open_in_memory().unwrap();
+
+    migrate(&mut connection);
+    assert_latest_schema(&connection);
+
+    // applying again changes nothing
+    migrate(&mut connection);
+}
+
+#[test]
+fn migrate_baseline_database() {
+    let mut connection = Connection::open_in_memory().unwrap();
+
+    // the schema before migrations were introduced, with a pre-v2 account
+    for ddl in [
+        SettingTable::get_ddl(),
+        AccountTable::get_ddl(),
+        ProductTable::get_ddl(),
+        ProductDownloadTable::get_ddl(),
+    ] {
+        connection.execute_batch(ddl).unwrap();
+    }
+    connection
+        .execute_batch(
+            r#"
+CREATE TABLE accounts (
+    id INTEGER PRIMARY KEY NOT NULL,
+    username TEXT NOT NULL,
+    password TEXT NOT NULL,
+    memo TEXT
+);
+INSERT INTO accounts (username, password, memo) VALUES ('legacy', 'password', 'memo');
+INSERT INTO v2_settings (id, download_root_dir) VALUES (1, '/downloads');
+INSERT INTO v2_products (id, ty, age, title, thumbnail, group_id, group_name)
+VALUES ('RJ123456', 'Voice', 'All', 'title', '', 'RG12345', 'circle');
+INSERT INTO v2_product_downloads (product_id, path) VALUES ('RJ123456', '/downloads/RJ123456');
+"#,
+        )
+        .unwrap();
+
+    migrate(&mut connection);
+    assert_latest_schema(&connection);
+
+    let (username, memo) = connection
+        .query_row("SELECT username, memo FROM v2_accounts", [], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+        })
+        .unwrap();
+    assert_eq!((username.as_str(), memo.as_str()), ("legacy", "memo"));
+
+    let download_root_dir = connection
+        .query_row("SELECT download_root_dir FROM v2_settings", [], |row| {
+            row.get::<_, String>(0)
+        })
+        .unwrap();
+    assert_eq!(download_root_dir, "/downloads");
+
+    let indexed_title = connection
+        .query_row(
+            "SELECT title FROM v2_indexed_products WHERE id = 'RJ123456'",
+            [],
+            |row| row.get::<_, String>(0),
+        )
+        .unwrap();
+    assert_eq!(indexed_title, "title");
+
+    let path = connection
+        .query_row(
+            "SELECT path FROM v2_product_downloads WHERE product_id = 'RJ123456'",
+            [],
+            |row| row.get::<_, String>(0),
+        )
+        .unwrap();
+    assert_eq!(path, "/downloads/RJ123456");
+}
+
+#[test]
+fn migrate_legacy_product_ids() {
+    let mut connection = Connection::open_in_memory().unwrap();
+
+    for ddl in [
+        SettingTable::get_ddl(),
+        AccountTable::get_ddl(),
+        ProductTable::get_ddl(),
+        ProductDownloadTable::get_ddl(),
+    ] {
+        connection.execute_batch(ddl).unwrap();
+    }
+    connection
+        .execute_batch(
+            r#"
+INSERT INTO v2_products (id, ty, age, title, thumbnail, group_id, group_name)
+VALUES
+    ('rj123456', 'Voice', 'All', 'lowercase', '', 'RG12345', 'circle'),
+    ('rj000001', 'Voice', 'All', 'duplicate', '', 'RG12345', 'circle'),
+    ('RJ000001', 'Voice', 'All', 'canonical', '', 'RG12345', 'circle'),
+    ('not-a-product', 'Voice', 'All', 'invalid', '', 'RG12345', 'circle');
+INSERT INTO v2_product_downloads (product_id, path)
+VALUES
+    ('rj123456', '/downloads/RJ123456'),
+    ('not-a-product', '/downloads/not-a-product');
+"#,
+        )
+        .unwrap();
+
+    migrate(&mut connection);
+
+    let mut stmt = connection
+        .prepare("SELECT id, title FROM v2_products ORDER BY id")
+        .unwrap();
+    let products = stmt
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+        .unwrap()
+        .collect::<rusqlite::Result<Vec<(String, String)>>>()
+        .unwrap();
+    assert_eq!(
+        products,
+        vec![
+            ("RJ000001".to_owned(), "canonical".to_owned()),
+            ("RJ123456".to_owned(), "lowercase".to_owned()),
+        ]
+    );
+
+    let mut stmt = connection
+        .prepare("SELECT id FROM v2_indexed_products ORDER BY id")
+        .unwrap();
+    let indexed_ids = stmt
+        .query_map([], |row| row.get(0))
+        .unwrap()
+        .collect::<rusqlite::Result<Vec<String>>>()
+        .unwrap();
+    assert_eq!(
+        indexed_ids,
+        vec!["RJ000001".to_owned(), "RJ123456".to_owned()]
+    );
+
+    // the stored IDs load again, and the rows of the invalid one are gone
+    let download =
+        ProductDownloadTable::get_one(&connection, &ProductId::parse("RJ123456").unwrap())
+            .unwrap()
+            .unwrap();
+    assert_eq!(download.path.to_str(), Some("/downloads/RJ123456"));
+
+    let download_count = connection
+        .query_row("SELECT COUNT(*) FROM v2_product_downloads", [], |row| {
+            row.get::<_, i64>(0)
+        })
+        .unwrap();
+    assert_eq!(download_count, 1);
+}
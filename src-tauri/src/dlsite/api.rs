@@ -1,27 +1,38 @@
-use super::dto::{
-    DLsiteProduct, DLsiteProductFiles, DLsiteProductFromNonOwnerApi, DLsiteProductI18nString,
-    DLsiteProductListFromOwnerApi, DLsiteVoiceComicRequestInfo, DLsiteVoiceComicZipTree,
+use super::{
+    client::DLsiteClient,
+    dto::{
+        DLsiteProduct, DLsiteProductCreator, DLsiteProductCreatorRole, DLsiteProductFiles,
+        DLsiteProductFromNonOwnerApi, DLsiteProductI18nString, DLsiteProductListFromOwnerApi,
+        DLsiteProductMetadata, DLsiteVoiceComicRequestInfo, DLsiteVoiceComicZipTree,
+        DLsiteVoiceComicZipTreeItem,
+    },
+    endpoints::DLsiteEndpoints,
+    product_id::{DLsiteFloor, ProductId},
 };
 use anyhow::{anyhow, Context, Error};
 use chrono::{FixedOffset, NaiveDateTime, TimeZone};
 use lazy_static::lazy_static;
-use reqwest::{Client, ClientBuilder};
+use reqwest::StatusCode;
 use reqwest_cookie_store::{CookieStore, CookieStoreMutex, RawCookie};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use std::{
     collections::HashMap,
+    io::SeekFrom,
     path::{Path, PathBuf},
     sync::{
         atomic::{AtomicU64, Ordering},
         Arc,
     },
-    time::{Duration, UNIX_EPOCH},
+    time::UNIX_EPOCH,
 };
 use thiserror::Error;
 use tokio::{
-    fs::{create_dir_all, remove_dir_all, OpenOptions},
-    io::{AsyncWriteExt, BufWriter},
+    fs::{
+        create_dir_all, metadata, read, read_dir, remove_dir_all, remove_file, rename, write,
+        OpenOptions,
+    },
+    io::{AsyncSeekExt, AsyncWriteExt, BufWriter},
 };
-use url::Url;
 
 lazy_static! {
     static ref GROUP_NAME_SELECTOR_STR: &'static str = "#work_maker>tbody>tr>td>span>a";
@@ -38,402 +49,633 @@ pub enum LoginError {
     Other(#[from] Error),
 }
 
-pub async fn login(
-    username: impl AsRef<str>,
-    password: impl AsRef<str>,
-) -> Result<Arc<CookieStoreMutex>, LoginError> {
-    let cookie_store = Arc::new(CookieStoreMutex::new(CookieStore::default()));
-    let client = ClientBuilder::new()
-        .cookie_store(true)
-        .cookie_provider(cookie_store.clone())
-        .build()
-        .with_context(|| "[login]")
-        .with_context(|| "failed to create HTTP client")?;
-
-    client
-        .get("https://www.dlsite.com/maniax/login/=/skip_register/1")
-        .send()
-        .await
-        .with_context(|| "[login]")
-        .with_context(|| "request failed for `skip_register`")?;
-    client
-        .get("https://login.dlsite.com/login")
-        .send()
-        .await
-        .with_context(|| "[login]")
-        .with_context(|| "request failed for `fetch_initial_cookies`")?;
-
-    let res = client
-        .post("https://login.dlsite.com/login")
-        .form(&[
-            ("login_id", username.as_ref()),
-            ("password", password.as_ref()),
-            ("_token", &{
-                let cookie = cookie_store
-                    .lock()
-                    .unwrap()
-                    .get("login.dlsite.com", "/", "XSRF-TOKEN")
-                    .ok_or_else(|| anyhow!("cookie `XSRF-TOKEN` not found"))?
-                    .value()
-                    .to_owned();
-                cookie
-            }),
-        ])
-        .send()
-        .await
-        .with_context(|| "[login]")
-        .with_context(|| "request failed for `authenticate`")?;
-    let text = res
-        .text()
-        .await
-        .with_context(|| "[login]")
-        .with_context(|| "parse failed for `authenticate`")?;
+impl DLsiteClient {
+    /// Logs in with a fresh cookie store, returning the client bound to the logged in session.
+    pub async fn login(
+        endpoints: DLsiteEndpoints,
+        username: impl AsRef<str>,
+        password: impl AsRef<str>,
+    ) -> Result<Self, LoginError> {
+        let client = Self::new(
+            endpoints,
+            Arc::new(CookieStoreMutex::new(CookieStore::default())),
+        )
+        .with_context(|| "[login]")?;
+
+        client
+            .send(
+                client.api_get(
+                    client
+                        .endpoints()
+                        .www_url("/maniax/login/=/skip_register/1"),
+                ),
+            )
+            .await
+            .with_context(|| "[login]")
+            .with_context(|| "request failed for `skip_register`")?;
+        client
+            .send(client.api_get(client.endpoints().login_url("/login")))
+            .await
+            .with_context(|| "[login]")
+            .with_context(|| "request failed for `fetch_initial_cookies`")?;
+
+        let res = client
+            .send(
+                client
+                    .api_post(client.endpoints().login_url("/login"))
+                    .form(&[
+                        ("login_id", username.as_ref()),
+                        ("password", password.as_ref()),
+                        ("_token", &{
+                            let cookie = client
+                                .cookie_store()
+                                .lock()
+                                .unwrap()
+                                .get(
+                                    client.endpoints().login.host_str().unwrap_or_default(),
+                                    "/",
+                                    "XSRF-TOKEN",
+                                )
+                                .ok_or_else(|| anyhow!("cookie `XSRF-TOKEN` not found"))?
+                                .value()
+                                .to_owned();
+                            cookie
+                        }),
+                    ]),
+            )
+            .await
+            .with_context(|| "[login]")
+            .with_context(|| "request failed for `authenticate`")?;
+        let text = res
+            .text()
+            .await
+            .with_context(|| "[login]")
+            .with_context(|| "parse failed for `authenticate`")?;
 
-    if text.contains("ログインIDかパスワードが間違っています。") {
-        return Err(LoginError::WrongCredentials);
-    }
+        if text.contains("ログインIDかパスワードが間違っています。") {
+            return Err(LoginError::WrongCredentials);
+        }
 
-    Ok(cookie_store)
-}
+        Ok(client)
+    }
 
-/// Tests the given cookie store. Returns `true` if the cookie store contains valid credentials, `false` otherwise.
-pub async fn test_cookie_store(cookie_store: Arc<CookieStoreMutex>) -> Result<bool, Error> {
-    let client = ClientBuilder::new()
-        .cookie_store(true)
-        .cookie_provider(cookie_store)
-        .build()
-        .with_context(|| "[get_product_count]")
-        .with_context(|| "failed to create HTTP client")?;
-    let res = client
-        .get("https://play.dlsite.com/api/product_count")
-        .send()
-        .await
-        .with_context(|| "[get_product_count]")
-        .with_context(|| "request failed")?;
+    /// Tests the cookie store of the client. Returns `true` if the cookie store contains valid credentials, `false` otherwise.
+    pub async fn test_cookie_store(&self) -> Result<bool, Error> {
+        let res = self
+            .send(self.api_get(self.endpoints().play_url("/api/product_count")))
+            .await
+            .with_context(|| "[test_cookie_store]")
+            .with_context(|| "request failed")?;
 
-    let text = res.text().await?;
+        let text = res.text().await?;
 
-    Ok(!text.contains("status") && !text.contains("401"))
-}
+        Ok(!text.contains("status") && !text.contains("401"))
+    }
 
-pub async fn get_product_count(cookie_store: Arc<CookieStoreMutex>) -> Result<u32, Error> {
-    let client = ClientBuilder::new()
-        .cookie_store(true)
-        .cookie_provider(cookie_store)
-        .build()
-        .with_context(|| "[get_product_count]")
-        .with_context(|| "failed to create HTTP client")?;
-    let res = client
-        .get("https://play.dlsite.com/api/product_count")
-        .send()
-        .await
-        .with_context(|| "[get_product_count]")
-        .with_context(|| "request failed")?;
+    pub async fn get_product_count(&self) -> Result<u32, Error> {
+        let res = self
+            .send(self.api_get(self.endpoints().play_url("/api/product_count")))
+            .await
+            .with_context(|| "[get_product_count]")
+            .with_context(|| "request failed")?;
 
-    let product_count_map = res
-        .json::<HashMap<String, u32>>()
-        .await
-        .with_context(|| "[get_product_count]")
-        .with_context(|| "parse failed")?;
-
-    product_count_map
-        .get("user")
-        .cloned()
-        .ok_or_else(|| anyhow!("unable to get product count; `user` key not found in response"))
-        .with_context(|| "[get_product_count]")
-}
+        let product_count_map = res
+            .json::<HashMap<String, u32>>()
+            .await
+            .with_context(|| "[get_product_count]")
+            .with_context(|| "parse failed")?;
 
-pub async fn get_products(
-    cookie_store: Arc<CookieStoreMutex>,
-    page: u32,
-) -> Result<Vec<DLsiteProduct>, Error> {
-    let client = ClientBuilder::new()
-        .cookie_store(true)
-        .cookie_provider(cookie_store)
-        .build()
-        .with_context(|| format!("[get_products]"))
-        .with_context(|| format!("failed to create HTTP client for page `{}`", page))?;
-    let url = format!("https://play.dlsite.com/api/purchases?page={}", page);
-    let res = client
-        .get(&url)
-        .send()
-        .await
-        .with_context(|| format!("[get_products]"))
-        .with_context(|| format!("request failed for page `{}` with url: `{}`", page, url))?;
-    let product_list = res
-        .json::<DLsiteProductListFromOwnerApi>()
-        .await
-        .with_context(|| format!("[get_products]"))
-        .with_context(|| format!("parse failed for page `{}`", page))?;
-
-    fn get_localized_string(i18n: &DLsiteProductI18nString) -> Result<String, Error> {
-        i18n.japanese
-            .as_ref()
-            .or_else(|| i18n.english.as_ref())
-            .or_else(|| i18n.korean.as_ref())
-            .or_else(|| i18n.taiwanese.as_ref())
-            .or_else(|| i18n.chinese.as_ref())
+        product_count_map
+            .get("user")
             .cloned()
-            .ok_or_else(|| anyhow!("localized string is empty"))
+            .ok_or_else(|| anyhow!("unable to get product count; `user` key not found in response"))
+            .with_context(|| "[get_product_count]")
     }
 
-    let product_list = product_list
-        .works
+    pub async fn get_products(&self, page: u32) -> Result<Vec<DLsiteProduct>, Error> {
+        let url = self
+            .endpoints()
+            .play_url(&format!("/api/purchases?page={}", page));
+        let res = self
+            .send(self.api_get(&url))
+            .await
+            .with_context(|| format!("[get_products]"))
+            .with_context(|| format!("request failed for page `{}` with url: `{}`", page, url))?;
+        let product_list = res
+            .json::<DLsiteProductListFromOwnerApi>()
+            .await
+            .with_context(|| format!("[get_products]"))
+            .with_context(|| format!("parse failed for page `{}`", page))?;
+
+        fn get_localized_string(i18n: &DLsiteProductI18nString) -> Result<String, Error> {
+            i18n.japanese
+                .as_ref()
+                .or_else(|| i18n.english.as_ref())
+                .or_else(|| i18n.korean.as_ref())
+                .or_else(|| i18n.taiwanese.as_ref())
+                .or_else(|| i18n.chinese.as_ref())
+                .cloned()
+                .ok_or_else(|| anyhow!("localized string is empty"))
+        }
+
+        let product_list = product_list
+            .works
+            .into_iter()
+            .map(|product| -> Result<_, Error> {
+                Ok(DLsiteProduct {
+                    id: product.id.clone(),
+                    ty: product.ty,
+                    age: product.age,
+                    title: get_localized_string(&product.title).with_context(|| {
+                        format!("mapping `title` of product id `{}`", product.id)
+                    })?,
+                    thumbnail: product.icon.main,
+                    group_id: product.group.id,
+                    group_name: get_localized_string(&product.group.name).with_context(|| {
+                        format!("mapping `group_name` of product id `{}`", product.id)
+                    })?,
+                    registered_at: product.registered_at,
+                    metadata: None,
+                })
+            })
+            .collect::<Result<Vec<_>, Error>>()
+            .with_context(|| format!("[get_products]"))
+            .with_context(|| format!("mapping failed for page `{}`", page))?;
+
+        Ok(product_list)
+    }
+
+    pub async fn get_product_from_non_owner_api(
+        &self,
+        id: &ProductId,
+    ) -> Result<DLsiteProduct, Error> {
+        let product = self
+            .get_product_json::<DLsiteProductFromNonOwnerApi>(id)
+            .await
+            .with_context(|| format!("[get_product_from_non_owner_api]"))?
+            .ok_or_else(|| anyhow!("product list is empty"))?;
+        let utc_registered_at = match product.registered_at {
+            Some(registered_at) => {
+                let naive_registered_at =
+                    NaiveDateTime::parse_from_str(&registered_at, "%Y-%m-%d %H:%M:%S")?;
+                let jst_offset = FixedOffset::east_opt(9 * 3600).unwrap();
+                let jst_registered_at = jst_offset
+                    .from_local_datetime(&naive_registered_at)
+                    .single()
+                    .unwrap();
+                Some(jst_registered_at.to_utc())
+            }
+            None => None,
+        };
+
+        let creators = &product.creators;
+        let creators = [
+            (DLsiteProductCreatorRole::Creator, &creators.created_by),
+            (DLsiteProductCreatorRole::Scenario, &creators.scenario_by),
+            (DLsiteProductCreatorRole::Illust, &creators.illust_by),
+            (DLsiteProductCreatorRole::Voice, &creators.voice_by),
+            (DLsiteProductCreatorRole::Music, &creators.music_by),
+            (DLsiteProductCreatorRole::Other, &creators.other_by),
+        ]
         .into_iter()
-        .map(|product| -> Result<_, Error> {
-            Ok(DLsiteProduct {
-                id: product.id.clone(),
-                ty: product.ty,
-                age: product.age,
-                title: get_localized_string(&product.title)
-                    .with_context(|| format!("mapping `title` of product id `{}`", product.id))?,
-                thumbnail: product.icon.main,
-                group_id: product.group.id,
-                group_name: get_localized_string(&product.group.name).with_context(|| {
-                    format!("mapping `group_name` of product id `{}`", product.id)
-                })?,
-                registered_at: product.registered_at,
+        .flat_map(|(role, creators)| {
+            creators.iter().map(move |creator| DLsiteProductCreator {
+                role,
+                name: creator.name.clone(),
             })
         })
-        .collect::<Result<Vec<_>, Error>>()
-        .with_context(|| format!("[get_products]"))
-        .with_context(|| format!("mapping failed for page `{}`", page))?;
-
-    Ok(product_list)
-}
+        .collect();
+        let metadata = DLsiteProductMetadata {
+            description: product
+                .description
+                .filter(|description| !description.is_empty()),
+            series_id: product.series_id.filter(|series_id| !series_id.is_empty()),
+            series_name: product
+                .series_name
+                .filter(|series_name| !series_name.is_empty()),
+            file_format: product
+                .file_format
+                .filter(|file_format| !file_format.is_empty()),
+            page_count: product.page_number.filter(|page_count| *page_count != 0),
+            track_count: product
+                .track_list
+                .map(|track_list| track_list.len() as u32)
+                .filter(|track_count| *track_count != 0),
+            genres: product.genres.into_iter().map(|genre| genre.name).collect(),
+            creators,
+        };
 
-pub async fn get_product_from_non_owner_api(id: &str) -> Result<DLsiteProduct, Error> {
-    let url = format!(
-        "https://www.dlsite.com/maniax/api/=/product.json?workno={}",
-        id
-    );
-    let res = reqwest::get(&url)
-        .await
-        .with_context(|| format!("[get_product_from_non_owner_api]"))
-        .with_context(|| format!("request failed for product id `{}` with url: `{}`", id, url))?;
-    let products = res
-        .json::<Vec<DLsiteProductFromNonOwnerApi>>()
-        .await
-        .with_context(|| format!("[get_product_from_non_owner_api]"))
-        .with_context(|| format!("parse failed for product id `{}`", id))?;
+        Ok(DLsiteProduct {
+            id: id.clone(),
+            ty: product.ty,
+            age: product.age,
+            title: product.title,
+            thumbnail: if product.image.url.starts_with("http") {
+                product.image.url
+            } else {
+                format!("https:{}", product.image.url)
+            },
+            group_id: product.group_id,
+            group_name: product.group_name,
+            registered_at: utc_registered_at,
+            metadata: Some(metadata),
+        })
+    }
 
-    if products.is_empty() {
-        return Err(anyhow!("product list is empty"));
+    pub async fn get_product_files(&self, id: &ProductId) -> Result<DLsiteProductFiles, Error> {
+        self.get_product_json::<DLsiteProductFiles>(id)
+            .await
+            .with_context(|| format!("[get_product_files]"))?
+            .ok_or_else(|| anyhow!("product details list is empty"))
     }
 
-    let product = products.into_iter().next().unwrap();
-    let utc_registered_at = match product.registered_at {
-        Some(registered_at) => {
-            let naive_registered_at =
-                NaiveDateTime::parse_from_str(&registered_at, "%Y-%m-%d %H:%M:%S")?;
-            let jst_offset = FixedOffset::east_opt(9 * 3600).unwrap();
-            let jst_registered_at = jst_offset
-                .from_local_datetime(&naive_registered_at)
-                .single()
-                .unwrap();
-            Some(jst_registered_at.to_utc())
+    /// Requests `product.json` of the product, which answers an empty list on a wrong floor.
+    /// The floor the product was found on before is tried first, then the usual floor of
+    /// the prefix, then every other floor. Returns `None` if no floor has the product.
+    async fn get_product_json<T: DeserializeOwned>(
+        &self,
+        id: &ProductId,
+    ) -> Result<Option<T>, Error> {
+        let mut floors = vec![];
+
+        for floor in self
+            .product_floor(id)
+            .into_iter()
+            .chain([id.floor()])
+            .chain(DLsiteFloor::ALL)
+        {
+            if !floors.contains(&floor) {
+                floors.push(floor);
+            }
         }
-        None => None,
-    };
 
-    Ok(DLsiteProduct {
-        id: id.to_owned(),
-        ty: product.ty,
-        age: product.age,
-        title: product.title,
-        thumbnail: if product.image.url.starts_with("http") {
-            product.image.url
-        } else {
-            format!("https:{}", product.image.url)
-        },
-        group_id: product.group_id,
-        group_name: product.group_name,
-        registered_at: utc_registered_at,
-    })
-}
+        for floor in floors {
+            let url = self
+                .endpoints()
+                .www_url(&format!("/{}/api/=/product.json?workno={}", floor, id));
+            let res = self
+                .send(self.api_get(&url))
+                .await
+                .with_context(|| format!("[get_product_json]"))
+                .with_context(|| {
+                    format!("request failed for product id `{}` with url: `{}`", id, url)
+                })?;
+            let products = res
+                .json::<Vec<T>>()
+                .await
+                .with_context(|| format!("[get_product_json]"))
+                .with_context(|| format!("parse failed for product id `{}`", id))?;
 
-pub async fn get_product_files(id: &str) -> Result<DLsiteProductFiles, Error> {
-    let url = format!(
-        "https://www.dlsite.com/maniax/api/=/product.json?workno={}",
-        id
-    );
-    let res = reqwest::get(&url)
-        .await
-        .with_context(|| format!("[get_product_files]"))
-        .with_context(|| format!("request failed for product id `{}` with url: `{}`", id, url))?;
+            if let Some(product) = products.into_iter().next() {
+                self.set_product_floor(id, floor);
+                return Ok(Some(product));
+            }
+        }
 
-    let product_details_list = res
-        .json::<Vec<DLsiteProductFiles>>()
-        .await
-        .with_context(|| format!("[get_product_files]"))
-        .with_context(|| format!("parse failed for product id `{}`", id))?;
+        Ok(None)
+    }
 
-    if product_details_list.is_empty() {
-        return Err(anyhow!("product details list is empty"));
+    pub async fn get_voice_comic_request_info(
+        &self,
+        id: &ProductId,
+    ) -> Result<DLsiteVoiceComicRequestInfo, Error> {
+        let url = self
+            .endpoints()
+            .play_dl_url(&format!("/api/download/sign/cookie?workno={}", id));
+        let res = self
+            .send(self.api_get(&url))
+            .await
+            .with_context(|| format!("[get_voice_comic_request_info]"))
+            .with_context(|| format!("request failed for id `{}` with url: `{}`", id, url))?;
+        let request_info = res
+            .json::<DLsiteVoiceComicRequestInfo>()
+            .await
+            .with_context(|| format!("[get_voice_comic_request_info]"))
+            .with_context(|| format!("parse failed for id `{}`", id))?;
+
+        Ok(request_info)
     }
 
-    Ok(product_details_list.into_iter().next().unwrap())
-}
+    pub async fn get_voice_comic_zip_tree(
+        &self,
+        request_info: &DLsiteVoiceComicRequestInfo,
+    ) -> Result<DLsiteVoiceComicZipTree, Error> {
+        let cookie_url = &self.endpoints().play_dl;
+        let mut cookie_store_guard = self.cookie_store().lock().unwrap();
 
-pub async fn get_voice_comic_request_info(
-    cookie_store: Arc<CookieStoreMutex>,
-    id: &str,
-) -> Result<DLsiteVoiceComicRequestInfo, Error> {
-    let client = ClientBuilder::new()
-        .cookie_store(true)
-        .cookie_provider(cookie_store)
-        .build()
-        .with_context(|| format!("[get_voice_comic_request_info]"))
-        .with_context(|| format!("failed to create HTTP client for id `{}`", id))?;
-    let url = format!(
-        "https://play.dl.dlsite.com/api/download/sign/cookie?workno={}",
-        id
-    );
-    let res = client
-        .get(&url)
-        .send()
-        .await
-        .with_context(|| format!("[get_voice_comic_request_info]"))
-        .with_context(|| format!("request failed for id `{}` with url: `{}`", id, url))?;
-    let request_info = res
-        .json::<DLsiteVoiceComicRequestInfo>()
-        .await
-        .with_context(|| format!("[get_voice_comic_request_info]"))
-        .with_context(|| format!("parse failed for id `{}`", id))?;
+        for (key, value) in &request_info.cookies {
+            cookie_store_guard
+                .insert_raw(&RawCookie::new(key, value), cookie_url)
+                .unwrap();
+        }
 
-    Ok(request_info)
-}
+        drop(cookie_store_guard);
 
-pub async fn get_voice_comic_zip_tree(
-    cookie_store: Arc<CookieStoreMutex>,
-    request_info: &DLsiteVoiceComicRequestInfo,
-) -> Result<DLsiteVoiceComicZipTree, Error> {
-    let cookie_url = Url::parse("https://play.dl.dlsite.com").unwrap();
-    let mut cookie_store_guard = cookie_store.lock().unwrap();
-
-    for (key, value) in &request_info.cookies {
-        cookie_store_guard
-            .insert_raw(&RawCookie::new(key, value), &cookie_url)
-            .unwrap();
-    }
+        let url = format!("{}ziptree.json", &request_info.url);
+        let res = self
+            .send(self.api_get(&url))
+            .await
+            .with_context(|| format!("[get_voice_comic_zip_tree]"))
+            .with_context(|| {
+                format!(
+                    "request failed for request_info url `{}` with url: `{}`",
+                    &request_info.url, url
+                )
+            })?;
+        let zip_tree = res
+            .json::<DLsiteVoiceComicZipTree>()
+            .await
+            .with_context(|| format!("[get_voice_comic_zip_tree]"))
+            .with_context(|| {
+                format!("parse failed for request_info url `{}`", &request_info.url)
+            })?;
 
-    drop(cookie_store_guard);
+        Ok(zip_tree)
+    }
 
-    let client = ClientBuilder::new()
-        .cookie_store(true)
-        .cookie_provider(cookie_store)
-        .build()
-        .with_context(|| format!("[get_voice_comic_zip_tree]"))
-        .with_context(|| {
-            format!(
-                "failed to create HTTP client for request_info url `{}`",
-                &request_info.url
-            )
-        })?;
-    let url = format!("{}ziptree.json", &request_info.url);
-    let res = client
-        .get(&url)
-        .send()
+    /// Downloads every play file (mp4/vtt/images) described by the zip tree into the product folder
+    /// at `target_path`, keeping the original directory structure.
+    /// The progress is reported as the number of files.
+    pub async fn download_voice_comic_files(
+        &self,
+        id: &ProductId,
+        request_info: &DLsiteVoiceComicRequestInfo,
+        zip_tree: &DLsiteVoiceComicZipTree,
+        target_path: impl AsRef<Path>,
+        on_progress: impl Fn(u64, u64),
+    ) -> Result<(), Error> {
+        use futures::{StreamExt, TryStreamExt};
+
+        let mut play_files = Vec::new();
+        collect_voice_comic_play_files(zip_tree, &zip_tree.items, PathBuf::new(), &mut play_files)
+            .with_context(|| format!("[download_voice_comic_files]"))
+            .with_context(|| format!("failed to resolve play files for product id `{}`", id))?;
+
+        let total_file_count = play_files.len() as u64;
+        let target_path = prepare_target_path(target_path.as_ref()).await?;
+        let progress = AtomicU64::new(0);
+
+        on_progress(0, total_file_count);
+
+        futures::stream::iter(play_files.iter().map(|(relative_path, optimized_name)| {
+            let target_path = &target_path;
+            let progress = &progress;
+            let on_progress = &on_progress;
+
+            async move {
+                let url = format!("{}optimized/{}", &request_info.url, optimized_name);
+                download_voice_comic_play_file(self, &url, &target_path.join(relative_path))
+                    .await?;
+
+                let progress = progress.fetch_add(1, Ordering::SeqCst) + 1;
+                on_progress(progress, total_file_count);
+
+                Ok::<_, Error>(())
+            }
+        }))
+        .buffer_unordered(4)
+        .try_collect::<Vec<_>>()
         .await
-        .with_context(|| format!("[get_voice_comic_zip_tree]"))
+        .with_context(|| format!("[download_voice_comic_files]"))
         .with_context(|| {
             format!(
-                "request failed for request_info url `{}` with url: `{}`",
-                &request_info.url, url
+                "failed to download voice comic files for product id `{}`",
+                id
             )
         })?;
-    let zip_tree = res
-        .json::<DLsiteVoiceComicZipTree>()
-        .await
-        .with_context(|| format!("[get_voice_comic_zip_tree]"))
-        .with_context(|| format!("parse failed for request_info url `{}`", &request_info.url))?;
 
-    Ok(zip_tree)
-}
+        Ok(())
+    }
 
-// download vtt from: https://play.dlsite.fun/work/<PRODUCT-ID>/<HASH>.vtt
-// download mp4 from: https://play.dlsite.fun/work/<PRODUCT-ID>/<HASH>.mp4
+    /// Downloads the files of the product into the product folder at `target_path`.
+    /// The progress is reported in bytes.
+    pub async fn download_product_files(
+        &self,
+        id: &ProductId,
+        product_files: &DLsiteProductFiles,
+        target_path: impl AsRef<Path>,
+        on_progress: impl Fn(u64, u64),
+    ) -> Result<(), Error> {
+        let file_sizes = product_files
+            .files
+            .iter()
+            .map(|file| {
+                file.file_size
+                    .parse::<u64>()
+                    .with_context(|| format!("[download_product_files]"))
+                    .with_context(|| {
+                        format!(
+                            "invalid file size `{}` of file `{}`",
+                            file.file_size, file.file_name
+                        )
+                    })
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+        let total_file_size = file_sizes.iter().sum::<u64>();
+        let floor = self.product_floor(id).unwrap_or_else(|| id.floor());
+        let file_urls = resolve_file_urls(self.endpoints(), id, floor, product_files);
+        let target_path = prepare_target_path(target_path.as_ref()).await?;
 
-pub async fn download_product_files(
-    cookie_store: Arc<CookieStoreMutex>,
-    id: &str,
-    product_files: &DLsiteProductFiles,
-    base_path: impl AsRef<Path>,
-    on_progress: impl Fn(u64, u64),
+        let last_callback_time = AtomicU64::new(0);
+        let progress = AtomicU64::new(0);
+
+        let on_chunk_received = |chunk_received| {
+            let prev_progress = progress.fetch_add(chunk_received, Ordering::SeqCst);
+
+            let now = UNIX_EPOCH.elapsed().unwrap().as_secs();
+
+            if now == last_callback_time.swap(now, Ordering::SeqCst) {
+                return;
+            }
+
+            on_progress(prev_progress + chunk_received, total_file_size);
+        };
+
+        let results =
+            futures::future::try_join_all(file_urls.iter().enumerate().map(|(index, file_url)| {
+                download_single_file(
+                    self,
+                    file_url,
+                    &target_path,
+                    &product_files.files[index].file_name,
+                    file_sizes[index],
+                    on_chunk_received,
+                )
+            }))
+            .await;
+
+        if let Err(err) = results {
+            // partially downloaded files are kept, so the next attempt can continue from them
+            return Err(err)
+                .with_context(|| format!("[download_product_files]"))
+                .with_context(|| {
+                    format!("failed to download product files for product id `{}`", id)
+                })?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Walks the zip tree and collects `(relative path, optimized file name)` of every file item.
+fn collect_voice_comic_play_files(
+    zip_tree: &DLsiteVoiceComicZipTree,
+    items: &[DLsiteVoiceComicZipTreeItem],
+    parent_path: PathBuf,
+    play_files: &mut Vec<(PathBuf, String)>,
 ) -> Result<(), Error> {
-    let total_file_size = product_files.files.iter().fold(0, |acc, detail| {
-        acc + detail.file_size.parse::<u64>().unwrap()
-    });
-    let file_urls = resolve_file_urls(id, product_files);
-    let target_path = prepare_target_path(id, base_path).await?;
-
-    let last_callback_time = AtomicU64::new(0);
-    let progress = AtomicU64::new(0);
-    let client = ClientBuilder::new()
-        .cookie_store(true)
-        .cookie_provider(cookie_store)
-        .build()?;
-
-    let on_chunk_received = |chunk_received| {
-        let prev_progress = progress.fetch_add(chunk_received, Ordering::SeqCst);
-
-        let now = UNIX_EPOCH.elapsed().unwrap().as_secs();
-
-        if now == last_callback_time.swap(now, Ordering::SeqCst) {
-            return;
+    for item in items {
+        if item.name.is_empty()
+            || item.name == "."
+            || item.name == ".."
+            || item.name.contains(['/', '\\'])
+        {
+            return Err(anyhow!("invalid item name `{}` in zip tree", item.name));
         }
 
-        on_progress(prev_progress + chunk_received, total_file_size);
-    };
+        let path = parent_path.join(&item.name);
 
-    let results =
-        futures::future::try_join_all(file_urls.iter().enumerate().map(|(index, file_url)| {
-            download_single_file(
-                &client,
-                file_url,
-                &target_path,
-                &product_files.files[index].file_name,
-                on_chunk_received,
-            )
-        }))
-        .await;
+        if item.ty == "folder" {
+            collect_voice_comic_play_files(zip_tree, &item.children, path, play_files)?;
+            continue;
+        }
+
+        let play_file_key = match &item.play_file_key {
+            Some(play_file_key) => play_file_key,
+            None => continue,
+        };
+        let play_file = zip_tree
+            .play_files
+            .get(play_file_key)
+            .ok_or_else(|| anyhow!("play file `{}` not found in zip tree", play_file_key))?;
+        let optimized = play_file
+            .items
+            .get(&play_file.ty)
+            .or_else(|| play_file.items.values().next())
+            .ok_or_else(|| anyhow!("play file `{}` has no optimized item", play_file_key))?;
+
+        play_files.push((path, optimized.optimized.name.clone()));
+    }
 
-    if let Err(err) = results {
-        // ignore errors occurred during cleanup
-        remove_dir_all(&target_path).await.ok();
+    Ok(())
+}
 
-        return Err(err)
-            .with_context(|| format!("[download_product_files]"))
-            .with_context(|| {
-                format!("failed to download product files for product id `{}`", id)
-            })?;
+async fn download_voice_comic_play_file(
+    client: &DLsiteClient,
+    url: &str,
+    file_path: &Path,
+) -> Result<(), Error> {
+    if let Some(parent) = file_path.parent() {
+        create_dir_all(parent)
+            .await
+            .with_context(|| format!("[download_voice_comic_play_file]"))
+            .with_context(|| format!("failed to create directory `{}`", parent.display()))?;
+    }
+
+    let mut res = client
+        .send(client.http_client().get(url))
+        .await
+        .and_then(|res| Ok(res.error_for_status()?))
+        .with_context(|| format!("[download_voice_comic_play_file]"))
+        .with_context(|| format!("request failed with url: `{}`", url))?;
+    // play files have no size listed, so the one the server reports is verified, if any
+    let expected_size = res.content_length();
+    let file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(file_path)
+        .await
+        .with_context(|| format!("[download_voice_comic_play_file]"))
+        .with_context(|| format!("failed to open file `{}`", file_path.display()))?;
+    let mut writer = BufWriter::new(file);
+
+    while let Some(chunk) = res
+        .chunk()
+        .await
+        .with_context(|| format!("[download_voice_comic_play_file]"))
+        .with_context(|| format!("failed to receive chunk from url: `{}`", url))?
+    {
+        writer
+            .write_all(&chunk)
+            .await
+            .with_context(|| format!("[download_voice_comic_play_file]"))
+            .with_context(|| format!("failed to write chunk to file `{}`", file_path.display()))?;
+    }
+
+    writer
+        .flush()
+        .await
+        .with_context(|| format!("[download_voice_comic_play_file]"))
+        .with_context(|| format!("failed to flush file `{}`", file_path.display()))?;
+    drop(writer);
+
+    if let Some(expected_size) = expected_size {
+        if let Err(err) = verify_file_size(file_path, expected_size).await {
+            remove_file(file_path).await.ok();
+            return Err(err)
+                .with_context(|| format!("[download_voice_comic_play_file]"))
+                .with_context(|| format!("failed to download file `{}`", file_path.display()));
+        }
     }
 
     Ok(())
 }
 
-fn resolve_file_urls(id: &str, product_files: &DLsiteProductFiles) -> Vec<String> {
+/// Checks that the downloaded file has the expected size. Every download is verified here
+/// before the product is extracted or its manifest is recorded; DLsite provides no hash of
+/// the files, so the size is all there is to verify.
+async fn verify_file_size(path: &Path, expected_size: u64) -> Result<(), Error> {
+    let size = metadata(path)
+        .await
+        .with_context(|| format!("[verify_file_size]"))
+        .with_context(|| format!("failed to read metadata of `{}`", path.display()))?
+        .len();
+
+    if size != expected_size {
+        return Err(anyhow!(
+            "the size of the file is {} bytes, but {} bytes are expected",
+            size,
+            expected_size
+        ));
+    }
+
+    Ok(())
+}
+
+fn resolve_file_urls(
+    endpoints: &DLsiteEndpoints,
+    id: &ProductId,
+    fallback_floor: DLsiteFloor,
+    product_files: &DLsiteProductFiles,
+) -> Vec<String> {
+    // the floor reported by DLsite is preferred, since it can differ from the usual one of the prefix
+    let floor = product_files
+        .site_id
+        .as_deref()
+        .and_then(DLsiteFloor::from_site_id)
+        .unwrap_or(fallback_floor);
+
     match product_files.files.len() {
         0 => vec![],
-        1 => vec![format!(
-            "https://www.dlsite.com/maniax/download/=/product_id/{}.html",
-            id
-        )],
+        1 => vec![endpoints.www_url(&format!("/{}/download/=/product_id/{}.html", floor, id))],
         len => (1..=len)
             .map(|index| {
-                format!(
-                    "https://www.dlsite.com/maniax/download/=/number/{}/product_id/{}.html",
-                    index, id
-                )
+                endpoints.www_url(&format!(
+                    "/{}/download/=/number/{}/product_id/{}.html",
+                    floor, index, id
+                ))
             })
             .collect(),
     }
 }
 
-async fn prepare_target_path(id: &str, base_path: impl AsRef<Path>) -> Result<PathBuf, Error> {
-    let target_path = base_path.as_ref().join(id);
+/// Prepares the target path of the product. The existing target path is cleaned up,
+/// unless it contains partially downloaded files that can be resumed.
+async fn prepare_target_path(target_path: &Path) -> Result<PathBuf, Error> {
+    let target_path = target_path.to_owned();
 
-    if target_path.exists() {
+    if target_path.exists() && !has_partial_files(&target_path).await {
         remove_dir_all(&target_path)
             .await
             .with_context(|| format!("[prepare_target_path]"))
@@ -453,36 +695,138 @@ async fn prepare_target_path(id: &str, base_path: impl AsRef<Path>) -> Result<Pa
     Ok(target_path)
 }
 
+async fn has_partial_files(target_path: &Path) -> bool {
+    let mut entries = match read_dir(target_path).await {
+        Ok(entries) => entries,
+        Err(_) => return false,
+    };
+
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        if entry
+            .file_name()
+            .to_string_lossy()
+            .ends_with(PARTIAL_SIDECAR_EXTENSION)
+        {
+            return true;
+        }
+    }
+
+    false
+}
+
+pub const PARTIAL_FILE_EXTENSION: &str = ".part";
+pub const PARTIAL_SIDECAR_EXTENSION: &str = ".part.json";
+
+/// Checks whether the file is a partially downloaded file or its sidecar.
+pub fn is_partial_file_name(file_name: &str) -> bool {
+    file_name.ends_with(PARTIAL_FILE_EXTENSION) || file_name.ends_with(PARTIAL_SIDECAR_EXTENSION)
+}
+
+/// Sidecar of a `.part` file, written next to it while the file is being downloaded.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct PartialFileSidecar {
+    pub expected_size: u64,
+}
+
 async fn download_single_file(
-    client: &Client,
+    client: &DLsiteClient,
     url: &str,
     target_path: impl AsRef<Path>,
     file_name: &str,
+    expected_size: u64,
     mut on_chunk_received: impl FnMut(u64),
 ) -> Result<(), Error> {
     let file_path = target_path.as_ref().join(file_name);
+    let part_path = target_path
+        .as_ref()
+        .join(format!("{}{}", file_name, PARTIAL_FILE_EXTENSION));
+    let sidecar_path = target_path
+        .as_ref()
+        .join(format!("{}{}", file_name, PARTIAL_SIDECAR_EXTENSION));
+
+    // the file has been completed by a previous attempt
+    if !part_path.exists() {
+        if let Ok(metadata) = metadata(&file_path).await {
+            if metadata.len() == expected_size {
+                on_chunk_received(expected_size);
+                return Ok(());
+            }
+        }
+    }
+
+    let mut total_chunk_received =
+        read_partial_offset(&part_path, &sidecar_path, expected_size).await;
+
+    if total_chunk_received == 0 {
+        let sidecar = serde_json::to_vec(&PartialFileSidecar { expected_size })
+            .with_context(|| format!("[download_single_file]"))
+            .with_context(|| format!("failed to serialize sidecar of file `{}`", file_name))?;
+        write(&sidecar_path, sidecar)
+            .await
+            .with_context(|| format!("[download_single_file]"))
+            .with_context(|| format!("failed to write sidecar `{}`", sidecar_path.display()))?;
+    } else {
+        on_chunk_received(total_chunk_received);
+    }
+
     let file = OpenOptions::new()
         .write(true)
-        .create_new(true)
-        .open(&file_path)
+        .create(true)
+        .append(total_chunk_received != 0)
+        .truncate(total_chunk_received == 0)
+        .open(&part_path)
         .await
         .with_context(|| format!("[download_single_file]"))
-        .with_context(|| format!("failed to open file `{}`", file_path.display()))?;
+        .with_context(|| format!("failed to open file `{}`", part_path.display()))?;
 
     let mut writer = BufWriter::with_capacity(1 * 1024 * 1024, file);
-    let mut total_chunk_received: u64 = 0;
     let mut retry_count = 0;
     const MAX_RETRY_COUNT: u32 = 3;
 
     'req: loop {
-        let mut res = match client
-            .get(url)
-            .header("range", format!("bytes={}-", total_chunk_received))
-            .send()
+        if total_chunk_received == expected_size {
+            break;
+        }
+
+        // the scheduler retries failed requests by itself
+        let mut res = client
+            .send(
+                client
+                    .http_client()
+                    .get(url)
+                    .header("range", format!("bytes={}-", total_chunk_received)),
+            )
             .await
-        {
-            Ok(response) => response,
+            .and_then(|res| Ok(res.error_for_status()?))
+            .with_context(|| format!("[download_single_file]"))
+            .with_context(|| format!("failed to download file `{}`", file_path.display()))?;
+
+        if total_chunk_received != 0 && res.status() != StatusCode::PARTIAL_CONTENT {
+            // the server ignored the range; start over from the beginning
+            writer
+                .flush()
+                .await
+                .with_context(|| format!("[download_single_file]"))
+                .with_context(|| format!("failed to flush file `{}`", part_path.display()))?;
+            writer
+                .get_mut()
+                .set_len(0)
+                .await
+                .with_context(|| format!("[download_single_file]"))
+                .with_context(|| format!("failed to truncate file `{}`", part_path.display()))?;
+            writer
+                .get_mut()
+                .seek(SeekFrom::Start(0))
+                .await
+                .with_context(|| format!("[download_single_file]"))
+                .with_context(|| format!("failed to rewind file `{}`", part_path.display()))?;
+            total_chunk_received = 0;
+        }
+
+        while let Some(chunk) = match res.chunk().await {
+            Ok(chunk) => chunk,
             Err(err) => {
+                // the connection dropped in the middle; continue from the received bytes
                 retry_count += 1;
 
                 if MAX_RETRY_COUNT < retry_count {
@@ -493,38 +837,88 @@ async fn download_single_file(
                         })?;
                 }
 
-                // wait for 5 seconds
-                tokio::time::sleep(Duration::from_secs(5)).await;
-
-                continue;
-            }
-        };
-
-        while let Some(chunk) = match res.chunk().await {
-            Ok(chunk) => chunk,
-            Err(_) => {
+                tokio::time::sleep(client.scheduler().backoff(retry_count)).await;
                 continue 'req;
             }
         } {
+            if expected_size < total_chunk_received + chunk.len() as u64 {
+                drop(writer);
+                discard_partial_file(&part_path, &sidecar_path).await;
+                return Err(anyhow!(
+                    "received more than the expected size of {} bytes",
+                    expected_size
+                ))
+                .with_context(|| format!("[download_single_file]"))
+                .with_context(|| format!("failed to download file `{}`", file_path.display()));
+            }
+
             writer
                 .write_all(&chunk)
                 .await
                 .with_context(|| format!("[download_single_file]"))
                 .with_context(|| {
-                    format!("failed to write chunk to file `{}`", file_path.display())
+                    format!("failed to write chunk to file `{}`", part_path.display())
                 })?;
             total_chunk_received += chunk.len() as u64;
             on_chunk_received(chunk.len() as u64);
         }
 
-        writer
-            .flush()
-            .await
-            .with_context(|| format!("[download_single_file]"))
-            .with_context(|| format!("failed to flush file `{}`", file_path.display()))?;
-
         break;
     }
 
+    writer
+        .flush()
+        .await
+        .with_context(|| format!("[download_single_file]"))
+        .with_context(|| format!("failed to flush file `{}`", part_path.display()))?;
+    drop(writer);
+
+    if let Err(err) = verify_file_size(&part_path, expected_size).await {
+        discard_partial_file(&part_path, &sidecar_path).await;
+        return Err(err)
+            .with_context(|| format!("[download_single_file]"))
+            .with_context(|| format!("failed to download file `{}`", file_path.display()));
+    }
+
+    rename(&part_path, &file_path)
+        .await
+        .with_context(|| format!("[download_single_file]"))
+        .with_context(|| {
+            format!(
+                "failed to rename `{}` to `{}`",
+                part_path.display(),
+                file_path.display()
+            )
+        })?;
+    remove_file(&sidecar_path).await.ok();
+
     Ok(())
 }
+
+/// Removes a partial file that cannot be continued, so the next attempt starts over.
+async fn discard_partial_file(part_path: &Path, sidecar_path: &Path) {
+    remove_file(part_path).await.ok();
+    remove_file(sidecar_path).await.ok();
+}
+
+/// Returns the number of bytes already downloaded into the `.part` file,
+/// or `0` if the partial file is missing or does not belong to the expected file.
+async fn read_partial_offset(part_path: &Path, sidecar_path: &Path, expected_size: u64) -> u64 {
+    let sidecar = match read(sidecar_path).await {
+        Ok(sidecar) => sidecar,
+        Err(_) => return 0,
+    };
+    let sidecar = match serde_json::from_slice::<PartialFileSidecar>(&sidecar) {
+        Ok(sidecar) => sidecar,
+        Err(_) => return 0,
+    };
+
+    if sidecar.expected_size != expected_size {
+        return 0;
+    }
+
+    match metadata(part_path).await {
+        Ok(metadata) if metadata.len() <= expected_size => metadata.len(),
+        _ => 0,
+    }
+}
@@ -0,0 +1,148 @@
+use super::{
+    endpoints::DLsiteEndpoints,
+    product_id::{DLsiteFloor, ProductId},
+    scheduler::RequestScheduler,
+};
+use anyhow::{Context, Error};
+use parking_lot::Mutex;
+use reqwest::{
+    header::{HeaderMap, HeaderValue, ACCEPT_LANGUAGE},
+    Client, ClientBuilder, IntoUrl, RequestBuilder, Response, StatusCode,
+};
+use reqwest_cookie_store::{CookieStore, CookieStoreMutex};
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+const USER_AGENT: &str = concat!("dlsite-manager/", env!("CARGO_PKG_VERSION"));
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(15);
+/// Timeout of a single API request. File downloads are not bounded, since they may take hours.
+const API_TIMEOUT: Duration = Duration::from_secs(30);
+const POOL_IDLE_TIMEOUT: Duration = Duration::from_secs(90);
+const TCP_KEEPALIVE: Duration = Duration::from_secs(60);
+
+/// An HTTP client for DLsite, bound to a single cookie store (i.e. a single account).
+/// Every request made through it shares the same connection pool and settings.
+/// Cloning is cheap; clones share the pool and the cookie store.
+///
+/// The API methods are implemented in [`super::api`].
+#[derive(Clone)]
+pub struct DLsiteClient {
+    endpoints: DLsiteEndpoints,
+    cookie_store: Arc<CookieStoreMutex>,
+    client: Client,
+    scheduler: Arc<RequestScheduler>,
+    /// set once a response shows that the session is not logged in anymore
+    is_session_expired: Arc<AtomicBool>,
+    /// the floors the products were found on, as they can differ from the usual one of the prefix
+    product_floors: Arc<Mutex<HashMap<ProductId, DLsiteFloor>>>,
+}
+
+impl DLsiteClient {
+    pub fn new(
+        endpoints: DLsiteEndpoints,
+        cookie_store: Arc<CookieStoreMutex>,
+    ) -> Result<Self, Error> {
+        let mut default_headers = HeaderMap::new();
+        default_headers.insert(ACCEPT_LANGUAGE, HeaderValue::from_static("ja-JP,ja;q=0.9"));
+
+        let client = ClientBuilder::new()
+            .user_agent(USER_AGENT)
+            .default_headers(default_headers)
+            .connect_timeout(CONNECT_TIMEOUT)
+            .pool_idle_timeout(POOL_IDLE_TIMEOUT)
+            .tcp_keepalive(TCP_KEEPALIVE)
+            .cookie_store(true)
+            .cookie_provider(cookie_store.clone())
+            .build()
+            .with_context(|| format!("[DLsiteClient::new]"))
+            .with_context(|| format!("failed to create HTTP client"))?;
+
+        Ok(Self {
+            endpoints,
+            cookie_store,
+            client,
+            scheduler: RequestScheduler::shared(),
+            is_session_expired: Arc::new(AtomicBool::new(false)),
+            product_floors: Arc::new(Mutex::new(HashMap::new())),
+        })
+    }
+
+    /// Creates a client with an empty cookie store, for the APIs that do not need an account.
+    pub fn anonymous(endpoints: DLsiteEndpoints) -> Result<Self, Error> {
+        Self::new(
+            endpoints,
+            Arc::new(CookieStoreMutex::new(CookieStore::default())),
+        )
+    }
+
+    pub fn endpoints(&self) -> &DLsiteEndpoints {
+        &self.endpoints
+    }
+
+    pub fn cookie_store(&self) -> &Arc<CookieStoreMutex> {
+        &self.cookie_store
+    }
+
+    pub fn scheduler(&self) -> &RequestScheduler {
+        &self.scheduler
+    }
+
+    /// The underlying pooled client, for requests outside of the DLsite API such as images.
+    pub fn http_client(&self) -> &Client {
+        &self.client
+    }
+
+    /// Returns `true` if a request was rejected for the lack of a logged in session,
+    /// either with `401 Unauthorized` or by a redirect to the login page.
+    pub fn is_session_expired(&self) -> bool {
+        self.is_session_expired.load(Ordering::SeqCst)
+    }
+
+    /// Returns the floor the product was last found on, if any.
+    pub fn product_floor(&self, id: &ProductId) -> Option<DLsiteFloor> {
+        self.product_floors.lock().get(id).copied()
+    }
+
+    pub(super) fn set_product_floor(&self, id: &ProductId, floor: DLsiteFloor) {
+        self.product_floors.lock().insert(id.clone(), floor);
+    }
+
+    /// Sends the request through the scheduler, which rate limits and retries it.
+    pub(super) async fn send(&self, request: RequestBuilder) -> Result<Response, Error> {
+        let url = request
+            .try_clone()
+            .and_then(|request| request.build().ok())
+            .map(|request| request.url().clone());
+        let res = self.scheduler.send(request).await?;
+
+        let is_redirected_to_login = url.map_or(false, |url| {
+            url != *res.url()
+                && res
+                    .url()
+                    .as_str()
+                    .starts_with(&self.endpoints.login_url("/login"))
+        });
+
+        if res.status() == StatusCode::UNAUTHORIZED || is_redirected_to_login {
+            self.is_session_expired.store(true, Ordering::SeqCst);
+        }
+
+        Ok(res)
+    }
+
+    /// Starts a `GET` request to an API endpoint, bounded by the API timeout.
+    pub(super) fn api_get(&self, url: impl IntoUrl) -> RequestBuilder {
+        self.client.get(url).timeout(API_TIMEOUT)
+    }
+
+    /// Starts a `POST` request to an API endpoint, bounded by the API timeout.
+    pub(super) fn api_post(&self, url: impl IntoUrl) -> RequestBuilder {
+        self.client.post(url).timeout(API_TIMEOUT)
+    }
+}
@@ -1,3 +1,4 @@
+use super::product_id::ProductId;
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use std::{
@@ -7,7 +8,7 @@ use std::{
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct DLsiteProduct {
-    pub id: String,
+    pub id: ProductId,
     pub ty: DLsiteProductType,
     pub age: DLsiteProductAgeCategory,
     pub title: String,
@@ -15,6 +16,36 @@ pub struct DLsiteProduct {
     pub group_id: String,
     pub group_name: String,
     pub registered_at: Option<DateTime<Utc>>,
+    /// only available from the non-owner API
+    pub metadata: Option<DLsiteProductMetadata>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct DLsiteProductMetadata {
+    pub description: Option<String>,
+    pub series_id: Option<String>,
+    pub series_name: Option<String>,
+    pub file_format: Option<String>,
+    pub page_count: Option<u32>,
+    pub track_count: Option<u32>,
+    pub genres: Vec<String>,
+    pub creators: Vec<DLsiteProductCreator>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct DLsiteProductCreator {
+    pub role: DLsiteProductCreatorRole,
+    pub name: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DLsiteProductCreatorRole {
+    Creator,
+    Scenario,
+    Illust,
+    Voice,
+    Music,
+    Other,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -27,7 +58,7 @@ pub struct DLsiteProductListFromOwnerApi {
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct DLsiteProductFromOwnerApi {
     #[serde(alias = "workno")]
-    pub id: String,
+    pub id: ProductId,
     #[serde(rename = "work_type")]
     pub ty: DLsiteProductType,
     #[serde(rename = "age_category")]
@@ -86,6 +117,94 @@ pub struct DLsiteProductFromNonOwnerApi {
     /// Since this struct is only used for parsing JSON, it's okay to keep it as `String` here.
     /// This field will be parsed and converted to `DateTime<Utc>` later in `get_product` function.
     pub registered_at: Option<String>,
+    #[serde(rename = "intro_s", default)]
+    pub description: Option<String>,
+    #[serde(rename = "title_id", default)]
+    pub series_id: Option<String>,
+    #[serde(rename = "title_name", default)]
+    pub series_name: Option<String>,
+    #[serde(rename = "file_type_string", default)]
+    pub file_format: Option<String>,
+    #[serde(default, deserialize_with = "deserialize_lenient_u32")]
+    pub page_number: Option<u32>,
+    #[serde(default)]
+    pub track_list: Option<Vec<serde_json::Value>>,
+    #[serde(default, deserialize_with = "deserialize_lenient_vec")]
+    pub genres: Vec<DLsiteProductGenreFromNonOwnerApi>,
+    /// NOTE: the key is misspelled by DLsite
+    #[serde(
+        rename = "creaters",
+        default,
+        deserialize_with = "deserialize_lenient_creators"
+    )]
+    pub creators: DLsiteProductCreatorsFromNonOwnerApi,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DLsiteProductGenreFromNonOwnerApi {
+    pub name: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct DLsiteProductCreatorsFromNonOwnerApi {
+    #[serde(default)]
+    pub created_by: Vec<DLsiteProductCreatorFromNonOwnerApi>,
+    #[serde(default)]
+    pub scenario_by: Vec<DLsiteProductCreatorFromNonOwnerApi>,
+    #[serde(default)]
+    pub illust_by: Vec<DLsiteProductCreatorFromNonOwnerApi>,
+    #[serde(default)]
+    pub voice_by: Vec<DLsiteProductCreatorFromNonOwnerApi>,
+    #[serde(default)]
+    pub music_by: Vec<DLsiteProductCreatorFromNonOwnerApi>,
+    #[serde(default)]
+    pub other_by: Vec<DLsiteProductCreatorFromNonOwnerApi>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DLsiteProductCreatorFromNonOwnerApi {
+    pub name: String,
+}
+
+/// DLsite returns numbers as strings for some products, and empty strings for missing values.
+fn deserialize_lenient_u32<'de, D>(deserializer: D) -> std::result::Result<Option<u32>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Ok(match serde_json::Value::deserialize(deserializer)? {
+        serde_json::Value::Number(number) => number.as_u64().and_then(|n| u32::try_from(n).ok()),
+        serde_json::Value::String(str) => str.trim().parse().ok(),
+        _ => None,
+    })
+}
+
+/// DLsite returns `false` or `null` instead of an empty array for some products.
+fn deserialize_lenient_vec<'de, D, T>(deserializer: D) -> std::result::Result<Vec<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: serde::de::DeserializeOwned,
+{
+    Ok(match serde_json::Value::deserialize(deserializer)? {
+        value @ serde_json::Value::Array(_) => {
+            serde_json::from_value(value).map_err(serde::de::Error::custom)?
+        }
+        _ => vec![],
+    })
+}
+
+/// DLsite returns an empty array instead of an empty object when there is no creator.
+fn deserialize_lenient_creators<'de, D>(
+    deserializer: D,
+) -> std::result::Result<DLsiteProductCreatorsFromNonOwnerApi, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Ok(match serde_json::Value::deserialize(deserializer)? {
+        value @ serde_json::Value::Object(_) => {
+            serde_json::from_value(value).map_err(serde::de::Error::custom)?
+        }
+        _ => DLsiteProductCreatorsFromNonOwnerApi::default(),
+    })
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -328,6 +447,9 @@ impl<'de> Deserialize<'de> for DLsiteProductAgeCategory {
 pub struct DLsiteProductFiles {
     #[serde(rename = "contents")]
     pub files: Vec<DLsiteProductFile>,
+    /// the floor the product is sold on, e.g. `maniax`
+    #[serde(default)]
+    pub site_id: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -352,11 +474,16 @@ pub struct DLsiteVoiceComicZipTree {
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct DLsiteVoiceComicZipTreeItem {
+    /// either `folder` or `file`
     #[serde(rename = "type")]
     pub ty: String,
     pub name: String,
-    #[serde(rename = "hashname")]
-    pub play_file_key: String,
+    /// only present for `file` items
+    #[serde(rename = "hashname", default)]
+    pub play_file_key: Option<String>,
+    /// only present for `folder` items
+    #[serde(default)]
+    pub children: Vec<DLsiteVoiceComicZipTreeItem>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -0,0 +1,80 @@
+use anyhow::{Context, Error};
+use url::Url;
+
+/// Name of the environment variable that points every DLsite endpoint at a single base URL,
+/// e.g. a local mock server.
+pub const ENDPOINTS_OVERRIDE_ENV: &str = "DLSITE_MANAGER_ENDPOINTS";
+
+/// Base URLs of the DLsite services used by the API layer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DLsiteEndpoints {
+    /// `https://www.dlsite.com`; product pages, `product.json` and file downloads.
+    pub www: Url,
+    /// `https://login.dlsite.com`; authentication.
+    pub login: Url,
+    /// `https://play.dlsite.com`; purchases of the logged in account.
+    pub play: Url,
+    /// `https://play.dl.dlsite.com`; voice comic play files.
+    pub play_dl: Url,
+}
+
+impl Default for DLsiteEndpoints {
+    fn default() -> Self {
+        // SAFETY: below URLs are valid, so unwrap here is safe
+        Self {
+            www: Url::parse("https://www.dlsite.com").unwrap(),
+            login: Url::parse("https://login.dlsite.com").unwrap(),
+            play: Url::parse("https://play.dlsite.com").unwrap(),
+            play_dl: Url::parse("https://play.dl.dlsite.com").unwrap(),
+        }
+    }
+}
+
+impl DLsiteEndpoints {
+    /// Points every endpoint at the same base URL.
+    pub fn single(base_url: &str) -> Result<Self, Error> {
+        let base_url = Url::parse(base_url)
+            .with_context(|| format!("[DLsiteEndpoints::single]"))
+            .with_context(|| format!("invalid base url `{}`", base_url))?;
+
+        Ok(Self {
+            www: base_url.clone(),
+            login: base_url.clone(),
+            play: base_url.clone(),
+            play_dl: base_url,
+        })
+    }
+
+    /// Loads the endpoints, honoring the [`ENDPOINTS_OVERRIDE_ENV`] environment variable.
+    pub fn load() -> Result<Self, Error> {
+        match std::env::var(ENDPOINTS_OVERRIDE_ENV) {
+            Ok(base_url) if !base_url.is_empty() => Self::single(&base_url),
+            _ => Ok(Self::default()),
+        }
+    }
+
+    pub fn www_url(&self, path: &str) -> String {
+        join(&self.www, path)
+    }
+
+    pub fn login_url(&self, path: &str) -> String {
+        join(&self.login, path)
+    }
+
+    pub fn play_url(&self, path: &str) -> String {
+        join(&self.play, path)
+    }
+
+    pub fn play_dl_url(&self, path: &str) -> String {
+        join(&self.play_dl, path)
+    }
+}
+
+/// Joins the path to the base URL, keeping the path of the base URL if any.
+fn join(base_url: &Url, path: &str) -> String {
+    format!(
+        "{}/{}",
+        base_url.as_str().trim_end_matches('/'),
+        path.trim_start_matches('/')
+    )
+}
@@ -0,0 +1,12 @@
+//! Helpers shared by the tests of the API layer and of the services.
+
+use super::product_id::ProductId;
+
+pub fn product_id(id: &str) -> ProductId {
+    ProductId::parse(id).unwrap()
+}
+
+/// Data of the size that differs from byte to byte, so that a misplaced chunk is noticed.
+pub fn file_data(size: usize) -> Vec<u8> {
+    (0..size).map(|index| (index % 251) as u8).collect()
+}
@@ -0,0 +1,449 @@
+//! A local server emulating the parts of DLsite used by the API layer, for tests only.
+//! Every endpoint is served from a single origin, so it is used with [`DLsiteEndpoints::single`].
+
+use super::{endpoints::DLsiteEndpoints, product_id::ProductId};
+use axum::{
+    extract::{Query, State},
+    http::{header, HeaderMap, StatusCode, Uri},
+    response::{IntoResponse, Response},
+    routing::get,
+    Form, Json, Router,
+};
+use serde::Deserialize;
+use serde_json::json;
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc, Mutex,
+};
+use tokio::{net::TcpListener, task::JoinHandle};
+
+pub const MOCK_LOGIN_ID: &str = "mock-user";
+pub const MOCK_PASSWORD: &str = "mock-password";
+/// the content of every thumbnail
+pub const MOCK_THUMBNAIL: &[u8] = b"mock-thumbnail";
+
+const XSRF_TOKEN: &str = "mock-xsrf-token";
+const SESSION_COOKIE_NAME: &str = "__DLsite_SID";
+const SESSION_COOKIE_PREFIX: &str = "mock-session";
+const PURCHASES_PAGE_LIMIT: usize = 50;
+
+#[derive(Debug, Clone)]
+pub struct MockProduct {
+    pub id: String,
+    pub title: String,
+    pub work_type: String,
+    /// the floor the product is sold on; downloads are only served on it
+    pub site_id: String,
+    pub group_id: String,
+    pub group_name: String,
+    pub genres: Vec<String>,
+    pub voice_actors: Vec<String>,
+    pub files: Vec<MockFile>,
+}
+
+#[derive(Debug, Clone)]
+pub struct MockFile {
+    pub name: String,
+    pub data: Vec<u8>,
+}
+
+impl MockProduct {
+    pub fn new(id: impl Into<String>, title: impl Into<String>) -> Self {
+        let id = id.into();
+        let site_id = ProductId::parse(&id)
+            .map(|id| id.floor().as_str())
+            .unwrap_or("maniax")
+            .to_owned();
+
+        Self {
+            id,
+            title: title.into(),
+            work_type: "SOU".to_owned(),
+            site_id,
+            group_id: "RG00001".to_owned(),
+            group_name: "Mock Circle".to_owned(),
+            genres: vec![],
+            voice_actors: vec![],
+            files: vec![],
+        }
+    }
+
+    pub fn with_site_id(mut self, site_id: impl Into<String>) -> Self {
+        self.site_id = site_id.into();
+        self
+    }
+
+    pub fn with_genre(mut self, genre: impl Into<String>) -> Self {
+        self.genres.push(genre.into());
+        self
+    }
+
+    pub fn with_voice_actor(mut self, name: impl Into<String>) -> Self {
+        self.voice_actors.push(name.into());
+        self
+    }
+
+    pub fn with_file(mut self, name: impl Into<String>, data: Vec<u8>) -> Self {
+        self.files.push(MockFile {
+            name: name.into(),
+            data,
+        });
+        self
+    }
+}
+
+struct MockState {
+    products: Vec<MockProduct>,
+    range_requests: Mutex<Vec<String>>,
+    throttled_requests: AtomicUsize,
+    /// bumped to expire every session logged in so far
+    session_generation: AtomicUsize,
+    product_count_requests: AtomicUsize,
+}
+
+impl MockState {
+    fn session(&self) -> String {
+        format!(
+            "{}-{}",
+            SESSION_COOKIE_PREFIX,
+            self.session_generation.load(Ordering::SeqCst)
+        )
+    }
+
+    fn has_session(&self, headers: &HeaderMap) -> bool {
+        has_cookie(headers, SESSION_COOKIE_NAME, &self.session())
+    }
+}
+
+/// A running mock server. It is shut down when dropped.
+pub struct MockDLsite {
+    endpoints: DLsiteEndpoints,
+    state: Arc<MockState>,
+    server: JoinHandle<()>,
+}
+
+impl MockDLsite {
+    /// Starts the server on a random local port, serving the given products as the purchases
+    /// of the [`MOCK_LOGIN_ID`] account.
+    pub async fn start(products: Vec<MockProduct>) -> Self {
+        let state = Arc::new(MockState {
+            products,
+            range_requests: Mutex::new(vec![]),
+            throttled_requests: AtomicUsize::new(0),
+            session_generation: AtomicUsize::new(0),
+            product_count_requests: AtomicUsize::new(0),
+        });
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let router = Router::new()
+            .route("/login", get(login_page).post(login))
+            .route("/api/product_count", get(product_count))
+            .route("/api/purchases", get(purchases))
+            .fallback(floor_routes)
+            .with_state(state.clone());
+        let server = tokio::spawn(async move {
+            axum::serve(listener, router).await.unwrap();
+        });
+
+        Self {
+            endpoints: DLsiteEndpoints::single(&format!("http://{}", address)).unwrap(),
+            state,
+            server,
+        }
+    }
+
+    pub fn endpoints(&self) -> &DLsiteEndpoints {
+        &self.endpoints
+    }
+
+    /// Makes the next `count` requests to `product.json` fail with `429 Too Many Requests`.
+    pub fn throttle_next(&self, count: usize) {
+        self.state.throttled_requests.store(count, Ordering::SeqCst);
+    }
+
+    /// Expires every session logged in so far, as if they timed out.
+    pub fn expire_sessions(&self) {
+        self.state.session_generation.fetch_add(1, Ordering::SeqCst);
+    }
+
+    /// Returns the number of requests to `product_count`, which is also how sessions are tested.
+    pub fn product_count_requests(&self) -> usize {
+        self.state.product_count_requests.load(Ordering::SeqCst)
+    }
+
+    /// Returns the `Range` headers of every download request received so far.
+    pub fn range_requests(&self) -> Vec<String> {
+        self.state.range_requests.lock().unwrap().clone()
+    }
+}
+
+impl Drop for MockDLsite {
+    fn drop(&mut self) {
+        self.server.abort();
+    }
+}
+
+fn has_cookie(headers: &HeaderMap, name: &str, value: &str) -> bool {
+    let expected = format!("{}={}", name, value);
+
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|cookie| cookie.to_str().ok())
+        .flat_map(|cookie| cookie.split(';'))
+        .any(|pair| pair.trim() == expected)
+}
+
+async fn login_page() -> impl IntoResponse {
+    (
+        [(
+            header::SET_COOKIE,
+            format!("XSRF-TOKEN={}; Path=/", XSRF_TOKEN),
+        )],
+        "login",
+    )
+}
+
+#[derive(Deserialize)]
+struct LoginForm {
+    login_id: String,
+    password: String,
+    _token: String,
+}
+
+async fn login(
+    State(state): State<Arc<MockState>>,
+    headers: HeaderMap,
+    Form(form): Form<LoginForm>,
+) -> Response {
+    if form._token != XSRF_TOKEN || !has_cookie(&headers, "XSRF-TOKEN", XSRF_TOKEN) {
+        // the status code DLsite uses for an expired XSRF token
+        return (StatusCode::from_u16(419).unwrap(), "page expired").into_response();
+    }
+
+    if form.login_id != MOCK_LOGIN_ID || form.password != MOCK_PASSWORD {
+        return "<p>ログインIDかパスワードが間違っています。</p>".into_response();
+    }
+
+    (
+        [(
+            header::SET_COOKIE,
+            format!("{}={}; Path=/", SESSION_COOKIE_NAME, state.session()),
+        )],
+        "ok",
+    )
+        .into_response()
+}
+
+async fn product_count(State(state): State<Arc<MockState>>, headers: HeaderMap) -> Response {
+    state.product_count_requests.fetch_add(1, Ordering::SeqCst);
+
+    if !state.has_session(&headers) {
+        return Json(json!({ "status": 401 })).into_response();
+    }
+
+    Json(json!({ "user": state.products.len() })).into_response()
+}
+
+#[derive(Deserialize)]
+struct PurchasesQuery {
+    page: Option<usize>,
+}
+
+async fn purchases(
+    State(state): State<Arc<MockState>>,
+    headers: HeaderMap,
+    Query(query): Query<PurchasesQuery>,
+) -> Response {
+    if !state.has_session(&headers) {
+        return (StatusCode::UNAUTHORIZED, Json(json!({ "status": 401 }))).into_response();
+    }
+
+    // the thumbnails are served by the mock as well, so that nothing reaches the real DLsite
+    let origin = headers
+        .get(header::HOST)
+        .and_then(|host| host.to_str().ok())
+        .unwrap_or_default();
+    let offset = query.page.unwrap_or(1).saturating_sub(1) * PURCHASES_PAGE_LIMIT;
+    let works = state
+        .products
+        .iter()
+        .skip(offset)
+        .take(PURCHASES_PAGE_LIMIT)
+        .map(|product| {
+            json!({
+                "workno": product.id,
+                "work_type": product.work_type,
+                "age_category": 3,
+                "name": { "ja_JP": product.title },
+                "work_files": { "main": format!("http://{}/img/{}_img_main.jpg", origin, product.id) },
+                "maker": { "id": product.group_id, "name": { "ja_JP": product.group_name } },
+                "regist_date": "2024-01-02T03:00:00.000000Z",
+            })
+        })
+        .collect::<Vec<_>>();
+
+    Json(json!({
+        "limit": PURCHASES_PAGE_LIMIT,
+        "offset": offset,
+        "works": works,
+    }))
+    .into_response()
+}
+
+#[derive(Deserialize)]
+struct ProductJsonQuery {
+    workno: String,
+}
+
+/// Routes the requests under a floor, e.g. `/maniax/...`. They are routed by hand,
+/// since the floor is a leading path parameter overlapping the other routes.
+async fn floor_routes(
+    State(state): State<Arc<MockState>>,
+    headers: HeaderMap,
+    uri: Uri,
+) -> Response {
+    let segments = uri
+        .path()
+        .trim_start_matches('/')
+        .split('/')
+        .collect::<Vec<_>>();
+
+    match segments.as_slice() {
+        ["img", file] => thumbnail(&state, file),
+        [_, "login", "=", "skip_register", "1"] => "ok".into_response(),
+        [floor, "api", "=", "product.json"] => {
+            match Query::<ProductJsonQuery>::try_from_uri(&uri) {
+                Ok(Query(query)) => product_json(&state, floor, &query),
+                Err(err) => err.into_response(),
+            }
+        }
+        [floor, "download", "=", "product_id", file] => download(&state, &headers, floor, file, 1),
+        [floor, "download", "=", "number", number, "product_id", file] => match number.parse() {
+            Ok(number) => download(&state, &headers, floor, file, number),
+            Err(_) => StatusCode::NOT_FOUND.into_response(),
+        },
+        _ => StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+/// Serves `product.json`, which lists the product only on the floor it is sold on.
+fn product_json(state: &MockState, floor: &str, query: &ProductJsonQuery) -> Response {
+    let is_throttled = state
+        .throttled_requests
+        .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |count| {
+            count.checked_sub(1)
+        })
+        .is_ok();
+
+    if is_throttled {
+        return (
+            StatusCode::TOO_MANY_REQUESTS,
+            [(header::RETRY_AFTER, "0")],
+            "too many requests",
+        )
+            .into_response();
+    }
+
+    let product = match state
+        .products
+        .iter()
+        .find(|product| product.id == query.workno && product.site_id == floor)
+    {
+        Some(product) => product,
+        None => return Json(json!([])).into_response(),
+    };
+
+    Json(json!([{
+        "work_type": product.work_type,
+        "site_id": product.site_id,
+        "age_category": 3,
+        "work_name": product.title,
+        "image_main": { "url": format!("//img.dlsite.jp/{}_img_main.jpg", product.id) },
+        "maker_id": product.group_id,
+        "maker_name": product.group_name,
+        "regist_date": "2024-01-02 12:00:00",
+        "genres": product.genres.iter().map(|name| json!({ "name": name })).collect::<Vec<_>>(),
+        "creaters": {
+            "voice_by": product.voice_actors.iter().map(|name| json!({ "name": name })).collect::<Vec<_>>(),
+        },
+        "contents": product.files.iter().map(|file| json!({
+            "file_name": file.name,
+            "file_size": file.data.len().to_string(),
+        })).collect::<Vec<_>>(),
+    }]))
+    .into_response()
+}
+
+fn thumbnail(state: &MockState, file: &str) -> Response {
+    let is_known = file.strip_suffix("_img_main.jpg").map_or(false, |id| {
+        state.products.iter().any(|product| product.id == id)
+    });
+
+    if !is_known {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+
+    ([(header::CONTENT_TYPE, "image/jpeg")], MOCK_THUMBNAIL).into_response()
+}
+
+/// Serves the `number`th (1-based) file of the product, honoring `Range: bytes=N-` headers.
+fn download(
+    state: &MockState,
+    headers: &HeaderMap,
+    floor: &str,
+    file: &str,
+    number: usize,
+) -> Response {
+    if !state.has_session(headers) {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+
+    let file = file
+        .strip_suffix(".html")
+        .and_then(|id| {
+            state
+                .products
+                .iter()
+                .find(|product| product.id == id && product.site_id == floor)
+        })
+        .and_then(|product| product.files.get(number.wrapping_sub(1)));
+    let file = match file {
+        Some(file) => file,
+        None => return StatusCode::NOT_FOUND.into_response(),
+    };
+
+    let range = match headers
+        .get(header::RANGE)
+        .and_then(|range| range.to_str().ok())
+    {
+        Some(range) => range,
+        None => return file.data.clone().into_response(),
+    };
+
+    state.range_requests.lock().unwrap().push(range.to_owned());
+
+    let start = match range
+        .strip_prefix("bytes=")
+        .and_then(|range| range.strip_suffix('-'))
+        .and_then(|start| start.parse::<usize>().ok())
+    {
+        Some(start) if start < file.data.len() => start,
+        _ => return StatusCode::RANGE_NOT_SATISFIABLE.into_response(),
+    };
+
+    (
+        StatusCode::PARTIAL_CONTENT,
+        [(
+            header::CONTENT_RANGE,
+            format!(
+                "bytes {}-{}/{}",
+                start,
+                file.data.len() - 1,
+                file.data.len()
+            ),
+        )],
+        file.data[start..].to_vec(),
+    )
+        .into_response()
+}
@@ -1,2 +1,13 @@
 pub mod api;
+pub mod client;
 pub mod dto;
+pub mod endpoints;
+pub mod product_id;
+pub mod scheduler;
+
+#[cfg(test)]
+pub mod fixtures;
+#[cfg(test)]
+pub mod mock_server;
+#[cfg(test)]
+mod tests;
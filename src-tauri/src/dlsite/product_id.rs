@@ -0,0 +1,234 @@
+use rusqlite::{
+    types::{FromSql, FromSqlError, FromSqlResult, ToSqlOutput, Value, ValueRef},
+    ToSql,
+};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::{fmt::Display, str::FromStr};
+use thiserror::Error;
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum ProductIdError {
+    #[error("the product id `{id}` has an unknown prefix")]
+    UnknownPrefix { id: String },
+    #[error("the product id `{id}` must have a number of 6 or 8 digits")]
+    InvalidNumber { id: String },
+}
+
+/// The prefix of a product ID, which tells the kind of the product.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ProductIdPrefix {
+    /// doujin works
+    RJ,
+    /// doujin works translated to English
+    RE,
+    /// books and comics
+    BJ,
+    /// commercial PC software and games
+    VJ,
+}
+
+impl ProductIdPrefix {
+    pub const ALL: [ProductIdPrefix; 4] = [
+        ProductIdPrefix::RJ,
+        ProductIdPrefix::RE,
+        ProductIdPrefix::BJ,
+        ProductIdPrefix::VJ,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ProductIdPrefix::RJ => "RJ",
+            ProductIdPrefix::RE => "RE",
+            ProductIdPrefix::BJ => "BJ",
+            ProductIdPrefix::VJ => "VJ",
+        }
+    }
+
+    /// The floor the products of this prefix are usually sold on.
+    /// All-ages and female-oriented works are sold on other floors under the same prefix,
+    /// which is only known from the product itself; see [`DLsiteFloor::from_site_id`].
+    pub fn default_floor(&self) -> DLsiteFloor {
+        match self {
+            ProductIdPrefix::RJ | ProductIdPrefix::RE => DLsiteFloor::Maniax,
+            ProductIdPrefix::BJ => DLsiteFloor::Books,
+            ProductIdPrefix::VJ => DLsiteFloor::Pro,
+        }
+    }
+}
+
+/// A section of the DLsite site. Every floor has its own URL prefix, e.g. `/maniax/`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DLsiteFloor {
+    Home,
+    Maniax,
+    Girls,
+    GirlsPro,
+    Bl,
+    BlPro,
+    Pro,
+    Soft,
+    Books,
+    Comic,
+}
+
+impl DLsiteFloor {
+    pub const ALL: [DLsiteFloor; 10] = [
+        DLsiteFloor::Home,
+        DLsiteFloor::Maniax,
+        DLsiteFloor::Girls,
+        DLsiteFloor::GirlsPro,
+        DLsiteFloor::Bl,
+        DLsiteFloor::BlPro,
+        DLsiteFloor::Pro,
+        DLsiteFloor::Soft,
+        DLsiteFloor::Books,
+        DLsiteFloor::Comic,
+    ];
+
+    /// The URL path segment of the floor, which is also the `site_id` reported by `product.json`.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DLsiteFloor::Home => "home",
+            DLsiteFloor::Maniax => "maniax",
+            DLsiteFloor::Girls => "girls",
+            DLsiteFloor::GirlsPro => "girls-pro",
+            DLsiteFloor::Bl => "bl",
+            DLsiteFloor::BlPro => "bl-pro",
+            DLsiteFloor::Pro => "pro",
+            DLsiteFloor::Soft => "soft",
+            DLsiteFloor::Books => "books",
+            DLsiteFloor::Comic => "comic",
+        }
+    }
+
+    pub fn from_site_id(site_id: &str) -> Option<Self> {
+        Some(match site_id {
+            "home" => DLsiteFloor::Home,
+            "maniax" => DLsiteFloor::Maniax,
+            "girls" => DLsiteFloor::Girls,
+            "girls-pro" => DLsiteFloor::GirlsPro,
+            "bl" => DLsiteFloor::Bl,
+            "bl-pro" => DLsiteFloor::BlPro,
+            "pro" => DLsiteFloor::Pro,
+            "soft" => DLsiteFloor::Soft,
+            "books" => DLsiteFloor::Books,
+            "comic" => DLsiteFloor::Comic,
+            _ => return None,
+        })
+    }
+}
+
+impl Display for DLsiteFloor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// A DLsite product ID such as `RJ123456` or `RJ01234567`; a known prefix followed by
+/// a number of 6 or 8 digits. As it only consists of ASCII letters and digits,
+/// it is safe to be used as a file name.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ProductId(String);
+
+impl ProductId {
+    pub fn parse(id: &str) -> Result<Self, ProductIdError> {
+        let prefix = ProductIdPrefix::ALL
+            .into_iter()
+            .find(|prefix| {
+                id.get(..2)
+                    .map_or(false, |head| head.eq_ignore_ascii_case(prefix.as_str()))
+            })
+            .ok_or_else(|| ProductIdError::UnknownPrefix { id: id.to_owned() })?;
+        let number = &id[2..];
+
+        if !matches!(number.len(), 6 | 8) || !number.bytes().all(|byte| byte.is_ascii_digit()) {
+            return Err(ProductIdError::InvalidNumber { id: id.to_owned() });
+        }
+
+        Ok(Self(format!("{}{}", prefix.as_str(), number)))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    pub fn into_string(self) -> String {
+        self.0
+    }
+
+    pub fn prefix(&self) -> ProductIdPrefix {
+        // SAFETY: the prefix is validated on parsing, so unwrap here is safe
+        ProductIdPrefix::ALL
+            .into_iter()
+            .find(|prefix| self.0.starts_with(prefix.as_str()))
+            .unwrap()
+    }
+
+    /// The floor the product is usually sold on. See [`ProductIdPrefix::default_floor`].
+    pub fn floor(&self) -> DLsiteFloor {
+        self.prefix().default_floor()
+    }
+}
+
+impl FromStr for ProductId {
+    type Err = ProductIdError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse(s)
+    }
+}
+
+impl AsRef<str> for ProductId {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Display for ProductId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl From<ProductId> for String {
+    fn from(id: ProductId) -> Self {
+        id.0
+    }
+}
+
+impl From<ProductId> for Value {
+    fn from(id: ProductId) -> Self {
+        Value::Text(id.0)
+    }
+}
+
+impl Serialize for ProductId {
+    fn serialize<S>(&self, serializer: S) -> core::result::Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(&self.0)
+    }
+}
+
+impl<'de> Deserialize<'de> for ProductId {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let str = String::deserialize(deserializer)?;
+        Self::parse(&str).map_err(serde::de::Error::custom)
+    }
+}
+
+impl ToSql for ProductId {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        self.0.to_sql()
+    }
+}
+
+impl FromSql for ProductId {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        Self::parse(value.as_str()?).map_err(|err| FromSqlError::Other(Box::new(err)))
+    }
+}
@@ -0,0 +1,198 @@
+use anyhow::{anyhow, Context, Error};
+use chrono::{DateTime, Utc};
+use lazy_static::lazy_static;
+use log::warn;
+use parking_lot::Mutex;
+use rand::Rng;
+use reqwest::{
+    header::{HeaderMap, RETRY_AFTER},
+    RequestBuilder, Response, StatusCode,
+};
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::sync::Semaphore;
+
+lazy_static! {
+    /// Every DLsite request goes through this scheduler, so that the limits apply across accounts.
+    static ref SHARED_SCHEDULER: Arc<RequestScheduler> =
+        Arc::new(RequestScheduler::new(RequestSchedulerConfig::default()));
+}
+
+#[derive(Debug, Clone)]
+pub struct RequestSchedulerConfig {
+    /// The number of requests that can be sent at once after being idle.
+    pub burst: u32,
+    /// The sustained number of requests per second.
+    pub requests_per_second: f64,
+    /// The maximum number of requests waiting for their response at once.
+    pub max_concurrency: usize,
+    /// The number of retries after the first attempt.
+    pub max_retries: u32,
+    /// The backoff of the first retry. It doubles on every retry.
+    pub base_backoff: Duration,
+    /// The upper limit of a single backoff, including the ones requested by `Retry-After`.
+    pub max_backoff: Duration,
+}
+
+impl Default for RequestSchedulerConfig {
+    fn default() -> Self {
+        Self {
+            burst: 10,
+            requests_per_second: 5.0,
+            max_concurrency: 8,
+            max_retries: 5,
+            base_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(120),
+        }
+    }
+}
+
+/// Sends requests with a token-bucket rate limit and bounded concurrency.
+/// Requests failing with a connection error, `429 Too Many Requests` or a `5xx` status are retried
+/// with an exponential backoff with jitter, honoring `Retry-After` if the server sent one.
+pub struct RequestScheduler {
+    config: RequestSchedulerConfig,
+    semaphore: Semaphore,
+    bucket: Mutex<TokenBucket>,
+}
+
+struct TokenBucket {
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl RequestScheduler {
+    pub fn new(config: RequestSchedulerConfig) -> Self {
+        Self {
+            semaphore: Semaphore::new(config.max_concurrency),
+            bucket: Mutex::new(TokenBucket {
+                tokens: config.burst as f64,
+                refilled_at: Instant::now(),
+            }),
+            config,
+        }
+    }
+
+    pub fn shared() -> Arc<Self> {
+        SHARED_SCHEDULER.clone()
+    }
+
+    /// Sends the request, retrying it if needed. The returned response has a successful status
+    /// or a client error other than `429`; every other failure is returned as an error.
+    pub async fn send(&self, request: RequestBuilder) -> Result<Response, Error> {
+        let mut retry_count = 0;
+
+        loop {
+            let attempt = request
+                .try_clone()
+                .ok_or_else(|| anyhow!("the request is not retryable"))
+                .with_context(|| format!("[RequestScheduler::send]"))?;
+
+            let result = {
+                let _permit = self
+                    .semaphore
+                    .acquire()
+                    .await
+                    .with_context(|| format!("[RequestScheduler::send]"))?;
+                self.acquire_token().await;
+                attempt.send().await
+            };
+
+            let (failure, retry_after) = match result {
+                Ok(res)
+                    if res.status() == StatusCode::TOO_MANY_REQUESTS
+                        || res.status().is_server_error() =>
+                {
+                    let retry_after = parse_retry_after(res.headers());
+                    (
+                        anyhow!(
+                            "server responded with `{}` for url: `{}`",
+                            res.status(),
+                            res.url()
+                        ),
+                        retry_after,
+                    )
+                }
+                Ok(res) => return Ok(res),
+                Err(err) if err.is_connect() || err.is_timeout() || err.is_request() => {
+                    (Error::from(err), None)
+                }
+                Err(err) => {
+                    return Err(err).with_context(|| format!("[RequestScheduler::send]"));
+                }
+            };
+
+            if self.config.max_retries <= retry_count {
+                return Err(failure)
+                    .with_context(|| format!("[RequestScheduler::send]"))
+                    .with_context(|| format!("gave up after {} retries", retry_count));
+            }
+
+            retry_count += 1;
+
+            let backoff = retry_after
+                .unwrap_or_else(|| self.backoff(retry_count))
+                .min(self.config.max_backoff);
+            warn!(
+                "[RequestScheduler::send] retrying ({}/{}) in {:?}: {:?}",
+                retry_count, self.config.max_retries, backoff, failure
+            );
+            tokio::time::sleep(backoff).await;
+        }
+    }
+
+    /// Returns the backoff of the given retry (1-based): the exponential backoff capped at
+    /// the maximum, with a full jitter applied to its upper half.
+    pub fn backoff(&self, retry_count: u32) -> Duration {
+        let exponential = self
+            .config
+            .base_backoff
+            .saturating_mul(1u32 << retry_count.saturating_sub(1).min(16))
+            .min(self.config.max_backoff);
+        let half = exponential / 2;
+
+        half + half.mul_f64(rand::thread_rng().gen_range(0.0..=1.0))
+    }
+
+    /// Waits until a token is available in the bucket, then takes it.
+    async fn acquire_token(&self) {
+        loop {
+            let wait = {
+                let mut bucket = self.bucket.lock();
+                let now = Instant::now();
+                let elapsed = now.duration_since(bucket.refilled_at).as_secs_f64();
+
+                bucket.tokens = (bucket.tokens + elapsed * self.config.requests_per_second)
+                    .min(self.config.burst as f64);
+                bucket.refilled_at = now;
+
+                if 1.0 <= bucket.tokens {
+                    bucket.tokens -= 1.0;
+                    return;
+                }
+
+                Duration::from_secs_f64((1.0 - bucket.tokens) / self.config.requests_per_second)
+            };
+
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
+
+/// Parses `Retry-After`, which is either a number of seconds or an HTTP date.
+fn parse_retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let retry_after = headers.get(RETRY_AFTER)?.to_str().ok()?.trim();
+
+    if let Ok(seconds) = retry_after.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+
+    let date = DateTime::parse_from_rfc2822(retry_after).ok()?;
+    Some(
+        (date.with_timezone(&Utc) - Utc::now())
+            .to_std()
+            .unwrap_or_default(),
+    )
+}
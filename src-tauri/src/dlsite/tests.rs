@@ -0,0 +1,331 @@
+use super::{
+    api::LoginError,
+    client::DLsiteClient,
+    dto::{DLsiteProductCreator, DLsiteProductCreatorRole, DLsiteProductType},
+    fixtures::{file_data, product_id},
+    mock_server::{MockDLsite, MockProduct, MOCK_LOGIN_ID, MOCK_PASSWORD},
+    product_id::{DLsiteFloor, ProductId, ProductIdError},
+};
+use chrono::{TimeZone, Utc};
+
+fn products(count: usize) -> Vec<MockProduct> {
+    (0..count)
+        .map(|index| {
+            MockProduct::new(
+                format!("RJ{:06}", index + 1),
+                format!("Product {}", index + 1),
+            )
+        })
+        .collect()
+}
+
+#[test]
+fn parse_product_id() {
+    assert_eq!(product_id("RJ123456").as_str(), "RJ123456");
+    assert_eq!(product_id("rj01234567").as_str(), "RJ01234567");
+    assert_eq!(product_id("RJ123456").floor(), DLsiteFloor::Maniax);
+    assert_eq!(product_id("RE123456").floor(), DLsiteFloor::Maniax);
+    assert_eq!(product_id("BJ123456").floor(), DLsiteFloor::Books);
+    assert_eq!(product_id("VJ123456").floor(), DLsiteFloor::Pro);
+    assert!(matches!(
+        ProductId::parse("RG12345"),
+        Err(ProductIdError::UnknownPrefix { .. })
+    ));
+    assert!(matches!(
+        ProductId::parse("RJ12a456"),
+        Err(ProductIdError::InvalidNumber { .. })
+    ));
+    // only the 6 and 8 digit forms exist
+    for id in ["RJ", "RJ12345", "RJ1234567", "RJ123456789"] {
+        assert!(
+            matches!(
+                ProductId::parse(id),
+                Err(ProductIdError::InvalidNumber { .. })
+            ),
+            "{}",
+            id
+        );
+    }
+    // the ID is used as a folder name, so it must never escape the download folder
+    for id in [
+        "RJ123456/..",
+        "../RJ123456",
+        "RJ../../..",
+        "RJ１２３４５６",
+        "RJ 123456",
+    ] {
+        assert!(ProductId::parse(id).is_err(), "{}", id);
+    }
+}
+
+#[test]
+fn serde_product_id() {
+    assert_eq!(
+        serde_json::to_string(&product_id("RJ01234567")).unwrap(),
+        r#""RJ01234567""#
+    );
+    assert_eq!(
+        serde_json::from_str::<ProductId>(r#""rj123456""#).unwrap(),
+        product_id("RJ123456")
+    );
+    assert!(serde_json::from_str::<ProductId>(r#""../../etc""#).is_err());
+}
+
+#[tokio::test]
+async fn login_with_valid_credentials() {
+    let server = MockDLsite::start(vec![]).await;
+    let client = DLsiteClient::login(server.endpoints().clone(), MOCK_LOGIN_ID, MOCK_PASSWORD)
+        .await
+        .unwrap();
+
+    assert!(client.test_cookie_store().await.unwrap());
+}
+
+#[tokio::test]
+async fn login_with_wrong_credentials() {
+    let server = MockDLsite::start(vec![]).await;
+    let result =
+        DLsiteClient::login(server.endpoints().clone(), MOCK_LOGIN_ID, "wrong-password").await;
+
+    assert!(matches!(result, Err(LoginError::WrongCredentials)));
+}
+
+#[tokio::test]
+async fn test_cookie_store_without_session() {
+    let server = MockDLsite::start(vec![]).await;
+    let client = DLsiteClient::anonymous(server.endpoints().clone()).unwrap();
+
+    assert!(!client.test_cookie_store().await.unwrap());
+}
+
+#[tokio::test]
+async fn detect_expired_session() {
+    let server = MockDLsite::start(products(1)).await;
+    let client = DLsiteClient::login(server.endpoints().clone(), MOCK_LOGIN_ID, MOCK_PASSWORD)
+        .await
+        .unwrap();
+
+    client.get_products(1).await.unwrap();
+    assert!(!client.is_session_expired());
+
+    server.expire_sessions();
+
+    assert!(client.get_products(1).await.is_err());
+    assert!(client.is_session_expired());
+}
+
+#[tokio::test]
+async fn get_product_count_of_account() {
+    let server = MockDLsite::start(products(120)).await;
+    let client = DLsiteClient::login(server.endpoints().clone(), MOCK_LOGIN_ID, MOCK_PASSWORD)
+        .await
+        .unwrap();
+
+    assert_eq!(client.get_product_count().await.unwrap(), 120);
+}
+
+#[tokio::test]
+async fn get_products_by_page() {
+    let server = MockDLsite::start(products(120)).await;
+    let client = DLsiteClient::login(server.endpoints().clone(), MOCK_LOGIN_ID, MOCK_PASSWORD)
+        .await
+        .unwrap();
+    let mut ids = vec![];
+
+    for (page, expected_len) in [(1, 50), (2, 50), (3, 20), (4, 0)] {
+        let products = client.get_products(page).await.unwrap();
+        assert_eq!(products.len(), expected_len, "page {}", page);
+        ids.extend(products.into_iter().map(|product| product.id));
+    }
+
+    assert_eq!(
+        ids,
+        (1..=120)
+            .map(|index| product_id(&format!("RJ{:06}", index)))
+            .collect::<Vec<_>>()
+    );
+}
+
+#[tokio::test]
+async fn get_product_from_non_owner_api_with_metadata() {
+    let server = MockDLsite::start(vec![MockProduct::new("RJ000001", "Product 1")
+        .with_genre("ASMR")
+        .with_voice_actor("Voice Actor")])
+    .await;
+    let client = DLsiteClient::anonymous(server.endpoints().clone()).unwrap();
+    let product = client
+        .get_product_from_non_owner_api(&product_id("RJ000001"))
+        .await
+        .unwrap();
+    let metadata = product.metadata.unwrap();
+
+    assert_eq!(product.title, "Product 1");
+    assert_eq!(product.ty, DLsiteProductType::Voice);
+    assert_eq!(
+        product.thumbnail,
+        "https://img.dlsite.jp/RJ000001_img_main.jpg"
+    );
+    // `regist_date` is in JST
+    assert_eq!(
+        product.registered_at,
+        Some(Utc.with_ymd_and_hms(2024, 1, 2, 3, 0, 0).unwrap())
+    );
+    assert_eq!(metadata.genres, vec!["ASMR".to_owned()]);
+    assert_eq!(
+        metadata.creators,
+        vec![DLsiteProductCreator {
+            role: DLsiteProductCreatorRole::Voice,
+            name: "Voice Actor".to_owned(),
+        }]
+    );
+}
+
+#[tokio::test]
+async fn get_product_from_non_owner_api_on_other_floor() {
+    let server = MockDLsite::start(vec![
+        MockProduct::new("RJ000002", "All ages 2").with_site_id("home")
+    ])
+    .await;
+    let client = DLsiteClient::anonymous(server.endpoints().clone()).unwrap();
+    let product = client
+        .get_product_from_non_owner_api(&product_id("RJ000002"))
+        .await
+        .unwrap();
+
+    assert_eq!(product.title, "All ages 2");
+    // the floor is remembered, so that the next requests go there directly
+    assert_eq!(
+        client.product_floor(&product_id("RJ000002")),
+        Some(DLsiteFloor::Home)
+    );
+    assert!(client
+        .get_product_from_non_owner_api(&product_id("RJ999999"))
+        .await
+        .is_err());
+}
+
+#[tokio::test]
+async fn retry_throttled_request() {
+    let server = MockDLsite::start(vec![MockProduct::new("RJ000001", "Product 1")]).await;
+    let client = DLsiteClient::anonymous(server.endpoints().clone()).unwrap();
+    server.throttle_next(2);
+
+    let product = client
+        .get_product_from_non_owner_api(&product_id("RJ000001"))
+        .await
+        .unwrap();
+
+    assert_eq!(product.title, "Product 1");
+}
+
+#[tokio::test]
+async fn download_every_product_file() {
+    let server = MockDLsite::start(vec![MockProduct::new("RJ000001", "Product 1")
+        .with_file("RJ000001.part1.exe", file_data(3000))
+        .with_file("RJ000001.part2.rar", file_data(1500))])
+    .await;
+    let client = DLsiteClient::login(server.endpoints().clone(), MOCK_LOGIN_ID, MOCK_PASSWORD)
+        .await
+        .unwrap();
+    let base_path = tempfile::tempdir().unwrap();
+    let target_path = base_path.path().join("RJ000001");
+    let product_files = client
+        .get_product_files(&product_id("RJ000001"))
+        .await
+        .unwrap();
+
+    client
+        .download_product_files(
+            &product_id("RJ000001"),
+            &product_files,
+            &target_path,
+            |_, _| {},
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(
+        std::fs::read(target_path.join("RJ000001.part1.exe")).unwrap(),
+        file_data(3000)
+    );
+    assert_eq!(
+        std::fs::read(target_path.join("RJ000001.part2.rar")).unwrap(),
+        file_data(1500)
+    );
+    assert_eq!(std::fs::read_dir(&target_path).unwrap().count(), 2);
+}
+
+#[tokio::test]
+async fn download_product_file_resumes_partial_file() {
+    let server = MockDLsite::start(vec![
+        MockProduct::new("RJ000001", "Product 1").with_file("RJ000001.zip", file_data(1000))
+    ])
+    .await;
+    let client = DLsiteClient::login(server.endpoints().clone(), MOCK_LOGIN_ID, MOCK_PASSWORD)
+        .await
+        .unwrap();
+    let base_path = tempfile::tempdir().unwrap();
+    let target_path = base_path.path().join("RJ000001");
+    std::fs::create_dir_all(&target_path).unwrap();
+    std::fs::write(
+        target_path.join("RJ000001.zip.part"),
+        &file_data(1000)[..400],
+    )
+    .unwrap();
+    std::fs::write(
+        target_path.join("RJ000001.zip.part.json"),
+        r#"{"expected_size":1000}"#,
+    )
+    .unwrap();
+
+    let product_files = client
+        .get_product_files(&product_id("RJ000001"))
+        .await
+        .unwrap();
+    client
+        .download_product_files(
+            &product_id("RJ000001"),
+            &product_files,
+            &target_path,
+            |_, _| {},
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(server.range_requests(), vec!["bytes=400-".to_owned()]);
+    assert_eq!(
+        std::fs::read(target_path.join("RJ000001.zip")).unwrap(),
+        file_data(1000)
+    );
+    assert!(!target_path.join("RJ000001.zip.part").exists());
+    assert!(!target_path.join("RJ000001.zip.part.json").exists());
+}
+
+#[tokio::test]
+async fn download_product_files_from_floor_of_product() {
+    let server = MockDLsite::start(vec![
+        MockProduct::new("BJ000001", "Book 1").with_file("BJ000001.zip", file_data(100)),
+        MockProduct::new("RJ000002", "All ages 2")
+            .with_site_id("home")
+            .with_file("RJ000002.zip", file_data(200)),
+    ])
+    .await;
+    let client = DLsiteClient::login(server.endpoints().clone(), MOCK_LOGIN_ID, MOCK_PASSWORD)
+        .await
+        .unwrap();
+    let base_path = tempfile::tempdir().unwrap();
+
+    for (id, size) in [("BJ000001", 100), ("RJ000002", 200)] {
+        let target_path = base_path.path().join(id);
+        let product_files = client.get_product_files(&product_id(id)).await.unwrap();
+        client
+            .download_product_files(&product_id(id), &product_files, &target_path, |_, _| {})
+            .await
+            .unwrap();
+
+        assert_eq!(
+            std::fs::read(target_path.join(format!("{}.zip", id))).unwrap(),
+            file_data(size)
+        );
+    }
+}
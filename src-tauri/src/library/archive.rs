@@ -0,0 +1,456 @@
+use anyhow::{Context, Error as AnyError};
+use lazy_static::lazy_static;
+use regex::Regex;
+use std::{
+    collections::BTreeMap,
+    fmt::{self, Display},
+    fs::File,
+    io::{Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+};
+
+/// How far past the end of an executable image the payload of a self-extracting archive is
+/// searched for. Some SFX modules put their configuration between the image and the payload.
+const SFX_SCAN_LIMIT: u64 = 4 * 1024 * 1024;
+/// The end of central directory record of a zip archive is within this many bytes of its end,
+/// as its trailing comment is at most 65535 bytes long.
+const ZIP_EOCD_SCAN_LIMIT: u64 = 22 + 65535;
+
+const ZIP_LOCAL_FILE_SIGNATURE: &[u8] = b"PK\x03\x04";
+const ZIP_EOCD_SIGNATURE: &[u8] = b"PK\x05\x06";
+const ZIP_SPLIT_SIGNATURE: &[u8] = b"PK\x07\x08";
+const RAR4_SIGNATURE: &[u8] = b"Rar!\x1a\x07\x00";
+const RAR5_SIGNATURE: &[u8] = b"Rar!\x1a\x07\x01\x00";
+const SEVEN_ZIP_SIGNATURE: &[u8] = b"7z\xbc\xaf\x27\x1c";
+const PDF_SIGNATURE: &[u8] = b"%PDF-";
+const EXECUTABLE_SIGNATURE: &[u8] = b"MZ";
+
+lazy_static! {
+    /// A volume of a multi-volume RAR archive, such as `RJ01234567.part1.exe`.
+    static ref RAR_PART_REGEX: Regex = Regex::new(r"(?i)^(.*)\.part0*([0-9]+)\.(?:rar|exe)$").unwrap();
+    /// A following volume of an old style multi-volume RAR archive, such as `.r00`.
+    static ref RAR_OLD_VOLUME_REGEX: Regex = Regex::new(r"(?i)\.r[0-9]{2}$").unwrap();
+    /// A volume of a split zip archive, such as `.z01` or `.zip.001`.
+    static ref SPLIT_ZIP_REGEX: Regex = Regex::new(r"(?i)(?:\.z[0-9]{2}|\.zip\.[0-9]{3})$").unwrap();
+    /// A volume of a split 7z archive, such as `.7z.001`.
+    static ref SPLIT_SEVEN_ZIP_REGEX: Regex = Regex::new(r"(?i)\.7z\.[0-9]{3}$").unwrap();
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveFormat {
+    Zip,
+    Rar,
+    SevenZip,
+}
+
+/// What a file is, judged by its content rather than its name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileSignature {
+    /// an archive whose payload begins at the offset; it is not zero for a self-extracting one
+    Archive {
+        format: ArchiveFormat,
+        offset: u64,
+    },
+    /// a volume of a split zip archive
+    SplitZip,
+    /// an LZH archive, which cannot be extracted
+    Lzh,
+    /// an executable without an archive in it, such as an installer
+    Executable,
+    Pdf,
+    Unknown,
+}
+
+/// An archive to extract, along with every volume of it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DetectedArchive {
+    pub format: ArchiveFormat,
+    /// the first volume, which the extraction starts from
+    pub path: PathBuf,
+    /// where the archive begins in the first volume
+    pub offset: u64,
+    /// every volume including the first one, which are removed after the extraction
+    pub volumes: Vec<PathBuf>,
+}
+
+/// Why the downloaded files are left as they are.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExtractionSkipReason {
+    /// every file is an executable without an archive in it
+    Installer,
+    /// every file is a PDF document
+    Document,
+    SplitZip,
+    SplitSevenZip,
+    Lzh,
+    /// no file is recognized as an archive
+    NotArchive,
+}
+
+impl Display for ExtractionSkipReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Installer => "the product is an installer, which needs no extraction",
+            Self::Document => "the product is a document, which needs no extraction",
+            Self::SplitZip => "split zip archives are not supported",
+            Self::SplitSevenZip => "split 7z archives are not supported",
+            Self::Lzh => "LZH archives are not supported",
+            Self::NotArchive => "the product contains no archive",
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ExtractionPlan {
+    Extract(Vec<DetectedArchive>),
+    Skip(ExtractionSkipReason),
+}
+
+/// Decides how to extract the downloaded files in the folder by their content.
+/// The files that are not archives, such as a readme next to an archive, are left as they are.
+pub fn plan_extraction(
+    folder: &Path,
+    file_names: &[impl AsRef<str>],
+) -> Result<ExtractionPlan, AnyError> {
+    let file_names = file_names
+        .iter()
+        .map(|file_name| file_name.as_ref())
+        .collect::<Vec<_>>();
+
+    if file_names.iter().any(|name| SPLIT_ZIP_REGEX.is_match(name)) {
+        return Ok(ExtractionPlan::Skip(ExtractionSkipReason::SplitZip));
+    }
+
+    if file_names
+        .iter()
+        .any(|name| SPLIT_SEVEN_ZIP_REGEX.is_match(name))
+    {
+        return Ok(ExtractionPlan::Skip(ExtractionSkipReason::SplitSevenZip));
+    }
+
+    let mut archives = Vec::new();
+    let mut signatures = Vec::new();
+    // the volumes of each multi-volume RAR archive by their number, keyed by the common stem
+    let mut rar_parts = BTreeMap::<String, BTreeMap<u32, PathBuf>>::new();
+    let mut rar_old_volumes = Vec::new();
+
+    for name in &file_names {
+        let path = folder.join(name);
+
+        if let Some(captures) = RAR_PART_REGEX.captures(name) {
+            let number = captures[2].parse().unwrap_or(u32::MAX);
+            rar_parts
+                .entry(captures[1].to_owned())
+                .or_default()
+                .insert(number, path);
+            continue;
+        }
+
+        if RAR_OLD_VOLUME_REGEX.is_match(name) {
+            rar_old_volumes.push(path);
+            continue;
+        }
+
+        let signature = sniff_file(&path)?;
+
+        match signature {
+            FileSignature::Archive { format, offset } => archives.push(DetectedArchive {
+                format,
+                path: path.clone(),
+                offset,
+                volumes: vec![path],
+            }),
+            FileSignature::SplitZip => {
+                return Ok(ExtractionPlan::Skip(ExtractionSkipReason::SplitZip));
+            }
+            _ => signatures.push(signature),
+        }
+    }
+
+    for parts in rar_parts.into_values() {
+        let volumes = parts.into_values().collect::<Vec<_>>();
+
+        // only the first volume has the SFX module, if any
+        if let FileSignature::Archive {
+            format: ArchiveFormat::Rar,
+            offset,
+        } = sniff_file(&volumes[0])?
+        {
+            archives.push(DetectedArchive {
+                format: ArchiveFormat::Rar,
+                path: volumes[0].clone(),
+                offset,
+                volumes,
+            });
+        } else {
+            signatures.push(FileSignature::Unknown);
+        }
+    }
+
+    // old style volumes follow the `.rar` archive of the same stem
+    for volume in rar_old_volumes {
+        let stem = volume.with_extension("");
+        let archive = archives.iter_mut().find(|archive| {
+            archive.format == ArchiveFormat::Rar && archive.path.with_extension("") == stem
+        });
+
+        match archive {
+            Some(archive) => archive.volumes.push(volume),
+            None => signatures.push(FileSignature::Unknown),
+        }
+    }
+
+    if !archives.is_empty() {
+        return Ok(ExtractionPlan::Extract(archives));
+    }
+
+    let reason = if signatures.contains(&FileSignature::Lzh) {
+        ExtractionSkipReason::Lzh
+    } else if !signatures.is_empty()
+        && signatures
+            .iter()
+            .all(|signature| *signature == FileSignature::Executable)
+    {
+        ExtractionSkipReason::Installer
+    } else if !signatures.is_empty()
+        && signatures
+            .iter()
+            .all(|signature| *signature == FileSignature::Pdf)
+    {
+        ExtractionSkipReason::Document
+    } else {
+        ExtractionSkipReason::NotArchive
+    };
+
+    Ok(ExtractionPlan::Skip(reason))
+}
+
+/// Tells what the file is by its magic bytes. An executable is searched for the payload of
+/// a self-extracting archive past the end of its image.
+pub fn sniff_file(path: &Path) -> Result<FileSignature, AnyError> {
+    let mut file = File::open(path)
+        .with_context(|| format!("[sniff_file]"))
+        .with_context(|| format!("failed to open the file `{}`", path.display()))?;
+    let mut header = Vec::with_capacity(16);
+
+    (&mut file)
+        .take(16)
+        .read_to_end(&mut header)
+        .with_context(|| format!("[sniff_file]"))
+        .with_context(|| format!("failed to read the file `{}`", path.display()))?;
+
+    if header.starts_with(EXECUTABLE_SIGNATURE) {
+        return sniff_executable(&mut file)
+            .with_context(|| format!("[sniff_file]"))
+            .with_context(|| format!("failed to read the executable `{}`", path.display()));
+    }
+
+    if let Some(format) = archive_format_at(&header) {
+        return Ok(FileSignature::Archive { format, offset: 0 });
+    }
+
+    Ok(
+        if header.starts_with(ZIP_LOCAL_FILE_SIGNATURE) || header.starts_with(ZIP_EOCD_SIGNATURE) {
+            FileSignature::Archive {
+                format: ArchiveFormat::Zip,
+                offset: 0,
+            }
+        } else if header.starts_with(ZIP_SPLIT_SIGNATURE) {
+            FileSignature::SplitZip
+        } else if header.len() >= 7 && &header[2..4] == b"-l" && header[6] == b'-' {
+            FileSignature::Lzh
+        } else if header.starts_with(PDF_SIGNATURE) {
+            FileSignature::Pdf
+        } else {
+            FileSignature::Unknown
+        },
+    )
+}
+
+fn sniff_executable(file: &mut File) -> Result<FileSignature, AnyError> {
+    let file_size = file.metadata()?.len();
+    let overlay_offset = match find_overlay_offset(file)? {
+        Some(offset) if offset < file_size => offset,
+        _ => return Ok(FileSignature::Executable),
+    };
+
+    let mut overlay = Vec::new();
+    file.seek(SeekFrom::Start(overlay_offset))?;
+    (&mut *file)
+        .take(SFX_SCAN_LIMIT)
+        .read_to_end(&mut overlay)?;
+
+    // a zip archive is read from its end, so a zip payload must have its trailing record
+    let tail_offset = file_size
+        .saturating_sub(ZIP_EOCD_SCAN_LIMIT)
+        .max(overlay_offset);
+    let mut tail = Vec::new();
+    file.seek(SeekFrom::Start(tail_offset))?;
+    file.read_to_end(&mut tail)?;
+
+    let has_zip_eocd = tail
+        .windows(ZIP_EOCD_SIGNATURE.len())
+        .any(|window| window == ZIP_EOCD_SIGNATURE);
+
+    // the first archive found is the payload; the others may be the files archived in it
+    for index in 0..overlay.len() {
+        let format = if has_zip_eocd && overlay[index..].starts_with(ZIP_LOCAL_FILE_SIGNATURE) {
+            Some(ArchiveFormat::Zip)
+        } else {
+            archive_format_at(&overlay[index..])
+        };
+
+        if let Some(format) = format {
+            return Ok(FileSignature::Archive {
+                format,
+                offset: overlay_offset + index as u64,
+            });
+        }
+    }
+
+    Ok(FileSignature::Executable)
+}
+
+/// Finds where the image of a PE executable ends, which is where an appended payload begins.
+/// Returns `None` if the executable is not a valid PE one.
+fn find_overlay_offset(file: &mut File) -> Result<Option<u64>, AnyError> {
+    let mut dos_header = [0u8; 64];
+    file.seek(SeekFrom::Start(0))?;
+
+    if file.read_exact(&mut dos_header).is_err() {
+        return Ok(None);
+    }
+
+    let pe_offset = read_u32(&dos_header, 0x3c) as u64;
+    let mut pe_header = [0u8; 24];
+    file.seek(SeekFrom::Start(pe_offset))?;
+
+    if file.read_exact(&mut pe_header).is_err() || &pe_header[..4] != b"PE\0\0" {
+        return Ok(None);
+    }
+
+    let section_count = read_u16(&pe_header, 6) as u64;
+    let optional_header_size = read_u16(&pe_header, 20) as u64;
+    let mut sections = vec![0u8; section_count as usize * 40];
+    file.seek(SeekFrom::Start(pe_offset + 24 + optional_header_size))?;
+
+    if file.read_exact(&mut sections).is_err() {
+        return Ok(None);
+    }
+
+    let end = sections
+        .chunks_exact(40)
+        .map(|section| read_u32(section, 20) as u64 + read_u32(section, 16) as u64)
+        .max();
+    Ok(end)
+}
+
+/// Checks whether a RAR or 7z archive begins at the start of the bytes. The header following
+/// the signature is checked as well, since an SFX module may contain the signature itself.
+fn archive_format_at(bytes: &[u8]) -> Option<ArchiveFormat> {
+    if bytes.starts_with(RAR5_SIGNATURE) {
+        // the main archive header follows: CRC32, then the header size and type as vints
+        let mut rest = bytes.get(RAR5_SIGNATURE.len() + 4..)?;
+        read_vint(&mut rest)?;
+        return (read_vint(&mut rest)? == 1).then_some(ArchiveFormat::Rar);
+    }
+
+    if bytes.starts_with(RAR4_SIGNATURE) {
+        // the main archive header follows: CRC16, then the header type
+        let header_type = *bytes.get(RAR4_SIGNATURE.len() + 2)?;
+        return (header_type == 0x73).then_some(ArchiveFormat::Rar);
+    }
+
+    if bytes.starts_with(SEVEN_ZIP_SIGNATURE) {
+        // the start header is 20 bytes long, preceded by its CRC32
+        let crc = read_u32(bytes.get(8..12)?, 0);
+        let start_header = bytes.get(12..32)?;
+        return (crc32(start_header) == crc).then_some(ArchiveFormat::SevenZip);
+    }
+
+    None
+}
+
+/// Reads a variable length integer of the RAR5 format, advancing the bytes past it.
+fn read_vint(bytes: &mut &[u8]) -> Option<u64> {
+    let mut value = 0u64;
+
+    for (index, byte) in bytes.iter().take(10).enumerate() {
+        value |= ((byte & 0x7f) as u64) << (index * 7);
+
+        if byte & 0x80 == 0 {
+            *bytes = &bytes[index + 1..];
+            return Some(value);
+        }
+    }
+
+    None
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([
+        bytes[offset],
+        bytes[offset + 1],
+        bytes[offset + 2],
+        bytes[offset + 3],
+    ])
+}
+
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+
+    for byte in bytes {
+        crc ^= *byte as u32;
+
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xedb88320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+
+    !crc
+}
+
+/// Reads a file as if it began at the offset, so that the payload of a self-extracting archive
+/// is read as a standalone archive.
+pub struct PayloadReader<R> {
+    inner: R,
+    offset: u64,
+}
+
+impl<R: Seek> PayloadReader<R> {
+    pub fn new(mut inner: R, offset: u64) -> std::io::Result<Self> {
+        inner.seek(SeekFrom::Start(offset))?;
+        Ok(Self { inner, offset })
+    }
+}
+
+impl<R: Read> Read for PayloadReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.inner.read(buf)
+    }
+}
+
+impl<R: Seek> Seek for PayloadReader<R> {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        let pos = match pos {
+            SeekFrom::Start(pos) => SeekFrom::Start(self.offset + pos),
+            pos => pos,
+        };
+        let position = self.inner.seek(pos)?;
+
+        position.checked_sub(self.offset).ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "seeking before the start of the payload",
+            )
+        })
+    }
+}
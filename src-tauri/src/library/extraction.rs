@@ -0,0 +1,683 @@
+use super::name_encoding::{detect_name_encoding, NameEncoding};
+use anyhow::{anyhow, Context, Error as AnyError};
+use log::{info, warn};
+use serde::Serialize;
+use sevenz_rust::{Password, SevenZReader};
+use std::{
+    fs::{create_dir_all, read_dir, remove_file, rename, File, OpenOptions},
+    io::{copy, sink, ErrorKind, Read, Seek, SeekFrom, Write},
+    path::{Component, Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{channel, RecvTimeoutError},
+    },
+    thread,
+    time::Duration,
+};
+use unrar::{Archive, FileHeader};
+use zip::{read::ZipFile, ZipArchive};
+
+/// The most entries an archive may have, as a bomb may consist of countless empty files.
+pub const MAX_ENTRY_COUNT: u64 = 200_000;
+/// The free disk space left untouched by an extraction.
+pub const FREE_SPACE_RESERVE: u64 = 512 * 1024 * 1024;
+
+/// The size of the chunks that a file entry is written and reported in.
+const CHUNK_SIZE: usize = 64 * 1024;
+/// How often the size of a RAR entry is checked while it is extracted.
+const RAR_POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// The file type bits of a Unix mode, and the ones of a symbolic link.
+const UNIX_FILE_TYPE_MASK: u32 = 0o170000;
+const UNIX_SYMLINK: u32 = 0o120000;
+/// The Windows attribute of a symbolic link or a junction.
+const WINDOWS_REPARSE_POINT: u32 = 0x400;
+/// The Windows attribute telling that the high 16 bits are a Unix mode, as 7-Zip writes it.
+const WINDOWS_UNIX_EXTENSION: u32 = 0x8000;
+
+/// Why an entry of an archive is not extracted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum SkippedEntryReason {
+    /// the name is absolute, or goes up with `..`
+    UnsafePath,
+    /// the entry is a symbolic link, which could point anywhere
+    Symlink,
+    /// the path leads out of the folder through an existing symbolic link
+    OutsideTarget,
+    /// another file already has the name in the product folder
+    AlreadyExists,
+    /// the entry could not be written
+    Failed,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SkippedEntry {
+    pub name: String,
+    pub reason: SkippedEntryReason,
+    pub message: Option<String>,
+}
+
+/// The outcome of an extraction. An archive that cannot be extracted safely as a whole,
+/// such as one too large for the disk, is an error instead.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ExtractionReport {
+    pub extracted: u64,
+    pub skipped: Vec<SkippedEntry>,
+}
+
+impl ExtractionReport {
+    fn skip(&mut self, name: &str, reason: SkippedEntryReason, message: Option<String>) {
+        warn!(
+            "[ExtractionReport::skip] skipping the entry `{}`: {:?} {}",
+            name,
+            reason,
+            message.as_deref().unwrap_or_default()
+        );
+        self.skipped.push(SkippedEntry {
+            name: name.to_owned(),
+            reason,
+            message,
+        });
+    }
+}
+
+/// The progress of an extraction, reported as each entry begins and as its bytes are written.
+#[derive(Debug, Clone, Copy)]
+pub struct ExtractionProgress<'a> {
+    /// the name of the entry being extracted
+    pub entry_name: &'a str,
+    /// the number of the entries before the entry
+    pub entry_index: u64,
+    pub entry_count: u64,
+    /// the bytes written so far, out of the declared size of every entry
+    pub written_size: u64,
+    pub declared_size: u64,
+}
+
+/// Receives the progress of an extraction, possibly from another thread.
+pub type OnExtractionProgress<'a> = &'a (dyn Fn(ExtractionProgress) + Sync);
+
+/// Writes the entries of an archive under a folder, keeping every one of them inside it
+/// and the total size within the free disk space.
+struct Extractor<'a> {
+    /// the canonical path of the folder
+    root: PathBuf,
+    /// the bytes that may still be written
+    budget: u64,
+    declared_size: u64,
+    entry_count: u64,
+    entry_index: u64,
+    written_size: u64,
+    /// checked between the entries, as an extraction runs on a thread of its own
+    is_cancelled: &'a AtomicBool,
+    on_progress: OnExtractionProgress<'a>,
+    report: ExtractionReport,
+}
+
+impl<'a> Extractor<'a> {
+    /// Checks the declared size and number of the entries against the limits.
+    fn new(
+        target: &Path,
+        declared_size: u64,
+        entry_count: u64,
+        is_cancelled: &'a AtomicBool,
+        on_progress: OnExtractionProgress<'a>,
+    ) -> Result<Self, AnyError> {
+        create_dir_all(target)
+            .with_context(|| format!("[Extractor::new]"))
+            .with_context(|| format!("failed to create the folder `{}`", target.display()))?;
+
+        if MAX_ENTRY_COUNT < entry_count {
+            return Err(anyhow!(
+                "the archive has {} entries, more than {}",
+                entry_count,
+                MAX_ENTRY_COUNT
+            ));
+        }
+
+        let budget = match fs2::available_space(target) {
+            Ok(available) => available.saturating_sub(FREE_SPACE_RESERVE),
+            Err(err) => {
+                warn!(
+                    "[Extractor::new] failed to get the free space of `{}`; not limiting the size: {:?}",
+                    target.display(),
+                    err
+                );
+                u64::MAX
+            }
+        };
+
+        if budget < declared_size {
+            return Err(anyhow!(
+                "the archive extracts to {} bytes, but only {} bytes can be used",
+                declared_size,
+                budget
+            ));
+        }
+
+        Ok(Self {
+            root: target.canonicalize()?,
+            budget,
+            declared_size,
+            entry_count,
+            entry_index: 0,
+            written_size: 0,
+            is_cancelled,
+            on_progress,
+            report: ExtractionReport::default(),
+        })
+    }
+
+    fn progress<'n>(&self, name: &'n str, entry_written: u64) -> ExtractionProgress<'n> {
+        ExtractionProgress {
+            entry_name: name,
+            entry_index: self.entry_index,
+            entry_count: self.entry_count,
+            written_size: self.written_size.saturating_add(entry_written),
+            declared_size: self.declared_size,
+        }
+    }
+
+    /// Reports that the entry at the index begins, whether it is extracted or skipped.
+    /// It is an error if the extraction is cancelled meanwhile.
+    fn begin(&mut self, index: u64, name: &str) -> Result<(), AnyError> {
+        if self.is_cancelled.load(Ordering::SeqCst) {
+            return Err(anyhow!("the extraction is cancelled"));
+        }
+
+        self.entry_index = index;
+        (self.on_progress)(self.progress(name, 0));
+        Ok(())
+    }
+
+    /// Resolves the name of an entry to its path, or records why it is skipped.
+    /// An entry naming the folder itself, such as `./`, is skipped silently.
+    fn resolve(&mut self, name: &str, is_symlink: bool) -> Option<PathBuf> {
+        if is_symlink {
+            self.report.skip(name, SkippedEntryReason::Symlink, None);
+            return None;
+        }
+
+        match safe_relative_path(name) {
+            Some(path) if path.as_os_str().is_empty() => None,
+            Some(path) => Some(self.root.join(path)),
+            None => {
+                self.report.skip(name, SkippedEntryReason::UnsafePath, None);
+                None
+            }
+        }
+    }
+
+    fn create_dir(&mut self, name: &str, path: &Path) {
+        if let Err(err) = create_dir_all(path) {
+            self.report
+                .skip(name, SkippedEntryReason::Failed, Some(err.to_string()));
+        }
+    }
+
+    /// Creates the parent folders of the path, making sure that they are inside of the root
+    /// and that the path is not an existing symbolic link.
+    fn prepare(&mut self, name: &str, path: &Path) -> bool {
+        let parent = match path.parent() {
+            Some(parent) => parent,
+            None => return false,
+        };
+        let parent = match create_dir_all(parent).and_then(|_| parent.canonicalize()) {
+            Ok(parent) => parent,
+            Err(err) => {
+                self.report
+                    .skip(name, SkippedEntryReason::Failed, Some(err.to_string()));
+                return false;
+            }
+        };
+
+        let is_symlink = path
+            .symlink_metadata()
+            .map_or(false, |metadata| metadata.file_type().is_symlink());
+
+        if !parent.starts_with(&self.root) || is_symlink {
+            self.report
+                .skip(name, SkippedEntryReason::OutsideTarget, None);
+            return false;
+        }
+
+        true
+    }
+
+    /// Writes a file entry. It is an error only if the entry exceeds the remaining budget,
+    /// since the archive is then larger than it declares.
+    fn write_file(
+        &mut self,
+        name: &str,
+        path: &Path,
+        reader: &mut dyn Read,
+    ) -> Result<(), AnyError> {
+        if !self.prepare(name, path) {
+            return Ok(());
+        }
+
+        let mut file = match File::create(path) {
+            Ok(file) => file,
+            Err(err) => {
+                self.report
+                    .skip(name, SkippedEntryReason::Failed, Some(err.to_string()));
+                return Ok(());
+            }
+        };
+        let written = match self.copy_reporting(name, reader, &mut file) {
+            Ok(written) => written,
+            Err(err) => {
+                drop(file);
+                remove_file(path).ok();
+                self.report
+                    .skip(name, SkippedEntryReason::Failed, Some(err.to_string()));
+                return Ok(());
+            }
+        };
+
+        self.consume(name, path, written)
+    }
+
+    /// Copies the entry up to one byte past the budget, reporting each chunk written.
+    fn copy_reporting(
+        &self,
+        name: &str,
+        reader: &mut dyn Read,
+        file: &mut File,
+    ) -> std::io::Result<u64> {
+        let mut reader = reader.take(self.budget.saturating_add(1));
+        let mut buffer = vec![0; CHUNK_SIZE];
+        let mut written = 0u64;
+
+        loop {
+            let read = match reader.read(&mut buffer) {
+                Ok(0) => return Ok(written),
+                Ok(read) => read,
+                Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err),
+            };
+
+            file.write_all(&buffer[..read])?;
+            written += read as u64;
+            (self.on_progress)(self.progress(name, written));
+        }
+    }
+
+    /// Takes the written bytes from the budget.
+    fn consume(&mut self, name: &str, path: &Path, written: u64) -> Result<(), AnyError> {
+        if self.budget < written {
+            remove_file(path).ok();
+            return Err(anyhow!(
+                "the entry `{}` exceeds the free space; the archive is larger than it declares",
+                name
+            ));
+        }
+
+        self.budget -= written;
+        self.written_size = self.written_size.saturating_add(written);
+        self.report.extracted += 1;
+        Ok(())
+    }
+}
+
+/// Extracts the zip archive into the folder. The names of the entries without the UTF-8 flag
+/// are decoded in the encoding, or in the one guessed from every such name in the archive.
+pub fn extract_zip(
+    reader: impl Read + Seek,
+    target: &Path,
+    encoding: Option<NameEncoding>,
+    is_cancelled: &AtomicBool,
+    on_progress: OnExtractionProgress,
+) -> Result<ExtractionReport, AnyError> {
+    let mut archive = ZipArchive::new(reader).with_context(|| format!("[extract_zip]"))?;
+    let mut raw_names = Vec::new();
+    let mut declared_size = 0u64;
+
+    for index in 0..archive.len() {
+        let file = archive.by_index_raw(index)?;
+        declared_size = declared_size.saturating_add(file.size());
+
+        if !is_utf8_flagged(&file) {
+            raw_names.push(file.name_raw().to_vec());
+        }
+    }
+
+    let encoding =
+        encoding.unwrap_or_else(|| detect_name_encoding(raw_names.iter().map(Vec::as_slice)));
+    let mut extractor = Extractor::new(
+        target,
+        declared_size,
+        archive.len() as u64,
+        is_cancelled,
+        on_progress,
+    )
+    .with_context(|| format!("[extract_zip]"))?;
+
+    info!(
+        "[extract_zip] extracting {} entries to `{}`, decoding the names as {:?}",
+        archive.len(),
+        target.display(),
+        encoding
+    );
+
+    for index in 0..archive.len() {
+        let mut file = archive.by_index(index)?;
+        let name = if is_utf8_flagged(&file) {
+            file.name().to_owned()
+        } else {
+            encoding.decode(file.name_raw())
+        };
+        extractor
+            .begin(index as u64, &name)
+            .with_context(|| format!("[extract_zip]"))?;
+
+        let is_symlink = file
+            .unix_mode()
+            .map_or(false, |mode| mode & UNIX_FILE_TYPE_MASK == UNIX_SYMLINK);
+        let path = match extractor.resolve(&name, is_symlink) {
+            Some(path) => path,
+            None => continue,
+        };
+
+        if file.is_dir() {
+            extractor.create_dir(&name, &path);
+        } else {
+            extractor
+                .write_file(&name, &path, &mut file)
+                .with_context(|| format!("[extract_zip]"))?;
+        }
+    }
+
+    Ok(extractor.report)
+}
+
+/// Extracts the RAR archive into the folder. The following volumes are looked up by the name of
+/// the first one, which must end with `.rar`. As the library writes each entry by itself,
+/// the progress within an entry is reported by watching the size of its file.
+pub fn extract_rar(
+    path: &Path,
+    target: &Path,
+    is_cancelled: &AtomicBool,
+    on_progress: OnExtractionProgress,
+) -> Result<ExtractionReport, AnyError> {
+    let mut declared_size = 0u64;
+    let mut entry_count = 0u64;
+
+    for header in Archive::new(path)
+        .open_for_listing()
+        .with_context(|| format!("[extract_rar]"))?
+    {
+        let header = header.with_context(|| format!("[extract_rar]"))?;
+        declared_size = declared_size.saturating_add(header.unpacked_size);
+        entry_count += 1;
+    }
+
+    let mut extractor = Extractor::new(
+        target,
+        declared_size,
+        entry_count,
+        is_cancelled,
+        on_progress,
+    )
+    .with_context(|| format!("[extract_rar]"))?;
+    let mut archive = Archive::new(path)
+        .open_for_processing()
+        .with_context(|| format!("[extract_rar]"))?;
+
+    info!(
+        "[extract_rar] extracting {} entries to `{}`",
+        entry_count,
+        target.display()
+    );
+
+    for index in 0.. {
+        let header = match archive
+            .read_header()
+            .with_context(|| format!("[extract_rar]"))?
+        {
+            Some(header) => header,
+            None => break,
+        };
+        let entry = header.entry();
+        let name = entry.filename.to_string_lossy().into_owned();
+        let is_directory = entry.is_directory();
+        extractor
+            .begin(index, &name)
+            .with_context(|| format!("[extract_rar]"))?;
+
+        let path = extractor.resolve(&name, is_rar_symlink(entry));
+
+        archive = match path {
+            Some(path) if is_directory => {
+                extractor.create_dir(&name, &path);
+                header.skip()?
+            }
+            Some(path) if extractor.prepare(&name, &path) => {
+                let archive =
+                    extract_rar_entry(&extractor, &name, &path, || header.extract_to(&path))
+                        .with_context(|| format!("[extract_rar]"))?
+                        .with_context(|| format!("[extract_rar]"))
+                        .with_context(|| format!("failed to extract the entry `{}`", name))?;
+                let written = path.metadata().map_or(0, |metadata| metadata.len());
+
+                extractor
+                    .consume(&name, &path, written)
+                    .with_context(|| format!("[extract_rar]"))?;
+                // the entry may have been written between two checks of its size
+                (extractor.on_progress)(extractor.progress(&name, 0));
+                archive
+            }
+            _ => header.skip()?,
+        };
+    }
+
+    Ok(extractor.report)
+}
+
+/// Extracts the RAR entry with the function, reporting the size of its file meanwhile.
+/// It is an error if the file grows past the remaining budget. The library cannot be stopped
+/// within an entry, so the file is emptied as it grows until the entry ends.
+fn extract_rar_entry<T>(
+    extractor: &Extractor,
+    name: &str,
+    path: &Path,
+    extract: impl FnOnce() -> T,
+) -> Result<T, AnyError> {
+    let (done, wait) = channel::<()>();
+    let is_exceeded = AtomicBool::new(false);
+
+    let result = thread::scope(|scope| {
+        let is_exceeded = &is_exceeded;
+
+        scope.spawn(move || {
+            while let Err(RecvTimeoutError::Timeout) = wait.recv_timeout(RAR_POLL_INTERVAL) {
+                let size = match path.metadata() {
+                    Ok(metadata) => metadata.len(),
+                    Err(_) => continue,
+                };
+
+                if extractor.budget < size {
+                    is_exceeded.store(true, Ordering::SeqCst);
+                    OpenOptions::new()
+                        .write(true)
+                        .open(path)
+                        .and_then(|file| file.set_len(0))
+                        .ok();
+                } else if !is_exceeded.load(Ordering::SeqCst) {
+                    (extractor.on_progress)(extractor.progress(name, size));
+                }
+            }
+        });
+
+        let result = extract();
+        drop(done);
+        result
+    });
+
+    if is_exceeded.load(Ordering::SeqCst) {
+        remove_file(path).ok();
+        return Err(anyhow!(
+            "the entry `{}` exceeds the free space; the archive is larger than it declares",
+            name
+        ));
+    }
+
+    Ok(result)
+}
+
+/// Extracts the 7z archive into the folder.
+pub fn extract_seven_zip(
+    mut reader: impl Read + Seek,
+    target: &Path,
+    is_cancelled: &AtomicBool,
+    on_progress: OnExtractionProgress,
+) -> Result<ExtractionReport, AnyError> {
+    let len = reader.seek(SeekFrom::End(0))?;
+    reader.seek(SeekFrom::Start(0))?;
+
+    let mut archive = SevenZReader::new(reader, len, Password::empty())
+        .with_context(|| format!("[extract_seven_zip]"))?;
+    let entry_count = archive.archive().files.len() as u64;
+    let declared_size = archive
+        .archive()
+        .files
+        .iter()
+        .fold(0u64, |size, entry| size.saturating_add(entry.size()));
+    let mut extractor = Extractor::new(
+        target,
+        declared_size,
+        entry_count,
+        is_cancelled,
+        on_progress,
+    )
+    .with_context(|| format!("[extract_seven_zip]"))?;
+    let mut entry_index = 0;
+    let mut error = None;
+
+    info!(
+        "[extract_seven_zip] extracting {} entries to `{}`",
+        entry_count,
+        target.display()
+    );
+
+    archive
+        .for_each_entries(|entry, reader| {
+            let name = entry.name().to_owned();
+            if let Err(err) = extractor.begin(entry_index, &name) {
+                error = Some(err);
+                return Ok(false);
+            }
+            entry_index += 1;
+
+            let attributes = entry.windows_attributes;
+            let is_symlink = entry.has_windows_attributes
+                && (attributes & WINDOWS_REPARSE_POINT != 0
+                    || attributes & WINDOWS_UNIX_EXTENSION != 0
+                        && (attributes >> 16) & UNIX_FILE_TYPE_MASK == UNIX_SYMLINK);
+
+            match extractor.resolve(&name, is_symlink) {
+                Some(path) if entry.is_directory() => extractor.create_dir(&name, &path),
+                Some(path) => {
+                    if let Err(err) = extractor.write_file(&name, &path, reader) {
+                        error = Some(err);
+                        return Ok(false);
+                    }
+                }
+                // the stream of a solid archive goes on to the next entry
+                None => {
+                    if let Err(err) = copy(reader, &mut sink()) {
+                        error = Some(err.into());
+                        return Ok(false);
+                    }
+                }
+            }
+
+            Ok(true)
+        })
+        .with_context(|| format!("[extract_seven_zip]"))?;
+
+    match error {
+        Some(err) => Err(err.context(format!("[extract_seven_zip]"))),
+        None => Ok(extractor.report),
+    }
+}
+
+/// Moves the extracted contents into the product folder. A single top-level folder is
+/// unwrapped, as most archives put everything in a folder named after the product.
+/// An entry whose name is already taken is left out and reported.
+pub fn move_extracted(
+    from: &Path,
+    to: &Path,
+    report: &mut ExtractionReport,
+) -> Result<(), AnyError> {
+    let mut content_paths = read_dir(from)?.collect::<std::io::Result<Vec<_>>>()?;
+    let content_prefix_path;
+
+    if content_paths.len() == 1 && content_paths[0].file_type()?.is_dir() {
+        content_prefix_path = content_paths[0].path();
+        content_paths = read_dir(content_paths[0].path())?.collect::<std::io::Result<Vec<_>>>()?;
+    } else {
+        content_prefix_path = from.to_owned();
+    }
+
+    for content_path in content_paths {
+        let content_path = content_path.path();
+        let relative_path = content_path.strip_prefix(&content_prefix_path)?;
+        let name = relative_path.to_string_lossy();
+        let path = to.join(relative_path);
+
+        if path.symlink_metadata().is_ok() {
+            report.skip(&name, SkippedEntryReason::AlreadyExists, None);
+            continue;
+        }
+
+        if let Err(err) = rename(&content_path, &path) {
+            report.skip(&name, SkippedEntryReason::Failed, Some(err.to_string()));
+        }
+    }
+
+    Ok(())
+}
+
+/// Checks whether the name of the entry is flagged as UTF-8. The zip reader decodes the other
+/// names in CP437, which differs from the raw name once it has a non-ASCII byte.
+fn is_utf8_flagged(file: &ZipFile) -> bool {
+    std::str::from_utf8(file.name_raw()).map_or(false, |name| name == file.name())
+}
+
+/// Checks the attributes of a RAR entry, which are a Unix mode or Windows attributes
+/// depending on where the archive was made.
+fn is_rar_symlink(entry: &FileHeader) -> bool {
+    entry.file_attr & UNIX_FILE_TYPE_MASK == UNIX_SYMLINK
+        || entry.file_attr & WINDOWS_REPARSE_POINT != 0
+}
+
+/// Turns the name of an entry into a relative path, or returns `None` if it is absolute or
+/// goes up with `..`. Both separators are accepted, as archives made on Windows often use
+/// backslashes.
+pub fn safe_relative_path(name: &str) -> Option<PathBuf> {
+    if name.starts_with(['/', '\\']) {
+        return None;
+    }
+
+    let mut path = PathBuf::new();
+
+    for part in name.split(['/', '\\']) {
+        match part {
+            "" | "." => continue,
+            ".." => return None,
+            // a drive such as `C:` on Windows, or an alternate data stream
+            part if part.contains(':') => return None,
+            _ => {}
+        }
+
+        let mut components = Path::new(part).components();
+
+        match (components.next(), components.next()) {
+            (Some(Component::Normal(part)), None) => path.push(part),
+            _ => return None,
+        }
+    }
+
+    Some(path)
+}
@@ -0,0 +1,381 @@
+use super::scanner::find_product_ids;
+use crate::{database::models::v2::Product, dlsite::product_id::ProductId};
+use std::path::{Component, Path, PathBuf};
+use thiserror::Error;
+
+/// The template used when none is configured; every product is placed in a folder named after its ID.
+pub const DEFAULT_FOLDER_TEMPLATE: &str = "{id}";
+
+/// The maximum length of a single rendered folder name in bytes. Most filesystems allow 255.
+const MAX_SEGMENT_BYTES: usize = 240;
+/// The maximum length of the literal text in a single folder name of the template, in bytes.
+/// The rest is left for the product values.
+const MAX_LITERAL_BYTES: usize = 120;
+/// The maximum length of a single product value, such as the title, in characters.
+/// It is shortened further if the folder name still exceeds [`MAX_SEGMENT_BYTES`].
+const MAX_VALUE_CHARS: usize = 80;
+const MIN_VALUE_CHARS: usize = 8;
+
+/// Characters not allowed in file names on Windows, replaced with their full-width forms.
+const RESERVED_CHARS: [(char, char); 9] = [
+    ('<', '＜'),
+    ('>', '＞'),
+    (':', '：'),
+    ('"', '＂'),
+    ('/', '／'),
+    ('\\', '＼'),
+    ('|', '｜'),
+    ('?', '？'),
+    ('*', '＊'),
+];
+/// File names reserved by Windows regardless of their extension.
+const RESERVED_NAMES: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum FolderTemplateError {
+    #[error("the folder template contains an empty folder name")]
+    EmptyFolderName,
+    #[error("the placeholder `{{{name}}}` is unknown")]
+    UnknownPlaceholder { name: String },
+    #[error("the placeholder at {position} is not closed")]
+    UnclosedPlaceholder { position: usize },
+    #[error("the folder template must contain `{{id}}` exactly once")]
+    InvalidIdCount,
+    #[error("the character `{character}` is not allowed in a folder name")]
+    InvalidCharacter { character: char },
+    #[error("the text of a folder name is too long")]
+    TooLongFolderName,
+}
+
+/// A value of a product that can be placed in a folder name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FolderTemplateField {
+    Id,
+    Title,
+    GroupId,
+    GroupName,
+    Type,
+    Age,
+}
+
+impl FolderTemplateField {
+    pub const ALL: [FolderTemplateField; 6] = [
+        FolderTemplateField::Id,
+        FolderTemplateField::Title,
+        FolderTemplateField::GroupId,
+        FolderTemplateField::GroupName,
+        FolderTemplateField::Type,
+        FolderTemplateField::Age,
+    ];
+
+    /// The name of the placeholder, written as `{name}` in a template.
+    pub fn name(&self) -> &'static str {
+        match self {
+            FolderTemplateField::Id => "id",
+            FolderTemplateField::Title => "title",
+            FolderTemplateField::GroupId => "group_id",
+            FolderTemplateField::GroupName => "group_name",
+            FolderTemplateField::Type => "ty",
+            FolderTemplateField::Age => "age",
+        }
+    }
+
+    fn value(&self, product: &Product) -> String {
+        match self {
+            FolderTemplateField::Id => product.id.to_string(),
+            FolderTemplateField::Title => product.title.clone(),
+            FolderTemplateField::GroupId => product.group_id.clone(),
+            FolderTemplateField::GroupName => product.group_name.clone(),
+            FolderTemplateField::Type => product.ty.to_string(),
+            FolderTemplateField::Age => product.age.to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum FolderTemplatePart {
+    Literal(String),
+    Field(FolderTemplateField),
+}
+
+/// A layout of the product folders under the download root, such as `{group_name}/{id} {title}`.
+/// Every `/` starts a nested folder, and `{...}` is replaced with a value of the product.
+///
+/// The template must contain `{id}` exactly once, so that the product of an existing folder
+/// can be told from its path; see [`FolderTemplate::match_path`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FolderTemplate {
+    segments: Vec<Vec<FolderTemplatePart>>,
+    id_segment: usize,
+}
+
+impl FolderTemplate {
+    pub fn parse(template: &str) -> Result<Self, FolderTemplateError> {
+        let mut segments = Vec::new();
+        let mut offset = 0;
+
+        for segment in template.split(['/', '\\']) {
+            segments.push(parse_segment(segment, offset)?);
+            offset += segment.len() + 1;
+        }
+
+        let id_count = segments
+            .iter()
+            .flatten()
+            .filter(|part| **part == FolderTemplatePart::Field(FolderTemplateField::Id))
+            .count();
+
+        if id_count != 1 {
+            return Err(FolderTemplateError::InvalidIdCount);
+        }
+
+        let id_segment = segments
+            .iter()
+            .position(|parts| parts.contains(&FolderTemplatePart::Field(FolderTemplateField::Id)))
+            .unwrap();
+
+        Ok(Self {
+            segments,
+            id_segment,
+        })
+    }
+
+    /// The number of nested folders of a product folder, counted from the download root.
+    pub fn depth(&self) -> usize {
+        self.segments.len()
+    }
+
+    /// Renders the path of the product folder, relative to the download root.
+    /// Every folder name is sanitized, so the path never escapes the download root.
+    pub fn render(&self, product: &Product) -> PathBuf {
+        self.segments
+            .iter()
+            .map(|parts| render_segment(parts, product))
+            .collect()
+    }
+
+    /// Extracts the product ID of a folder laid out by this template, given its path relative to
+    /// the download root. Returns `None` if the path does not have the depth of the template,
+    /// or no product ID is found where the template places it.
+    pub fn match_path(&self, relative_path: &Path) -> Option<ProductId> {
+        let names = relative_path
+            .components()
+            .map(|component| match component {
+                Component::Normal(name) => name.to_str(),
+                _ => None,
+            })
+            .collect::<Option<Vec<_>>>()?;
+
+        if names.len() != self.segments.len() {
+            return None;
+        }
+
+        extract_id(&self.segments[self.id_segment], names[self.id_segment])
+    }
+}
+
+impl Default for FolderTemplate {
+    fn default() -> Self {
+        Self::parse(DEFAULT_FOLDER_TEMPLATE).unwrap()
+    }
+}
+
+fn parse_segment(
+    segment: &str,
+    offset: usize,
+) -> Result<Vec<FolderTemplatePart>, FolderTemplateError> {
+    let mut parts = Vec::new();
+    let mut literal = String::new();
+    let mut chars = segment.char_indices();
+
+    while let Some((index, c)) = chars.next() {
+        if c != '{' {
+            if c.is_control() || RESERVED_CHARS.iter().any(|(reserved, _)| *reserved == c) {
+                return Err(FolderTemplateError::InvalidCharacter { character: c });
+            }
+
+            literal.push(c);
+            continue;
+        }
+
+        let name = chars
+            .by_ref()
+            .map(|(_, c)| c)
+            .take_while(|c| *c != '}')
+            .collect::<String>();
+
+        if !segment[index..].contains('}') {
+            return Err(FolderTemplateError::UnclosedPlaceholder {
+                position: offset + index,
+            });
+        }
+
+        let field = FolderTemplateField::ALL
+            .into_iter()
+            .find(|field| field.name() == name)
+            .ok_or_else(|| FolderTemplateError::UnknownPlaceholder { name })?;
+
+        if !literal.is_empty() {
+            parts.push(FolderTemplatePart::Literal(std::mem::take(&mut literal)));
+        }
+
+        parts.push(FolderTemplatePart::Field(field));
+    }
+
+    if !literal.is_empty() {
+        parts.push(FolderTemplatePart::Literal(literal));
+    }
+
+    if parts.is_empty() {
+        return Err(FolderTemplateError::EmptyFolderName);
+    }
+
+    let literal_len = parts
+        .iter()
+        .map(|part| match part {
+            FolderTemplatePart::Literal(text) => text.len(),
+            FolderTemplatePart::Field(_) => 0,
+        })
+        .sum::<usize>();
+
+    if MAX_LITERAL_BYTES < literal_len {
+        return Err(FolderTemplateError::TooLongFolderName);
+    }
+
+    Ok(parts)
+}
+
+/// Renders a single folder name. The product values are shortened until the name fits.
+fn render_segment(parts: &[FolderTemplatePart], product: &Product) -> String {
+    let mut max_value_chars = MAX_VALUE_CHARS;
+
+    loop {
+        let name = parts
+            .iter()
+            .map(|part| match part {
+                FolderTemplatePart::Literal(text) => text.clone(),
+                FolderTemplatePart::Field(FolderTemplateField::Id) => product.id.to_string(),
+                FolderTemplatePart::Field(field) => {
+                    sanitize_value(&field.value(product), max_value_chars)
+                }
+            })
+            .collect::<String>();
+        let name = sanitize_folder_name(&name);
+
+        if name.len() <= MAX_SEGMENT_BYTES || max_value_chars <= MIN_VALUE_CHARS {
+            return name;
+        }
+
+        max_value_chars /= 2;
+    }
+}
+
+/// Makes a product value safe to be a part of a folder name on every platform.
+fn sanitize_value(value: &str, max_chars: usize) -> String {
+    let sanitized = value
+        .chars()
+        .map(|c| {
+            if c.is_control() {
+                return ' ';
+            }
+
+            RESERVED_CHARS
+                .iter()
+                .find(|(reserved, _)| *reserved == c)
+                .map_or(c, |(_, replacement)| *replacement)
+        })
+        .collect::<String>();
+
+    sanitized.trim().chars().take(max_chars).collect()
+}
+
+/// Fixes up a whole folder name: Windows ignores trailing dots and spaces, and rejects
+/// the reserved device names. An empty name is replaced with `_`.
+fn sanitize_folder_name(name: &str) -> String {
+    let name = name
+        .trim_start()
+        .trim_end_matches(|c: char| c == '.' || c.is_whitespace());
+
+    if name.is_empty() {
+        return "_".to_owned();
+    }
+
+    let stem = name.split('.').next().unwrap_or_default();
+
+    if RESERVED_NAMES
+        .iter()
+        .any(|reserved| reserved.eq_ignore_ascii_case(stem.trim_end()))
+    {
+        return format!("{}_", name);
+    }
+
+    name.to_owned()
+}
+
+/// Finds the product ID in a folder name rendered from the given parts. The ID is looked up
+/// right after the literal text before `{id}` or right before the literal text after it;
+/// otherwise the folder name must contain exactly one product ID.
+fn extract_id(parts: &[FolderTemplatePart], name: &str) -> Option<ProductId> {
+    let position = parts
+        .iter()
+        .position(|part| *part == FolderTemplatePart::Field(FolderTemplateField::Id))?;
+
+    if let Some(prefix) = literal_text(&parts[..position]) {
+        if let Some(id) = name
+            .strip_prefix(prefix.as_str())
+            .and_then(parse_leading_id)
+        {
+            return Some(id);
+        }
+    }
+
+    if let Some(suffix) = literal_text(&parts[position + 1..]) {
+        if let Some(id) = name
+            .strip_suffix(suffix.as_str())
+            .and_then(parse_trailing_id)
+        {
+            return Some(id);
+        }
+    }
+
+    let mut ids = find_product_ids(name);
+
+    if ids.len() == 1 {
+        ids.pop()
+    } else {
+        None
+    }
+}
+
+/// Concatenates the parts if all of them are literal.
+fn literal_text(parts: &[FolderTemplatePart]) -> Option<String> {
+    parts
+        .iter()
+        .map(|part| match part {
+            FolderTemplatePart::Literal(text) => Some(text.as_str()),
+            FolderTemplatePart::Field(_) => None,
+        })
+        .collect()
+}
+
+fn parse_leading_id(text: &str) -> Option<ProductId> {
+    let digits = text
+        .get(2..)?
+        .bytes()
+        .take_while(|byte| byte.is_ascii_digit())
+        .count();
+    ProductId::parse(&text[..2 + digits]).ok()
+}
+
+fn parse_trailing_id(text: &str) -> Option<ProductId> {
+    let digits = text
+        .bytes()
+        .rev()
+        .take_while(|byte| byte.is_ascii_digit())
+        .count();
+    let start = text.len().checked_sub(digits + 2)?;
+    ProductId::parse(text.get(start..)?).ok()
+}
@@ -0,0 +1,145 @@
+use crate::database::models::v2::ProductManifestFile;
+use anyhow::{Context, Error as AnyError};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    fs::{read_dir, File},
+    io::{BufReader, Read},
+    path::{Path, PathBuf},
+};
+
+/// How a file differs from the manifest.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum ManifestProblemKind {
+    /// the file in the manifest no longer exists
+    Missing,
+    /// the file has a different size or content
+    Modified,
+    /// the file is not in the manifest
+    Added,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ManifestProblem {
+    pub kind: ManifestProblemKind,
+    pub path: String,
+}
+
+/// Records every file under the product folder with its size and hash.
+/// Symbolic links are not followed, nor recorded.
+pub fn build_manifest(root: &Path) -> Result<Vec<ProductManifestFile>, AnyError> {
+    let mut files = Vec::new();
+
+    for (path, relative_path) in list_files(root)? {
+        let (size, hash) = hash_file(&path)?;
+        files.push(ProductManifestFile {
+            path: relative_path,
+            size,
+            hash,
+        });
+    }
+
+    Ok(files)
+}
+
+/// Compares the product folder with its manifest. The content of a file is only hashed
+/// if its size matches, as a different size is a difference already.
+pub fn verify_manifest(
+    root: &Path,
+    manifest: &[ProductManifestFile],
+) -> Result<Vec<ManifestProblem>, AnyError> {
+    let mut files = list_files(root)?
+        .into_iter()
+        .map(|(path, relative_path)| (relative_path, path))
+        .collect::<HashMap<_, _>>();
+    let mut problems = Vec::new();
+
+    for expected in manifest {
+        let kind = match files.remove(&expected.path) {
+            None => Some(ManifestProblemKind::Missing),
+            Some(path) if path.metadata()?.len() != expected.size => {
+                Some(ManifestProblemKind::Modified)
+            }
+            Some(path) if hash_file(&path)?.1 != expected.hash => {
+                Some(ManifestProblemKind::Modified)
+            }
+            Some(_) => None,
+        };
+
+        if let Some(kind) = kind {
+            problems.push(ManifestProblem {
+                kind,
+                path: expected.path.clone(),
+            });
+        }
+    }
+
+    problems.extend(files.into_keys().map(|path| ManifestProblem {
+        kind: ManifestProblemKind::Added,
+        path,
+    }));
+    problems.sort_by(|a, b| a.path.cmp(&b.path));
+
+    Ok(problems)
+}
+
+/// Lists the files under the folder along with their paths relative to it, separated by `/`
+/// on every platform so that a manifest stays valid wherever the library is opened.
+fn list_files(root: &Path) -> Result<Vec<(PathBuf, String)>, AnyError> {
+    let mut files = Vec::new();
+    let mut folders = vec![root.to_owned()];
+
+    while let Some(folder) = folders.pop() {
+        let entries = read_dir(&folder)
+            .with_context(|| format!("[list_files]"))
+            .with_context(|| format!("failed to read the folder `{}`", folder.display()))?;
+
+        for entry in entries {
+            let entry = entry?;
+            let file_type = entry.file_type()?;
+
+            if file_type.is_dir() {
+                folders.push(entry.path());
+            } else if file_type.is_file() {
+                let path = entry.path();
+                let relative_path = path
+                    .strip_prefix(root)?
+                    .components()
+                    .map(|component| component.as_os_str().to_string_lossy())
+                    .collect::<Vec<_>>()
+                    .join("/");
+                files.push((path, relative_path));
+            }
+        }
+    }
+
+    files.sort_by(|a, b| a.1.cmp(&b.1));
+    Ok(files)
+}
+
+fn hash_file(path: &Path) -> Result<(u64, String), AnyError> {
+    let file = File::open(path)
+        .with_context(|| format!("[hash_file]"))
+        .with_context(|| format!("failed to open the file `{}`", path.display()))?;
+    let mut reader = BufReader::new(file);
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0; 1024 * 1024];
+    let mut size = 0;
+
+    loop {
+        let read = reader
+            .read(&mut buffer)
+            .with_context(|| format!("[hash_file]"))
+            .with_context(|| format!("failed to read the file `{}`", path.display()))?;
+
+        if read == 0 {
+            break;
+        }
+
+        hasher.update(&buffer[..read]);
+        size += read as u64;
+    }
+
+    Ok((size, format!("{:x}", hasher.finalize())))
+}
@@ -0,0 +1,11 @@
+pub mod archive;
+pub mod extraction;
+pub mod folder_template;
+pub mod manifest;
+pub mod name_encoding;
+pub mod relocation;
+pub mod scanner;
+pub mod watcher;
+
+#[cfg(test)]
+mod tests;
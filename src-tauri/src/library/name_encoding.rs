@@ -0,0 +1,237 @@
+use anyhow::{Context, Error as AnyError};
+use encoding_rs::{Encoding, EUC_JP, GBK, SHIFT_JIS, UTF_8};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{read_dir, rename},
+    path::{Path, PathBuf},
+};
+
+/// The characters of the bytes 0x80 to 0xFF in CP437, which zip archives without the UTF-8 flag
+/// are decoded in by default.
+const CP437_HIGH_CHARS: [char; 128] = [
+    'Ç', 'ü', 'é', 'â', 'ä', 'à', 'å', 'ç', 'ê', 'ë', 'è', 'ï', 'î', 'ì', 'Ä', 'Å', //
+    'É', 'æ', 'Æ', 'ô', 'ö', 'ò', 'û', 'ù', 'ÿ', 'Ö', 'Ü', '¢', '£', '¥', '₧', 'ƒ', //
+    'á', 'í', 'ó', 'ú', 'ñ', 'Ñ', 'ª', 'º', '¿', '⌐', '¬', '½', '¼', '¡', '«', '»', //
+    '░', '▒', '▓', '│', '┤', '╡', '╢', '╖', '╕', '╣', '║', '╗', '╝', '╜', '╛', '┐', //
+    '└', '┴', '┬', '├', '─', '┼', '╞', '╟', '╚', '╔', '╩', '╦', '╠', '═', '╬', '╧', //
+    '╨', '╤', '╥', '╙', '╘', '╒', '╓', '╫', '╪', '┘', '┌', '█', '▄', '▌', '▐', '▀', //
+    'α', 'ß', 'Γ', 'π', 'Σ', 'σ', 'µ', 'τ', 'Φ', 'Θ', 'Ω', 'δ', '∞', 'φ', 'ε', '∩', //
+    '≡', '±', '≥', '≤', '⌠', '⌡', '÷', '≈', '°', '∙', '·', '√', 'ⁿ', '²', '■', '\u{a0}',
+];
+
+/// The encoding of the file names in an archive that has no UTF-8 flag.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum NameEncoding {
+    Utf8,
+    /// Shift_JIS as extended by Windows, which most Japanese archives are made with
+    Cp932,
+    EucJp,
+    Gbk,
+}
+
+impl NameEncoding {
+    /// Every encoding, in the order preferred when the names decode equally well.
+    pub const ALL: [NameEncoding; 4] = [Self::Utf8, Self::Cp932, Self::EucJp, Self::Gbk];
+
+    fn encoding(self) -> &'static Encoding {
+        match self {
+            Self::Utf8 => UTF_8,
+            // the WHATWG Shift_JIS is CP932
+            Self::Cp932 => SHIFT_JIS,
+            Self::EucJp => EUC_JP,
+            Self::Gbk => GBK,
+        }
+    }
+
+    /// Decodes the name, replacing the malformed sequences.
+    pub fn decode(self, name: &[u8]) -> String {
+        self.encoding()
+            .decode_without_bom_handling(name)
+            .0
+            .into_owned()
+    }
+
+    /// Decodes the name, or returns `None` if it is not valid in the encoding.
+    pub fn decode_strict(self, name: &[u8]) -> Option<String> {
+        self.encoding()
+            .decode_without_bom_handling_and_without_replacement(name)
+            .map(|name| name.into_owned())
+    }
+}
+
+/// Guesses the encoding of the names. Each encoding that decodes every name is scored by
+/// how Japanese or Chinese the decoded names look, since a wrong guess tends to produce
+/// half-width katakana and symbols instead.
+pub fn detect_name_encoding<'a>(names: impl IntoIterator<Item = &'a [u8]>) -> NameEncoding {
+    let names = names
+        .into_iter()
+        .filter(|name| !name.is_ascii())
+        .collect::<Vec<_>>();
+
+    if names.is_empty() {
+        return NameEncoding::Utf8;
+    }
+
+    let mut best = None;
+
+    for encoding in NameEncoding::ALL {
+        let decoded = match names
+            .iter()
+            .map(|name| encoding.decode_strict(name))
+            .collect::<Option<Vec<_>>>()
+        {
+            Some(decoded) => decoded,
+            None => continue,
+        };
+
+        // valid UTF-8 is almost never a coincidence
+        if encoding == NameEncoding::Utf8 {
+            return encoding;
+        }
+
+        let score = decoded.iter().map(|name| score_name(name)).sum::<i64>();
+
+        if best.map_or(true, |(_, best_score)| best_score < score) {
+            best = Some((encoding, score));
+        }
+    }
+
+    best.map_or(NameEncoding::Cp932, |(encoding, _)| encoding)
+}
+
+fn score_name(name: &str) -> i64 {
+    name.chars()
+        .map(|char| match char {
+            // hiragana and katakana
+            '\u{3040}'..='\u{30ff}' => 3,
+            // CJK punctuation and full-width forms
+            '\u{3000}'..='\u{303f}' | '\u{ff01}'..='\u{ff60}' => 1,
+            // CJK ideographs
+            '\u{4e00}'..='\u{9fff}' => 1,
+            // half-width katakana, which is rare in file names
+            '\u{ff61}'..='\u{ff9f}' => -2,
+            char if char.is_ascii() => 0,
+            _ => -3,
+        })
+        .sum()
+}
+
+/// Reverts a name decoded in CP437 back to its bytes, or returns `None` if the name contains
+/// a character that CP437 does not have, meaning it was not decoded so.
+pub fn encode_cp437(name: &str) -> Option<Vec<u8>> {
+    name.chars()
+        .map(|char| {
+            if char.is_ascii() {
+                Some(char as u8)
+            } else {
+                CP437_HIGH_CHARS
+                    .iter()
+                    .position(|high_char| *high_char == char)
+                    .map(|index| 0x80 + index as u8)
+            }
+        })
+        .collect()
+}
+
+/// A file or folder name that was decoded in a wrong encoding, renamed by [`fix_names`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct NameFix {
+    pub from: PathBuf,
+    pub to: PathBuf,
+}
+
+/// Renames the files and folders under the folder whose names were decoded in CP437 when
+/// extracted, decoding them in the encoding instead, or in the one guessed from the names.
+/// A name is left as it is if it is not CP437, or if another file already has the new name.
+pub fn fix_names(
+    root: &Path,
+    encoding: Option<NameEncoding>,
+) -> Result<(NameEncoding, Vec<NameFix>), AnyError> {
+    let mut entries = Vec::new();
+    list_entries(root, &mut entries)?;
+
+    let raw_names = entries
+        .iter()
+        .filter_map(|(_, raw_name)| raw_name.as_deref())
+        .collect::<Vec<_>>();
+    let encoding = encoding.unwrap_or_else(|| detect_name_encoding(raw_names));
+    let mut fixes = Vec::new();
+
+    // the deepest entries come first, so that their parents are still where they were listed
+    entries.sort_by_key(|(path, _)| std::cmp::Reverse(path.components().count()));
+
+    for (path, raw_name) in entries {
+        let raw_name = match raw_name {
+            Some(raw_name) => raw_name,
+            None => continue,
+        };
+        let name = match encoding.decode_strict(&raw_name) {
+            Some(name) if !name.contains(['/', '\\']) => name,
+            _ => continue,
+        };
+        let to = path.with_file_name(name);
+
+        if to == path {
+            continue;
+        }
+
+        if to.exists() {
+            warn!(
+                "[fix_names] not renaming `{}`, as `{}` already exists",
+                path.display(),
+                to.display()
+            );
+            continue;
+        }
+
+        rename(&path, &to)
+            .with_context(|| format!("[fix_names]"))
+            .with_context(|| {
+                format!(
+                    "failed to rename `{}` to `{}`",
+                    path.display(),
+                    to.display()
+                )
+            })?;
+        fixes.push(NameFix { from: path, to });
+    }
+
+    info!(
+        "[fix_names] renamed {} names under `{}` as {:?}",
+        fixes.len(),
+        root.display(),
+        encoding
+    );
+
+    Ok((encoding, fixes))
+}
+
+/// Lists every entry under the folder along with its name reverted to the bytes from CP437,
+/// if it is a non-ASCII name that CP437 has every character of.
+fn list_entries(
+    folder: &Path,
+    entries: &mut Vec<(PathBuf, Option<Vec<u8>>)>,
+) -> Result<(), AnyError> {
+    let read = read_dir(folder)
+        .with_context(|| format!("[list_entries]"))
+        .with_context(|| format!("failed to read the folder `{}`", folder.display()))?;
+
+    for entry in read {
+        let entry = entry?;
+        let path = entry.path();
+        let raw_name = entry
+            .file_name()
+            .to_str()
+            .filter(|name| !name.is_ascii())
+            .and_then(encode_cp437);
+
+        if entry.file_type()?.is_dir() {
+            list_entries(&path, entries)?;
+        }
+
+        entries.push((path, raw_name));
+    }
+
+    Ok(())
+}
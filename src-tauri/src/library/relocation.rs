@@ -0,0 +1,215 @@
+use anyhow::{anyhow, Context, Error as AnyError};
+use log::{info, warn};
+use std::{
+    fs::{copy, create_dir, create_dir_all, read_dir, remove_dir, remove_dir_all, rename, File},
+    io::{BufReader, Read},
+    path::{Path, PathBuf},
+};
+
+/// The suffix of the folder a product is copied into before it is moved into place.
+pub const STAGING_SUFFIX: &str = ".moving";
+
+/// How a folder was moved.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MoveMethod {
+    /// renamed in place, on the same filesystem
+    Rename,
+    /// copied and verified, then the source was removed; used across filesystems
+    Copy,
+}
+
+/// Moves a folder to `to`, creating its parent folders. The folder is renamed if possible;
+/// otherwise, e.g. across filesystems, it is copied next to the target, verified against
+/// the source, moved into place and only then is the source removed.
+/// The target must not exist.
+pub fn move_folder(from: &Path, to: &Path) -> Result<MoveMethod, AnyError> {
+    if let Some(parent) = to.parent() {
+        create_dir_all(parent)
+            .with_context(|| format!("[move_folder]"))
+            .with_context(|| format!("failed to create the folder `{}`", parent.display()))?;
+    }
+
+    match rename(from, to) {
+        Ok(()) => return Ok(MoveMethod::Rename),
+        Err(err) if !from.is_dir() => {
+            return Err(err)
+                .with_context(|| format!("[move_folder]"))
+                .with_context(|| format!("the folder `{}` does not exist", from.display()));
+        }
+        Err(err) => {
+            info!(
+                "[move_folder] failed to rename `{}` to `{}`; copying instead: {}",
+                from.display(),
+                to.display(),
+                err
+            );
+        }
+    }
+
+    let mut staging_name = to.file_name().unwrap_or_default().to_owned();
+    staging_name.push(STAGING_SUFFIX);
+    let staging = to.with_file_name(staging_name);
+
+    if staging.exists() {
+        remove_dir_all(&staging)
+            .with_context(|| format!("[move_folder]"))
+            .with_context(|| {
+                format!(
+                    "failed to remove the stale staging folder `{}`",
+                    staging.display()
+                )
+            })?;
+    }
+
+    let copied = copy_folder(from, &staging).and_then(|_| verify_copy(from, &staging));
+
+    if let Err(err) = copied {
+        if let Err(err) = remove_dir_all(&staging) {
+            warn!(
+                "[move_folder] failed to remove the staging folder `{}`: {:?}",
+                staging.display(),
+                err
+            );
+        }
+
+        return Err(err)
+            .with_context(|| format!("[move_folder]"))
+            .with_context(|| {
+                format!(
+                    "failed to copy `{}` to `{}`",
+                    from.display(),
+                    staging.display()
+                )
+            });
+    }
+
+    rename(&staging, to)
+        .with_context(|| format!("[move_folder]"))
+        .with_context(|| {
+            format!(
+                "failed to move `{}` into `{}`",
+                staging.display(),
+                to.display()
+            )
+        })?;
+
+    // the product is safe at the target from here; a leftover source only wastes space
+    if let Err(err) = remove_dir_all(from) {
+        warn!(
+            "[move_folder] failed to remove the source folder `{}` after copying it: {:?}",
+            from.display(),
+            err
+        );
+    }
+
+    Ok(MoveMethod::Copy)
+}
+
+/// Removes the folders left empty between a removed folder and the root (exclusive),
+/// such as the folder of a circle whose last product was moved away.
+pub fn remove_empty_parents(path: &Path, root: &Path) {
+    for parent in path.ancestors().skip(1) {
+        if parent == root || !parent.starts_with(root) {
+            break;
+        }
+
+        // fails if the folder is not empty
+        if remove_dir(parent).is_err() {
+            break;
+        }
+    }
+}
+
+/// Copies the folder recursively. Symbolic links are rejected, since they may point outside of it.
+fn copy_folder(from: &Path, to: &Path) -> Result<(), AnyError> {
+    create_dir(to)?;
+
+    for entry in read_dir(from)? {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        let target = to.join(entry.file_name());
+
+        if file_type.is_symlink() {
+            return Err(anyhow!(
+                "the symbolic link `{}` cannot be copied",
+                entry.path().display()
+            ));
+        } else if file_type.is_dir() {
+            copy_folder(&entry.path(), &target)?;
+        } else {
+            copy(entry.path(), &target)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Verifies that the copy has the same files with the same contents as the source.
+fn verify_copy(from: &Path, to: &Path) -> Result<(), AnyError> {
+    let source_files = list_files(from)?;
+    let copied_files = list_files(to)?;
+
+    if source_files != copied_files {
+        return Err(anyhow!(
+            "the copy of `{}` does not have the same files",
+            from.display()
+        ));
+    }
+
+    for relative_path in source_files {
+        if !is_same_content(&from.join(&relative_path), &to.join(&relative_path))? {
+            return Err(anyhow!(
+                "the copy of `{}` differs from the source",
+                from.join(&relative_path).display()
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// Lists the files under the folder as sorted paths relative to it.
+fn list_files(root: &Path) -> Result<Vec<PathBuf>, AnyError> {
+    let mut files = Vec::new();
+    let mut folders = vec![root.to_owned()];
+
+    while let Some(folder) = folders.pop() {
+        for entry in read_dir(&folder)? {
+            let entry = entry?;
+
+            if entry.file_type()?.is_dir() {
+                folders.push(entry.path());
+            } else {
+                files.push(entry.path().strip_prefix(root)?.to_owned());
+            }
+        }
+    }
+
+    files.sort();
+    Ok(files)
+}
+
+fn is_same_content(left: &Path, right: &Path) -> Result<bool, AnyError> {
+    if left.metadata()?.len() != right.metadata()?.len() {
+        return Ok(false);
+    }
+
+    let mut left = BufReader::new(File::open(left)?);
+    let mut right = BufReader::new(File::open(right)?);
+    let mut left_buffer = vec![0; 64 * 1024];
+    let mut right_buffer = vec![0; 64 * 1024];
+
+    loop {
+        let read = left.read(&mut left_buffer)?;
+
+        if read == 0 {
+            return Ok(true);
+        }
+
+        right.read_exact(&mut right_buffer[..read])?;
+
+        if left_buffer[..read] != right_buffer[..read] {
+            return Ok(false);
+        }
+    }
+}
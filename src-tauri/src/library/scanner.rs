@@ -0,0 +1,294 @@
+use super::{folder_template::FolderTemplate, relocation::STAGING_SUFFIX};
+use crate::{database::models::v2::ProductDownload, dlsite::product_id::ProductId};
+use anyhow::Error as AnyError;
+use lazy_static::lazy_static;
+use log::info;
+use regex::Regex;
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, HashMap},
+    ffi::OsStr,
+    fs::read_dir,
+    path::{Path, PathBuf},
+};
+
+/// The default number of folder levels searched under the download root.
+pub const DEFAULT_SCAN_DEPTH: u32 = 4;
+/// The maximum number of folder levels searched under the download root.
+pub const MAX_SCAN_DEPTH: u32 = 16;
+
+/// The extensions of the archives recognized as not extracted products.
+const ARCHIVE_EXTENSIONS: [&str; 4] = ["zip", "rar", "7z", "lzh"];
+
+lazy_static! {
+    /// A product ID candidate; its boundaries and number of digits are checked afterwards,
+    /// since the regex crate has no look-around.
+    static ref PRODUCT_ID_REGEX: Regex = Regex::new(r"(?i)(?:RJ|RE|BJ|VJ)[0-9]+").unwrap();
+}
+
+/// Finds every distinct product ID in the text that is not a part of a longer word,
+/// such as `RJ01234567` in `[RJ01234567] Title`.
+pub fn find_product_ids(text: &str) -> Vec<ProductId> {
+    let mut ids = Vec::new();
+
+    for found in PRODUCT_ID_REGEX.find_iter(text) {
+        let is_word_start = text[..found.start()]
+            .chars()
+            .next_back()
+            .map_or(true, |char| !char.is_ascii_alphanumeric());
+        let is_word_end = text[found.end()..]
+            .chars()
+            .next()
+            .map_or(true, |char| !char.is_ascii_alphanumeric());
+
+        if !is_word_start || !is_word_end {
+            continue;
+        }
+
+        if let Ok(id) = ProductId::parse(found.as_str()) {
+            if !ids.contains(&id) {
+                ids.push(id);
+            }
+        }
+    }
+
+    ids
+}
+
+/// What a scanned product download is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum ScannedProductKind {
+    /// an extracted product folder
+    Folder,
+    /// an archive that is not extracted
+    Archive,
+}
+
+/// A product found under the download root.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScannedProduct {
+    pub id: ProductId,
+    pub path: PathBuf,
+    pub kind: ScannedProductKind,
+}
+
+/// Why a folder or an archive could not be assigned to a single product.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum ScanAmbiguityKind {
+    /// the name contains more than one product ID
+    MultipleIds,
+    /// the folder is inside of the folder of another product, which is `related_path`
+    Nested,
+    /// the product is also found at `related_path`, which is the one kept
+    Duplicate,
+}
+
+/// A match that is left for the user to resolve, e.g. by renaming the folder.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ScanAmbiguity {
+    pub kind: ScanAmbiguityKind,
+    pub path: PathBuf,
+    pub product_ids: Vec<ProductId>,
+    pub related_path: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct LibraryScan {
+    pub products: Vec<ScannedProduct>,
+    pub ambiguities: Vec<ScanAmbiguity>,
+}
+
+/// The changes of a scan against the recorded product downloads.
+#[derive(Debug, Clone, Default)]
+pub struct ScanDiff {
+    /// the products found in a new place, or found again after they were missing
+    pub found: Vec<ScannedProduct>,
+    /// the recorded products whose folders no longer exist, which were not missing before
+    pub missing: Vec<ProductId>,
+}
+
+/// Compares a scan with the recorded product downloads. A recorded download that still exists
+/// is kept even if the product is found elsewhere, and one that is not found by the scan is
+/// only missing if its folder no longer exists, e.g. it may be outside of the download root.
+pub fn diff_scan(downloads: &[ProductDownload], scanned_products: Vec<ScannedProduct>) -> ScanDiff {
+    let mut downloads = downloads
+        .iter()
+        .map(|download| (&download.product_id, download))
+        .collect::<HashMap<_, _>>();
+    let mut diff = ScanDiff::default();
+
+    for product in scanned_products {
+        match downloads.remove(&product.id) {
+            Some(download) if download.missing_at.is_none() && download.path == product.path => {}
+            Some(download) if download.missing_at.is_none() && download.path.exists() => {}
+            _ => diff.found.push(product),
+        }
+    }
+
+    for download in downloads.into_values() {
+        if download.missing_at.is_none() && !download.path.exists() {
+            diff.missing.push(download.product_id.clone());
+        }
+    }
+
+    diff.missing.sort();
+    diff
+}
+
+/// Scans the download root for products, down to the given number of folder levels.
+/// A folder belongs to a product if it is laid out by the folder template, or if its name
+/// contains a single product ID; archives are recognized by their names as well.
+/// Product folders are searched further, to report the products nested inside of them.
+pub fn scan_library(
+    root: &Path,
+    template: &FolderTemplate,
+    max_depth: u32,
+) -> Result<LibraryScan, AnyError> {
+    let max_depth = max_depth.max(template.depth() as u32).min(MAX_SCAN_DEPTH);
+    let mut scan = LibraryScan::default();
+
+    scan_folder(root, root, template, 1, max_depth, None, &mut scan)?;
+    remove_duplicates(&mut scan);
+
+    Ok(scan)
+}
+
+fn scan_folder(
+    root: &Path,
+    path: &Path,
+    template: &FolderTemplate,
+    depth: u32,
+    max_depth: u32,
+    owner: Option<&ScannedProduct>,
+    scan: &mut LibraryScan,
+) -> Result<(), AnyError> {
+    let mut entries = read_dir(path)?.collect::<Result<Vec<_>, _>>()?;
+    entries.sort_by_key(|entry| entry.file_name());
+
+    for entry in entries {
+        let file_name = entry.file_name();
+        let name = match file_name.to_str() {
+            Some(name) if !name.starts_with('.') && !name.ends_with(STAGING_SUFFIX) => name,
+            _ => continue,
+        };
+        let file_type = entry.file_type()?;
+        let path = entry.path();
+
+        let kind = if file_type.is_dir() {
+            ScannedProductKind::Folder
+        } else if file_type.is_file() && owner.is_none() && is_archive(&path) {
+            ScannedProductKind::Archive
+        } else {
+            continue;
+        };
+
+        let ids = match kind {
+            ScannedProductKind::Folder => match template.match_path(path.strip_prefix(root)?) {
+                Some(id) => vec![id],
+                None => find_product_ids(name),
+            },
+            ScannedProductKind::Archive => find_product_ids(name),
+        };
+
+        match (ids.len(), owner) {
+            (0, _) => {
+                if kind == ScannedProductKind::Folder && depth < max_depth {
+                    scan_folder(root, &path, template, depth + 1, max_depth, owner, scan)?;
+                }
+            }
+            (1, Some(owner)) => {
+                // extracted archives often have an inner folder named after the product itself
+                if ids[0] != owner.id {
+                    scan.ambiguities.push(ScanAmbiguity {
+                        kind: ScanAmbiguityKind::Nested,
+                        path,
+                        product_ids: ids,
+                        related_path: Some(owner.path.clone()),
+                    });
+                }
+            }
+            (1, None) => {
+                let product = ScannedProduct {
+                    id: ids.into_iter().next().unwrap(),
+                    path: path.clone(),
+                    kind,
+                };
+
+                if kind == ScannedProductKind::Folder && depth < max_depth {
+                    scan_folder(
+                        root,
+                        &path,
+                        template,
+                        depth + 1,
+                        max_depth,
+                        Some(&product),
+                        scan,
+                    )?;
+                }
+
+                scan.products.push(product);
+            }
+            _ => {
+                info!(
+                    "[scan_folder] skipping `{}`: more than one product id found",
+                    path.display()
+                );
+                scan.ambiguities.push(ScanAmbiguity {
+                    kind: ScanAmbiguityKind::MultipleIds,
+                    path,
+                    product_ids: ids,
+                    related_path: None,
+                });
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Keeps a single download of every product. A folder is preferred over an archive, since
+/// the archive is usually the source of the folder next to it, and split archives are
+/// silently represented by their first part. More than one folder is reported.
+fn remove_duplicates(scan: &mut LibraryScan) {
+    let mut products = BTreeMap::<ProductId, Vec<ScannedProduct>>::new();
+
+    for product in scan.products.drain(..) {
+        products
+            .entry(product.id.clone())
+            .or_default()
+            .push(product);
+    }
+
+    for (id, mut candidates) in products {
+        candidates.sort_by(|a, b| {
+            (a.kind != ScannedProductKind::Folder, &a.path)
+                .cmp(&(b.kind != ScannedProductKind::Folder, &b.path))
+        });
+
+        let mut candidates = candidates.into_iter();
+        let kept = candidates.next().unwrap();
+
+        for duplicate in candidates {
+            if duplicate.kind == ScannedProductKind::Folder {
+                scan.ambiguities.push(ScanAmbiguity {
+                    kind: ScanAmbiguityKind::Duplicate,
+                    path: duplicate.path,
+                    product_ids: vec![id.clone()],
+                    related_path: Some(kept.path.clone()),
+                });
+            }
+        }
+
+        scan.products.push(kept);
+    }
+}
+
+fn is_archive(path: &Path) -> bool {
+    path.extension()
+        .and_then(OsStr::to_str)
+        .map_or(false, |extension| {
+            ARCHIVE_EXTENSIONS
+                .iter()
+                .any(|archive| extension.eq_ignore_ascii_case(archive))
+        })
+}
@@ -0,0 +1,705 @@
+use super::{
+    archive::{
+        plan_extraction, sniff_file, ArchiveFormat, DetectedArchive, ExtractionPlan,
+        ExtractionSkipReason, FileSignature,
+    },
+    extraction::{extract_zip, safe_relative_path, ExtractionProgress, SkippedEntryReason},
+    folder_template::{FolderTemplate, FolderTemplateError},
+    manifest::{build_manifest, verify_manifest, ManifestProblem, ManifestProblemKind},
+    name_encoding::{detect_name_encoding, fix_names, NameEncoding},
+    relocation::{move_folder, remove_empty_parents, MoveMethod},
+    scanner::{
+        diff_scan, find_product_ids, scan_library, ScanAmbiguityKind, ScannedProduct,
+        ScannedProductKind,
+    },
+};
+use crate::{
+    database::models::v2::{Product, ProductDownload},
+    dlsite::{
+        dto::{DLsiteProductAgeCategory, DLsiteProductType},
+        product_id::ProductId,
+    },
+};
+use std::{
+    fs::{create_dir_all, read_to_string, remove_file, write},
+    path::{Path, PathBuf},
+    sync::atomic::AtomicBool,
+};
+
+fn product(id: &str, title: &str, group_name: &str) -> Product {
+    Product {
+        id: ProductId::parse(id).unwrap(),
+        account_id: None,
+        ty: DLsiteProductType::Voice,
+        age: DLsiteProductAgeCategory::R18,
+        title: title.to_owned(),
+        thumbnail: String::new(),
+        group_id: "RG00001".to_owned(),
+        group_name: group_name.to_owned(),
+        registered_at: None,
+    }
+}
+
+#[test]
+fn parse_folder_template() {
+    assert!(FolderTemplate::parse("{id}").is_ok());
+    assert!(FolderTemplate::parse("{group_name}/{id} {title}").is_ok());
+    assert!(FolderTemplate::parse("{ty}\\{age}\\{id}").is_ok());
+    assert_eq!(
+        FolderTemplate::parse("{title}"),
+        Err(FolderTemplateError::InvalidIdCount)
+    );
+    assert_eq!(
+        FolderTemplate::parse("{id}/{id}"),
+        Err(FolderTemplateError::InvalidIdCount)
+    );
+    assert_eq!(
+        FolderTemplate::parse("{name} {id}"),
+        Err(FolderTemplateError::UnknownPlaceholder {
+            name: "name".to_owned()
+        })
+    );
+    assert_eq!(
+        FolderTemplate::parse("{id} {title"),
+        Err(FolderTemplateError::UnclosedPlaceholder { position: 5 })
+    );
+    assert_eq!(
+        FolderTemplate::parse("/{id}"),
+        Err(FolderTemplateError::EmptyFolderName)
+    );
+    assert_eq!(
+        FolderTemplate::parse("C:{id}"),
+        Err(FolderTemplateError::InvalidCharacter { character: ':' })
+    );
+}
+
+#[test]
+fn render_folder_template() {
+    let template = FolderTemplate::parse("{group_name}/{id} {title}").unwrap();
+
+    assert_eq!(
+        template.render(&product("RJ123456", "タイトル", "サークル")),
+        PathBuf::from("サークル").join("RJ123456 タイトル")
+    );
+    assert_eq!(
+        FolderTemplate::parse("{ty}/{age}/{id}")
+            .unwrap()
+            .render(&product("RJ123456", "", "")),
+        PathBuf::from("Voice").join("R18").join("RJ123456")
+    );
+}
+
+#[test]
+fn render_folder_template_sanitizes_values() {
+    let template = FolderTemplate::parse("{group_name}/{id} {title}").unwrap();
+
+    // separators and reserved characters never create extra folders
+    assert_eq!(
+        template.render(&product("RJ123456", "a/b\\c: <d>?", "../..")),
+        PathBuf::from("..／").join("RJ123456 a／b＼c： ＜d＞？")
+    );
+    // trailing dots and spaces are dropped, reserved names are escaped, empty names are filled
+    assert_eq!(
+        template.render(&product("RJ123456", "title... ", "con")),
+        PathBuf::from("con_").join("RJ123456 title")
+    );
+    assert_eq!(
+        template.render(&product("RJ123456", "", "")),
+        PathBuf::from("_").join("RJ123456")
+    );
+
+    let rendered = template.render(&product("RJ123456", &"あ".repeat(500), &"い".repeat(500)));
+    for name in rendered.iter() {
+        assert!(name.len() <= 240, "{}", name.to_string_lossy());
+    }
+    assert!(rendered
+        .file_name()
+        .unwrap()
+        .to_str()
+        .unwrap()
+        .starts_with("RJ123456 あ"));
+}
+
+#[test]
+fn match_folder_template() {
+    let template = FolderTemplate::parse("{group_name}/{id} {title}").unwrap();
+    let product = product("RJ01234567", "続編 RJ111111", "サークル");
+
+    assert_eq!(
+        template.match_path(&template.render(&product)),
+        Some(product.id.clone())
+    );
+    assert_eq!(template.match_path(Path::new("RJ01234567 タイトル")), None);
+    assert_eq!(template.match_path(Path::new("サークル/タイトル")), None);
+
+    let template = FolderTemplate::parse("{title} [{id}]").unwrap();
+    assert_eq!(
+        template.match_path(Path::new("RJ111111 の続編 [RJ123456]")),
+        Some(ProductId::parse("RJ123456").unwrap())
+    );
+
+    let template = FolderTemplate::parse("{title}-{id}-{group_name}").unwrap();
+    assert_eq!(
+        template.match_path(Path::new("タイトル-RJ123456-サークル")),
+        Some(ProductId::parse("RJ123456").unwrap())
+    );
+    assert_eq!(
+        template.match_path(Path::new("RJ111111-RJ123456-サークル")),
+        None
+    );
+}
+
+#[test]
+fn move_folder_into_new_layout() {
+    let root = tempfile::tempdir().unwrap();
+    let from = root.path().join("old").join("RJ123456");
+    let to = root.path().join("サークル").join("RJ123456 タイトル");
+
+    create_dir_all(from.join("nested")).unwrap();
+    write(from.join("nested").join("track.mp3"), b"data").unwrap();
+
+    assert_eq!(move_folder(&from, &to).unwrap(), MoveMethod::Rename);
+    assert!(!from.exists());
+    assert_eq!(
+        read_to_string(to.join("nested").join("track.mp3")).unwrap(),
+        "data"
+    );
+
+    remove_empty_parents(&from, root.path());
+    assert!(!root.path().join("old").exists());
+    assert!(root.path().exists());
+
+    assert!(move_folder(&from, &root.path().join("RJ123456")).is_err());
+}
+
+#[test]
+fn find_product_ids_in_names() {
+    let ids = |text: &str| {
+        find_product_ids(text)
+            .into_iter()
+            .map(ProductId::into_string)
+            .collect::<Vec<_>>()
+    };
+
+    assert_eq!(ids("[RJ01234567] タイトル"), vec!["RJ01234567"]);
+    assert_eq!(ids("rj123456_v2.zip"), vec!["RJ123456"]);
+    assert_eq!(ids("RJ111111 続編 RJ222222"), vec!["RJ111111", "RJ222222"]);
+    assert_eq!(ids("RJ123456 RJ123456"), vec!["RJ123456"]);
+    assert!(ids("XRJ123456 RJ1234567 BJ12345").is_empty());
+    assert!(ids("RJ01234567x RJ123456abc").is_empty());
+}
+
+#[test]
+fn scan_library_recursively() {
+    let root = tempfile::tempdir().unwrap();
+    let root = root.path();
+    let template = FolderTemplate::default();
+
+    create_dir_all(
+        root.join("サークル")
+            .join("[RJ01234567] タイトル")
+            .join("RJ01234567"),
+    )
+    .unwrap();
+    create_dir_all(root.join("RJ111111").join("特典 RJ222222")).unwrap();
+    create_dir_all(root.join("RJ333333 と RJ444444")).unwrap();
+    create_dir_all(root.join("RJ555555")).unwrap();
+    write(root.join("RJ555555.zip"), b"").unwrap();
+    write(root.join("VJ666666 ゲーム.7z"), b"").unwrap();
+    write(root.join("RJ777777.txt"), b"").unwrap();
+
+    let mut scan = scan_library(root, &template, 4).unwrap();
+    scan.products.sort_by(|a, b| a.id.cmp(&b.id));
+
+    assert_eq!(
+        scan.products,
+        vec![
+            ScannedProduct {
+                id: ProductId::parse("RJ01234567").unwrap(),
+                path: root.join("サークル").join("[RJ01234567] タイトル"),
+                kind: ScannedProductKind::Folder,
+            },
+            ScannedProduct {
+                id: ProductId::parse("RJ111111").unwrap(),
+                path: root.join("RJ111111"),
+                kind: ScannedProductKind::Folder,
+            },
+            ScannedProduct {
+                id: ProductId::parse("RJ555555").unwrap(),
+                path: root.join("RJ555555"),
+                kind: ScannedProductKind::Folder,
+            },
+            ScannedProduct {
+                id: ProductId::parse("VJ666666").unwrap(),
+                path: root.join("VJ666666 ゲーム.7z"),
+                kind: ScannedProductKind::Archive,
+            },
+        ]
+    );
+
+    let kinds = scan
+        .ambiguities
+        .iter()
+        .map(|ambiguity| (ambiguity.kind, ambiguity.path.clone()))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        kinds,
+        vec![
+            (
+                ScanAmbiguityKind::Nested,
+                root.join("RJ111111").join("特典 RJ222222")
+            ),
+            (
+                ScanAmbiguityKind::MultipleIds,
+                root.join("RJ333333 と RJ444444")
+            ),
+        ]
+    );
+
+    // the circle folder is beyond the depth of one level
+    let scan = scan_library(root, &template, 1).unwrap();
+    assert!(scan
+        .products
+        .iter()
+        .all(|product| product.id.as_str() != "RJ01234567"));
+}
+
+#[test]
+fn diff_scan_with_recorded_downloads() {
+    let root = tempfile::tempdir().unwrap();
+    let root = root.path();
+    let id = |id: &str| ProductId::parse(id).unwrap();
+    let download = |product_id: &str, name: &str, missing: bool| ProductDownload {
+        product_id: id(product_id),
+        path: root.join(name),
+        missing_at: missing.then(chrono::Utc::now),
+        extraction_skip_reason: None,
+    };
+    let scanned = |product_id: &str, name: &str| ScannedProduct {
+        id: id(product_id),
+        path: root.join(name),
+        kind: ScannedProductKind::Folder,
+    };
+
+    for name in [
+        "RJ111111",
+        "RJ222222",
+        "RJ333333 moved",
+        "RJ444444",
+        "outside",
+    ] {
+        create_dir_all(root.join(name)).unwrap();
+    }
+
+    let downloads = vec![
+        // unchanged
+        download("RJ111111", "RJ111111", false),
+        // found again after it was missing
+        download("RJ222222", "RJ222222", true),
+        // moved by the user
+        download("RJ333333", "RJ333333", false),
+        // not found by the scan, but still exists
+        download("RJ555555", "outside", false),
+        // removed by the user
+        download("RJ666666", "RJ666666", false),
+        // already missing
+        download("RJ777777", "RJ777777", true),
+    ];
+    let diff = diff_scan(
+        &downloads,
+        vec![
+            scanned("RJ111111", "RJ111111"),
+            scanned("RJ222222", "RJ222222"),
+            scanned("RJ333333", "RJ333333 moved"),
+            scanned("RJ444444", "RJ444444"),
+        ],
+    );
+
+    assert_eq!(
+        diff.found,
+        vec![
+            scanned("RJ222222", "RJ222222"),
+            scanned("RJ333333", "RJ333333 moved"),
+            scanned("RJ444444", "RJ444444"),
+        ]
+    );
+    assert_eq!(diff.missing, vec![id("RJ666666")]);
+}
+
+#[test]
+fn verify_manifest_of_product_folder() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path();
+
+    create_dir_all(root.join("sub")).unwrap();
+    write(root.join("a.txt"), "a").unwrap();
+    write(root.join("b.txt"), "b").unwrap();
+    write(root.join("sub/c.txt"), "c").unwrap();
+    write(root.join("sub/d.txt"), "d").unwrap();
+
+    let manifest = build_manifest(root).unwrap();
+
+    assert_eq!(
+        manifest
+            .iter()
+            .map(|file| file.path.as_str())
+            .collect::<Vec<_>>(),
+        vec!["a.txt", "b.txt", "sub/c.txt", "sub/d.txt"]
+    );
+    assert!(manifest.iter().all(|file| file.size == 1));
+    assert!(verify_manifest(root, &manifest).unwrap().is_empty());
+
+    // the same size, but a different content
+    write(root.join("a.txt"), "x").unwrap();
+    // a different size
+    write(root.join("sub/c.txt"), "cc").unwrap();
+    remove_file(root.join("b.txt")).unwrap();
+    write(root.join("sub/e.txt"), "e").unwrap();
+
+    let problem = |kind: ManifestProblemKind, path: &str| ManifestProblem {
+        kind,
+        path: path.to_owned(),
+    };
+
+    assert_eq!(
+        verify_manifest(root, &manifest).unwrap(),
+        vec![
+            problem(ManifestProblemKind::Modified, "a.txt"),
+            problem(ManifestProblemKind::Missing, "b.txt"),
+            problem(ManifestProblemKind::Modified, "sub/c.txt"),
+            problem(ManifestProblemKind::Added, "sub/e.txt"),
+        ]
+    );
+}
+
+const RAR5_HEADER: &[u8] = b"Rar!\x1a\x07\x01\x00\0\0\0\0\x0b\x01\0\0";
+const RAR4_HEADER: &[u8] = b"Rar!\x1a\x07\x00\0\0\x73\0\0";
+const ZIP_CONTENT: &[u8] = b"PK\x03\x04 local file PK\x05\x06 end of central directory";
+
+/// A 7z archive with an empty start header, whose CRC32 is 0x0fd59b8d.
+fn seven_zip_header() -> Vec<u8> {
+    let mut bytes = b"7z\xbc\xaf\x27\x1c\x00\x04".to_vec();
+    bytes.extend_from_slice(&0x0fd59b8du32.to_le_bytes());
+    bytes.extend_from_slice(&[0; 20]);
+    bytes
+}
+
+/// A PE executable with a single section at 0x200..0x400 followed by the payload. The section
+/// contains a RAR signature, as an SFX module may.
+fn executable(payload: &[u8]) -> Vec<u8> {
+    let mut bytes = vec![0; 0x400];
+    bytes[..2].copy_from_slice(b"MZ");
+    bytes[0x3c..0x40].copy_from_slice(&0x40u32.to_le_bytes());
+    bytes[0x40..0x44].copy_from_slice(b"PE\0\0");
+    bytes[0x46..0x48].copy_from_slice(&1u16.to_le_bytes());
+    bytes[0x68..0x6c].copy_from_slice(&0x200u32.to_le_bytes());
+    bytes[0x6c..0x70].copy_from_slice(&0x200u32.to_le_bytes());
+    bytes[0x300..0x300 + RAR5_HEADER.len()].copy_from_slice(RAR5_HEADER);
+    bytes.extend_from_slice(payload);
+    bytes
+}
+
+#[test]
+fn sniff_archive_signatures() {
+    let root = tempfile::tempdir().unwrap();
+    let sniff = |name: &str, content: &[u8]| {
+        let path = root.path().join(name);
+        write(&path, content).unwrap();
+        sniff_file(&path).unwrap()
+    };
+    let archive = |format: ArchiveFormat, offset: u64| FileSignature::Archive { format, offset };
+
+    assert_eq!(sniff("a.zip", ZIP_CONTENT), archive(ArchiveFormat::Zip, 0));
+    assert_eq!(sniff("a.rar", RAR5_HEADER), archive(ArchiveFormat::Rar, 0));
+    assert_eq!(sniff("b.rar", RAR4_HEADER), archive(ArchiveFormat::Rar, 0));
+    assert_eq!(
+        sniff("a.7z", &seven_zip_header()),
+        archive(ArchiveFormat::SevenZip, 0)
+    );
+    assert_eq!(sniff("a.pdf", b"%PDF-1.7"), FileSignature::Pdf);
+    assert_eq!(sniff("a.lzh", b"\0\0-lh5-"), FileSignature::Lzh);
+    assert_eq!(
+        sniff("a.z01", b"PK\x07\x08PK\x03\x04"),
+        FileSignature::SplitZip
+    );
+    assert_eq!(sniff("a.txt", b"text"), FileSignature::Unknown);
+
+    // the signature in the image of the executable is not mistaken for the payload
+    assert_eq!(
+        sniff("setup.exe", &executable(b"")),
+        FileSignature::Executable
+    );
+    assert_eq!(
+        sniff(
+            "rar.exe",
+            &executable(&[b"config".as_slice(), RAR5_HEADER].concat())
+        ),
+        archive(ArchiveFormat::Rar, 0x406)
+    );
+    assert_eq!(
+        sniff("7z.exe", &executable(&seven_zip_header())),
+        archive(ArchiveFormat::SevenZip, 0x400)
+    );
+    assert_eq!(
+        sniff("zip.exe", &executable(ZIP_CONTENT)),
+        archive(ArchiveFormat::Zip, 0x400)
+    );
+}
+
+#[test]
+fn plan_extraction_by_content() {
+    let root = tempfile::tempdir().unwrap();
+    let root = root.path();
+    let plan = |files: &[(&str, &[u8])]| {
+        for (name, content) in files {
+            write(root.join(name), content).unwrap();
+        }
+
+        let names = files.iter().map(|(name, _)| *name).collect::<Vec<_>>();
+        plan_extraction(root, &names).unwrap()
+    };
+
+    assert_eq!(
+        plan(&[
+            ("RJ123456.part2.rar", RAR5_HEADER),
+            ("RJ123456.part1.exe", &executable(RAR5_HEADER)),
+            ("readme.txt", b"text"),
+        ]),
+        ExtractionPlan::Extract(vec![DetectedArchive {
+            format: ArchiveFormat::Rar,
+            path: root.join("RJ123456.part1.exe"),
+            offset: 0x400,
+            volumes: vec![
+                root.join("RJ123456.part1.exe"),
+                root.join("RJ123456.part2.rar"),
+            ],
+        }])
+    );
+    assert_eq!(
+        plan(&[("RJ123457.rar", RAR4_HEADER), ("RJ123457.r00", b"")]),
+        ExtractionPlan::Extract(vec![DetectedArchive {
+            format: ArchiveFormat::Rar,
+            path: root.join("RJ123457.rar"),
+            offset: 0,
+            volumes: vec![root.join("RJ123457.rar"), root.join("RJ123457.r00")],
+        }])
+    );
+    // named as a zip, but actually a 7z archive
+    assert_eq!(
+        plan(&[("RJ123458.zip", &seven_zip_header())]),
+        ExtractionPlan::Extract(vec![DetectedArchive {
+            format: ArchiveFormat::SevenZip,
+            path: root.join("RJ123458.zip"),
+            offset: 0,
+            volumes: vec![root.join("RJ123458.zip")],
+        }])
+    );
+    assert_eq!(
+        plan(&[("RJ123459.z01", b""), ("RJ123459.zip", ZIP_CONTENT)]),
+        ExtractionPlan::Skip(ExtractionSkipReason::SplitZip)
+    );
+    assert_eq!(
+        plan(&[("setup.exe", &executable(b""))]),
+        ExtractionPlan::Skip(ExtractionSkipReason::Installer)
+    );
+    assert_eq!(
+        plan(&[("book.pdf", b"%PDF-1.7")]),
+        ExtractionPlan::Skip(ExtractionSkipReason::Document)
+    );
+    assert_eq!(
+        plan(&[("track.mp3", b"ID3")]),
+        ExtractionPlan::Skip(ExtractionSkipReason::NotArchive)
+    );
+}
+
+#[test]
+fn detect_zip_name_encodings() {
+    let detect = |names: &[&[u8]]| detect_name_encoding(names.iter().copied());
+    let cp932 = |name: &str| encoding_rs::SHIFT_JIS.encode(name).0.into_owned();
+    let euc_jp = |name: &str| encoding_rs::EUC_JP.encode(name).0.into_owned();
+
+    assert_eq!(detect(&[b"track.mp3"]), NameEncoding::Utf8);
+    assert_eq!(
+        detect(&["ボイス/トラック1.mp3".as_bytes()]),
+        NameEncoding::Utf8
+    );
+    assert_eq!(
+        detect(&[&cp932("ボイス/トラック1.mp3"), &cp932("おまけ/画像.png")]),
+        NameEncoding::Cp932
+    );
+    assert_eq!(
+        detect(&[&euc_jp("ボイス/トラック1.mp3"), &euc_jp("おまけ/画像.png")]),
+        NameEncoding::EucJp
+    );
+}
+
+#[test]
+fn fix_names_decoded_in_cp437() {
+    let root = tempfile::tempdir().unwrap();
+    let root = root.path();
+
+    // `ボイス/トラック1.mp3` and `おまけ` in CP932, decoded in CP437
+    create_dir_all(root.join("â{âCâX")).unwrap();
+    write(root.join("â{âCâX").join("âgâëâbâN1.mp3"), b"data").unwrap();
+    create_dir_all(root.join("é¿é▄é»")).unwrap();
+    write(root.join("readme.txt"), b"text").unwrap();
+
+    let (encoding, fixes) = fix_names(root, None).unwrap();
+
+    assert_eq!(encoding, NameEncoding::Cp932);
+    assert_eq!(fixes.len(), 3);
+    assert_eq!(
+        read_to_string(root.join("ボイス").join("トラック1.mp3")).unwrap(),
+        "data"
+    );
+    assert!(root.join("おまけ").is_dir());
+    assert!(root.join("readme.txt").is_file());
+    assert!(!root.join("â{âCâX").exists());
+}
+
+#[test]
+fn reject_unsafe_entry_paths() {
+    assert_eq!(
+        safe_relative_path("voice/track1.mp3"),
+        Some(Path::new("voice").join("track1.mp3"))
+    );
+    assert_eq!(
+        safe_relative_path("./voice\\track1.mp3"),
+        Some(Path::new("voice").join("track1.mp3"))
+    );
+    assert_eq!(safe_relative_path("../track1.mp3"), None);
+    assert_eq!(safe_relative_path("voice/../../track1.mp3"), None);
+    assert_eq!(safe_relative_path("/etc/passwd"), None);
+    assert_eq!(safe_relative_path("\\server\\share"), None);
+    assert_eq!(safe_relative_path("C:/Windows/win.ini"), None);
+}
+
+#[test]
+fn extract_zip_skipping_unsafe_entries() {
+    use std::io::{Cursor, Write};
+    use zip::{write::FileOptions, ZipWriter};
+
+    let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+    let options = FileOptions::default();
+
+    writer.start_file("voice/track1.mp3", options).unwrap();
+    writer.write_all(b"data").unwrap();
+    writer.start_file("../escaped.txt", options).unwrap();
+    writer.write_all(b"text").unwrap();
+    writer.add_symlink("link", "/etc", options).unwrap();
+
+    let archive = writer.finish().unwrap();
+    let root = tempfile::tempdir().unwrap();
+    let target = root.path().join("product");
+    create_dir_all(&target).unwrap();
+
+    let report = extract_zip(
+        Cursor::new(archive.into_inner()),
+        &target,
+        None,
+        &AtomicBool::new(false),
+        &|_| {},
+    )
+    .unwrap();
+
+    assert_eq!(report.extracted, 1);
+    assert_eq!(
+        read_to_string(target.join("voice").join("track1.mp3")).unwrap(),
+        "data"
+    );
+    assert!(!root.path().join("escaped.txt").exists());
+    assert!(!target.join("link").exists());
+    assert_eq!(
+        report
+            .skipped
+            .iter()
+            .map(|entry| (entry.name.as_str(), entry.reason))
+            .collect::<Vec<_>>(),
+        vec![
+            ("../escaped.txt", SkippedEntryReason::UnsafePath),
+            ("link", SkippedEntryReason::Symlink),
+        ]
+    );
+}
+
+#[test]
+fn stop_cancelled_zip_extraction() {
+    use std::io::{Cursor, Write};
+    use zip::{write::FileOptions, ZipWriter};
+
+    let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+    writer
+        .start_file("voice/track1.mp3", FileOptions::default())
+        .unwrap();
+    writer.write_all(b"data").unwrap();
+
+    let archive = writer.finish().unwrap();
+    let target = tempfile::tempdir().unwrap();
+
+    assert!(extract_zip(
+        Cursor::new(archive.into_inner()),
+        target.path(),
+        None,
+        &AtomicBool::new(true),
+        &|_| {},
+    )
+    .is_err());
+    assert!(!target.path().join("voice").exists());
+}
+
+#[test]
+fn report_zip_extraction_progress() {
+    use std::{
+        io::{Cursor, Write},
+        sync::Mutex,
+    };
+    use zip::{write::FileOptions, ZipWriter};
+
+    let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+    let options = FileOptions::default();
+
+    writer.add_directory("voice", options).unwrap();
+    writer.start_file("voice/track1.mp3", options).unwrap();
+    writer.write_all(&vec![1; 100 * 1024]).unwrap();
+    writer.start_file("readme.txt", options).unwrap();
+    writer.write_all(b"text").unwrap();
+
+    let archive = writer.finish().unwrap();
+    let target = tempfile::tempdir().unwrap();
+    let progresses = Mutex::new(Vec::new());
+    let on_progress = |progress: ExtractionProgress| {
+        progresses.lock().unwrap().push((
+            progress.entry_name.to_owned(),
+            progress.entry_index,
+            progress.entry_count,
+            progress.written_size,
+            progress.declared_size,
+        ));
+    };
+
+    extract_zip(
+        Cursor::new(archive.into_inner()),
+        target.path(),
+        None,
+        &AtomicBool::new(false),
+        &on_progress,
+    )
+    .unwrap();
+
+    let total = 100 * 1024 + 4;
+    let progresses = progresses.into_inner().unwrap();
+    // the first report of each entry is as it begins; the chunks read from the zip may vary
+    let mut begins = progresses.clone();
+    begins.dedup_by_key(|(_, index, ..)| *index);
+
+    assert_eq!(
+        begins,
+        vec![
+            ("voice/".to_owned(), 0, 3, 0, total),
+            ("voice/track1.mp3".to_owned(), 1, 3, 0, total),
+            ("readme.txt".to_owned(), 2, 3, 100 * 1024, total),
+        ]
+    );
+    assert!(progresses.windows(2).all(|pair| pair[0].3 <= pair[1].3));
+    assert_eq!(progresses.last().unwrap().3, total);
+}
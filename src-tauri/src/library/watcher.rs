@@ -0,0 +1,87 @@
+use anyhow::{Context, Error as AnyError};
+use log::warn;
+use notify::{recommended_watcher, Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use tauri::async_runtime::{spawn, JoinHandle};
+use tokio::{
+    sync::mpsc::{unbounded_channel, UnboundedReceiver},
+    time::timeout,
+};
+
+/// How long the folder must be quiet before the changes are reported, so that a large copy
+/// is reported once after it finishes rather than for every file written.
+pub const DEBOUNCE_DELAY: Duration = Duration::from_secs(3);
+
+/// Watches a folder recursively, reporting the changed paths once they settle down.
+/// The folder is no longer watched once this is dropped.
+pub struct LibraryWatcher {
+    root: PathBuf,
+    // kept alive to keep watching
+    _watcher: RecommendedWatcher,
+    task: JoinHandle<()>,
+}
+
+impl LibraryWatcher {
+    pub fn watch(
+        root: &Path,
+        on_change: impl FnMut(Vec<PathBuf>) + Send + 'static,
+    ) -> Result<Self, AnyError> {
+        let (sender, receiver) = unbounded_channel();
+        let mut watcher = recommended_watcher(move |event: notify::Result<Event>| match event {
+            // reads do not change anything
+            Ok(event) if matches!(event.kind, EventKind::Access(_)) => {}
+            Ok(event) => {
+                sender.send(event.paths).ok();
+            }
+            Err(err) => {
+                warn!("[LibraryWatcher] failed to watch a change: {:?}", err);
+            }
+        })
+        .with_context(|| format!("[LibraryWatcher::watch]"))?;
+
+        watcher
+            .watch(root, RecursiveMode::Recursive)
+            .with_context(|| format!("[LibraryWatcher::watch]"))
+            .with_context(|| format!("failed to watch the folder `{}`", root.display()))?;
+
+        Ok(Self {
+            root: root.to_owned(),
+            _watcher: watcher,
+            task: spawn(debounce(receiver, on_change)),
+        })
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+}
+
+impl Drop for LibraryWatcher {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+async fn debounce(
+    mut receiver: UnboundedReceiver<Vec<PathBuf>>,
+    mut on_change: impl FnMut(Vec<PathBuf>),
+) {
+    while let Some(paths) = receiver.recv().await {
+        let mut changed = paths.into_iter().collect::<HashSet<_>>();
+
+        loop {
+            match timeout(DEBOUNCE_DELAY, receiver.recv()).await {
+                Ok(Some(paths)) => changed.extend(paths),
+                // the watcher is dropped
+                Ok(None) => return,
+                Err(_) => break,
+            }
+        }
+
+        on_change(changed.into_iter().collect());
+    }
+}
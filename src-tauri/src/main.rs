@@ -6,9 +6,13 @@
 mod application;
 mod application_error;
 mod command;
+mod credential;
 mod database;
 mod dlsite;
+mod library;
 mod menu;
+mod product_query;
+mod protocol;
 mod services;
 mod window;
 
@@ -16,6 +20,7 @@ use application::{create_application, use_application};
 use command::CommandProvider;
 use flexi_logger::{Cleanup, Criterion, FileSpec, Naming};
 use menu::{create_menu, handle_menu};
+use protocol::ProtocolProvider;
 use tauri::{Manager, RunEvent};
 
 fn main() {
@@ -34,6 +39,7 @@ fn main() {
             Ok(())
         })
         .attach_commands()
+        .attach_protocols()
         .build(tauri::generate_context!())
         .expect("error while running application");
 
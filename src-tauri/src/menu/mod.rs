@@ -1,10 +1,13 @@
 mod fetch_new_products;
 mod refresh_products_all;
+mod reorganize_library;
 mod scan_downloaded_products;
+mod verify_library;
 
 use self::{
     fetch_new_products::fetch_new_products, refresh_products_all::refresh_products_all,
-    scan_downloaded_products::scan_downloaded_products,
+    reorganize_library::reorganize_library, scan_downloaded_products::scan_downloaded_products,
+    verify_library::verify_library,
 };
 use crate::{
     application::use_application,
@@ -53,6 +56,11 @@ pub fn create_menu<R: Runtime>(manager: &impl Manager<R>) -> Result<Menu<R>, tau
                 "product/scan-downloaded-products",
                 "ダウンロード済み商品をスキャン",
             )
+            .text(
+                "product/reorganize-library",
+                "ダウンロード済み商品のフォルダを再編成",
+            )
+            .text("product/verify-library", "ダウンロード済み商品を検証")
             .separator()
             .text(
                 "product/refresh-products-all",
@@ -74,7 +82,6 @@ pub fn create_menu<R: Runtime>(manager: &impl Manager<R>) -> Result<Menu<R>, tau
     Ok(menu)
 }
 
-
 pub fn handle_menu(event: MenuEvent) -> Result<(), AnyError> {
     match event.id.as_ref() {
         "account/open-account-management" => {
@@ -134,6 +141,42 @@ pub fn handle_menu(event: MenuEvent) -> Result<(), AnyError> {
                 result.unwrap();
             })());
         }
+        "product/reorganize-library" => {
+            spawn((|| async {
+                {
+                    let mut is_updating_product = use_application().is_updating_product();
+
+                    if *is_updating_product {
+                        return ();
+                    }
+
+                    *is_updating_product = true;
+                }
+
+                let result = reorganize_library().await;
+                *use_application().is_updating_product() = false;
+
+                result.unwrap();
+            })());
+        }
+        "product/verify-library" => {
+            spawn((|| async {
+                {
+                    let mut is_updating_product = use_application().is_updating_product();
+
+                    if *is_updating_product {
+                        return ();
+                    }
+
+                    *is_updating_product = true;
+                }
+
+                let result = verify_library().await;
+                *use_application().is_updating_product() = false;
+
+                result.unwrap();
+            })());
+        }
         "setting/open-setting" => {
             SettingWindow.build_or_focus(use_application().app_handle())?;
         }
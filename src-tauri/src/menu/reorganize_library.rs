@@ -0,0 +1,126 @@
+use crate::{
+    application::use_application,
+    dlsite::product_id::ProductId,
+    services::library_service::{LibraryService, ReorganizePlan},
+    window::{MainWindow, WindowInfoProvider},
+};
+use anyhow::Error as AnyError;
+use serde::Serialize;
+use tauri::{async_runtime::spawn_blocking, Manager};
+use tauri_plugin_dialog::{DialogExt, MessageDialogKind};
+
+/// The number of moves listed in the confirmation dialog; the rest are summarized.
+const PREVIEW_COUNT: usize = 10;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ReorganizeProgressEvent<'a> {
+    pub product_id: &'a ProductId,
+    pub progress: u32,
+    pub total_progress: u32,
+}
+
+pub async fn reorganize_library() -> Result<(), AnyError> {
+    let plan = spawn_blocking(|| LibraryService::new().plan_reorganize()).await??;
+
+    if !confirm_plan(&plan) {
+        return Ok(());
+    }
+
+    if let Some(window) = use_application()
+        .app_handle()
+        .get_webview_window(&MainWindow.label())
+    {
+        window.emit("reorganize-begin", ())?;
+    }
+
+    let result = spawn_blocking(move || {
+        LibraryService::new().reorganize(&plan, |product_id, progress, total_progress| {
+            if let Some(window) = use_application()
+                .app_handle()
+                .get_webview_window(&MainWindow.label())
+            {
+                window
+                    .emit(
+                        "reorganize-progress",
+                        ReorganizeProgressEvent {
+                            product_id,
+                            progress,
+                            total_progress,
+                        },
+                    )
+                    .ok();
+            }
+        })
+    })
+    .await?;
+
+    if let Some(window) = use_application()
+        .app_handle()
+        .get_webview_window(&MainWindow.label())
+    {
+        match &result {
+            Ok(report) => window.emit("reorganize-end", report)?,
+            Err(_) => window.emit("reorganize-end", ())?,
+        }
+    }
+
+    Ok(result.map(|_| ())?)
+}
+
+/// Shows the dry-run of the reorganization and asks whether to execute it.
+fn confirm_plan(plan: &ReorganizePlan) -> bool {
+    let dialog = use_application().app_handle().dialog();
+    let mut message = String::new();
+
+    for conflict in &plan.conflicts {
+        message.push_str(&format!(
+            "移動できません: {} ({})\n",
+            conflict.product_id, conflict.reason
+        ));
+    }
+
+    if plan.moves.is_empty() {
+        message.insert_str(0, "移動が必要なフォルダはありません。\n\n");
+
+        dialog
+            .message(message.trim_end())
+            .title("ライブラリを再編成")
+            .kind(MessageDialogKind::Info)
+            .blocking_show();
+        return false;
+    }
+
+    if !plan.conflicts.is_empty() {
+        message.push('\n');
+    }
+
+    for planned in plan.moves.iter().take(PREVIEW_COUNT) {
+        message.push_str(&format!(
+            "{}\n  {}\n→ {}\n",
+            planned.product_id,
+            planned.from.display(),
+            planned.to.display()
+        ));
+    }
+
+    if PREVIEW_COUNT < plan.moves.len() {
+        message.push_str(&format!("ほか {} 件\n", plan.moves.len() - PREVIEW_COUNT));
+    }
+
+    message.insert_str(
+        0,
+        &format!(
+            "{} 件のフォルダを `{}` 以下に移動します。\n\n",
+            plan.moves.len(),
+            plan.root.display()
+        ),
+    );
+
+    dialog
+        .message(message.trim_end())
+        .title("ライブラリを再編成")
+        .kind(MessageDialogKind::Warning)
+        .ok_button_label("移動")
+        .cancel_button_label("キャンセル")
+        .blocking_show()
+}
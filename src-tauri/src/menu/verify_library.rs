@@ -0,0 +1,58 @@
+use crate::{
+    application::use_application,
+    dlsite::product_id::ProductId,
+    services::library_service::LibraryService,
+    window::{MainWindow, WindowInfoProvider},
+};
+use anyhow::Error as AnyError;
+use serde::Serialize;
+use tauri::{async_runtime::spawn_blocking, Manager};
+
+#[derive(Debug, Clone, Serialize)]
+pub struct VerifyProgressEvent<'a> {
+    pub product_id: &'a ProductId,
+    pub progress: u32,
+    pub total_progress: u32,
+}
+
+pub async fn verify_library() -> Result<(), AnyError> {
+    if let Some(window) = use_application()
+        .app_handle()
+        .get_webview_window(&MainWindow.label())
+    {
+        window.emit("verify-begin", ())?;
+    }
+
+    let result = spawn_blocking(|| {
+        LibraryService::new().verify_library(|product_id, progress, total_progress| {
+            if let Some(window) = use_application()
+                .app_handle()
+                .get_webview_window(&MainWindow.label())
+            {
+                window
+                    .emit(
+                        "verify-progress",
+                        VerifyProgressEvent {
+                            product_id,
+                            progress,
+                            total_progress,
+                        },
+                    )
+                    .ok();
+            }
+        })
+    })
+    .await?;
+
+    if let Some(window) = use_application()
+        .app_handle()
+        .get_webview_window(&MainWindow.label())
+    {
+        match &result {
+            Ok(report) => window.emit("verify-end", report)?,
+            Err(_) => window.emit("verify-end", ())?,
+        }
+    }
+
+    Ok(result.map(|_| ())?)
+}
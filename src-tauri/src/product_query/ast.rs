@@ -0,0 +1,114 @@
+use crate::dlsite::dto::{DLsiteProductAgeCategory, DLsiteProductType};
+use chrono::{DateTime, NaiveDate, TimeZone, Utc};
+
+/// A parsed product query. Every term must match for a product to match.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct ProductQuery {
+    pub terms: Vec<ProductQueryTerm>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProductQueryTerm {
+    /// `true` if the term is prefixed with `-` or its value with `!`.
+    pub negated: bool,
+    pub filter: ProductFilter,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProductFilter {
+    /// A bare word, matched against the search index.
+    Text(String),
+    /// A quoted phrase, matched against the search index as a whole.
+    Phrase(String),
+    /// `id:RJ123456,RJ654321`
+    Id(Vec<String>),
+    /// `title:foo`
+    Title(String),
+    /// `circle:foo`, matches the group ID exactly or the group name partially.
+    Circle(String),
+    /// `genre:foo`
+    Genre(String),
+    /// `creator:foo`, or `cv:foo` to match voice actors only.
+    Creator { name: String, voice_only: bool },
+    /// `series:foo`, matches the series ID exactly or the series name partially.
+    Series(String),
+    /// `type:voice,manga`
+    Type(Vec<DLsiteProductType>),
+    /// `age:r15,r18`
+    Age(Vec<DLsiteProductAgeCategory>),
+    /// `downloaded:yes`
+    Downloaded(bool),
+    /// `registered:>2023-01`
+    Registered(DateComparison),
+    /// `account:2`, or `account:none` for products not owned by any account.
+    Account(Vec<Option<i64>>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ComparisonOp {
+    Eq,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+/// A date with an optional month and day, e.g. `2023`, `2023-01` or `2023-01-31`.
+/// It denotes the whole period it covers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PartialDate {
+    pub year: i32,
+    pub month: Option<u32>,
+    pub day: Option<u32>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DateComparison {
+    pub op: ComparisonOp,
+    pub date: PartialDate,
+}
+
+impl PartialDate {
+    /// Returns `None` if the date does not exist.
+    pub fn new(year: i32, month: Option<u32>, day: Option<u32>) -> Option<Self> {
+        let date = Self { year, month, day };
+        date.start_date()?;
+        date.end_date()?;
+        Some(date)
+    }
+
+    fn start_date(&self) -> Option<NaiveDate> {
+        NaiveDate::from_ymd_opt(self.year, self.month.unwrap_or(1), self.day.unwrap_or(1))
+    }
+
+    /// The first day after the period.
+    fn end_date(&self) -> Option<NaiveDate> {
+        match (self.month, self.day) {
+            (Some(month), Some(day)) => NaiveDate::from_ymd_opt(self.year, month, day)?.succ_opt(),
+            (Some(12), None) => NaiveDate::from_ymd_opt(self.year + 1, 1, 1),
+            (Some(month), None) => NaiveDate::from_ymd_opt(self.year, month + 1, 1),
+            (None, _) => NaiveDate::from_ymd_opt(self.year + 1, 1, 1),
+        }
+    }
+}
+
+impl DateComparison {
+    /// Returns the range matched by the comparison, as an inclusive lower bound and an
+    /// exclusive upper bound.
+    pub fn range(&self) -> (Option<DateTime<Utc>>, Option<DateTime<Utc>>) {
+        let start = self.date.start_date().map(to_utc);
+        let end = self.date.end_date().map(to_utc);
+
+        match self.op {
+            ComparisonOp::Eq => (start, end),
+            ComparisonOp::Lt => (None, start),
+            ComparisonOp::Le => (None, end),
+            ComparisonOp::Gt => (end, None),
+            ComparisonOp::Ge => (start, None),
+        }
+    }
+}
+
+fn to_utc(date: NaiveDate) -> DateTime<Utc> {
+    Utc.from_utc_datetime(&date.and_hms_opt(0, 0, 0).unwrap())
+}
@@ -0,0 +1,5 @@
+pub mod ast;
+pub mod parser;
+
+#[cfg(test)]
+mod tests;
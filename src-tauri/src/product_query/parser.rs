@@ -0,0 +1,426 @@
+use super::ast::{
+    ComparisonOp, DateComparison, PartialDate, ProductFilter, ProductQuery, ProductQueryTerm,
+};
+use crate::dlsite::dto::{DLsiteProductAgeCategory, DLsiteProductType};
+use serde::Serialize;
+use thiserror::Error;
+
+/// A syntax error in a product query.
+/// `start` and `end` are UTF-16 offsets into the query, so that they can be used as is
+/// in JavaScript.
+#[derive(Error, Debug, Clone, PartialEq, Eq, Serialize)]
+#[error("{message} ({start}..{end})")]
+pub struct ProductQuerySyntaxError {
+    pub message: String,
+    pub start: usize,
+    pub end: usize,
+}
+
+#[derive(Error, Debug, Clone)]
+#[error("the product query is invalid: {errors:?}")]
+pub struct ProductQueryError {
+    pub errors: Vec<ProductQuerySyntaxError>,
+}
+
+/// The names of the fields, including their aliases.
+const FIELDS: &[&str] = &[
+    "id",
+    "title",
+    "circle",
+    "group",
+    "genre",
+    "tag",
+    "creator",
+    "cv",
+    "series",
+    "type",
+    "age",
+    "downloaded",
+    "registered",
+    "account",
+];
+
+/// Parses a product query such as
+/// `circle:foo type:voice,manga age:!r18 downloaded:yes registered:>2023-01 account:2 "exact title"`.
+///
+/// Terms are separated by whitespace and must all match. A term can be negated by prefixing it
+/// with `-`, or by prefixing the value of a field with `!`. A word whose prefix before `:` is not
+/// a field, such as `Re:ゼロ`, is searched as text. Every syntax error is reported, not only
+/// the first one.
+pub fn parse(query: &str) -> Result<ProductQuery, ProductQueryError> {
+    let mut parser = Parser {
+        input: query,
+        pos: 0,
+        errors: Vec::new(),
+    };
+    let mut terms = Vec::new();
+
+    while let Some(term) = parser.parse_term() {
+        if let Some(term) = term {
+            terms.push(term);
+        }
+    }
+
+    if parser.errors.is_empty() {
+        Ok(ProductQuery { terms })
+    } else {
+        Err(ProductQueryError {
+            errors: parser.errors,
+        })
+    }
+}
+
+struct Parser<'a> {
+    input: &'a str,
+    /// The current byte offset.
+    pos: usize,
+    errors: Vec<ProductQuerySyntaxError>,
+}
+
+impl<'a> Parser<'a> {
+    /// Returns `None` at the end of the input, and `Some(None)` if the term is invalid.
+    fn parse_term(&mut self) -> Option<Option<ProductQueryTerm>> {
+        self.skip_whitespace();
+
+        if self.peek().is_none() {
+            return None;
+        }
+
+        let mut negated = false;
+
+        if self.peek() == Some('-') && self.peek_nth(1).map_or(false, |c| !c.is_whitespace()) {
+            negated = true;
+            self.pos += 1;
+        }
+
+        if self.peek() == Some('"') {
+            return Some(match self.parse_quoted() {
+                Some(phrase) if !phrase.is_empty() => Some(ProductQueryTerm {
+                    negated,
+                    filter: ProductFilter::Phrase(phrase),
+                }),
+                _ => None,
+            });
+        }
+
+        let field_start = self.pos;
+        let field_name = self.take_while(|c| c.is_ascii_alphanumeric() || c == '_');
+
+        if self.peek() != Some(':') || !FIELDS.contains(&field_name.to_ascii_lowercase().as_str()) {
+            self.pos = field_start;
+            let word = self.take_while(|c| !c.is_whitespace());
+            return Some(Some(ProductQueryTerm {
+                negated,
+                filter: ProductFilter::Text(word.to_owned()),
+            }));
+        }
+
+        self.pos += 1;
+
+        let value_negated = if self.peek() == Some('!') {
+            self.pos += 1;
+            true
+        } else {
+            false
+        };
+        let mut value_start = self.pos;
+        let value = if self.peek() == Some('"') {
+            value_start += 1;
+
+            match self.parse_quoted() {
+                Some(value) => value,
+                None => return Some(None),
+            }
+        } else {
+            self.take_while(|c| !c.is_whitespace()).to_owned()
+        };
+        let value_end = self.pos;
+
+        if value.is_empty() {
+            self.error(
+                format!("missing a value for the field `{}`", field_name),
+                field_start,
+                value_end,
+            );
+            return Some(None);
+        }
+
+        let filter = match field_name.to_ascii_lowercase().as_str() {
+            "id" => self.parse_ids(&value, value_start),
+            "title" => Some(ProductFilter::Title(value)),
+            "circle" | "group" => Some(ProductFilter::Circle(value)),
+            "genre" | "tag" => Some(ProductFilter::Genre(value)),
+            "creator" => Some(ProductFilter::Creator {
+                name: value,
+                voice_only: false,
+            }),
+            "cv" => Some(ProductFilter::Creator {
+                name: value,
+                voice_only: true,
+            }),
+            "series" => Some(ProductFilter::Series(value)),
+            "type" => self.parse_types(&value, value_start),
+            "age" => self.parse_ages(&value, value_start),
+            "downloaded" => self.parse_downloaded(&value, value_start, value_end),
+            "registered" => self.parse_registered(&value, value_start, value_end),
+            "account" => self.parse_accounts(&value, value_start),
+            _ => unreachable!("the field `{}` is checked above", field_name),
+        };
+
+        Some(filter.map(|filter| ProductQueryTerm {
+            negated: negated != value_negated,
+            filter,
+        }))
+    }
+
+    /// Parses a double-quoted string, the current character being the opening quote.
+    fn parse_quoted(&mut self) -> Option<String> {
+        let start = self.pos;
+        self.pos += 1;
+
+        match self.input[self.pos..].find('"') {
+            Some(length) => {
+                let value = self.input[self.pos..self.pos + length].to_owned();
+                self.pos += length + 1;
+                Some(value)
+            }
+            None => {
+                self.pos = self.input.len();
+                self.error("unterminated quote".to_owned(), start, self.pos);
+                None
+            }
+        }
+    }
+
+    fn parse_ids(&mut self, value: &str, value_start: usize) -> Option<ProductFilter> {
+        let ids = self.parse_list(value, value_start, |id| {
+            if id.chars().all(|c| c.is_ascii_alphanumeric()) {
+                Ok(id.to_ascii_uppercase())
+            } else {
+                Err(format!("`{}` is not a valid product ID", id))
+            }
+        })?;
+        Some(ProductFilter::Id(ids))
+    }
+
+    fn parse_types(&mut self, value: &str, value_start: usize) -> Option<ProductFilter> {
+        let types = self.parse_list(value, value_start, |ty| {
+            parse_product_type(ty).ok_or_else(|| format!("unknown product type `{}`", ty))
+        })?;
+        Some(ProductFilter::Type(types))
+    }
+
+    fn parse_ages(&mut self, value: &str, value_start: usize) -> Option<ProductFilter> {
+        let ages = self.parse_list(value, value_start, |age| {
+            match age.to_ascii_lowercase().as_str() {
+                "all" => Ok(DLsiteProductAgeCategory::All),
+                "r15" => Ok(DLsiteProductAgeCategory::R15),
+                "r18" => Ok(DLsiteProductAgeCategory::R18),
+                _ => Err(format!(
+                    "unknown age category `{}`; expected `all`, `r15` or `r18`",
+                    age
+                )),
+            }
+        })?;
+        Some(ProductFilter::Age(ages))
+    }
+
+    fn parse_downloaded(
+        &mut self,
+        value: &str,
+        value_start: usize,
+        value_end: usize,
+    ) -> Option<ProductFilter> {
+        match value.to_ascii_lowercase().as_str() {
+            "yes" | "true" => Some(ProductFilter::Downloaded(true)),
+            "no" | "false" => Some(ProductFilter::Downloaded(false)),
+            _ => {
+                self.error(
+                    format!("`{}` is not valid; expected `yes` or `no`", value),
+                    value_start,
+                    value_end,
+                );
+                None
+            }
+        }
+    }
+
+    fn parse_registered(
+        &mut self,
+        value: &str,
+        value_start: usize,
+        value_end: usize,
+    ) -> Option<ProductFilter> {
+        let (op, date) = if let Some(date) = value.strip_prefix(">=") {
+            (ComparisonOp::Ge, date)
+        } else if let Some(date) = value.strip_prefix("<=") {
+            (ComparisonOp::Le, date)
+        } else if let Some(date) = value.strip_prefix('>') {
+            (ComparisonOp::Gt, date)
+        } else if let Some(date) = value.strip_prefix('<') {
+            (ComparisonOp::Lt, date)
+        } else if let Some(date) = value.strip_prefix('=') {
+            (ComparisonOp::Eq, date)
+        } else {
+            (ComparisonOp::Eq, value)
+        };
+
+        match parse_partial_date(date) {
+            Some(date) => Some(ProductFilter::Registered(DateComparison { op, date })),
+            None => {
+                self.error(
+                    format!(
+                        "`{}` is not a valid date; expected `YYYY`, `YYYY-MM` or `YYYY-MM-DD`",
+                        date
+                    ),
+                    value_start + (value.len() - date.len()),
+                    value_end,
+                );
+                None
+            }
+        }
+    }
+
+    fn parse_accounts(&mut self, value: &str, value_start: usize) -> Option<ProductFilter> {
+        let accounts = self.parse_list(value, value_start, |account| {
+            if account.eq_ignore_ascii_case("none") {
+                return Ok(None);
+            }
+
+            account
+                .parse::<i64>()
+                .map(Some)
+                .map_err(|_| format!("`{}` is not a valid account ID", account))
+        })?;
+        Some(ProductFilter::Account(accounts))
+    }
+
+    /// Parses a comma-separated list, reporting an error for each invalid item.
+    fn parse_list<T>(
+        &mut self,
+        value: &str,
+        value_start: usize,
+        parse_item: impl Fn(&str) -> Result<T, String>,
+    ) -> Option<Vec<T>> {
+        let mut items = Vec::new();
+        let mut is_valid = true;
+        let mut item_start = value_start;
+
+        for item in value.split(',') {
+            let item_end = item_start + item.len();
+
+            if item.is_empty() {
+                self.error("empty list item".to_owned(), item_start, item_end);
+                is_valid = false;
+            } else {
+                match parse_item(item) {
+                    Ok(item) => items.push(item),
+                    Err(message) => {
+                        self.error(message, item_start, item_end);
+                        is_valid = false;
+                    }
+                }
+            }
+
+            item_start = item_end + 1;
+        }
+
+        if is_valid {
+            Some(items)
+        } else {
+            None
+        }
+    }
+
+    fn error(&mut self, message: String, start: usize, end: usize) {
+        self.errors.push(ProductQuerySyntaxError {
+            message,
+            start: self.input[..start].encode_utf16().count(),
+            end: self.input[..end].encode_utf16().count(),
+        });
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.input[self.pos..].chars().next()
+    }
+
+    fn peek_nth(&self, n: usize) -> Option<char> {
+        self.input[self.pos..].chars().nth(n)
+    }
+
+    fn skip_whitespace(&mut self) {
+        self.take_while(char::is_whitespace);
+    }
+
+    fn take_while(&mut self, predicate: impl Fn(char) -> bool) -> &'a str {
+        let start = self.pos;
+        let rest = &self.input[start..];
+        let length = rest
+            .char_indices()
+            .find(|(_, c)| !predicate(*c))
+            .map_or(rest.len(), |(index, _)| index);
+        self.pos += length;
+        &self.input[start..self.pos]
+    }
+}
+
+fn parse_partial_date(date: &str) -> Option<PartialDate> {
+    let mut parts = date.split('-');
+    let year = parts.next()?;
+
+    if year.len() != 4 {
+        return None;
+    }
+
+    let year = year.parse::<i32>().ok()?;
+    let month = match parts.next() {
+        Some(month) => Some(month.parse::<u32>().ok()?),
+        None => None,
+    };
+    let day = match parts.next() {
+        Some(day) => Some(day.parse::<u32>().ok()?),
+        None => None,
+    };
+
+    if parts.next().is_some() {
+        return None;
+    }
+
+    PartialDate::new(year, month, day)
+}
+
+/// Parses a product type by its name (e.g. `voice`) or its DLsite code (e.g. `SOU`).
+fn parse_product_type(ty: &str) -> Option<DLsiteProductType> {
+    Some(match ty.to_ascii_lowercase().as_str() {
+        "adult" | "adl" => DLsiteProductType::Adult,
+        "doujinshi" | "doujinsji" | "doh" => DLsiteProductType::Doujinsji,
+        "software" | "sof" => DLsiteProductType::Software,
+        "game" | "gam" => DLsiteProductType::Game,
+        "action" | "acn" => DLsiteProductType::Action,
+        "adventure" | "adv" => DLsiteProductType::Adventure,
+        "audiomaterial" | "amt" => DLsiteProductType::AudioMaterial,
+        "comic" | "com" => DLsiteProductType::Comic,
+        "digitalnovel" | "dnv" => DLsiteProductType::DigitalNovel,
+        "other" | "et3" => DLsiteProductType::Other,
+        "othergame" | "etc" => DLsiteProductType::OtherGame,
+        "illust" | "icg" => DLsiteProductType::Illust,
+        "imagematerial" | "imt" => DLsiteProductType::ImageMaterial,
+        "manga" | "mng" => DLsiteProductType::Manga,
+        "anime" | "mov" => DLsiteProductType::Anime,
+        "music" | "mus" => DLsiteProductType::Music,
+        "novel" | "nre" => DLsiteProductType::Novel,
+        "puzzle" | "pzl" => DLsiteProductType::Puzzle,
+        "quiz" | "qiz" => DLsiteProductType::Quiz,
+        "roleplaying" | "rpg" => DLsiteProductType::RolePlaying,
+        "gekiga" | "scm" => DLsiteProductType::Gekiga,
+        "simulation" | "sln" => DLsiteProductType::Simulation,
+        "voice" | "sou" => DLsiteProductType::Voice,
+        "shooter" | "stg" => DLsiteProductType::Shooter,
+        "tabletop" | "tbl" => DLsiteProductType::Tabletop,
+        "utility" | "tol" => DLsiteProductType::Utility,
+        "typing" | "typ" => DLsiteProductType::Typing,
+        "sexualnovel" | "ksv" => DLsiteProductType::SexualNovel,
+        "voicecomic" | "vcm" => DLsiteProductType::VoiceComic,
+        _ => return None,
+    })
+}
@@ -0,0 +1,164 @@
+use super::{
+    ast::{ComparisonOp, DateComparison, PartialDate, ProductFilter, ProductQueryTerm},
+    parser::{parse, ProductQuerySyntaxError},
+};
+use crate::dlsite::dto::{DLsiteProductAgeCategory, DLsiteProductType};
+
+fn terms(query: &str) -> Vec<ProductQueryTerm> {
+    parse(query).unwrap().terms
+}
+
+fn term(filter: ProductFilter) -> ProductQueryTerm {
+    ProductQueryTerm {
+        negated: false,
+        filter,
+    }
+}
+
+fn negated(filter: ProductFilter) -> ProductQueryTerm {
+    ProductQueryTerm {
+        negated: true,
+        filter,
+    }
+}
+
+/// Returns the errors as the message and the text they point at.
+fn errors(query: &str) -> Vec<(String, usize, usize)> {
+    parse(query)
+        .unwrap_err()
+        .errors
+        .into_iter()
+        .map(
+            |ProductQuerySyntaxError {
+                 message,
+                 start,
+                 end,
+             }| (message, start, end),
+        )
+        .collect()
+}
+
+#[test]
+fn parse_fields() {
+    assert_eq!(
+        terms("circle:foo type:voice,MNG age:r15,r18 downloaded:yes id:rj123456 account:2,none"),
+        vec![
+            term(ProductFilter::Circle("foo".to_owned())),
+            term(ProductFilter::Type(vec![
+                DLsiteProductType::Voice,
+                DLsiteProductType::Manga
+            ])),
+            term(ProductFilter::Age(vec![
+                DLsiteProductAgeCategory::R15,
+                DLsiteProductAgeCategory::R18
+            ])),
+            term(ProductFilter::Downloaded(true)),
+            term(ProductFilter::Id(vec!["RJ123456".to_owned()])),
+            term(ProductFilter::Account(vec![Some(2), None])),
+        ]
+    );
+    assert_eq!(
+        terms("registered:>=2023-01 cv:foo Group:bar"),
+        vec![
+            term(ProductFilter::Registered(DateComparison {
+                op: ComparisonOp::Ge,
+                date: PartialDate::new(2023, Some(1), None).unwrap(),
+            })),
+            term(ProductFilter::Creator {
+                name: "foo".to_owned(),
+                voice_only: true,
+            }),
+            term(ProductFilter::Circle("bar".to_owned())),
+        ]
+    );
+}
+
+#[test]
+fn parse_negations() {
+    assert_eq!(
+        terms("-foo -circle:bar age:!r18 -age:!all"),
+        vec![
+            negated(ProductFilter::Text("foo".to_owned())),
+            negated(ProductFilter::Circle("bar".to_owned())),
+            negated(ProductFilter::Age(vec![DLsiteProductAgeCategory::R18])),
+            term(ProductFilter::Age(vec![DLsiteProductAgeCategory::All])),
+        ]
+    );
+    // a lone `-` is a word
+    assert_eq!(terms("- foo").len(), 2);
+}
+
+#[test]
+fn parse_quoted_phrases() {
+    assert_eq!(
+        terms(r#""exact title" -"not this" title:"foo bar" "Re:ゼロ""#),
+        vec![
+            term(ProductFilter::Phrase("exact title".to_owned())),
+            negated(ProductFilter::Phrase("not this".to_owned())),
+            term(ProductFilter::Title("foo bar".to_owned())),
+            term(ProductFilter::Phrase("Re:ゼロ".to_owned())),
+        ]
+    );
+    // an empty phrase matches anything, so it is dropped
+    assert_eq!(terms(r#""""#), vec![]);
+}
+
+#[test]
+fn search_unknown_fields_as_text() {
+    assert_eq!(
+        terms("Re:ゼロ https://example.com foo:bar"),
+        vec![
+            term(ProductFilter::Text("Re:ゼロ".to_owned())),
+            term(ProductFilter::Text("https://example.com".to_owned())),
+            term(ProductFilter::Text("foo:bar".to_owned())),
+        ]
+    );
+    assert_eq!(
+        terms("ゼロ:から"),
+        vec![term(ProductFilter::Text("ゼロ:から".to_owned()))]
+    );
+}
+
+#[test]
+fn report_error_positions() {
+    assert_eq!(
+        errors("type:voice,foo downloaded:maybe"),
+        vec![
+            ("unknown product type `foo`".to_owned(), 11, 14),
+            (
+                "`maybe` is not valid; expected `yes` or `no`".to_owned(),
+                26,
+                31
+            ),
+        ]
+    );
+    // the positions are in UTF-16, as in JavaScript
+    assert_eq!(
+        errors("ボイス🎧 age:r20"),
+        vec![(
+            "unknown age category `r20`; expected `all`, `r15` or `r18`".to_owned(),
+            10,
+            13
+        )]
+    );
+    assert_eq!(
+        errors("registered:<2023-13 circle:"),
+        vec![
+            (
+                "`2023-13` is not a valid date; expected `YYYY`, `YYYY-MM` or `YYYY-MM-DD`"
+                    .to_owned(),
+                12,
+                19
+            ),
+            ("missing a value for the field `circle`".to_owned(), 20, 27),
+        ]
+    );
+    assert_eq!(
+        errors(r#"foo "bar"#),
+        vec![("unterminated quote".to_owned(), 4, 8)]
+    );
+    assert_eq!(
+        errors("type:voice,,manga"),
+        vec![("empty list item".to_owned(), 11, 11)]
+    );
+}
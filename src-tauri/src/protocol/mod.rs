@@ -0,0 +1,26 @@
+mod thumbnail;
+
+use tauri::{Builder, Runtime};
+
+pub trait ProtocolProvider<R>
+where
+    R: Runtime,
+{
+    fn attach_protocols(self) -> Self;
+}
+
+impl<R> ProtocolProvider<R> for Builder<R>
+where
+    R: Runtime,
+{
+    fn attach_protocols(self) -> Self {
+        self.register_asynchronous_uri_scheme_protocol(
+            thumbnail::SCHEME,
+            |_context, request, responder| {
+                tauri::async_runtime::spawn(async move {
+                    responder.respond(thumbnail::handle(request).await);
+                });
+            },
+        )
+    }
+}
@@ -0,0 +1,39 @@
+use crate::{dlsite::product_id::ProductId, services::thumbnail_service::ThumbnailService};
+use log::error;
+use tauri::http::{header::CONTENT_TYPE, Request, Response, StatusCode};
+
+/// Serves the cached thumbnail of a product at `thumbnail://localhost/<product id>`
+/// (`http://thumbnail.localhost/<product id>` on Windows).
+pub const SCHEME: &str = "thumbnail";
+
+pub async fn handle(request: Request<Vec<u8>>) -> Response<Vec<u8>> {
+    let product_id = match ProductId::parse(request.uri().path().trim_start_matches('/')) {
+        Ok(product_id) => product_id,
+        Err(_) => {
+            return Response::builder()
+                .status(StatusCode::NOT_FOUND)
+                .body(Vec::new())
+                .unwrap();
+        }
+    };
+
+    match ThumbnailService::new().load(&product_id).await {
+        Ok(Some(thumbnail)) => Response::builder()
+            .status(StatusCode::OK)
+            .header(CONTENT_TYPE, thumbnail.content_type)
+            .body(thumbnail.data),
+        Ok(None) => Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(Vec::new()),
+        Err(err) => {
+            error!(
+                "[handle] failed to load the thumbnail of the product `{}`: {:?}",
+                product_id, err
+            );
+            Response::builder()
+                .status(StatusCode::NOT_FOUND)
+                .body(Vec::new())
+        }
+    }
+    .unwrap()
+}
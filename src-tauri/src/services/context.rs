@@ -0,0 +1,80 @@
+use crate::{
+    application_error::Result, credential::CredentialCipher, database::Database,
+    dlsite::client::DLsiteClient,
+};
+use parking_lot::{MappedMutexGuard, Mutex, MutexGuard};
+use rusqlite::Connection;
+use std::{collections::HashMap, path::PathBuf, time::Instant};
+
+/// A logged in client kept for reuse.
+#[derive(Clone)]
+pub struct CachedClient {
+    pub client: DLsiteClient,
+    /// when the session of the client was last known to be valid
+    pub validated_at: Instant,
+}
+
+/// The state the services work on. The application holds the one it runs on,
+/// while tests build their own over a temporary database and a mock DLsite.
+pub struct ServiceContext {
+    database: Mutex<Option<Database>>,
+    /// `None` while the credentials are locked behind a master password
+    credential_cipher: Mutex<Option<CredentialCipher>>,
+    /// the client for the APIs that do not need an account
+    dlsite_client: DLsiteClient,
+    /// logged in clients keyed by their account id, so that their connections are reused
+    dlsite_account_clients: Mutex<HashMap<i64, CachedClient>>,
+    thumbnail_dir: PathBuf,
+}
+
+impl ServiceContext {
+    pub fn new(
+        database: Database,
+        credential_cipher: Option<CredentialCipher>,
+        dlsite_client: DLsiteClient,
+        thumbnail_dir: PathBuf,
+    ) -> Self {
+        Self {
+            database: Mutex::new(Some(database)),
+            credential_cipher: Mutex::new(credential_cipher),
+            dlsite_client,
+            dlsite_account_clients: Mutex::new(HashMap::new()),
+            thumbnail_dir,
+        }
+    }
+
+    pub fn connection(&self) -> MappedMutexGuard<Connection> {
+        MutexGuard::map(self.database.lock(), |storage| {
+            storage.as_mut().unwrap().connection_mut()
+        })
+    }
+
+    pub fn credential_cipher(&self) -> MutexGuard<Option<CredentialCipher>> {
+        self.credential_cipher.lock()
+    }
+
+    pub fn dlsite_client(&self) -> &DLsiteClient {
+        &self.dlsite_client
+    }
+
+    pub fn dlsite_account_clients(&self) -> MutexGuard<HashMap<i64, CachedClient>> {
+        self.dlsite_account_clients.lock()
+    }
+
+    /// Returns the folder the thumbnails are cached in, creating it if needed.
+    pub fn thumbnail_dir(&self) -> std::io::Result<&PathBuf> {
+        std::fs::create_dir_all(&self.thumbnail_dir)?;
+        Ok(&self.thumbnail_dir)
+    }
+
+    pub fn prepare(&self) -> Result<()> {
+        self.database.lock().as_mut().unwrap().prepare()
+    }
+
+    pub fn drop_storage(&self) -> Result<()> {
+        if let Some(storage) = self.database.lock().take() {
+            storage.drop()?;
+        }
+        Ok(())
+    }
+}
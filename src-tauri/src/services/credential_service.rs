@@ -0,0 +1,193 @@
+use crate::{
+    application::{use_application, CREDENTIAL_KEY_FILE_NAME, MASTER_PASSWORD_FILE_NAME},
+    credential::{CredentialCipher, MasterPasswordFile},
+    database::tables::v2::{AccountTable, DBError},
+    services::download_queue_service::DownloadQueueService,
+};
+use anyhow::{Context, Error as AnyError};
+use log::{info, warn};
+use std::{fs, path::PathBuf};
+use tauri::Manager;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum CredentialServiceError {
+    #[error("the credentials are locked")]
+    Locked,
+    #[error("the given master password is wrong")]
+    WrongMasterPassword,
+    #[error("{0:?}")]
+    DBError(#[from] DBError),
+    #[error("{0:?}")]
+    AnyError(#[from] AnyError),
+}
+
+pub struct CredentialService;
+
+impl CredentialService {
+    pub fn new() -> Self {
+        Self
+    }
+
+    pub fn is_locked(&self) -> bool {
+        use_application().credential_cipher().is_none()
+    }
+
+    pub fn has_master_password(&self) -> Result<bool, CredentialServiceError> {
+        Ok(master_password_file_path()?.exists())
+    }
+
+    /// Unlocks the credentials with the master password, and starts the download queue
+    /// that was held back while they were locked.
+    pub fn unlock(&self, master_password: &str) -> Result<(), CredentialServiceError> {
+        let path = master_password_file_path()?;
+        let file = read_master_password_file(&path)?;
+        let cipher = CredentialCipher::unlock_master_password(master_password, &file)
+            .map_err(|_| CredentialServiceError::WrongMasterPassword)?;
+
+        info!("[unlock] the credentials are unlocked");
+
+        *use_application().credential_cipher() = Some(cipher);
+
+        if let Err(err) = AccountTable::encrypt_plaintext(
+            &mut use_application().connection(),
+            use_application().credential_cipher().as_ref(),
+        ) {
+            warn!(
+                "[unlock] failed to encrypt plaintext credentials: {:?}",
+                err
+            );
+        }
+
+        // the queue needs the accounts, so it is held back until the credentials are unlocked
+        if let Err(err) = DownloadQueueService::new().restore() {
+            warn!("[unlock] failed to restore the download queue: {:?}", err);
+        }
+
+        Ok(())
+    }
+
+    /// Re-encrypts every credential with a key derived from the given master password.
+    /// Passing `None` switches back to a locally generated key file.
+    pub fn set_master_password(
+        &self,
+        master_password: Option<&str>,
+    ) -> Result<(), CredentialServiceError> {
+        if self.is_locked() {
+            return Err(CredentialServiceError::Locked);
+        }
+
+        let key_file_path = key_file_path()?;
+        let master_password_file_path = master_password_file_path()?;
+        let prev_master_password_file = if master_password_file_path.exists() {
+            Some(read_master_password_file(&master_password_file_path)?)
+        } else {
+            None
+        };
+
+        // decrypted with the current key
+        let accounts = AccountTable::get_all(
+            &use_application().connection(),
+            use_application().credential_cipher().as_ref(),
+        )?;
+
+        let cipher = match master_password {
+            Some(master_password) => {
+                let (cipher, file) = CredentialCipher::create_master_password(master_password)?;
+                write_master_password_file(&master_password_file_path, &file)?;
+                cipher
+            }
+            None => CredentialCipher::load_or_create_key_file(&key_file_path)?,
+        };
+
+        let prev_cipher = use_application().credential_cipher().replace(cipher);
+
+        let result = AccountTable::update_many_secrets(
+            &mut use_application().connection(),
+            use_application().credential_cipher().as_ref(),
+            &accounts,
+        );
+
+        if let Err(err) = result {
+            *use_application().credential_cipher() = prev_cipher;
+
+            match (master_password, &prev_master_password_file) {
+                (Some(_), Some(prev_file)) => {
+                    write_master_password_file(&master_password_file_path, prev_file).ok();
+                }
+                (Some(_), None) => {
+                    fs::remove_file(&master_password_file_path).ok();
+                }
+                (None, _) => {}
+            }
+
+            return Err(err.into());
+        }
+
+        match master_password {
+            Some(_) => {
+                info!("[set_master_password] the credentials are now protected by the master password");
+
+                if key_file_path.exists() {
+                    fs::remove_file(&key_file_path)
+                        .with_context(|| format!("[set_master_password]"))
+                        .with_context(|| {
+                            format!("failed to remove key file `{}`", key_file_path.display())
+                        })?;
+                }
+            }
+            None => {
+                info!("[set_master_password] the credentials are now protected by the key file");
+
+                if master_password_file_path.exists() {
+                    fs::remove_file(&master_password_file_path)
+                        .with_context(|| format!("[set_master_password]"))
+                        .with_context(|| {
+                            format!(
+                                "failed to remove master password file `{}`",
+                                master_password_file_path.display()
+                            )
+                        })?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn key_file_path() -> Result<PathBuf, AnyError> {
+    Ok(use_application()
+        .app_handle()
+        .path()
+        .app_config_dir()?
+        .join(CREDENTIAL_KEY_FILE_NAME))
+}
+
+fn master_password_file_path() -> Result<PathBuf, AnyError> {
+    Ok(use_application()
+        .app_handle()
+        .path()
+        .app_config_dir()?
+        .join(MASTER_PASSWORD_FILE_NAME))
+}
+
+fn read_master_password_file(path: &PathBuf) -> Result<MasterPasswordFile, AnyError> {
+    let content = fs::read(path)
+        .with_context(|| format!("[read_master_password_file]"))
+        .with_context(|| format!("failed to read `{}`", path.display()))?;
+    let file = serde_json::from_slice(&content)
+        .with_context(|| format!("[read_master_password_file]"))
+        .with_context(|| format!("failed to parse `{}`", path.display()))?;
+    Ok(file)
+}
+
+fn write_master_password_file(path: &PathBuf, file: &MasterPasswordFile) -> Result<(), AnyError> {
+    let content = serde_json::to_vec(file)
+        .with_context(|| format!("[write_master_password_file]"))
+        .with_context(|| format!("failed to serialize `{}`", path.display()))?;
+    fs::write(path, content)
+        .with_context(|| format!("[write_master_password_file]"))
+        .with_context(|| format!("failed to write `{}`", path.display()))?;
+    Ok(())
+}
@@ -1,116 +1,189 @@
+use super::{
+    context::{CachedClient, ServiceContext},
+    thumbnail_service::ThumbnailService,
+};
 use crate::{
+    application::use_application,
+    credential::Secret,
     database::{
         models::v2::CreatingProduct,
-        tables::v2::{AccountTable, DBError, ProductTable},
-    },
-    dlsite::{
-        api::{get_product_count, get_products, login, test_cookie_store, LoginError},
-        dto::DLsiteProduct,
+        tables::v2::{AccountTable, DBError, ProductMetadataTable, ProductTable},
     },
+    dlsite::{api::LoginError, client::DLsiteClient, dto::DLsiteProduct},
 };
 use anyhow::{anyhow, Error as AnyError};
+use futures::StreamExt;
 use log::{error, info, warn};
 use reqwest_cookie_store::{CookieStore, CookieStoreMutex};
-use std::{io::BufWriter, sync::Arc};
+use std::{
+    collections::HashSet,
+    io::BufWriter,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 use thiserror::Error;
 
+/// How long a cached client is trusted without testing its session,
+/// unless one of its requests is rejected for the lack of a session first.
+const SESSION_TTL: Duration = Duration::from_secs(30 * 60);
+
 #[derive(Error, Debug)]
 pub enum DLsiteServiceError {
     #[error("the given account id `{id}` is not valid")]
     InvalidAccountId { id: i64 },
-    #[error("the given username `{username}` or password `{password}` is invalid")]
-    InvalidCredentials { username: String, password: String },
+    #[error("the given username `{username}` or password is invalid")]
+    InvalidCredentials { username: String },
     #[error("{0:?}")]
     DBError(#[from] DBError),
     #[error("{0:?}")]
     AnyError(#[from] AnyError),
 }
 
-pub struct DLsiteService;
+pub struct DLsiteService<'a> {
+    context: &'a ServiceContext,
+}
 
-impl DLsiteService {
+impl<'a> DLsiteService<'a> {
     pub fn new() -> Self {
-        Self
+        Self::with_context(use_application().context())
     }
 
-    pub async fn get_cookie_store(
-        &self,
-        account_id: i64,
-    ) -> Result<Arc<CookieStoreMutex>, DLsiteServiceError> {
+    pub fn with_context(context: &'a ServiceContext) -> Self {
+        Self { context }
+    }
+
+    /// Returns the logged in client of the account. The client is kept in the application
+    /// and reused as is until one of its requests is rejected for the lack of a session,
+    /// or until the session TTL passes; only then is its session tested again.
+    /// An expired client is replaced by the stored cookies, and as a last resort
+    /// the account logs in again.
+    pub async fn get_client(&self, account_id: i64) -> Result<DLsiteClient, DLsiteServiceError> {
         info!(
-            "[get_cookie_store] fetching cookie store of the account id `{}`",
+            "[get_client] fetching client of the account id `{}`",
             account_id
         );
 
-        let account = match AccountTable::get_one(account_id)? {
+        let cached = self
+            .context
+            .dlsite_account_clients()
+            .get(&account_id)
+            .cloned();
+
+        if let Some(cached) = cached {
+            if !cached.client.is_session_expired() {
+                if cached.validated_at.elapsed() < SESSION_TTL {
+                    return Ok(cached.client);
+                }
+
+                if cached.client.test_cookie_store().await? {
+                    self.cache_client(account_id, &cached.client);
+                    return Ok(cached.client);
+                }
+            }
+
+            info!("[get_client] the session of the cached client is expired");
+            self.context.dlsite_account_clients().remove(&account_id);
+        }
+
+        let account = match AccountTable::get_one(
+            &self.context.connection(),
+            self.context.credential_cipher().as_ref(),
+            account_id,
+        )? {
             Some(account) => account,
             None => {
-                warn!(
-                    "[get_cookie_store] invalid account id detected: {}",
-                    account_id
-                );
+                warn!("[get_client] invalid account id detected: {}", account_id);
                 return Err(DLsiteServiceError::InvalidAccountId { id: account_id });
             }
         };
 
-        match CookieStore::load_json(account.cookie_json.as_bytes()) {
+        match CookieStore::load_json(account.cookie_json.expose().as_bytes()) {
             Ok(cookies) => {
-                info!("[get_cookie_store] successfully parsed cookie_json of the account");
-                let cookie_store = Arc::new(CookieStoreMutex::new(cookies));
-                let is_valid = test_cookie_store(cookie_store.clone()).await?;
-
-                if is_valid {
-                    return Ok(cookie_store);
+                info!("[get_client] successfully parsed cookie_json of the account");
+                let client = DLsiteClient::new(
+                    self.context.dlsite_client().endpoints().clone(),
+                    Arc::new(CookieStoreMutex::new(cookies)),
+                )?;
+
+                if client.test_cookie_store().await? {
+                    self.cache_client(account_id, &client);
+                    return Ok(client);
                 }
 
-                info!("[get_cookie_store] the parsed cookie_json is invalid");
+                info!("[get_client] the parsed cookie_json is invalid");
             }
             Err(err) => {
                 info!(
-                    "[get_cookie_store] failed to parse cookie_json of the account: {:?}",
+                    "[get_client] failed to parse cookie_json of the account: {:?}",
                     err
                 );
             }
         }
 
-        info!("[get_cookie_store] fresh cookie_json is needed; logging in");
-
-        match login(&account.username, &account.password).await {
-            Ok(cookie_store) => {
-                update_cookie_json(account_id, &cookie_store);
-                Ok(cookie_store)
+        info!("[get_client] fresh cookie_json is needed; logging in");
+
+        match DLsiteClient::login(
+            self.context.dlsite_client().endpoints().clone(),
+            &account.username,
+            account.password.expose(),
+        )
+        .await
+        {
+            Ok(client) => {
+                update_cookie_json(self.context, account_id, client.cookie_store());
+                self.cache_client(account_id, &client);
+                Ok(client)
             }
             Err(err) => match err {
                 LoginError::WrongCredentials => {
-                    info!("[get_cookie_store] invalid credentials: {:?}", account);
+                    info!("[get_client] invalid credentials: {:?}", account);
                     return Err(DLsiteServiceError::InvalidCredentials {
                         username: account.username.clone(),
-                        password: account.password.clone(),
                     });
                 }
                 LoginError::Other(err) => {
-                    warn!("[get_cookie_store] error occurred: {:?}", err);
+                    warn!("[get_client] error occurred: {:?}", err);
                     return Err(DLsiteServiceError::AnyError(err));
                 }
             },
         }
     }
 
+    /// Forgets the cached client of the account, e.g. after its credentials are changed.
+    pub fn invalidate_client(&self, account_id: i64) {
+        self.context.dlsite_account_clients().remove(&account_id);
+    }
+
+    /// Keeps the client of the account, whose session is known to be valid as of now.
+    fn cache_client(&self, account_id: i64, client: &DLsiteClient) {
+        self.context.dlsite_account_clients().insert(
+            account_id,
+            CachedClient {
+                client: client.clone(),
+                validated_at: Instant::now(),
+            },
+        );
+    }
+
     pub async fn get_product_count(
         &self,
         account_id: i64,
-        cookie_store: Arc<CookieStoreMutex>,
+        client: &DLsiteClient,
     ) -> Result<u32, DLsiteServiceError> {
         info!(
             "[get_product_count] fetching product count of the account id `{}`",
             account_id
         );
 
-        let product_count = get_product_count(cookie_store.clone()).await?;
-        update_cookie_json(account_id, &cookie_store);
+        let product_count = client.get_product_count().await?;
+        update_cookie_json(self.context, account_id, client.cookie_store());
         Ok(product_count)
     }
 
+    /// Fetches new products of all accounts by comparing the product IDs returned by the API
+    /// against the stored ones. Paging stops as soon as a page contains only known products,
+    /// unless the product count indicates that some products were removed; in that case
+    /// every page is fetched so that the removed products can be reconciled as well.
     pub async fn fetch_new_products(
         &self,
         mut on_progress: impl FnMut(u32, u32),
@@ -119,12 +192,14 @@ impl DLsiteService {
 
         struct AccountDetail {
             pub account_id: i64,
-            pub cookie_store: Arc<CookieStoreMutex>,
-            pub prev_product_count: u32,
-            pub new_product_count: u32,
+            pub client: DLsiteClient,
+            pub product_count: u32,
         }
 
-        let accounts = AccountTable::get_all()?;
+        let accounts = AccountTable::get_all(
+            &self.context.connection(),
+            self.context.credential_cipher().as_ref(),
+        )?;
         let mut account_details = Vec::with_capacity(accounts.len());
 
         let mut progress = 0;
@@ -136,79 +211,135 @@ impl DLsiteService {
                 account.id
             );
 
-            let cookie_store = self.get_cookie_store(account.id).await?;
-            let prev_product_count = account.product_count as u32;
-            let new_product_count = self
-                .get_product_count(account.id, cookie_store.clone())
-                .await?;
+            let client = self.get_client(account.id).await?;
+            let product_count = self.get_product_count(account.id, &client).await?;
 
             info!(
                 "[fetch_new_products] the account id `{}` has {} product(s) before, now has {} product(s)",
                 account.id,
-                prev_product_count,
-                new_product_count
+                account.product_count,
+                product_count
             );
 
-            if new_product_count < prev_product_count {
-                warn!("[fetch_new_products] the account id `{}` has fewer product(s) then before; this account will be ignored", account.id);
-            }
-
-            total_progress += new_product_count - prev_product_count;
+            total_progress += product_count;
 
             account_details.push(AccountDetail {
                 account_id: account.id,
-                cookie_store,
-                prev_product_count,
-                new_product_count,
+                client,
+                product_count,
             });
         }
 
         if total_progress == 0 {
             info!("[fetch_new_products] nothing to update");
-            return Ok(());
+        } else {
+            on_progress(progress, total_progress);
         }
 
-        on_progress(progress, total_progress);
+        let owned_ids = ProductTable::get_many_owned_ids(&self.context.connection())?;
 
-        'outter: for mut detail in account_details {
+        'outter: for detail in account_details {
             const PAGE_LIMIT: u32 = 50;
 
-            // The first page may contain some products that are already fetched before.
-            let mut already_fetched_product_count =
-                (detail.prev_product_count % PAGE_LIMIT) as usize;
-
-            while detail.prev_product_count < detail.new_product_count {
-                let page = 1 + detail.prev_product_count / PAGE_LIMIT;
-                let products = match get_products(detail.cookie_store.clone(), page).await {
+            // products owned by any account are known, so that a product owned by several accounts
+            // is not moved between them; removals are only reconciled for this account's products
+            let account_known_ids = owned_ids
+                .iter()
+                .filter(|(_, account_id)| *account_id == detail.account_id)
+                .map(|(id, _)| id)
+                .collect::<HashSet<_>>();
+            let known_ids = owned_ids.iter().map(|(id, _)| id).collect::<HashSet<_>>();
+
+            let mut seen_ids = HashSet::new();
+            let mut added_products = Vec::new();
+            let mut is_complete = false;
+            let mut page = 1;
+
+            loop {
+                let products = match detail.client.get_products(page).await {
                     Ok(products) => products,
                     Err(err) => {
                         error!("[fetch_new_products] failed to fetch products of {} page of the account id `{}`: {:?}", page, detail.account_id, err);
                         continue 'outter;
                     }
                 };
-                let products = &products[already_fetched_product_count..];
+
+                if products.is_empty() {
+                    is_complete = true;
+                    break;
+                }
 
                 progress += products.len() as u32;
-                detail.prev_product_count += products.len() as u32;
-
-                if let Err(err) = ProductTable::insert_many(
-                    products
-                        .into_iter()
-                        .map(|product| make_creating_product(detail.account_id, product)),
-                ) {
-                    error!("[fetch_new_products] failed to update the products from the account id `{}` to the database: {:?}", detail.account_id, err);
-                    continue 'outter;
+                on_progress(progress.min(total_progress), total_progress);
+
+                let mut has_unknown = false;
+
+                for product in products {
+                    if !seen_ids.insert(product.id.clone()) {
+                        continue;
+                    }
+
+                    if !known_ids.contains(&product.id) {
+                        has_unknown = true;
+                        added_products.push(product);
+                    }
                 }
 
-                on_progress(progress, total_progress);
+                if detail.product_count <= page * PAGE_LIMIT {
+                    is_complete = true;
+                    break;
+                }
+
+                // a page with only known products; everything after it should be known as well,
+                // unless the counts disagree, which means some products were removed. a product
+                // shared with another account is stored under that one, so it is counted once seen
+                let unseen_known_count = account_known_ids
+                    .iter()
+                    .filter(|id| !seen_ids.contains(**id))
+                    .count();
+
+                if !has_unknown
+                    && seen_ids.len() + unseen_known_count == detail.product_count as usize
+                {
+                    break;
+                }
 
-                // first page is over
-                already_fetched_product_count = 0;
+                page += 1;
+            }
+
+            let removed_ids = if is_complete {
+                account_known_ids
+                    .iter()
+                    .filter(|id| !seen_ids.contains(**id))
+                    .map(|id| (*id).clone())
+                    .collect::<Vec<_>>()
+            } else {
+                vec![]
+            };
+
+            info!(
+                "[fetch_new_products] the account id `{}` has {} new product(s) and {} removed product(s)",
+                detail.account_id,
+                added_products.len(),
+                removed_ids.len()
+            );
+
+            if let Err(err) = ProductTable::sync_owned(
+                &mut self.context.connection(),
+                detail.account_id,
+                added_products
+                    .iter()
+                    .map(|product| make_creating_product(detail.account_id, product)),
+                &removed_ids,
+            ) {
+                error!("[fetch_new_products] failed to update the products from the account id `{}` to the database: {:?}", detail.account_id, err);
+                continue 'outter;
             }
 
             if let Err(err) = AccountTable::update_one_product_count(
+                &self.context.connection(),
                 detail.account_id,
-                detail.prev_product_count as i32,
+                detail.product_count as i32,
             ) {
                 error!(
                     "[fetch_new_products] failed to update the product count of the account id `{}` to the database: {:?}",
@@ -218,30 +349,44 @@ impl DLsiteService {
             }
         }
 
+        if total_progress != 0 {
+            on_progress(total_progress, total_progress);
+        }
+
+        self.fetch_missing_metadata().await;
+
+        if let Err(err) = ThumbnailService::with_context(self.context)
+            .cache_missing()
+            .await
+        {
+            error!(
+                "[fetch_new_products] failed to cache the thumbnails: {:?}",
+                err
+            );
+        }
+
         Ok(())
     }
 
+    /// Refetches every product of every account and swaps them into the database at once.
+    /// The purchase list of all accounts is collected first; if any account fails,
+    /// nothing is written and the existing products are left untouched.
     pub async fn refresh_products_all(
         &self,
         mut on_progress: impl FnMut(u32, u32),
     ) -> Result<(), DLsiteServiceError> {
-        info!("[refresh_products_all] fetching new products for all accounts");
-
-        if let Err(err) = ProductTable::remove_many_owned() {
-            error!(
-                "[refresh_products_all] failed to remove all owned products: {:?}",
-                err
-            );
-            return Err(DLsiteServiceError::DBError(err));
-        }
+        info!("[refresh_products_all] fetching all products for all accounts");
 
         struct AccountDetail {
             pub account_id: i64,
-            pub cookie_store: Arc<CookieStoreMutex>,
+            pub client: DLsiteClient,
             pub new_product_count: u32,
         }
 
-        let accounts = AccountTable::get_all()?;
+        let accounts = AccountTable::get_all(
+            &self.context.connection(),
+            self.context.credential_cipher().as_ref(),
+        )?;
         let mut account_details = Vec::with_capacity(accounts.len());
 
         let mut progress = 0;
@@ -253,10 +398,8 @@ impl DLsiteService {
                 account.id
             );
 
-            let cookie_store = self.get_cookie_store(account.id).await?;
-            let new_product_count = self
-                .get_product_count(account.id, cookie_store.clone())
-                .await?;
+            let client = self.get_client(account.id).await?;
+            let new_product_count = self.get_product_count(account.id, &client).await?;
 
             info!(
                 "[refresh_products_all] the account id `{}` now has {} product(s)",
@@ -267,64 +410,181 @@ impl DLsiteService {
 
             account_details.push(AccountDetail {
                 account_id: account.id,
-                cookie_store,
+                client,
                 new_product_count,
             });
         }
 
-        if total_progress == 0 {
-            info!("[refresh_products_all] nothing to update");
-            return Ok(());
+        if total_progress != 0 {
+            on_progress(progress, total_progress);
         }
 
-        on_progress(progress, total_progress);
+        struct AccountProducts {
+            pub account_id: i64,
+            pub products: Vec<DLsiteProduct>,
+        }
 
-        'outter: for detail in account_details {
-            const PAGE_LIMIT: u32 = 50;
+        let mut account_products = Vec::with_capacity(account_details.len());
 
-            let mut prev_product_count = 0;
+        for detail in account_details {
+            let mut products = Vec::with_capacity(detail.new_product_count as usize);
+            let mut page = 1;
 
-            while prev_product_count < detail.new_product_count {
-                let page = 1 + prev_product_count / PAGE_LIMIT;
-                let products = match get_products(detail.cookie_store.clone(), page).await {
-                    Ok(products) => products,
+            while (products.len() as u32) < detail.new_product_count {
+                let page_products = match detail.client.get_products(page).await {
+                    Ok(page_products) => page_products,
                     Err(err) => {
-                        error!("[refresh_products_all] failed to fetch products of {} page of the account id `{}`: {:?}", page, detail.account_id, err);
-                        continue 'outter;
+                        error!("[refresh_products_all] failed to fetch products of {} page of the account id `{}`; nothing will be updated: {:?}", page, detail.account_id, err);
+                        return Err(DLsiteServiceError::AnyError(err));
                     }
                 };
 
-                progress += products.len() as u32;
-                prev_product_count += products.len() as u32;
-
-                if let Err(err) = ProductTable::insert_many(
-                    products
-                        .iter()
-                        .map(|product| make_creating_product(detail.account_id, product)),
-                ) {
-                    error!("[refresh_products_all] failed to update the products from the account id `{}` to the database: {:?}", detail.account_id, err);
-                    continue 'outter;
+                // a partial list would remove the products it misses from the database
+                if page_products.is_empty() {
+                    error!("[refresh_products_all] the account id `{}` returned an empty page {} before reaching {} product(s); nothing will be updated", detail.account_id, page, detail.new_product_count);
+                    return Err(DLsiteServiceError::AnyError(anyhow!(
+                        "the account id `{}` returned {} of {} product(s)",
+                        detail.account_id,
+                        products.len(),
+                        detail.new_product_count
+                    )));
                 }
 
+                page += 1;
+                progress += page_products.len() as u32;
+                products.extend(page_products);
+
                 on_progress(progress, total_progress);
             }
 
-            if let Err(err) =
-                AccountTable::update_one_product_count(detail.account_id, prev_product_count as i32)
-            {
+            account_products.push(AccountProducts {
+                account_id: detail.account_id,
+                products,
+            });
+        }
+
+        if let Err(err) = ProductTable::replace_many_owned(
+            &mut self.context.connection(),
+            account_products.iter().flat_map(|account| {
+                account
+                    .products
+                    .iter()
+                    .map(|product| make_creating_product(account.account_id, product))
+            }),
+        ) {
+            error!(
+                "[refresh_products_all] failed to replace the owned products in the database: {:?}",
+                err
+            );
+            return Err(DLsiteServiceError::DBError(err));
+        }
+
+        for account in &account_products {
+            if let Err(err) = AccountTable::update_one_product_count(
+                &self.context.connection(),
+                account.account_id,
+                account.products.len() as i32,
+            ) {
                 error!(
                     "[refresh_products_all] failed to update the product count of the account id `{}` to the database: {:?}",
-                    detail.account_id,
+                    account.account_id,
                     err
                 );
             }
         }
 
+        self.fetch_missing_metadata().await;
+
+        if let Err(err) = ThumbnailService::with_context(self.context)
+            .cache_missing()
+            .await
+        {
+            error!(
+                "[refresh_products_all] failed to cache the thumbnails: {:?}",
+                err
+            );
+        }
+
         Ok(())
     }
+
+    /// Fetches the metadata of every owned product that has none yet.
+    /// The purchase list does not carry the metadata, so it is fetched per product.
+    /// Failures are logged and left for the next refresh.
+    pub async fn fetch_missing_metadata(&self) {
+        const CONCURRENCY: usize = 4;
+
+        let owned_ids = match ProductTable::get_many_owned_ids(&self.context.connection()) {
+            Ok(owned_ids) => owned_ids,
+            Err(err) => {
+                error!(
+                    "[fetch_missing_metadata] failed to fetch the owned products from the database: {:?}",
+                    err
+                );
+                return;
+            }
+        };
+        let product_ids = match ProductMetadataTable::get_many_missing_product_ids(
+            &self.context.connection(),
+            owned_ids.into_iter().map(|(id, _)| id),
+        ) {
+            Ok(product_ids) => product_ids,
+            Err(err) => {
+                error!(
+                    "[fetch_missing_metadata] failed to fetch the products without metadata from the database: {:?}",
+                    err
+                );
+                return;
+            }
+        };
+
+        if product_ids.is_empty() {
+            return;
+        }
+
+        info!(
+            "[fetch_missing_metadata] fetching metadata of {} product(s)",
+            product_ids.len()
+        );
+
+        let products = futures::stream::iter(product_ids)
+            .map(|product_id| async move {
+                match self
+                    .context
+                    .dlsite_client()
+                    .get_product_from_non_owner_api(&product_id)
+                    .await
+                {
+                    Ok(product) => Some(product),
+                    Err(err) => {
+                        warn!(
+                            "[fetch_missing_metadata] failed to fetch metadata of the product `{}`: {:?}",
+                            product_id, err
+                        );
+                        None
+                    }
+                }
+            })
+            .buffer_unordered(CONCURRENCY)
+            .collect::<Vec<_>>()
+            .await;
+
+        if let Err(err) = ProductMetadataTable::insert_many(
+            &mut self.context.connection(),
+            products
+                .iter()
+                .flatten()
+                .filter_map(|product| Some((&product.id, product.metadata.as_ref()?))),
+        ) {
+            error!(
+                "[fetch_missing_metadata] failed to update the product metadata to the database: {:?}",
+                err
+            );
+        }
+    }
 }
 
-fn update_cookie_json(account_id: i64, cookie_store: &CookieStoreMutex) {
+fn update_cookie_json(context: &ServiceContext, account_id: i64, cookie_store: &CookieStoreMutex) {
     info!(
         "[update_cookie_json] updating cookie_json of the account id `{}`",
         account_id
@@ -332,7 +592,12 @@ fn update_cookie_json(account_id: i64, cookie_store: &CookieStoreMutex) {
 
     match serialize_cookie_store(&cookie_store) {
         Ok(serialized) => {
-            if let Err(err) = AccountTable::update_one_cookie_json(account_id, &serialized) {
+            if let Err(err) = AccountTable::update_one_cookie_json(
+                &context.connection(),
+                context.credential_cipher().as_ref(),
+                account_id,
+                &Secret::new(serialized),
+            ) {
                 warn!(
                     "[update_cookie_json] failed to update the cookie_json of the account id `{}` to the database: {:?}",
                     account_id,
@@ -0,0 +1,638 @@
+use super::{
+    context::ServiceContext,
+    download_service::{
+        DecompressedProduct, DownloadCancellation, DownloadProgress, DownloadService,
+        DownloadServiceError,
+    },
+    library_service::LibraryService,
+};
+use crate::{
+    application::use_application,
+    command::get_product_download_path,
+    database::{
+        models::v2::{CreatingDownloadJob, DownloadJob, DownloadJobStatus},
+        tables::v2::{DBError, DownloadJobTable, ProductTable},
+    },
+    dlsite::{dto::DLsiteProductType, product_id::ProductId},
+    library::extraction::SkippedEntry,
+    window::{MainWindow, WindowInfoProvider},
+};
+use anyhow::Error as AnyError;
+use log::{error, info, warn};
+use parking_lot::Mutex;
+use rusqlite::Connection;
+use serde::Serialize;
+use std::{
+    collections::VecDeque,
+    path::Path,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tauri::{
+    async_runtime::{spawn, spawn_blocking, JoinHandle},
+    Manager,
+};
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum DownloadQueueServiceError {
+    #[error("the given download job id `{id}` is not valid")]
+    InvalidJobId { id: i64 },
+    #[error("{0:?}")]
+    DBError(#[from] DBError),
+    #[error("{0:?}")]
+    AnyError(#[from] AnyError),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum DownloadPhase {
+    Downloading,
+    Extracting,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ProductDownloadProgressEvent<'a> {
+    pub product_id: &'a ProductId,
+    pub phase: DownloadPhase,
+    /// the percentage of the current phase
+    pub progress: usize,
+    /// the bytes per second over the last few seconds; `None` if the progress is not in bytes
+    /// or has just begun
+    pub bytes_per_sec: Option<u64>,
+    /// the estimated seconds until the phase ends
+    pub eta_secs: Option<u64>,
+    /// the entry being extracted
+    pub entry: Option<ExtractingEntry<'a>>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ExtractingEntry<'a> {
+    pub name: &'a str,
+    /// the number of the entries before the entry
+    pub index: u64,
+    pub count: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ProductDownloadEndEvent<'a> {
+    pub product_id: &'a ProductId,
+    pub downloaded_path: Option<&'a Path>,
+    /// the entries of the archives that were left out when extracting them
+    pub skipped_entries: &'a [SkippedEntry],
+}
+
+/// A running download job, which can be stopped at any point.
+pub struct DownloadTask {
+    pub handle: JoinHandle<()>,
+    pub cancellation: Arc<DownloadCancellation>,
+}
+
+pub struct DownloadQueueService;
+
+/// Measures the speed of a phase over the last few seconds to estimate when it ends,
+/// and throttles the progress events.
+struct ProgressMeter {
+    phase: Option<DownloadPhase>,
+    samples: VecDeque<(Instant, u64)>,
+    last_emitted: Option<Instant>,
+}
+
+impl ProgressMeter {
+    /// How far back the samples are kept to measure the speed.
+    const WINDOW: Duration = Duration::from_secs(5);
+    /// How often the progress is emitted at most, other than as a phase begins or ends.
+    const INTERVAL: Duration = Duration::from_millis(250);
+
+    fn new() -> Self {
+        Self {
+            phase: None,
+            samples: VecDeque::new(),
+            last_emitted: None,
+        }
+    }
+
+    /// Records the progress, returning the speed per second and the estimated seconds left
+    /// if the progress should be emitted.
+    fn update(
+        &mut self,
+        phase: DownloadPhase,
+        progress: u64,
+        total: u64,
+    ) -> Option<(Option<u64>, Option<u64>)> {
+        let now = Instant::now();
+
+        // the progress starts over with a new phase or the next archive; the downloads of
+        // several files may report slightly out of order, which is not a new phase
+        let goes_back = self
+            .samples
+            .back()
+            .map_or(false, |&(_, last)| progress < last);
+
+        if self.phase != Some(phase) || goes_back && phase == DownloadPhase::Extracting {
+            self.phase = Some(phase);
+            self.samples.clear();
+            self.last_emitted = None;
+        }
+
+        self.samples.push_back((now, progress));
+
+        while let Some(&(time, _)) = self.samples.front() {
+            if now.duration_since(time) <= Self::WINDOW {
+                break;
+            }
+
+            self.samples.pop_front();
+        }
+
+        let is_throttled = self
+            .last_emitted
+            .map_or(false, |last| now.duration_since(last) < Self::INTERVAL);
+
+        if is_throttled && progress < total {
+            return None;
+        }
+
+        self.last_emitted = Some(now);
+
+        let &(first_time, first) = self.samples.front()?;
+        let elapsed = now.duration_since(first_time).as_secs_f64();
+
+        if elapsed < 1f64 || progress <= first {
+            return Some((None, None));
+        }
+
+        let speed = (progress - first) as f64 / elapsed;
+        let eta = total.saturating_sub(progress) as f64 / speed;
+
+        Some((Some(speed.round() as u64), Some(eta.ceil() as u64)))
+    }
+}
+
+impl DownloadQueueService {
+    pub fn new() -> Self {
+        Self
+    }
+
+    /// Restores the queue after an application restart.
+    /// Jobs that were active when the application exited are queued again.
+    pub fn restore(&self) -> Result<(), DownloadQueueServiceError> {
+        info!("[restore] restoring the download queue");
+
+        DownloadJobTable::update_many_status(
+            &use_application().connection(),
+            DownloadJobStatus::Active,
+            DownloadJobStatus::Queued,
+        )?;
+        self.pump()
+    }
+
+    pub fn list(&self) -> Result<Vec<DownloadJob>, DownloadQueueServiceError> {
+        Ok(DownloadJobTable::get_all(&use_application().connection())?)
+    }
+
+    /// Adds the product to the end of the queue and starts it if a slot is free.
+    /// A product that is already being downloaded is left untouched.
+    pub fn enqueue(
+        &self,
+        account_id: i64,
+        product_id: &ProductId,
+        decompress: bool,
+    ) -> Result<i64, DownloadQueueServiceError> {
+        if let Some(job) =
+            DownloadJobTable::get_one_by_product_id(&use_application().connection(), product_id)?
+        {
+            if job.status == DownloadJobStatus::Active {
+                info!(
+                    "[enqueue] the product `{}` is already being downloaded",
+                    product_id
+                );
+                return Ok(job.id);
+            }
+        }
+
+        info!(
+            "[enqueue] queueing the product `{}` of the account id `{}`",
+            product_id, account_id
+        );
+
+        let id = DownloadJobTable::insert_one(
+            &use_application().connection(),
+            CreatingDownloadJob {
+                account_id,
+                product_id,
+                decompress,
+            },
+        )?;
+
+        self.pump()?;
+        Ok(id)
+    }
+
+    /// Pauses the job. An active job is stopped and continues from its partial files when resumed.
+    pub fn pause(&self, job_id: i64) -> Result<(), DownloadQueueServiceError> {
+        let job = self.get_job(job_id)?;
+
+        info!(
+            "[pause] pausing the download of the product `{}`",
+            job.product_id
+        );
+
+        let task = use_application().download_tasks().remove(&job_id);
+
+        if let Some(task) = task {
+            task.cancellation.cancel();
+            task.handle.abort();
+            emit_download_end(&job.product_id, None, &[]);
+        }
+
+        DownloadJobTable::update_one_status(
+            &use_application().connection(),
+            job_id,
+            DownloadJobStatus::Paused,
+            None,
+        )?;
+        self.pump()
+    }
+
+    /// Puts a paused or failed job back into the queue.
+    pub fn resume(&self, job_id: i64) -> Result<(), DownloadQueueServiceError> {
+        let job = self.get_job(job_id)?;
+
+        if job.status == DownloadJobStatus::Active || job.status == DownloadJobStatus::Queued {
+            return Ok(());
+        }
+
+        info!(
+            "[resume] resuming the download of the product `{}`",
+            job.product_id
+        );
+
+        DownloadJobTable::update_one_status(
+            &use_application().connection(),
+            job_id,
+            DownloadJobStatus::Queued,
+            None,
+        )?;
+        self.pump()
+    }
+
+    /// Removes the job from the queue along with its partial files, whatever its status,
+    /// leaving an earlier download of the product as it is. An active job is stopped first.
+    pub fn cancel(&self, job_id: i64) -> Result<(), DownloadQueueServiceError> {
+        let job = self.get_job(job_id)?;
+
+        info!(
+            "[cancel] cancelling the download of the product `{}`",
+            job.product_id
+        );
+
+        let task = use_application().download_tasks().remove(&job_id);
+        let cancellation = task.map(|task| {
+            task.cancellation.cancel();
+            task.handle.abort();
+            emit_download_end(&job.product_id, None, &[]);
+            task.cancellation
+        });
+
+        let path = get_product_download_path(use_application().app_handle())?;
+        let product_id = job.product_id.clone();
+
+        // an extraction in progress is only stopped at its next entry, so the cleanup waits for it
+        spawn_blocking(move || {
+            if let Some(cancellation) = cancellation {
+                cancellation.wait();
+            }
+
+            if let Err(err) = DownloadService::new().remove_partial_download(&product_id, path) {
+                warn!(
+                    "[cancel] failed to cleanup the partially downloaded product `{}`: {:?}",
+                    product_id, err
+                );
+            }
+        });
+
+        DownloadJobTable::remove_one(&use_application().connection(), job_id)?;
+        self.pump()
+    }
+
+    /// Moves the given jobs to the front of the queue, in the given order.
+    pub fn reorder(&self, job_ids: &[i64]) -> Result<(), DownloadQueueServiceError> {
+        DownloadJobTable::update_order(&mut use_application().connection(), job_ids)?;
+        emit_queue_updated();
+        Ok(())
+    }
+
+    pub fn get_max_concurrent_downloads(&self) -> Result<u32, DownloadQueueServiceError> {
+        Ok(DownloadJobTable::get_max_concurrent_downloads(
+            &use_application().connection(),
+        )?)
+    }
+
+    /// Updates the concurrency limit. Lowering it does not stop jobs that are already running.
+    pub fn set_max_concurrent_downloads(
+        &self,
+        max_concurrent_downloads: u32,
+    ) -> Result<(), DownloadQueueServiceError> {
+        DownloadJobTable::set_max_concurrent_downloads(
+            &use_application().connection(),
+            max_concurrent_downloads.max(1),
+        )?;
+        self.pump()
+    }
+
+    /// Starts queued jobs until the concurrency limit is reached.
+    fn pump(&self) -> Result<(), DownloadQueueServiceError> {
+        let max_concurrent_downloads =
+            DownloadJobTable::get_max_concurrent_downloads(&use_application().connection())?.max(1);
+        let mut tasks = use_application().download_tasks();
+        let free_slots = max_concurrent_downloads.saturating_sub(tasks.len() as u32);
+
+        if free_slots != 0 {
+            let jobs =
+                DownloadJobTable::get_many_queued(&use_application().connection(), free_slots)?;
+
+            for job in jobs {
+                info!(
+                    "[pump] starting the download of the product `{}`",
+                    job.product_id
+                );
+
+                DownloadJobTable::update_one_status(
+                    &use_application().connection(),
+                    job.id,
+                    DownloadJobStatus::Active,
+                    None,
+                )?;
+
+                let cancellation = Arc::new(DownloadCancellation::default());
+                let job_id = job.id;
+                let handle = spawn(run_job(job, cancellation.clone()));
+                tasks.insert(
+                    job_id,
+                    DownloadTask {
+                        handle,
+                        cancellation,
+                    },
+                );
+            }
+        }
+
+        drop(tasks);
+        emit_queue_updated();
+        Ok(())
+    }
+
+    fn get_job(&self, job_id: i64) -> Result<DownloadJob, DownloadQueueServiceError> {
+        match DownloadJobTable::get_one(&use_application().connection(), job_id)? {
+            Some(job) => Ok(job),
+            None => Err(DownloadQueueServiceError::InvalidJobId { id: job_id }),
+        }
+    }
+}
+
+async fn run_job(job: DownloadJob, cancellation: Arc<DownloadCancellation>) {
+    let app_handle = use_application().app_handle();
+
+    if let Some(window) = app_handle.get_webview_window(&MainWindow.label()) {
+        window.emit("download-begin", &job.product_id).ok();
+    }
+
+    // owned by the callbacks, since an extraction reports its progress from a blocking thread
+    let meter = Arc::new(Mutex::new(ProgressMeter::new()));
+    let product_id = job.product_id.clone();
+    let emit_progress = move |phase: DownloadPhase,
+                              progress: u64,
+                              total_progress: u64,
+                              is_in_bytes: bool,
+                              entry: Option<ExtractingEntry>| {
+        let (speed, eta) = match meter.lock().update(phase, progress, total_progress) {
+            Some(estimate) => estimate,
+            None => return,
+        };
+
+        if let Some(window) = app_handle.get_webview_window(&MainWindow.label()) {
+            window
+                .emit(
+                    "download-progress",
+                    ProductDownloadProgressEvent {
+                        product_id: &product_id,
+                        phase,
+                        progress: if total_progress == 0 {
+                            100
+                        } else {
+                            (progress.min(total_progress) as f64 / total_progress as f64 * 100f64)
+                                .round() as usize
+                        },
+                        bytes_per_sec: speed.filter(|_| is_in_bytes),
+                        eta_secs: eta,
+                        entry,
+                    },
+                )
+                .ok();
+        }
+    };
+
+    let result = match get_product_download_path(app_handle) {
+        Ok(path) => {
+            download_job(
+                use_application().context(),
+                &job,
+                &path,
+                cancellation,
+                emit_progress,
+            )
+            .await
+        }
+        Err(err) => Err(DownloadServiceError::AnyError(err)),
+    };
+
+    match &result {
+        Ok(decompressed) => emit_download_end(
+            &job.product_id,
+            Some(&decompressed.path),
+            &decompressed.skipped_entries,
+        ),
+        Err(_) => emit_download_end(&job.product_id, None, &[]),
+    }
+
+    // the download root is created by the first download; nothing is watched until then
+    if result.is_ok() {
+        if let Err(err) = LibraryService::new().watch_downloads() {
+            warn!("[run_job] failed to watch the download root: {:?}", err);
+        }
+    }
+
+    finish_job(&use_application().connection(), &job, &result);
+    use_application().download_tasks().remove(&job.id);
+
+    if let Err(err) = DownloadQueueService::new().pump() {
+        error!("[run_job] failed to start the next download job: {:?}", err);
+    }
+}
+
+/// Downloads the product of the job into the download root, reporting the progress of each
+/// phase as `(phase, progress, total_progress, is_in_bytes, entry)`.
+pub(super) async fn download_job(
+    context: &ServiceContext,
+    job: &DownloadJob,
+    download_path: &Path,
+    cancellation: Arc<DownloadCancellation>,
+    emit_progress: impl Fn(DownloadPhase, u64, u64, bool, Option<ExtractingEntry>)
+        + Clone
+        + Send
+        + Sync
+        + 'static,
+) -> Result<DecompressedProduct, DownloadServiceError> {
+    let product = ProductTable::get_one(&context.connection(), &job.product_id);
+    let is_voice_comic = match product {
+        Ok(product) => product.map_or(false, |product| product.ty == DLsiteProductType::VoiceComic),
+        Err(err) => {
+            warn!(
+                "[download_job] failed to fetch the product `{}`; assuming it is not a voice comic: {:?}",
+                job.product_id, err
+            );
+            false
+        }
+    };
+    let service = DownloadService::with_context(context);
+
+    if is_voice_comic {
+        service
+            .download_voice_comic(
+                job.account_id,
+                &job.product_id,
+                download_path,
+                |progress, total_progress| {
+                    // the progress of a voice comic is in files
+                    emit_progress(
+                        DownloadPhase::Downloading,
+                        progress,
+                        total_progress,
+                        false,
+                        None,
+                    );
+                },
+            )
+            .await
+            .map(DecompressedProduct::from)
+    } else if job.decompress {
+        service
+            .download_with_decompression(
+                job.account_id,
+                &job.product_id,
+                download_path,
+                cancellation,
+                {
+                    let emit_progress = emit_progress.clone();
+                    move |progress| match progress {
+                        DownloadProgress::Downloading { downloaded, total } => {
+                            emit_progress(DownloadPhase::Downloading, downloaded, total, true, None)
+                        }
+                        DownloadProgress::Extracting(progress) => emit_progress(
+                            DownloadPhase::Extracting,
+                            progress.written_size,
+                            progress.declared_size,
+                            true,
+                            Some(ExtractingEntry {
+                                name: progress.entry_name,
+                                index: progress.entry_index,
+                                count: progress.entry_count,
+                            }),
+                        ),
+                    }
+                },
+            )
+            .await
+    } else {
+        service
+            .download(
+                job.account_id,
+                &job.product_id,
+                download_path,
+                |progress, total_progress| {
+                    emit_progress(
+                        DownloadPhase::Downloading,
+                        progress,
+                        total_progress,
+                        true,
+                        None,
+                    );
+                },
+            )
+            .await
+            .map(DecompressedProduct::from)
+    }
+}
+
+/// Records the outcome of the job. A finished job leaves the queue, while a failed one stays
+/// in it with its error until it is resumed or cancelled.
+pub(super) fn finish_job(
+    connection: &Connection,
+    job: &DownloadJob,
+    result: &Result<DecompressedProduct, DownloadServiceError>,
+) {
+    let update_result = match result {
+        Ok(_) => DownloadJobTable::remove_one(connection, job.id),
+        Err(err) => {
+            error!(
+                "[finish_job] failed to download the product `{}`: {:?}",
+                job.product_id, err
+            );
+            DownloadJobTable::update_one_status(
+                connection,
+                job.id,
+                DownloadJobStatus::Failed,
+                Some(&err.to_string()),
+            )
+        }
+    };
+
+    if let Err(err) = update_result {
+        error!(
+            "[finish_job] failed to update the download job of the product `{}`: {:?}",
+            job.product_id, err
+        );
+    }
+}
+
+fn emit_download_end(
+    product_id: &ProductId,
+    downloaded_path: Option<&Path>,
+    skipped_entries: &[SkippedEntry],
+) {
+    if let Some(window) = use_application()
+        .app_handle()
+        .get_webview_window(&MainWindow.label())
+    {
+        window
+            .emit(
+                "download-end",
+                ProductDownloadEndEvent {
+                    product_id,
+                    downloaded_path,
+                    skipped_entries,
+                },
+            )
+            .ok();
+    }
+}
+
+fn emit_queue_updated() {
+    let jobs = match DownloadJobTable::get_all(&use_application().connection()) {
+        Ok(jobs) => jobs,
+        Err(err) => {
+            warn!(
+                "[emit_queue_updated] failed to fetch the download jobs: {:?}",
+                err
+            );
+            return;
+        }
+    };
+
+    if let Some(window) = use_application()
+        .app_handle()
+        .get_webview_window(&MainWindow.label())
+    {
+        window.emit("download-queue-updated", jobs).ok();
+    }
+}
@@ -1,18 +1,37 @@
-use super::dlsite_service::DLsiteServiceError;
+use super::{context::ServiceContext, dlsite_service::DLsiteServiceError};
 use crate::{
+    application::use_application,
+    command::get_product_folder_template,
     database::{
         models::v2::CreatingProductDownload,
-        tables::v2::{DBError, ProductDownloadTable},
+        tables::v2::{
+            DBError, ProductDownloadTable, ProductManifestTable, ProductNameEncodingTable,
+            ProductTable,
+        },
     },
-    dlsite::{
-        api::{download_product_files, get_product_files},
-        dto::DLsiteProductFiles,
+    dlsite::{api::is_partial_file_name, dto::DLsiteProductFiles, product_id::ProductId},
+    library::{
+        archive::{plan_extraction, ArchiveFormat, DetectedArchive, ExtractionPlan, PayloadReader},
+        extraction::{
+            extract_rar, extract_seven_zip, extract_zip, move_extracted, ExtractionProgress,
+            ExtractionReport, OnExtractionProgress, SkippedEntry,
+        },
+        manifest::build_manifest,
+        name_encoding::NameEncoding,
+        relocation::remove_empty_parents,
     },
     services::dlsite_service::DLsiteService,
 };
-use anyhow::{Context, Error as AnyError};
+use anyhow::{anyhow, Context, Error as AnyError};
 use log::{error, info, warn};
-use std::path::{Path, PathBuf};
+use std::{
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+use tauri::async_runtime::spawn_blocking;
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -22,103 +41,274 @@ pub enum DownloadServiceError {
     #[error("{0:?}")]
     IOError(#[from] std::io::Error),
     #[error("{0:?}")]
-    ZipExtractError(#[from] zip_extract::ZipExtractError),
-    #[error("{0:?}")]
-    UnrarError(#[from] unrar::error::UnrarError),
-    #[error("{0:?}")]
     AnyError(#[from] AnyError),
     #[error("{0:?}")]
     DLsiteServiceError(#[from] DLsiteServiceError),
 }
 
-pub struct DownloadService;
+/// A downloaded product after its archives are extracted.
+#[derive(Debug, Clone)]
+pub struct DecompressedProduct {
+    pub path: PathBuf,
+    /// the entries of the archives that were left out of the product folder
+    pub skipped_entries: Vec<SkippedEntry>,
+}
+
+/// The progress of [`DownloadService::download_with_decompression`], in each of its phases.
+#[derive(Debug, Clone, Copy)]
+pub enum DownloadProgress<'a> {
+    /// the bytes of the product files downloaded so far
+    Downloading { downloaded: u64, total: u64 },
+    /// the progress of the archive being extracted
+    Extracting(ExtractionProgress<'a>),
+}
+
+/// Cancels a download from outside of its task.
+/// Aborting the task stops it at its next await, but not an extraction already running on a blocking thread, which checks this between the entries instead.
+#[derive(Debug, Default)]
+pub struct DownloadCancellation {
+    is_cancelled: AtomicBool,
+    /// held while an archive is extracted
+    extraction: parking_lot::Mutex<()>,
+}
 
-impl DownloadService {
+impl DownloadCancellation {
+    pub fn cancel(&self) {
+        self.is_cancelled.store(true, Ordering::SeqCst);
+    }
+
+    /// Blocks until the extraction in progress, if any, stops.
+    pub fn wait(&self) {
+        drop(self.extraction.lock());
+    }
+}
+
+impl From<PathBuf> for DecompressedProduct {
+    fn from(path: PathBuf) -> Self {
+        Self {
+            path,
+            skipped_entries: vec![],
+        }
+    }
+}
+
+pub struct DownloadService<'a> {
+    context: &'a ServiceContext,
+}
+
+impl<'a> DownloadService<'a> {
     pub fn new() -> Self {
-        Self
+        Self::with_context(use_application().context())
+    }
+
+    pub fn with_context(context: &'a ServiceContext) -> Self {
+        Self { context }
     }
 
     pub async fn download(
         &self,
         account_id: i64,
-        product_id: impl AsRef<str>,
+        product_id: &ProductId,
         base_path: impl AsRef<Path>,
         on_progress: impl Fn(u64, u64),
     ) -> Result<PathBuf, DownloadServiceError> {
-        Ok(download(account_id, product_id, base_path, on_progress)
-            .await?
-            .base_path)
+        let downloaded =
+            download(self.context, account_id, product_id, base_path, on_progress).await?;
+        record_manifest(self.context, product_id, &downloaded.base_path).await;
+        Ok(downloaded.base_path)
     }
 
     pub async fn download_with_decompression(
         &self,
         account_id: i64,
-        product_id: impl AsRef<str>,
+        product_id: &ProductId,
         base_path: impl AsRef<Path>,
-        on_progress: impl Fn(u64, u64, bool),
-    ) -> Result<PathBuf, DownloadServiceError> {
-        let product_id = product_id.as_ref();
-        let downloaded = download(account_id, product_id, base_path, |progress, total| {
-            on_progress(progress, total, false);
-        })
+        cancellation: Arc<DownloadCancellation>,
+        on_progress: impl Fn(DownloadProgress) + Send + Sync + 'static,
+    ) -> Result<DecompressedProduct, DownloadServiceError> {
+        // the extraction runs on a blocking thread, which reports its progress from there
+        let on_progress = Arc::new(on_progress);
+        let downloaded = download(
+            self.context,
+            account_id,
+            product_id,
+            base_path,
+            |downloaded, total| {
+                on_progress(DownloadProgress::Downloading { downloaded, total });
+            },
+        )
         .await?;
 
-        if downloaded.product_files.files.len() == 1
-            && downloaded.product_files.files[0]
-                .file_name
-                .to_ascii_lowercase()
-                .ends_with(".zip")
-        {
-            on_progress(1, 1, true);
-
-            if let Err(err) =
-                decompress_single(&downloaded.product_files, &downloaded.base_path).await
-            {
-                warn!(
-                    "[download_with_decompression] failed to decompress (single) the product `{}`: {:?}",
-                    product_id, err
+        let file_names = downloaded
+            .product_files
+            .files
+            .iter()
+            .map(|file| file.file_name.as_str())
+            .collect::<Vec<_>>();
+        let plan = plan_extraction(&downloaded.base_path, &file_names)?;
+        let mut skipped_entries = Vec::new();
+        let skip_reason = match &plan {
+            ExtractionPlan::Extract(archives) => {
+                let name_encoding = match ProductNameEncodingTable::get_one(
+                    &self.context.connection(),
+                    product_id,
+                ) {
+                    Ok(name_encoding) => name_encoding.map(|name_encoding| name_encoding.encoding),
+                    Err(err) => {
+                        warn!(
+                            "[download_with_decompression] failed to fetch the name encoding of the product `{}`; guessing it instead: {:?}",
+                            product_id, err
+                        );
+                        None
+                    }
+                };
+
+                let on_extraction_progress: Arc<dyn Fn(ExtractionProgress) + Send + Sync> = {
+                    let on_progress = on_progress.clone();
+                    Arc::new(move |progress: ExtractionProgress| {
+                        on_progress(DownloadProgress::Extracting(progress))
+                    })
+                };
+                let mut failures = Vec::new();
+
+                for archive in archives {
+                    match decompress(
+                        archive,
+                        &downloaded.base_path,
+                        name_encoding,
+                        cancellation.clone(),
+                        on_extraction_progress.clone(),
+                    )
+                    .await
+                    {
+                        Ok(report) => skipped_entries.extend(report.skipped),
+                        Err(err) => {
+                            warn!(
+                                "[download_with_decompression] failed to decompress the archive `{}` of the product `{}`: {:?}",
+                                archive.path.display(),
+                                product_id,
+                                err
+                            );
+                            failures.push(format!(
+                                "failed to extract `{}`: {}",
+                                archive.path.display(),
+                                err
+                            ));
+                        }
+                    }
+                }
+
+                // the archives that failed are left as they are, as if they were skipped
+                (!failures.is_empty()).then(|| failures.join("\n"))
+            }
+            ExtractionPlan::Skip(reason) => {
+                info!(
+                    "[download_with_decompression] not decompressing the product `{}`: {}",
+                    product_id, reason
                 );
+                Some(reason.to_string())
             }
+        };
+
+        if let Err(err) = ProductDownloadTable::update_one_extraction_skip_reason(
+            &self.context.connection(),
+            product_id,
+            skip_reason.as_deref(),
+        ) {
+            warn!(
+                "[download_with_decompression] failed to record the extraction of the product `{}` to the database: {:?}",
+                product_id, err
+            );
         }
 
-        if downloaded.product_files.files.len() != 0
-            && downloaded.product_files.files[0]
-                .file_name
-                .to_ascii_lowercase()
-                .ends_with(".exe")
-        {
-            on_progress(1, 1, true);
+        record_manifest(self.context, product_id, &downloaded.base_path).await;
+        Ok(DecompressedProduct {
+            path: downloaded.base_path,
+            skipped_entries,
+        })
+    }
 
-            if let Err(err) =
-                decompress_multiple(&downloaded.product_files, &downloaded.base_path).await
-            {
-                warn!(
-                    "[download_with_decompression] failed to decompress (multiple) the product `{}`: {:?}",
-                    product_id, err
-                );
-            }
+    /// Downloads a voice comic (VCM) product. Voice comics are not downloadable as archives,
+    /// so the optimized media files are fetched one by one through the play API instead.
+    pub async fn download_voice_comic(
+        &self,
+        account_id: i64,
+        product_id: &ProductId,
+        base_path: impl AsRef<Path>,
+        on_progress: impl Fn(u64, u64),
+    ) -> Result<PathBuf, DownloadServiceError> {
+        let path = resolve_product_path(self.context, product_id, base_path)?;
+
+        info!(
+            "[download_voice_comic] downloading voice comic `{}` of the account id `{}` at path `{}`",
+            product_id,
+            account_id,
+            path.display()
+        );
+
+        let client = DLsiteService::with_context(self.context)
+            .get_client(account_id)
+            .await?;
+        let result = async {
+            let request_info = client.get_voice_comic_request_info(product_id).await?;
+            let zip_tree = client.get_voice_comic_zip_tree(&request_info).await?;
+
+            client
+                .download_voice_comic_files(
+                    product_id,
+                    &request_info,
+                    &zip_tree,
+                    &path,
+                    on_progress,
+                )
+                .await
         }
+        .await;
 
-        Ok(downloaded.base_path)
-    }
+        if let Err(err) = result {
+            error!(
+                "[download_voice_comic] failed to download voice comic `{}` of the account id `{}` at path `{}`: {:?}",
+                product_id,
+                account_id,
+                path.display(),
+                err
+            );
+            return Err(DownloadServiceError::AnyError(err));
+        }
 
-    // pub async fn download_voice_comic(
-    //     &self,
-    //     account_id: i64,
-    //     product_id: impl AsRef<str>,
-    //     base_path: impl AsRef<Path>,
-    //     on_progress: impl Fn(u64, u64, bool),
-    // ) -> Result<PathBuf, DownloadServiceError> {
+        if let Err(err) = ProductDownloadTable::insert_one(
+            &self.context.connection(),
+            CreatingProductDownload {
+                product_id,
+                path: &path,
+            },
+        ) {
+            warn!(
+                "[download_voice_comic] failed to insert the downloaded product `{}` to the database at path `{}`: {:?}",
+                product_id,
+                path.display(),
+                err
+            );
+        }
 
-    // }
+        record_manifest(self.context, product_id, &path).await;
+        Ok(path)
+    }
 
     pub fn remove_downloaded(
         &self,
-        product_id: impl AsRef<str>,
+        product_id: &ProductId,
         base_path: impl AsRef<Path>,
     ) -> Result<(), DownloadServiceError> {
-        let product_id = product_id.as_ref();
-        let path = base_path.as_ref().join(product_id);
+        let base_path = base_path.as_ref();
+        let download = ProductDownloadTable::get_one(&self.context.connection(), product_id)?;
+        let path = match download {
+            // a scanned archive that is not extracted
+            Some(download) if download.path.is_file() && download.path.starts_with(base_path) => {
+                download.path
+            }
+            _ => resolve_product_path(self.context, product_id, base_path)?,
+        };
 
         info!(
             "[remove_downloaded] removing the downloaded product `{}` at path `{}`",
@@ -126,7 +316,14 @@ impl DownloadService {
             path.display()
         );
 
-        if let Err(err) = ProductDownloadTable::remove_one(product_id) {
+        if let Err(err) = ProductManifestTable::remove_one(&self.context.connection(), product_id) {
+            warn!(
+                "[remove_downloaded] failed to remove the manifest of the downloaded product `{}` from the database: {:?}",
+                product_id, err
+            );
+        }
+
+        if let Err(err) = ProductDownloadTable::remove_one(&self.context.connection(), product_id) {
             warn!("[remove_downloaded] failed to remove the downloaded product `{}` from the database at path `{}`: {:?}",
                 product_id,
                 path.display(),
@@ -134,7 +331,13 @@ impl DownloadService {
             );
         }
 
-        if let Err(err) = std::fs::remove_dir_all(&path) {
+        let removed = if path.is_file() {
+            std::fs::remove_file(&path)
+        } else {
+            std::fs::remove_dir_all(&path)
+        };
+
+        if let Err(err) = removed {
             warn!("[remove_downloaded] failed to remove the downloaded product `{}` to the file system at path `{}`: {:?}",
                 product_id,
                 path.display(),
@@ -142,23 +345,134 @@ impl DownloadService {
             );
         }
 
+        remove_empty_parents(&path, base_path);
+
+        Ok(())
+    }
+
+    /// Removes the partial files left by a cancelled download of the product. The folder is
+    /// removed as a whole only if the download created it, that is, if the product has no
+    /// earlier download; otherwise the files of the earlier download are kept.
+    pub fn remove_partial_download(
+        &self,
+        product_id: &ProductId,
+        base_path: impl AsRef<Path>,
+    ) -> Result<(), DownloadServiceError> {
+        let base_path = base_path.as_ref();
+        let path = resolve_product_path(self.context, product_id, base_path)?;
+
+        if !path.is_dir() || path == base_path {
+            return Ok(());
+        }
+
+        if ProductDownloadTable::get_one(&self.context.connection(), product_id)?.is_none() {
+            info!(
+                "[remove_partial_download] removing the folder `{}` created by the cancelled download of the product `{}`",
+                path.display(),
+                product_id
+            );
+
+            std::fs::remove_dir_all(&path)
+                .with_context(|| format!("[remove_partial_download]"))
+                .with_context(|| format!("failed to remove the folder `{}`", path.display()))?;
+            remove_empty_parents(&path, base_path);
+            return Ok(());
+        }
+
+        info!(
+            "[remove_partial_download] removing the partial files of the cancelled download of the product `{}` at path `{}`",
+            product_id,
+            path.display()
+        );
+
+        for entry in std::fs::read_dir(&path)? {
+            let entry = entry?;
+
+            if !is_partial_file_name(&entry.file_name().to_string_lossy()) {
+                continue;
+            }
+
+            if let Err(err) = std::fs::remove_file(entry.path()) {
+                warn!(
+                    "[remove_partial_download] failed to remove the partial file `{}`: {:?}",
+                    entry.path().display(),
+                    err
+                );
+            }
+        }
+
         Ok(())
     }
 }
 
+/// Resolves the folder of the product under the download root, following the folder template.
+/// A product that is already downloaded keeps its folder, so that it is replaced in place.
+fn resolve_product_path(
+    context: &ServiceContext,
+    product_id: &ProductId,
+    base_path: impl AsRef<Path>,
+) -> Result<PathBuf, DownloadServiceError> {
+    let base_path = base_path.as_ref();
+
+    let download = ProductDownloadTable::get_one(&context.connection(), product_id)?;
+
+    if let Some(download) = download {
+        if download.path.is_dir()
+            && download.path.starts_with(base_path)
+            && download.path != base_path
+        {
+            return Ok(download.path);
+        }
+    }
+
+    let product = ProductTable::get_one(&context.connection(), product_id)?;
+    let relative_path = match product {
+        Some(product) => get_product_folder_template(&context.connection())?.render(&product),
+        None => PathBuf::from(product_id.as_str()),
+    };
+
+    Ok(base_path.join(relative_path))
+}
+
+/// Records the files of the finished download, so that the library can be verified later.
+/// A product without a manifest is only left unverifiable, so failures are not fatal.
+async fn record_manifest(context: &ServiceContext, product_id: &ProductId, path: &Path) {
+    let root = path.to_owned();
+    let result = spawn_blocking(move || build_manifest(&root))
+        .await
+        .map_err(AnyError::from)
+        .and_then(|result| result)
+        .and_then(|files| {
+            Ok(ProductManifestTable::replace_one(
+                &mut context.connection(),
+                product_id,
+                &files,
+            )?)
+        });
+
+    if let Err(err) = result {
+        warn!(
+            "[record_manifest] failed to record the manifest of the product `{}` at path `{}`: {:?}",
+            product_id,
+            path.display(),
+            err
+        );
+    }
+}
+
 struct Downloaded {
     pub base_path: PathBuf,
     pub product_files: DLsiteProductFiles,
 }
 
 async fn download(
+    context: &ServiceContext,
     account_id: i64,
-    product_id: impl AsRef<str>,
+    product_id: &ProductId,
     base_path: impl AsRef<Path>,
     on_progress: impl Fn(u64, u64),
 ) -> Result<Downloaded, DownloadServiceError> {
-    let product_id = product_id.as_ref();
-    let path = base_path.as_ref().join(product_id);
+    let path = resolve_product_path(context, product_id, base_path)?;
 
     info!(
         "[download] downloading product `{}` of the account id `{}` at path `{}`",
@@ -167,8 +481,10 @@ async fn download(
         path.display()
     );
 
-    let cookie_store = DLsiteService::new().get_cookie_store(account_id).await?;
-    let product_files = match get_product_files(product_id).await {
+    let client = DLsiteService::with_context(context)
+        .get_client(account_id)
+        .await?;
+    let product_files = match client.get_product_files(product_id).await {
         Ok(product_files) => product_files,
         Err(err) => {
             error!("[download] failed to download product `{}` of the account id `{}` at path `{}`: {:?}",
@@ -181,14 +497,9 @@ async fn download(
         }
     };
 
-    if let Err(err) = download_product_files(
-        cookie_store,
-        product_id,
-        &product_files,
-        base_path,
-        on_progress,
-    )
-    .await
+    if let Err(err) = client
+        .download_product_files(product_id, &product_files, &path, on_progress)
+        .await
     {
         error!(
             "[download] failed to download product `{}` of the account id `{}` at path `{}`: {:?}",
@@ -200,10 +511,13 @@ async fn download(
         return Err(DownloadServiceError::AnyError(err));
     }
 
-    if let Err(err) = ProductDownloadTable::insert_one(CreatingProductDownload {
-        product_id,
-        path: &path,
-    }) {
+    if let Err(err) = ProductDownloadTable::insert_one(
+        &context.connection(),
+        CreatingProductDownload {
+            product_id,
+            path: &path,
+        },
+    ) {
         warn!(
             "[download] failed to insert the downloaded product `{}` to the database at path `{}`: {:?}",
             product_id,
@@ -218,96 +532,170 @@ async fn download(
     })
 }
 
-async fn decompress_single(
-    product_files: &DLsiteProductFiles,
-    path: impl AsRef<Path>,
-) -> Result<(), DownloadServiceError> {
+/// Extracts the archive into the folder on a blocking thread, as it may take minutes.
+async fn decompress(
+    archive: &DetectedArchive,
+    path: &Path,
+    name_encoding: Option<NameEncoding>,
+    cancellation: Arc<DownloadCancellation>,
+    on_progress: Arc<dyn Fn(ExtractionProgress) + Send + Sync>,
+) -> Result<ExtractionReport, DownloadServiceError> {
+    let archive = archive.clone();
+    let path = path.to_owned();
+
+    spawn_blocking(move || {
+        let _extraction = cancellation.extraction.lock();
+        decompress_blocking(
+            &archive,
+            &path,
+            name_encoding,
+            &cancellation.is_cancelled,
+            &*on_progress,
+        )
+    })
+    .await
+    .map_err(AnyError::from)?
+}
+
+fn decompress_blocking(
+    archive: &DetectedArchive,
+    path: &Path,
+    name_encoding: Option<NameEncoding>,
+    is_cancelled: &AtomicBool,
+    on_progress: OnExtractionProgress,
+) -> Result<ExtractionReport, DownloadServiceError> {
+    let tmp_path = path.join("__tmp__");
+    let result = match archive.format {
+        ArchiveFormat::Zip => {
+            decompress_zip(archive, &tmp_path, name_encoding, is_cancelled, on_progress)
+        }
+        ArchiveFormat::Rar => decompress_rar(archive, &tmp_path, is_cancelled, on_progress),
+        ArchiveFormat::SevenZip => {
+            decompress_seven_zip(archive, &tmp_path, is_cancelled, on_progress)
+        }
+    }
+    .and_then(|mut report| {
+        // a download cancelled after its last entry keeps the archive, to be removed with the rest
+        if is_cancelled.load(Ordering::SeqCst) {
+            return Err(anyhow!("the extraction is cancelled").into());
+        }
+
+        move_extracted(&tmp_path, path, &mut report)?;
+        Ok(report)
+    });
+
+    // whatever is left, such as the entries that could not be moved, is not needed anymore
+    if let Err(err) = std::fs::remove_dir_all(&tmp_path) {
+        warn!(
+            "[decompress_blocking] failed to remove the temporary folder `{}`: {:?}",
+            tmp_path.display(),
+            err
+        );
+    }
+
+    let report = result?;
+
+    for volume in &archive.volumes {
+        if let Err(err) = std::fs::remove_file(volume) {
+            warn!(
+                "[decompress_blocking] failed to remove the extracted archive `{}`: {:?}",
+                volume.display(),
+                err
+            );
+        }
+    }
+
+    Ok(report)
+}
+
+fn decompress_zip(
+    archive: &DetectedArchive,
+    tmp_path: &Path,
+    name_encoding: Option<NameEncoding>,
+    is_cancelled: &AtomicBool,
+    on_progress: OnExtractionProgress,
+) -> Result<ExtractionReport, DownloadServiceError> {
     use std::fs::*;
     use std::io::BufReader;
 
-    let path = path.as_ref();
-    let tmp_path = path.join("__tmp__");
-    let file_path = path.join(&product_files.files[0].file_name);
     let file = OpenOptions::new()
         .read(true)
-        .open(&file_path)
-        .with_context(|| format!("[decompress_single]"))
-        .with_context(|| format!("opening file `{}`", file_path.display()))?;
+        .open(&archive.path)
+        .with_context(|| format!("[decompress_zip]"))
+        .with_context(|| format!("opening file `{}`", archive.path.display()))?;
+    // the zip reader locates the payload of a self-extracting archive by itself
     let reader = BufReader::new(file);
 
-    zip_extract::extract(reader, &tmp_path, true)
-        .with_context(|| format!("[decompress_single]"))
+    let report = extract_zip(reader, tmp_path, name_encoding, is_cancelled, on_progress)
+        .with_context(|| format!("[decompress_zip]"))
         .with_context(|| {
             format!(
                 "extracting file `{}` to `{}`",
-                file_path.display(),
+                archive.path.display(),
                 tmp_path.display()
             )
         })?;
-
-    for content_path in read_dir(&tmp_path)? {
-        let content_path = content_path?.path();
-
-        rename(
-            &content_path,
-            path.join(content_path.strip_prefix(&tmp_path).unwrap()),
-        )?;
-    }
-
-    remove_dir_all(&tmp_path).ok();
-    remove_file(&file_path).ok();
-
-    Ok(())
+    Ok(report)
 }
 
-async fn decompress_multiple(
-    product_files: &DLsiteProductFiles,
-    path: impl AsRef<Path>,
-) -> Result<(), DownloadServiceError> {
+fn decompress_rar(
+    archive: &DetectedArchive,
+    tmp_path: &Path,
+    is_cancelled: &AtomicBool,
+    on_progress: OnExtractionProgress,
+) -> Result<ExtractionReport, DownloadServiceError> {
     use std::fs::*;
-    use unrar::Archive;
-
-    let path = path.as_ref();
-    let rar_file_name = path
-        .join(&product_files.files[0].file_name)
-        .with_extension("rar");
-
-    rename(path.join(&product_files.files[0].file_name), &rar_file_name)?;
 
-    let tmp_path = path.join("__tmp__");
-    let mut archive = Archive::new(&rar_file_name).open_for_processing()?;
-
-    while let Some(header) = archive.read_header()? {
-        archive = header.extract_with_base(&tmp_path)?;
-    }
+    // the following volumes are looked up by the name of the first one, which must be a `.rar`
+    let rar_file_name = archive.path.with_extension("rar");
+    let is_renamed = rar_file_name != archive.path;
 
-    rename(&rar_file_name, path.join(&product_files.files[0].file_name))?;
-
-    let mut content_paths = read_dir(&tmp_path)?.collect::<std::io::Result<Vec<_>>>()?;
-    let content_prefix_path;
-
-    if content_paths.len() == 1 && content_paths[0].file_type()?.is_dir() {
-        content_prefix_path = content_paths[0].path();
-        content_paths = read_dir(content_paths[0].path())?.collect::<std::io::Result<Vec<_>>>()?;
-    } else {
-        content_prefix_path = tmp_path.clone();
+    if is_renamed {
+        rename(&archive.path, &rar_file_name)?;
     }
 
-    for content_path in content_paths {
-        let content_path = content_path.path();
+    let result = extract_rar(&rar_file_name, tmp_path, is_cancelled, on_progress)
+        .with_context(|| format!("[decompress_rar]"))
+        .with_context(|| {
+            format!(
+                "extracting file `{}` to `{}`",
+                archive.path.display(),
+                tmp_path.display()
+            )
+        });
 
-        rename(
-            &content_path,
-            path.join(content_path.strip_prefix(&content_prefix_path).unwrap()),
-        )
-        .ok();
+    if is_renamed {
+        rename(&rar_file_name, &archive.path)?;
     }
 
-    remove_dir_all(&tmp_path).ok();
+    Ok(result?)
+}
 
-    for file in &product_files.files {
-        remove_file(&path.join(&file.file_name)).ok();
-    }
+fn decompress_seven_zip(
+    archive: &DetectedArchive,
+    tmp_path: &Path,
+    is_cancelled: &AtomicBool,
+    on_progress: OnExtractionProgress,
+) -> Result<ExtractionReport, DownloadServiceError> {
+    use std::fs::*;
+    use std::io::BufReader;
 
-    Ok(())
+    let file = OpenOptions::new()
+        .read(true)
+        .open(&archive.path)
+        .with_context(|| format!("[decompress_seven_zip]"))
+        .with_context(|| format!("opening file `{}`", archive.path.display()))?;
+    // the offsets in a 7z archive are relative to its start, past the SFX module if any
+    let reader = PayloadReader::new(BufReader::new(file), archive.offset)?;
+
+    let report = extract_seven_zip(reader, tmp_path, is_cancelled, on_progress)
+        .with_context(|| format!("[decompress_seven_zip]"))
+        .with_context(|| {
+            format!(
+                "extracting file `{}` to `{}`",
+                archive.path.display(),
+                tmp_path.display()
+            )
+        })?;
+    Ok(report)
 }
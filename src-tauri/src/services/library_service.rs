@@ -0,0 +1,661 @@
+use super::thumbnail_service::ThumbnailService;
+use crate::{
+    application::use_application,
+    command::{get_product_download_path, get_product_folder_template, get_product_scan_depth},
+    database::{
+        models::v2::{
+            CreatingProduct, CreatingProductDownload, DownloadJobStatus, ProductNameEncoding,
+        },
+        tables::v2::{
+            DBError, DownloadJobTable, ProductDownloadTable, ProductManifestTable,
+            ProductMetadataTable, ProductNameEncodingTable, ProductTable,
+        },
+    },
+    dlsite::{api::is_partial_file_name, dto::DLsiteProduct, product_id::ProductId},
+    library::{
+        manifest::{build_manifest, verify_manifest, ManifestProblem},
+        name_encoding::{fix_names, NameEncoding, NameFix},
+        relocation::{move_folder, remove_empty_parents, MoveMethod},
+        scanner::{diff_scan, scan_library, ScanAmbiguity},
+        watcher::{LibraryWatcher, DEBOUNCE_DELAY},
+    },
+    window::{MainWindow, WindowInfoProvider},
+};
+use anyhow::{anyhow, Error as AnyError};
+use chrono::Utc;
+use futures::StreamExt;
+use log::{error, info, warn};
+use serde::Serialize;
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicBool, Ordering},
+};
+use tauri::{
+    async_runtime::{spawn, spawn_blocking},
+    Manager,
+};
+use thiserror::Error;
+use tokio::time::sleep;
+
+#[derive(Error, Debug)]
+pub enum LibraryServiceError {
+    #[error("{0:?}")]
+    DBError(#[from] DBError),
+    #[error("{0:?}")]
+    TauriError(#[from] tauri::Error),
+    #[error("{0:?}")]
+    AnyError(#[from] AnyError),
+}
+
+/// A scanned product that could not be fetched, which is left unrecorded until the next scan.
+#[derive(Debug, Clone, Serialize)]
+pub struct DownloadSyncFailure {
+    pub product_id: ProductId,
+    pub reason: String,
+}
+
+/// The changes made by syncing the product downloads with the download root.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct DownloadSyncReport {
+    pub found: Vec<ProductId>,
+    pub missing: Vec<ProductId>,
+    pub failures: Vec<DownloadSyncFailure>,
+    pub ambiguities: Vec<ScanAmbiguity>,
+}
+
+impl DownloadSyncReport {
+    pub fn is_changed(&self) -> bool {
+        !self.found.is_empty() || !self.missing.is_empty()
+    }
+}
+
+/// A downloaded product folder that is moved by a reorganization.
+#[derive(Debug, Clone, Serialize)]
+pub struct ReorganizeMove {
+    pub product_id: ProductId,
+    pub from: PathBuf,
+    pub to: PathBuf,
+}
+
+/// A downloaded product folder that cannot be moved, left where it is.
+#[derive(Debug, Clone, Serialize)]
+pub struct ReorganizeConflict {
+    pub product_id: ProductId,
+    pub from: PathBuf,
+    pub to: PathBuf,
+    pub reason: String,
+}
+
+/// The dry-run of a reorganization; nothing is touched until it is executed.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ReorganizePlan {
+    pub root: PathBuf,
+    pub moves: Vec<ReorganizeMove>,
+    pub conflicts: Vec<ReorganizeConflict>,
+}
+
+/// The outcome of an executed reorganization.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ReorganizeReport {
+    pub moved: u32,
+    pub failures: Vec<ReorganizeConflict>,
+}
+
+/// A downloaded product whose files differ from its manifest.
+#[derive(Debug, Clone, Serialize)]
+pub struct VerifyProblem {
+    pub product_id: ProductId,
+    pub path: PathBuf,
+    pub files: Vec<ManifestProblem>,
+}
+
+/// The outcome of verifying the downloaded products against their manifests.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct VerifyReport {
+    /// the number of products that match their manifests
+    pub verified: u32,
+    /// the products without a manifest, such as the ones found by a scan
+    pub unverifiable: Vec<ProductId>,
+    pub problems: Vec<VerifyProblem>,
+}
+
+pub struct LibraryService;
+
+impl LibraryService {
+    pub fn new() -> Self {
+        Self
+    }
+
+    /// Syncs the product downloads with the download root without removing anything.
+    /// New folders are recorded, fetching only the products not known yet, and the folders
+    /// that no longer exist are marked missing.
+    pub async fn sync_downloads(&self) -> Result<DownloadSyncReport, LibraryServiceError> {
+        let download_path = get_product_download_path(use_application().app_handle())?;
+        let template = get_product_folder_template(&use_application().connection())?;
+        let scan_depth = get_product_scan_depth()?;
+        let scan =
+            spawn_blocking(move || scan_library(&download_path, &template, scan_depth)).await??;
+        let mut diff = diff_scan(
+            &ProductDownloadTable::get_all(&use_application().connection())?,
+            scan.products,
+        );
+
+        // a product being downloaded is recorded by its download once it finishes; until then
+        // its folder is partially written, or even removed while the download is prepared
+        let job_product_ids = DownloadJobTable::get_all(&use_application().connection())?
+            .into_iter()
+            .map(|job| job.product_id)
+            .collect::<HashSet<_>>();
+        let found = diff
+            .found
+            .into_iter()
+            .filter(|product| !job_product_ids.contains(&product.id))
+            .collect::<Vec<_>>();
+        diff.found = spawn_blocking(move || {
+            found
+                .into_iter()
+                .filter(|product| !has_partial_files(&product.path))
+                .collect::<Vec<_>>()
+        })
+        .await?;
+        diff.missing
+            .retain(|product_id| !job_product_ids.contains(product_id));
+
+        let mut report = DownloadSyncReport {
+            missing: diff.missing.clone(),
+            ambiguities: scan.ambiguities,
+            ..Default::default()
+        };
+
+        info!(
+            "[sync_downloads] found {} new, {} missing and {} ambiguous product downloads",
+            diff.found.len(),
+            diff.missing.len(),
+            report.ambiguities.len()
+        );
+
+        // only the products not known yet are fetched; known ones keep their metadata as is
+        let known_ids = ProductTable::get_many_known_ids(
+            &use_application().connection(),
+            diff.found.iter().map(|product| product.id.clone()),
+        )?
+        .into_iter()
+        .collect::<HashSet<_>>();
+        let (known_products, unknown_products) = diff
+            .found
+            .into_iter()
+            .partition::<Vec<_>, _>(|product| known_ids.contains(&product.id));
+
+        struct FetchedProduct {
+            pub id: ProductId,
+            pub path: PathBuf,
+            pub product: DLsiteProduct,
+        }
+
+        // the requests are rate limited by the client; this only bounds the pending futures
+        const CONCURRENCY: usize = 16;
+
+        let results = futures::stream::iter(unknown_products)
+            .map(|product| async move {
+                match use_application()
+                    .dlsite_client()
+                    .get_product_from_non_owner_api(&product.id)
+                    .await
+                {
+                    Ok(fetched_product) => Ok(FetchedProduct {
+                        id: product.id,
+                        path: product.path,
+                        product: fetched_product,
+                    }),
+                    Err(err) => Err((product, err)),
+                }
+            })
+            .buffer_unordered(CONCURRENCY)
+            .collect::<Vec<_>>()
+            .await;
+
+        let mut products = Vec::with_capacity(results.len());
+
+        for result in results {
+            match result {
+                Ok(product) => products.push(product),
+                Err((download, err)) => {
+                    // it is not recorded, since it has no product yet; the next scan tries again
+                    warn!(
+                        "[sync_downloads] failed to fetch the scanned product `{}`: {:?}",
+                        download.id, err
+                    );
+                    report.failures.push(DownloadSyncFailure {
+                        product_id: download.id,
+                        reason: format!("{:#}", err),
+                    });
+                }
+            }
+        }
+
+        if let Err(err) = ProductTable::insert_many(
+            &mut use_application().connection(),
+            products.iter().map(|product| CreatingProduct {
+                id: &product.product.id,
+                account_id: None,
+                ty: product.product.ty.clone(),
+                age: product.product.age.clone(),
+                title: &product.product.title,
+                thumbnail: &product.product.thumbnail,
+                group_id: &product.product.group_id,
+                group_name: &product.product.group_name,
+                registered_at: product.product.registered_at,
+            }),
+        ) {
+            error!(
+                "[sync_downloads] failed to update the products to the database: {:?}",
+                err
+            );
+            return Err(err.into());
+        }
+
+        if let Err(err) = ProductMetadataTable::insert_many(
+            &mut use_application().connection(),
+            products.iter().filter_map(|product| {
+                let metadata = product.product.metadata.as_ref()?;
+                Some((&product.product.id, metadata))
+            }),
+        ) {
+            error!(
+                "[sync_downloads] failed to update the product metadata to the database: {:?}",
+                err
+            );
+        }
+
+        let found = known_products
+            .iter()
+            .map(|product| (&product.id, &product.path))
+            .chain(products.iter().map(|product| (&product.id, &product.path)))
+            .map(|(product_id, path)| CreatingProductDownload { product_id, path });
+
+        if let Err(err) = ProductDownloadTable::sync_scanned(
+            &mut use_application().connection(),
+            found,
+            &diff.missing,
+            Utc::now(),
+        ) {
+            error!(
+                "[sync_downloads] failed to update the scanned products to the database: {:?}",
+                err
+            );
+            return Err(err.into());
+        }
+
+        report.found = known_products
+            .iter()
+            .map(|product| product.id.clone())
+            .chain(products.iter().map(|product| product.id.clone()))
+            .collect();
+
+        if let Err(err) = ThumbnailService::new().cache_missing().await {
+            error!("[sync_downloads] failed to cache the thumbnails: {:?}", err);
+        }
+
+        Ok(report)
+    }
+
+    /// Watches the download root, syncing the product downloads whenever its folders change.
+    /// The previous watcher is stopped if the download root has changed since.
+    pub fn watch_downloads(&self) -> Result<(), LibraryServiceError> {
+        let root = get_product_download_path(use_application().app_handle())?;
+        let mut library_watcher = use_application().library_watcher();
+
+        if let Some(watcher) = library_watcher.as_ref() {
+            if watcher.root() == root {
+                return Ok(());
+            }
+        }
+
+        *library_watcher = None;
+
+        if !root.is_dir() {
+            info!(
+                "[watch_downloads] the download root `{}` does not exist yet; not watching it",
+                root.display()
+            );
+            return Ok(());
+        }
+
+        info!(
+            "[watch_downloads] watching the download root `{}`",
+            root.display()
+        );
+
+        *library_watcher = Some(LibraryWatcher::watch(&root, |paths| {
+            spawn(sync_changed_downloads(paths));
+        })?);
+
+        Ok(())
+    }
+
+    /// Plans moving every downloaded product to its folder under the current download root,
+    /// laid out by the current folder template.
+    pub fn plan_reorganize(&self) -> Result<ReorganizePlan, LibraryServiceError> {
+        let root = get_product_download_path(use_application().app_handle())?;
+        let template = get_product_folder_template(&use_application().connection())?;
+        let mut plan = ReorganizePlan {
+            root: root.clone(),
+            ..Default::default()
+        };
+        let mut downloads = ProductDownloadTable::get_all(&use_application().connection())?;
+        let mut targets = HashSet::new();
+
+        downloads.sort_by(|a, b| a.product_id.cmp(&b.product_id));
+
+        for download in downloads {
+            let relative_path =
+                match ProductTable::get_one(&use_application().connection(), &download.product_id)?
+                {
+                    Some(product) => template.render(&product),
+                    None => PathBuf::from(download.product_id.as_str()),
+                };
+            let to = root.join(relative_path);
+
+            if download.path == to {
+                continue;
+            }
+
+            let conflict = if download.path.is_file() {
+                Some("the product is not extracted")
+            } else if !download.path.is_dir() {
+                Some("the folder does not exist")
+            } else if to.starts_with(&download.path) {
+                Some("the new folder is inside of the current folder")
+            } else if !targets.insert(to.clone()) {
+                Some("another product is moved to the same folder")
+            } else if to.exists() && !is_same_folder(&download.path, &to) {
+                Some("the new folder already exists")
+            } else if matches!(
+                DownloadJobTable::get_one_by_product_id(
+                    &use_application().connection(),
+                    &download.product_id
+                )?,
+                Some(job) if job.status == DownloadJobStatus::Active
+            ) {
+                Some("the product is being downloaded")
+            } else {
+                None
+            };
+
+            match conflict {
+                Some(reason) => plan.conflicts.push(ReorganizeConflict {
+                    product_id: download.product_id,
+                    from: download.path,
+                    to,
+                    reason: reason.to_owned(),
+                }),
+                None => plan.moves.push(ReorganizeMove {
+                    product_id: download.product_id,
+                    from: download.path,
+                    to,
+                }),
+            }
+        }
+
+        info!(
+            "[plan_reorganize] planned {} moves and {} conflicts under `{}`",
+            plan.moves.len(),
+            plan.conflicts.len(),
+            plan.root.display()
+        );
+
+        Ok(plan)
+    }
+
+    /// Executes the planned moves. The paths of the moved products are updated in a single
+    /// transaction afterwards; a product that fails to move stays where it was.
+    pub fn reorganize(
+        &self,
+        plan: &ReorganizePlan,
+        mut on_progress: impl FnMut(&ProductId, u32, u32),
+    ) -> Result<ReorganizeReport, LibraryServiceError> {
+        let total_progress = plan.moves.len() as u32;
+        let mut moved = Vec::with_capacity(plan.moves.len());
+        let mut report = ReorganizeReport::default();
+
+        for (index, planned) in plan.moves.iter().enumerate() {
+            info!(
+                "[reorganize] moving the product `{}` from `{}` to `{}`",
+                planned.product_id,
+                planned.from.display(),
+                planned.to.display()
+            );
+
+            match move_folder(&planned.from, &planned.to) {
+                Ok(method) => {
+                    if method == MoveMethod::Copy {
+                        info!(
+                            "[reorganize] copied the product `{}` across filesystems",
+                            planned.product_id
+                        );
+                    }
+
+                    remove_empty_parents(&planned.from, &plan.root);
+                    moved.push(planned);
+                }
+                Err(err) => {
+                    warn!(
+                        "[reorganize] failed to move the product `{}`: {:?}",
+                        planned.product_id, err
+                    );
+                    report.failures.push(ReorganizeConflict {
+                        product_id: planned.product_id.clone(),
+                        from: planned.from.clone(),
+                        to: planned.to.clone(),
+                        reason: format!("{:#}", err),
+                    });
+                }
+            }
+
+            on_progress(&planned.product_id, index as u32 + 1, total_progress);
+        }
+
+        if let Err(err) = ProductDownloadTable::update_many_paths(
+            &mut use_application().connection(),
+            moved.iter().map(|planned| CreatingProductDownload {
+                product_id: &planned.product_id,
+                path: &planned.to,
+            }),
+        ) {
+            error!(
+                "[reorganize] failed to update the paths of {} moved products to the database: {:?}",
+                moved.len(),
+                err
+            );
+            return Err(err.into());
+        }
+
+        report.moved = moved.len() as u32;
+        Ok(report)
+    }
+
+    /// Verifies every downloaded product against the manifest recorded when it was downloaded.
+    /// Products marked missing are skipped, as the scan already reports them.
+    pub fn verify_library(
+        &self,
+        mut on_progress: impl FnMut(&ProductId, u32, u32),
+    ) -> Result<VerifyReport, LibraryServiceError> {
+        let mut downloads = ProductDownloadTable::get_all(&use_application().connection())?
+            .into_iter()
+            .filter(|download| download.missing_at.is_none())
+            .collect::<Vec<_>>();
+        let total_progress = downloads.len() as u32;
+        let mut report = VerifyReport::default();
+
+        downloads.sort_by(|a, b| a.product_id.cmp(&b.product_id));
+
+        for (index, download) in downloads.into_iter().enumerate() {
+            let manifest = ProductManifestTable::get_one(
+                &use_application().connection(),
+                &download.product_id,
+            )?;
+
+            let files = if manifest.is_empty() || !download.path.is_dir() {
+                None
+            } else {
+                match verify_manifest(&download.path, &manifest) {
+                    Ok(files) => Some(files),
+                    Err(err) => {
+                        warn!(
+                            "[verify_library] failed to verify the product `{}` at path `{}`: {:?}",
+                            download.product_id,
+                            download.path.display(),
+                            err
+                        );
+                        None
+                    }
+                }
+            };
+
+            match files {
+                None => report.unverifiable.push(download.product_id.clone()),
+                Some(files) if files.is_empty() => report.verified += 1,
+                Some(files) => report.problems.push(VerifyProblem {
+                    product_id: download.product_id.clone(),
+                    path: download.path.clone(),
+                    files,
+                }),
+            }
+
+            on_progress(&download.product_id, index as u32 + 1, total_progress);
+        }
+
+        info!(
+            "[verify_library] verified {} products; {} have problems and {} are unverifiable",
+            report.verified,
+            report.problems.len(),
+            report.unverifiable.len()
+        );
+
+        Ok(report)
+    }
+
+    /// Fixes the mojibake names in the extracted folder of the product, decoding them in the
+    /// encoding, or in the one set for the product or guessed from the names. The encoding given
+    /// is kept for the product, so that the product is extracted in it from then on.
+    pub fn fix_product_names(
+        &self,
+        product_id: &ProductId,
+        encoding: Option<NameEncoding>,
+    ) -> Result<Vec<NameFix>, LibraryServiceError> {
+        let download = ProductDownloadTable::get_one(&use_application().connection(), product_id)?;
+        let path = match download {
+            Some(download) if download.path.is_dir() => download.path,
+            _ => {
+                return Err(
+                    anyhow!("the product `{}` is not downloaded as a folder", product_id).into(),
+                );
+            }
+        };
+
+        if let Some(encoding) = encoding {
+            ProductNameEncodingTable::insert_one(
+                &use_application().connection(),
+                &ProductNameEncoding {
+                    product_id: product_id.clone(),
+                    encoding,
+                },
+            )?;
+        }
+
+        let encoding = match encoding {
+            Some(encoding) => Some(encoding),
+            None => ProductNameEncodingTable::get_one(&use_application().connection(), product_id)?
+                .map(|name_encoding| name_encoding.encoding),
+        };
+        let (_, fixes) = fix_names(&path, encoding)?;
+
+        // the manifest is of the old names, which would all be reported as missing otherwise
+        if !fixes.is_empty()
+            && !ProductManifestTable::get_one(&use_application().connection(), product_id)?
+                .is_empty()
+        {
+            ProductManifestTable::replace_one(
+                &mut use_application().connection(),
+                product_id,
+                &build_manifest(&path)?,
+            )?;
+        }
+
+        Ok(fixes)
+    }
+}
+
+/// Set while a sync waits for the update in progress to finish, so that the changes reported
+/// meanwhile are synced by it rather than by a sync of their own.
+static IS_SYNC_PENDING: AtomicBool = AtomicBool::new(false);
+
+/// Syncs the product downloads after the watcher reported changes under the download root.
+async fn sync_changed_downloads(paths: Vec<PathBuf>) {
+    // the sync waiting already rescans the whole download root
+    if IS_SYNC_PENDING.swap(true, Ordering::SeqCst) {
+        return;
+    }
+
+    // a scan, a reorganization or another sync may be in progress; the changes are synced
+    // after it, since they may have been made after it scanned
+    loop {
+        {
+            let mut is_updating_product = use_application().is_updating_product();
+
+            if !*is_updating_product {
+                *is_updating_product = true;
+                IS_SYNC_PENDING.store(false, Ordering::SeqCst);
+                break;
+            }
+        }
+
+        sleep(DEBOUNCE_DELAY).await;
+    }
+
+    info!(
+        "[sync_changed_downloads] syncing after {} paths changed",
+        paths.len()
+    );
+
+    let result = LibraryService::new().sync_downloads().await;
+    *use_application().is_updating_product() = false;
+
+    match result {
+        Ok(report) if report.is_changed() => {
+            if let Some(window) = use_application()
+                .app_handle()
+                .get_webview_window(&MainWindow.label())
+            {
+                window.emit("library-changed", &report).ok();
+            }
+        }
+        Ok(_) => {}
+        Err(err) => {
+            error!(
+                "[sync_changed_downloads] failed to sync the product downloads: {:?}",
+                err
+            );
+        }
+    }
+}
+
+/// Checks whether the folder contains partially downloaded files.
+fn has_partial_files(path: &Path) -> bool {
+    match std::fs::read_dir(path) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok())
+            .any(|entry| is_partial_file_name(&entry.file_name().to_string_lossy())),
+        Err(_) => false,
+    }
+}
+
+/// Checks whether both paths are the same folder, such as a rename that only changes the case
+/// on a case-insensitive filesystem.
+fn is_same_folder(left: &Path, right: &Path) -> bool {
+    match (left.canonicalize(), right.canonicalize()) {
+        (Ok(left), Ok(right)) => left == right,
+        _ => false,
+    }
+}
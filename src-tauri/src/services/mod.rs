@@ -1,2 +1,10 @@
+pub mod context;
+pub mod credential_service;
 pub mod dlsite_service;
+pub mod download_queue_service;
 pub mod download_service;
+pub mod library_service;
+pub mod thumbnail_service;
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,298 @@
+use super::{
+    context::ServiceContext,
+    dlsite_service::DLsiteService,
+    download_queue_service::{download_job, finish_job},
+    download_service::DownloadService,
+};
+use crate::{
+    credential::{CredentialCipher, Secret},
+    database::{
+        models::v2::{CreatingAccount, CreatingDownloadJob, DownloadJobStatus},
+        tables::v2::{
+            AccountTable, DownloadJobTable, ProductDownloadTable, ProductManifestTable,
+            ProductMetadataTable, ProductTable, ThumbnailTable,
+        },
+        Database,
+    },
+    dlsite::{
+        client::DLsiteClient,
+        fixtures::{file_data, product_id},
+        mock_server::{MockDLsite, MockProduct, MOCK_LOGIN_ID, MOCK_PASSWORD, MOCK_THUMBNAIL},
+        product_id::ProductId,
+    },
+};
+use std::{path::Path, sync::Arc};
+use tempfile::TempDir;
+
+/// A context over a fresh database in a temporary folder, talking to the mock server.
+fn context(server: &MockDLsite, dir: &TempDir) -> ServiceContext {
+    let mut database = Database::load(dir.path().join("database.db")).unwrap();
+    database.prepare().unwrap();
+    let cipher =
+        CredentialCipher::load_or_create_key_file(dir.path().join("credential.key")).unwrap();
+    let client = DLsiteClient::anonymous(server.endpoints().clone()).unwrap();
+
+    ServiceContext::new(
+        database,
+        Some(cipher),
+        client,
+        dir.path().join("thumbnails"),
+    )
+}
+
+fn add_account(context: &ServiceContext) -> i64 {
+    AccountTable::insert_one(
+        &context.connection(),
+        context.credential_cipher().as_ref(),
+        CreatingAccount {
+            username: MOCK_LOGIN_ID,
+            password: Secret::new(MOCK_PASSWORD),
+            memo: None,
+        },
+    )
+    .unwrap()
+}
+
+fn manifest_paths(context: &ServiceContext, product_id: &ProductId) -> Vec<String> {
+    let mut paths = ProductManifestTable::get_one(&context.connection(), product_id)
+        .unwrap()
+        .into_iter()
+        .map(|file| file.path)
+        .collect::<Vec<_>>();
+    paths.sort();
+    paths
+}
+
+fn file_names(path: &Path) -> Vec<String> {
+    let mut names = std::fs::read_dir(path)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+        .collect::<Vec<_>>();
+    names.sort();
+    names
+}
+
+#[tokio::test]
+async fn refresh_products_of_account() {
+    let server = MockDLsite::start(
+        (1..=60)
+            .map(|index| {
+                MockProduct::new(format!("RJ{:06}", index), format!("Product {}", index))
+                    .with_genre("ASMR")
+            })
+            .collect(),
+    )
+    .await;
+    let dir = tempfile::tempdir().unwrap();
+    let context = context(&server, &dir);
+    let account_id = add_account(&context);
+    let mut progresses = vec![];
+
+    DLsiteService::with_context(&context)
+        .refresh_products_all(|progress, total_progress| {
+            progresses.push((progress, total_progress))
+        })
+        .await
+        .unwrap();
+
+    let mut owned_ids = ProductTable::get_many_owned_ids(&context.connection()).unwrap();
+    owned_ids.sort();
+
+    assert_eq!(
+        owned_ids,
+        (1..=60)
+            .map(|index| (product_id(&format!("RJ{:06}", index)), account_id))
+            .collect::<Vec<_>>()
+    );
+    assert_eq!(progresses, vec![(0, 60), (50, 60), (60, 60)]);
+
+    let account = AccountTable::get_one(
+        &context.connection(),
+        context.credential_cipher().as_ref(),
+        account_id,
+    )
+    .unwrap()
+    .unwrap();
+
+    assert_eq!(account.product_count, 60);
+    // the session is stored, so that the next launch does not log in again
+    assert!(account.cookie_json.expose().contains("mock-session"));
+
+    let metadata = ProductMetadataTable::get_one(&context.connection(), &product_id("RJ000001"))
+        .unwrap()
+        .unwrap();
+
+    assert_eq!(metadata.genres, vec!["ASMR".to_owned()]);
+    assert!(ThumbnailTable::get_many_missing(&context.connection())
+        .unwrap()
+        .is_empty());
+
+    let thumbnail = ThumbnailTable::get_one(&context.connection(), &product_id("RJ000001"))
+        .unwrap()
+        .unwrap();
+
+    assert_eq!(
+        std::fs::read(dir.path().join("thumbnails").join(thumbnail.file_name)).unwrap(),
+        MOCK_THUMBNAIL
+    );
+}
+
+#[tokio::test]
+async fn reuse_client_until_session_expires() {
+    let server = MockDLsite::start(vec![MockProduct::new("RJ000001", "Product 1")]).await;
+    let dir = tempfile::tempdir().unwrap();
+    let context = context(&server, &dir);
+    let account_id = add_account(&context);
+    let service = DLsiteService::with_context(&context);
+
+    let client = service.get_client(account_id).await.unwrap();
+    let product_count_requests = server.product_count_requests();
+
+    // a cached client is not tested again
+    service.get_client(account_id).await.unwrap();
+    assert_eq!(server.product_count_requests(), product_count_requests);
+
+    server.expire_sessions();
+    assert!(client.get_products(1).await.is_err());
+
+    let client = service.get_client(account_id).await.unwrap();
+
+    assert_eq!(client.get_products(1).await.unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn download_product() {
+    let server = MockDLsite::start(vec![
+        MockProduct::new("RJ000001", "Product 1").with_file("RJ000001.mp3", file_data(3000))
+    ])
+    .await;
+    let dir = tempfile::tempdir().unwrap();
+    let context = context(&server, &dir);
+    let account_id = add_account(&context);
+    let base_path = dir.path().join("library");
+
+    let path = DownloadService::with_context(&context)
+        .download(account_id, &product_id("RJ000001"), &base_path, |_, _| {})
+        .await
+        .unwrap();
+
+    // a product unknown to the database is put in a folder named after its ID
+    assert_eq!(path, base_path.join("RJ000001"));
+    assert_eq!(
+        std::fs::read(path.join("RJ000001.mp3")).unwrap(),
+        file_data(3000)
+    );
+    assert_eq!(
+        ProductDownloadTable::get_one(&context.connection(), &product_id("RJ000001"))
+            .unwrap()
+            .unwrap()
+            .path,
+        path
+    );
+    assert_eq!(
+        manifest_paths(&context, &product_id("RJ000001")),
+        vec!["RJ000001.mp3".to_owned()]
+    );
+}
+
+#[tokio::test]
+async fn download_product_with_extraction() {
+    use std::io::{Cursor, Write};
+    use zip::{write::FileOptions, ZipWriter};
+
+    let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+    let options = FileOptions::default();
+
+    writer
+        .start_file("RJ000001/voice/track1.mp3", options)
+        .unwrap();
+    writer.write_all(&file_data(2000)).unwrap();
+    writer.start_file("RJ000001/readme.txt", options).unwrap();
+    writer.write_all(b"text").unwrap();
+
+    let archive = writer.finish().unwrap().into_inner();
+    let server = MockDLsite::start(vec![
+        MockProduct::new("RJ000001", "Product 1").with_file("RJ000001.zip", archive)
+    ])
+    .await;
+    let dir = tempfile::tempdir().unwrap();
+    let context = context(&server, &dir);
+    let account_id = add_account(&context);
+    let base_path = dir.path().join("library");
+
+    let product = DownloadService::with_context(&context)
+        .download_with_decompression(
+            account_id,
+            &product_id("RJ000001"),
+            &base_path,
+            Arc::default(),
+            |_| {},
+        )
+        .await
+        .unwrap();
+
+    // the single top-level folder is unwrapped and the archive is removed
+    assert_eq!(product.path, base_path.join("RJ000001"));
+    assert!(product.skipped_entries.is_empty());
+    assert_eq!(
+        file_names(&product.path),
+        vec!["readme.txt".to_owned(), "voice".to_owned()]
+    );
+    assert_eq!(
+        std::fs::read(product.path.join("voice").join("track1.mp3")).unwrap(),
+        file_data(2000)
+    );
+
+    let download = ProductDownloadTable::get_one(&context.connection(), &product_id("RJ000001"))
+        .unwrap()
+        .unwrap();
+
+    assert_eq!(download.path, product.path);
+    assert_eq!(download.extraction_skip_reason, None);
+    assert_eq!(
+        manifest_paths(&context, &product_id("RJ000001")),
+        vec!["readme.txt".to_owned(), "voice/track1.mp3".to_owned()]
+    );
+}
+
+#[tokio::test]
+async fn fail_download_job() {
+    let server = MockDLsite::start(vec![MockProduct::new("RJ000001", "Product 1")]).await;
+    let dir = tempfile::tempdir().unwrap();
+    let context = context(&server, &dir);
+    let account_id = add_account(&context);
+    let job_id = DownloadJobTable::insert_one(
+        &context.connection(),
+        CreatingDownloadJob {
+            account_id,
+            // not sold on any floor of the mock
+            product_id: &product_id("RJ000002"),
+            decompress: true,
+        },
+    )
+    .unwrap();
+    let job = DownloadJobTable::get_one(&context.connection(), job_id)
+        .unwrap()
+        .unwrap();
+
+    let result = download_job(
+        &context,
+        &job,
+        &dir.path().join("library"),
+        Arc::default(),
+        |_, _, _, _, _| {},
+    )
+    .await;
+
+    assert!(result.is_err());
+
+    finish_job(&context.connection(), &job, &result);
+
+    // a failed job stays in the queue with its error
+    let job = DownloadJobTable::get_one(&context.connection(), job_id)
+        .unwrap()
+        .unwrap();
+
+    assert_eq!(job.status, DownloadJobStatus::Failed);
+    assert!(job.error.is_some());
+}
@@ -0,0 +1,229 @@
+use super::context::ServiceContext;
+use crate::{
+    application::use_application,
+    database::{
+        models::v2::CreatingThumbnail,
+        tables::v2::{DBError, ProductTable, ThumbnailTable},
+    },
+    dlsite::product_id::ProductId,
+};
+use anyhow::{Context, Error as AnyError};
+use futures::StreamExt;
+use log::{error, info, warn};
+use reqwest::{header::CONTENT_TYPE, Client};
+use std::collections::HashSet;
+use thiserror::Error;
+
+/// The maximum total size of the cached thumbnails. The least recently used ones are evicted first.
+const MAX_CACHE_SIZE: u64 = 256 * 1024 * 1024;
+
+#[derive(Error, Debug)]
+pub enum ThumbnailServiceError {
+    #[error("{0:?}")]
+    DBError(#[from] DBError),
+    #[error("{0:?}")]
+    IOError(#[from] std::io::Error),
+    #[error("{0:?}")]
+    AnyError(#[from] AnyError),
+}
+
+/// A cached thumbnail image.
+pub struct ThumbnailImage {
+    pub content_type: String,
+    pub data: Vec<u8>,
+}
+
+pub struct ThumbnailService<'a> {
+    context: &'a ServiceContext,
+}
+
+impl<'a> ThumbnailService<'a> {
+    pub fn new() -> Self {
+        Self::with_context(use_application().context())
+    }
+
+    pub fn with_context(context: &'a ServiceContext) -> Self {
+        Self { context }
+    }
+
+    /// Downloads every thumbnail that is not cached yet, then evicts the cache down to its limit.
+    /// Failed downloads are logged and retried on the next call.
+    pub async fn cache_missing(&self) -> Result<(), ThumbnailServiceError> {
+        const CONCURRENCY: usize = 8;
+
+        let missing = ThumbnailTable::get_many_missing(&self.context.connection())?;
+
+        if !missing.is_empty() {
+            info!("[cache_missing] caching {} thumbnail(s)", missing.len());
+
+            let client = self.context.dlsite_client().http_client();
+            futures::stream::iter(missing)
+                .for_each_concurrent(CONCURRENCY, |(product_id, url)| {
+                    async move {
+                        if let Err(err) = self.cache_one(client, &product_id, &url).await {
+                            warn!(
+                                "[cache_missing] failed to cache the thumbnail of the product `{}`: {:?}",
+                                product_id, err
+                            );
+                        }
+                    }
+                })
+                .await;
+        }
+
+        self.evict()
+    }
+
+    /// Loads the cached thumbnail of the product. If it is not cached, it is downloaded and cached
+    /// first. Returns `None` if the product is unknown.
+    pub async fn load(
+        &self,
+        product_id: &ProductId,
+    ) -> Result<Option<ThumbnailImage>, ThumbnailServiceError> {
+        let thumbnail = ThumbnailTable::get_one(&self.context.connection(), product_id)?;
+
+        if let Some(thumbnail) = thumbnail {
+            match tokio::fs::read(self.context.thumbnail_dir()?.join(&thumbnail.file_name)).await {
+                Ok(data) => {
+                    ThumbnailTable::touch_one(&self.context.connection(), product_id)?;
+                    return Ok(Some(ThumbnailImage {
+                        content_type: thumbnail.content_type,
+                        data,
+                    }));
+                }
+                Err(err) => {
+                    warn!(
+                        "[load] the cached thumbnail of the product `{}` is not readable; fetching it again: {:?}",
+                        product_id, err
+                    );
+                    ThumbnailTable::remove_many(
+                        &self.context.connection(),
+                        std::iter::once(product_id.clone()),
+                    )?;
+                }
+            }
+        }
+
+        let product = match ProductTable::get_one(&self.context.connection(), product_id)? {
+            Some(product) if !product.thumbnail.is_empty() => product,
+            _ => return Ok(None),
+        };
+
+        Ok(Some(
+            self.cache_one(
+                self.context.dlsite_client().http_client(),
+                product_id,
+                &product.thumbnail,
+            )
+            .await?,
+        ))
+    }
+
+    /// Evicts the least recently used thumbnails until the cache fits in its limit,
+    /// and removes the files no longer tracked by the database.
+    pub fn evict(&self) -> Result<(), ThumbnailServiceError> {
+        let thumbnail_dir = self.context.thumbnail_dir()?;
+        let thumbnails = ThumbnailTable::get_all_least_recently_used(&self.context.connection())?;
+        let mut total_size = thumbnails
+            .iter()
+            .map(|thumbnail| thumbnail.size)
+            .sum::<u64>();
+        let mut evicted_ids = Vec::new();
+        let mut kept_file_names = HashSet::new();
+
+        for thumbnail in thumbnails {
+            if MAX_CACHE_SIZE < total_size {
+                total_size -= thumbnail.size;
+                evicted_ids.push(thumbnail.product_id);
+            } else {
+                kept_file_names.insert(thumbnail.file_name);
+            }
+        }
+
+        if !evicted_ids.is_empty() {
+            info!(
+                "[evict] evicting {} thumbnail(s) from the cache",
+                evicted_ids.len()
+            );
+            ThumbnailTable::remove_many(&self.context.connection(), evicted_ids.into_iter())?;
+        }
+
+        for entry in std::fs::read_dir(thumbnail_dir)? {
+            let entry = entry?;
+
+            if !entry.file_type()?.is_file() {
+                continue;
+            }
+
+            // temporary files belong to downloads in progress
+            let is_kept = entry.file_name().to_str().map_or(false, |file_name| {
+                file_name.ends_with(".tmp") || kept_file_names.contains(file_name)
+            });
+
+            if !is_kept {
+                if let Err(err) = std::fs::remove_file(entry.path()) {
+                    error!(
+                        "[evict] failed to remove the thumbnail file `{}`: {:?}",
+                        entry.path().display(),
+                        err
+                    );
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn cache_one(
+        &self,
+        client: &Client,
+        product_id: &ProductId,
+        url: &str,
+    ) -> Result<ThumbnailImage, ThumbnailServiceError> {
+        let res = client
+            .get(url)
+            .send()
+            .await
+            .and_then(|res| res.error_for_status())
+            .with_context(|| format!("[cache_one]"))
+            .with_context(|| format!("request failed for url: `{}`", url))?;
+        let content_type = res
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|content_type| content_type.to_str().ok())
+            .unwrap_or("image/jpeg")
+            .to_owned();
+        let data = res
+            .bytes()
+            .await
+            .with_context(|| format!("[cache_one]"))
+            .with_context(|| format!("failed to read the response for url: `{}`", url))?
+            .to_vec();
+
+        let extension = match content_type.as_str() {
+            "image/png" => "png",
+            "image/webp" => "webp",
+            "image/gif" => "gif",
+            _ => "jpg",
+        };
+        let file_name = format!("{}.{}", product_id, extension);
+        let thumbnail_dir = self.context.thumbnail_dir()?;
+        let temp_path = thumbnail_dir.join(format!("{}.tmp", file_name));
+
+        tokio::fs::write(&temp_path, &data).await?;
+        tokio::fs::rename(&temp_path, thumbnail_dir.join(&file_name)).await?;
+
+        ThumbnailTable::insert_one(
+            &self.context.connection(),
+            CreatingThumbnail {
+                product_id,
+                url,
+                file_name: &file_name,
+                content_type: &content_type,
+                size: data.len() as u64,
+            },
+        )?;
+
+        Ok(ThumbnailImage { content_type, data })
+    }
+}
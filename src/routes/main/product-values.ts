@@ -72,3 +72,18 @@ export const BgCssAge: Record<DLsiteProductAge, string> = {
   R18: "bg-[#bf2a13]",
   Unknown: "bg-[#525252]",
 };
+
+/**
+ * Returns the DLsite floor of a product or circle ID, e.g. `maniax` for `RJ123456` and `RG12345`.
+ * All-ages works share the prefixes, but DLsite redirects them to the right floor.
+ */
+export function dlsiteFloor(id: string): string {
+  switch (id.substring(0, 1).toUpperCase()) {
+    case "B":
+      return "books";
+    case "V":
+      return "pro";
+    default:
+      return "maniax";
+  }
+}
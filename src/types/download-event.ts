@@ -1,10 +1,54 @@
+export type DownloadPhase = "Downloading" | "Extracting";
+
+export interface ExtractingEntry {
+  name: string;
+  /** the number of the entries before the entry */
+  index: number;
+  count: number;
+}
+
 export interface DownloadProgress {
   product_id: string;
+  phase: DownloadPhase;
+  /** the percentage of the current phase */
   progress: number;
-  decompressing: boolean;
+  bytes_per_sec: number | null;
+  eta_secs: number | null;
+  entry: ExtractingEntry | null;
+}
+
+export type SkippedEntryReason =
+  | "UnsafePath"
+  | "Symlink"
+  | "OutsideTarget"
+  | "AlreadyExists"
+  | "Failed";
+
+export interface SkippedEntry {
+  name: string;
+  reason: SkippedEntryReason;
+  message: string | null;
 }
 
 export interface DownloadComplete {
   product_id: string;
   downloaded_path: string;
+  /** the entries of the archives that were left out when extracting them */
+  skipped_entries: SkippedEntry[];
+}
+
+export enum DownloadJobStatus {
+  Queued = "Queued",
+  Active = "Active",
+  Paused = "Paused",
+  Failed = "Failed",
+}
+
+export interface DownloadJob {
+  id: number;
+  account_id: number;
+  product_id: string;
+  decompress: boolean;
+  status: DownloadJobStatus;
+  error?: string;
 }
@@ -13,6 +13,36 @@ export interface Product {
 export interface ProductDownload {
   product_id: string;
   path: string;
+  /** when a scan found the folder missing */
+  missing_at: string | null;
+  /** why the downloaded files were not extracted */
+  extraction_skip_reason: string | null;
+}
+
+export interface ProductMetadata {
+  product_id: string;
+  description?: string;
+  series_id?: string;
+  series_name?: string;
+  file_format?: string;
+  page_count?: number;
+  track_count?: number;
+  genres: string[];
+  creators: DLsiteProductCreator[];
+}
+
+export interface DLsiteProductCreator {
+  role: DLsiteProductCreatorRole;
+  name: string;
+}
+
+export enum DLsiteProductCreatorRole {
+  Creator = "Creator",
+  Scenario = "Scenario",
+  Illust = "Illust",
+  Voice = "Voice",
+  Music = "Music",
+  Other = "Other",
 }
 
 export interface ProductQuery {
@@ -22,6 +52,13 @@ export interface ProductQuery {
   order_by_asc?: boolean;
 }
 
+/** `start` and `end` are offsets into the query string. */
+export interface ProductQuerySyntaxError {
+  message: string;
+  start: number;
+  end: number;
+}
+
 export enum DLsiteProductType {
   Adult = "Adult",
   Doujinsji = "Doujinsji",
@@ -68,3 +105,11 @@ export enum DLsiteProductDownloadState {
   Downloaded = "Downloaded",
   DownloadingAndDownloaded = "DownloadingAndDownloaded",
 }
+
+/** the encoding of the file names in a zip archive */
+export type NameEncoding = "Utf8" | "Cp932" | "EucJp" | "Gbk";
+
+export interface NameFix {
+  from: string;
+  to: string;
+}
@@ -2,3 +2,17 @@ export interface RefreshProgress {
   progress: number;
   total_progress: number;
 }
+
+export interface RefreshFailure {
+  product_id: string;
+  reason: string;
+}
+
+export type ScanAmbiguityKind = "MultipleIds" | "Nested" | "Duplicate";
+
+export interface ScanAmbiguity {
+  kind: ScanAmbiguityKind;
+  path: string;
+  product_ids: string[];
+  related_path: string | null;
+}
@@ -0,0 +1,17 @@
+export interface ReorganizeProgress {
+  product_id: string;
+  progress: number;
+  total_progress: number;
+}
+
+export interface ReorganizeFailure {
+  product_id: string;
+  from: string;
+  to: string;
+  reason: string;
+}
+
+export interface ReorganizeReport {
+  moved: number;
+  failures: ReorganizeFailure[];
+}
@@ -1,3 +1,5 @@
 export interface Setting {
   download_root_dir: string;
+  folder_template: string | null;
+  scan_depth: number | null;
 }
@@ -0,0 +1,24 @@
+export interface VerifyProgress {
+  product_id: string;
+  progress: number;
+  total_progress: number;
+}
+
+export type ManifestProblemKind = "Missing" | "Modified" | "Added";
+
+export interface ManifestProblem {
+  kind: ManifestProblemKind;
+  path: string;
+}
+
+export interface VerifyProblem {
+  product_id: string;
+  path: string;
+  files: ManifestProblem[];
+}
+
+export interface VerifyReport {
+  verified: number;
+  unverifiable: string[];
+  problems: VerifyProblem[];
+}